@@ -0,0 +1,179 @@
+//! Color conversion and palette helpers for RGB565
+use embedded_graphics::{
+    pixelcolor::{raw::RawU16, Rgb565, Rgb888},
+    prelude::*,
+};
+
+/// Returns the RGB565 color nearest to the 8-bit channels.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer_core::color;
+///
+/// const ORANGE: Rgb565 = color::rgb(255, 165, 0);
+/// ```
+pub const fn rgb(r: u8, g: u8, b: u8) -> Rgb565 {
+    Rgb565::new(
+        ((r as u16 * 31 + 127) / 255) as u8,
+        ((g as u16 * 63 + 127) / 255) as u8,
+        ((b as u16 * 31 + 127) / 255) as u8,
+    )
+}
+
+/// Returns the 8-bit channels of the color, scaled to the full range.
+pub fn to_rgb(color: Rgb565) -> (u8, u8, u8) {
+    let rgb = Rgb888::from(color);
+    (rgb.r(), rgb.g(), rgb.b())
+}
+
+/// Returns the color of the raw 16-bit value, as sent to the panel.
+pub fn from_raw(raw: u16) -> Rgb565 {
+    Rgb565::from(RawU16::new(raw))
+}
+
+/// Returns the raw 16-bit value of the color.
+pub fn to_raw(color: Rgb565) -> u16 {
+    color.into_storage()
+}
+
+/// Returns the color of the hue in degrees, and the saturation and value
+/// from 0.0 to 1.0.
+pub fn hsv(hue: f32, saturation: f32, value: f32) -> Rgb565 {
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let (saturation, value) = (saturation.clamp(0.0, 1.0), value.clamp(0.0, 1.0));
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    let channel = |x: f32| ((x + m) * 255.0 + 0.5) as u8;
+    rgb(channel(r), channel(g), channel(b))
+}
+
+/// Blend the foreground over the background with the alpha from 0
+/// (background) to 255 (foreground).
+pub fn blend(background: Rgb565, foreground: Rgb565, alpha: u8) -> Rgb565 {
+    let alpha = alpha as u16;
+    let mix = |b: u8, f: u8| ((b as u16 * (255 - alpha) + f as u16 * alpha + 127) / 255) as u8;
+    Rgb565::new(
+        mix(background.r(), foreground.r()),
+        mix(background.g(), foreground.g()),
+        mix(background.b(), foreground.b()),
+    )
+}
+
+/// Returns the color at `t` from 0.0 (start) to 1.0 (end) between the colors.
+pub fn lerp(start: Rgb888, end: Rgb888, t: f32) -> Rgb888 {
+    let t = t.clamp(0.0, 1.0);
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t + 0.5) as u8;
+    Rgb888::new(
+        mix(start.r(), end.r()),
+        mix(start.g(), end.g()),
+        mix(start.b(), end.b()),
+    )
+}
+
+/// Returns `len` colors evenly spread over the stops.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer_core::color;
+///
+/// // heat map for a spectrum
+/// let palette = color::gradient(
+///     &[Rgb888::BLUE, Rgb888::GREEN, Rgb888::YELLOW, Rgb888::RED],
+///     32,
+/// );
+/// ```
+pub fn gradient(stops: &[Rgb888], len: usize) -> Vec<Rgb565> {
+    match stops {
+        [] => return vec![Rgb565::BLACK; len],
+        [x] => return vec![(*x).into(); len],
+        _ => {}
+    }
+    let segments = (stops.len() - 1) as f32;
+    (0..len)
+        .map(|i| {
+            let position = i as f32 / (len.max(2) - 1) as f32 * segments;
+            let index = (position as usize).min(stops.len() - 2);
+            lerp(stops[index], stops[index + 1], position - index as f32).into()
+        })
+        .collect()
+}
+
+/// Returns `len` colors of evenly spaced hues at full saturation and value.
+pub fn rainbow(len: usize) -> Vec<Rgb565> {
+    (0..len)
+        .map(|i| hsv(i as f32 * 360.0 / len.max(1) as f32, 1.0, 1.0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn converts_the_extremes() {
+        assert_eq!(rgb(0, 0, 0), Rgb565::BLACK);
+        assert_eq!(rgb(255, 255, 255), Rgb565::WHITE);
+        assert_eq!(to_rgb(Rgb565::WHITE), (255, 255, 255));
+        assert_eq!(to_raw(Rgb565::RED), 0xF800);
+        assert_eq!(from_raw(0x07E0), Rgb565::GREEN);
+    }
+
+    #[test]
+    fn hsv_primaries() {
+        assert_eq!(hsv(0.0, 1.0, 1.0), Rgb565::RED);
+        assert_eq!(hsv(120.0, 1.0, 1.0), Rgb565::GREEN);
+        assert_eq!(hsv(240.0, 1.0, 1.0), Rgb565::BLUE);
+        assert_eq!(hsv(-120.0, 1.0, 1.0), Rgb565::BLUE);
+        assert_eq!(hsv(60.0, 0.0, 1.0), Rgb565::WHITE);
+        assert_eq!(hsv(60.0, 1.0, 0.0), Rgb565::BLACK);
+    }
+
+    #[test]
+    fn gradient_spans_the_stops() {
+        let palette = gradient(&[Rgb888::BLUE, Rgb888::GREEN, Rgb888::RED], 5);
+        assert_eq!(
+            palette,
+            [
+                Rgb565::BLUE,
+                rgb(0, 128, 128),
+                Rgb565::GREEN,
+                rgb(128, 128, 0),
+                Rgb565::RED
+            ]
+        );
+        assert_eq!(gradient(&[], 2), [Rgb565::BLACK; 2]);
+        assert_eq!(gradient(&[Rgb888::RED], 3), [Rgb565::RED; 3]);
+        assert_eq!(gradient(&[Rgb888::RED, Rgb888::BLUE], 1), [Rgb565::RED]);
+        assert_eq!(rainbow(3), [Rgb565::RED, Rgb565::GREEN, Rgb565::BLUE]);
+    }
+
+    proptest! {
+        #[test]
+        fn rgb_round_trips(raw in any::<u16>()) {
+            let color = from_raw(raw);
+            let (r, g, b) = to_rgb(color);
+            prop_assert_eq!(rgb(r, g, b), color);
+            prop_assert_eq!(to_raw(color), raw);
+        }
+
+        #[test]
+        fn blend_ends_at_the_colors(background in any::<u16>(), foreground in any::<u16>()) {
+            let (background, foreground) = (from_raw(background), from_raw(foreground));
+            prop_assert_eq!(blend(background, foreground, 0), background);
+            prop_assert_eq!(blend(background, foreground, 255), foreground);
+            prop_assert_eq!(blend(foreground, foreground, 100), foreground);
+        }
+    }
+}
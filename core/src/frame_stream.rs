@@ -0,0 +1,343 @@
+//! Frame stream format and its host-side decoder
+//!
+//! A frame is the magic `CPFB`, then the sequence number (u32), the width
+//! and the height (u16), the payload length (u32), the payload and the
+//! CRC-16/XMODEM of the payload (u16), all little-endian. The payload is
+//! the RGB565 pixels in row-major order compressed as runs: a header byte
+//! `0x80 | (n - 1)` followed by one pixel repeated `n` times, or a header
+//! byte `n - 1` followed by `n` different pixels, with `n` up to 128.
+//!
+//! The port is shared with the console; the decoder skips the log lines
+//! between the frames.
+use anyhow::{anyhow, bail, Result};
+
+use crate::crc::crc16;
+
+/// Start of a frame
+pub const MAGIC: &[u8; 4] = b"CPFB";
+
+const HEADER_SIZE: usize = 16;
+const MAX_RUN: usize = 128;
+
+/// Encode the raw RGB565 pixels as a frame.
+pub fn encode(sequence: u32, width: u16, height: u16, pixels: &[u16]) -> Vec<u8> {
+    let payload = compress(pixels);
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len() + 2);
+    frame.extend_from_slice(MAGIC);
+    frame.extend_from_slice(&sequence.to_le_bytes());
+    frame.extend_from_slice(&width.to_le_bytes());
+    frame.extend_from_slice(&height.to_le_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    frame.extend_from_slice(&crc16(&payload).to_le_bytes());
+    frame
+}
+
+fn compress(pixels: &[u16]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let push_pixel = |out: &mut Vec<u8>, pixel: u16| {
+        out.extend_from_slice(&pixel.to_le_bytes());
+    };
+    let mut i = 0;
+    while i < pixels.len() {
+        let run = pixels[i..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|x| **x == pixels[i])
+            .count();
+        if run > 1 {
+            out.push(0x80 | (run - 1) as u8);
+            push_pixel(&mut out, pixels[i]);
+            i += run;
+            continue;
+        }
+        // literal pixels until the next run of two
+        let mut len = 1;
+        while len < MAX_RUN
+            && i + len < pixels.len()
+            && (i + len + 1 >= pixels.len() || pixels[i + len] != pixels[i + len + 1])
+        {
+            len += 1;
+        }
+        out.push((len - 1) as u8);
+        for pixel in &pixels[i..i + len] {
+            push_pixel(&mut out, *pixel);
+        }
+        i += len;
+    }
+    out
+}
+
+fn decompress(payload: &[u8], len: usize) -> Result<Vec<u16>> {
+    let mut pixels = Vec::with_capacity(len);
+    let mut data = payload;
+    let pixel = |data: &[u8], i: usize| u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]);
+    while let Some(header) = data.first() {
+        let count = (*header & 0x7F) as usize + 1;
+        if pixels.len() + count > len {
+            bail!("frame has more than {} pixels", len);
+        }
+        if *header & 0x80 != 0 {
+            if data.len() < 3 {
+                bail!("truncated run");
+            }
+            let color = pixel(data, 0);
+            pixels.extend((0..count).map(|_| color));
+            data = &data[3..];
+        } else {
+            if data.len() < 1 + count * 2 {
+                bail!("truncated literal");
+            }
+            pixels.extend((0..count).map(|i| pixel(data, i)));
+            data = &data[1 + count * 2..];
+        }
+    }
+    if pixels.len() != len {
+        bail!("frame has {} pixels, expected {}", pixels.len(), len);
+    }
+    Ok(pixels)
+}
+
+/// Frame decoded on the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub sequence: u32,
+    pub width: u16,
+    pub height: u16,
+    /// Raw RGB565 pixels in row-major order
+    pub pixels: Vec<u16>,
+}
+
+impl Frame {
+    /// Returns the raw pixel at the position, or `None` outside the frame.
+    pub fn pixel(&self, x: u16, y: u16) -> Option<u16> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.pixels
+            .get(y as usize * self.width as usize + x as usize)
+            .copied()
+    }
+
+    /// Returns the frame as a binary PPM image, for saving golden images.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for pixel in self.pixels.iter() {
+            let (r, g, b) = (pixel >> 11, (pixel >> 5) & 0x3F, pixel & 0x1F);
+            ppm.extend_from_slice(&[expand(r, 31), expand(g, 63), expand(b, 31)]);
+        }
+        ppm
+    }
+}
+
+/// Returns the 8-bit value of the channel of the maximum, rounded as
+/// embedded-graphics converts to Rgb888.
+fn expand(value: u16, max: u16) -> u8 {
+    ((value * 255 + max / 2) / max) as u8
+}
+
+/// Decoder of the frames read from the serial port, for host-side tests
+///
+/// # Examples
+///
+/// ```
+/// use cardputer_core::frame_stream::FrameDecoder;
+///
+/// let mut decoder = FrameDecoder::new();
+/// let mut buf = [0u8; 4096];
+/// loop {
+///     let len = serial.read(&mut buf).unwrap();
+///     decoder.push(&buf[..len]);
+///     while let Some(frame) = decoder.next_frame().unwrap() {
+///         std::fs::write(format!("frame{}.ppm", frame.sequence), frame.to_ppm()).unwrap();
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    /// Create new decoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the bytes read from the port.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Returns the next complete frame, or `None` if more bytes are needed.
+    /// Bytes before a frame are skipped. A corrupted frame is skipped and
+    /// returned as an error.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>> {
+        let Some(start) = self.buffer.windows(MAGIC.len()).position(|x| x == MAGIC) else {
+            // keep a possible partial magic at the end
+            let keep = self.buffer.len().min(MAGIC.len() - 1);
+            self.buffer.drain(..self.buffer.len() - keep);
+            return Ok(None);
+        };
+        self.buffer.drain(..start);
+        if self.buffer.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([self.buffer[i], self.buffer[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([0, 1, 2, 3].map(|x| self.buffer[i + x]));
+        let sequence = u32_at(4);
+        let (width, height) = (u16_at(8), u16_at(10));
+        let len = u32_at(12) as usize;
+        if len > width as usize * height as usize * 3 {
+            self.buffer.drain(..MAGIC.len());
+            bail!("invalid payload length {} in frame {}", len, sequence);
+        }
+        if self.buffer.len() < HEADER_SIZE + len + 2 {
+            return Ok(None);
+        }
+        let payload = &self.buffer[HEADER_SIZE..HEADER_SIZE + len];
+        let crc = u16_at(HEADER_SIZE + len);
+        let pixels = if crc == crc16(payload) {
+            decompress(payload, width as usize * height as usize)
+        } else {
+            Err(anyhow!("checksum mismatch in frame {}", sequence))
+        };
+        match pixels {
+            Ok(pixels) => {
+                self.buffer.drain(..HEADER_SIZE + len + 2);
+                Ok(Some(Frame {
+                    sequence,
+                    width,
+                    height,
+                    pixels,
+                }))
+            }
+            Err(e) => {
+                // resynchronize after the magic of the broken frame
+                self.buffer.drain(..MAGIC.len());
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::{collection::vec, prelude::*};
+
+    fn decode(data: &[u8]) -> Result<Option<Frame>> {
+        let mut decoder = FrameDecoder::new();
+        decoder.push(data);
+        decoder.next_frame()
+    }
+
+    #[test]
+    fn runs_and_literals_round_trip() {
+        let mut pixels = vec![0xF800; 300];
+        pixels.extend(0..200u16);
+        pixels.extend([0x07E0, 0x07E0, 0x001F, 0xFFFF]);
+        let frame = encode(7, 24, 21, &pixels);
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&frame);
+        let decoded = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(decoded.sequence, 7);
+        assert_eq!((decoded.width, decoded.height), (24, 21));
+        assert_eq!(decoded.pixels, pixels);
+        assert_eq!(decoded.pixel(0, 0), Some(0xF800));
+        assert_eq!(decoded.pixel(24, 0), None);
+        assert!(decoder.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn log_lines_between_frames_are_skipped() {
+        let mut data = b"I (123) boot: hello\n".to_vec();
+        data.extend(encode(0, 2, 1, &[1, 2]));
+        data.extend(b"CP log\n");
+        data.extend(encode(1, 2, 1, &[3, 3]));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&data);
+        assert_eq!(decoder.next_frame().unwrap().unwrap().pixels, vec![1, 2]);
+        assert_eq!(decoder.next_frame().unwrap().unwrap().pixels, vec![3, 3]);
+        assert!(decoder.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_frame_waits_for_the_rest() {
+        let frame = encode(3, 4, 4, &[0x1234; 16]);
+        let mut decoder = FrameDecoder::new();
+        for byte in &frame[..frame.len() - 1] {
+            decoder.push(&[*byte]);
+            assert!(decoder.next_frame().unwrap().is_none());
+        }
+        decoder.push(&frame[frame.len() - 1..]);
+        assert_eq!(decoder.next_frame().unwrap().unwrap().sequence, 3);
+    }
+
+    #[test]
+    fn corrupted_frame_is_reported_and_skipped() {
+        let mut data = encode(0, 2, 2, &[1, 2, 3, 4]);
+        let last = data.len() - 3;
+        data[last] ^= 0xFF;
+        data.extend(encode(1, 2, 2, &[5, 6, 7, 8]));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&data);
+        assert!(decoder.next_frame().is_err());
+        assert_eq!(decoder.next_frame().unwrap().unwrap().sequence, 1);
+    }
+
+    #[test]
+    fn payload_of_the_wrong_size_is_rejected() {
+        // a valid frame of 4 pixels announced as 3
+        let mut frame = encode(0, 2, 2, &[1, 2, 3, 4]);
+        frame[8..10].copy_from_slice(&3u16.to_le_bytes());
+        frame[10..12].copy_from_slice(&1u16.to_le_bytes());
+        assert!(decode(&frame).is_err());
+
+        // a truncated literal with a matching checksum
+        let payload = [0x03, 1, 0, 2, 0];
+        let mut frame = MAGIC.to_vec();
+        frame.extend(0u32.to_le_bytes());
+        frame.extend(4u16.to_le_bytes());
+        frame.extend(1u16.to_le_bytes());
+        frame.extend((payload.len() as u32).to_le_bytes());
+        frame.extend(payload);
+        frame.extend(crc16(&payload).to_le_bytes());
+        assert!(decode(&frame).is_err());
+    }
+
+    #[test]
+    fn ppm_expands_the_channels() {
+        let frame = Frame {
+            sequence: 0,
+            width: 2,
+            height: 1,
+            pixels: vec![0xFFFF, 0x0841],
+        };
+        let ppm = frame.to_ppm();
+        assert!(ppm.starts_with(b"P6\n2 1\n255\n"));
+        assert_eq!(&ppm[ppm.len() - 6..], &[255, 255, 255, 8, 8, 8]);
+    }
+
+    proptest! {
+        #[test]
+        fn frames_round_trip(
+            pixels in vec(prop_oneof![Just(0u16), Just(0xFFFF), any::<u16>()], 1..600),
+            sequence in any::<u32>(),
+            split in any::<prop::sample::Index>(),
+        ) {
+            let frame = encode(sequence, pixels.len() as u16, 1, &pixels);
+            let split = split.index(frame.len());
+            let mut decoder = FrameDecoder::new();
+            decoder.push(&frame[..split]);
+            prop_assert!(decoder.next_frame().unwrap().is_none());
+            decoder.push(&frame[split..]);
+            let decoded = decoder.next_frame().unwrap().unwrap();
+            prop_assert_eq!(decoded.sequence, sequence);
+            prop_assert_eq!(decoded.pixels, pixels);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Entry points for fuzzers
+//!
+//! Each function drives the keyboard decoding with arbitrary bytes and
+//! panics when an invariant breaks: a decoder panics, a key is pressed
+//! twice without a release, released without a press, or released as
+//! another key than it was pressed. They are used by
+//! the property tests and can be called from a `cargo fuzz` target with
+//! the `fuzzing` feature:
+//!
+//! ```text
+//! fuzz_target!(|data: &[u8]| cardputer_core::fuzz::tca8418_events(data));
+//! ```
+use anyhow::Result;
+
+use crate::keyboard::{
+    decode_matrix,
+    pipeline::{KeyEvent, KeyEventKind},
+    tca8418::{Event, HeldKeys},
+    KeyImprint, KeyType, KeyboardScanner, KeyboardState,
+};
+
+/// Scanner returning the prepared scans in order, then nothing
+struct Replay(std::vec::IntoIter<Vec<KeyType>>);
+
+impl KeyboardScanner for Replay {
+    fn scan_pressed_keytypes(&mut self) -> Result<Vec<KeyType>> {
+        Ok(self.0.next().unwrap_or_default())
+    }
+}
+
+/// Run the scans through a [`KeyboardState`], followed by an empty scan,
+/// and check that the pressed and released events pair up.
+fn check_pairing(scans: Vec<Vec<KeyType>>) {
+    let count = scans.len() + 1;
+    let mut scanner = Replay(scans.into_iter());
+    let mut state = KeyboardState::default();
+    let mut held: Vec<KeyEvent> = Vec::new();
+    for _ in 0..count {
+        state.update(&mut scanner).unwrap();
+        for event in state.drain_events() {
+            let position = held.iter().position(|x| x.imprint == event.imprint);
+            match (event.kind, position) {
+                (KeyEventKind::Pressed, None) => held.push(event),
+                (KeyEventKind::Released, Some(i)) => {
+                    let pressed = held.remove(i);
+                    assert_eq!(pressed.key, event.key, "release differs from press");
+                    assert_eq!(pressed.modifiers, event.modifiers);
+                }
+                (kind, _) => panic!("unpaired {:?} of {:?}", kind, event.imprint),
+            }
+        }
+    }
+    let held: Vec<KeyImprint> = held.iter().map(|x| x.imprint).collect();
+    assert!(held.is_empty(), "never released: {:?}", held);
+}
+
+/// Decode keyboard matrix scans. Each byte holds the input lines Y0 to Y6
+/// of an address in bits 0 to 6, eight bytes per scan.
+pub fn matrix_scans(data: &[u8]) {
+    let scans = data
+        .chunks(8)
+        .map(|bytes| {
+            let mut matrix = [[false; 7]; 8];
+            for (row, byte) in matrix.iter_mut().zip(bytes) {
+                for (i, pressed) in row.iter_mut().enumerate() {
+                    *pressed = byte >> i & 1 != 0;
+                }
+            }
+            let keys = decode_matrix(&matrix);
+            let count = matrix.iter().flatten().filter(|x| **x).count();
+            assert_eq!(keys.len(), count);
+            keys
+        })
+        .collect();
+    check_pairing(scans);
+}
+
+/// Decode TCA8418 event streams. Each byte is an event of the FIFO,
+/// including invalid key numbers, and 0x00 ends a scan.
+pub fn tca8418_events(data: &[u8]) {
+    let mut held = HeldKeys::new();
+    let scans = data
+        .split(|x| *x == 0)
+        .map(|events| {
+            for event in events {
+                held.apply(Event::decode(*event));
+            }
+            held.keys()
+        })
+        .collect();
+    check_pairing(scans);
+}
@@ -0,0 +1,229 @@
+//! HID keyboard usage table
+//!
+//! Maps [`KeyImprint`] and [`Modified`] to keyboard page usages and
+//! modifier bits for a US layout, and builds the boot-compatible keyboard
+//! report, so the BLE and USB HID modes of the cardputer crate send the
+//! same reports. [`bridge`] turns the key events into them.
+use crate::keyboard::{pipeline::Modifiers, KeyImprint, KeyType, Modified, KEY_MAP};
+
+pub mod bridge;
+
+/// Modifier bit of the left Ctrl key
+pub const LEFT_CTRL: u8 = 0x01;
+/// Modifier bit of the left Shift key
+pub const LEFT_SHIFT: u8 = 0x02;
+/// Modifier bit of the left Alt key
+pub const LEFT_ALT: u8 = 0x04;
+/// Modifier bit of the left GUI key, sent for Opt
+pub const LEFT_GUI: u8 = 0x08;
+
+/// Digits and their shifted symbols from usage 0x1E
+const DIGITS: [(char, char); 10] = [
+    ('1', '!'),
+    ('2', '@'),
+    ('3', '#'),
+    ('4', '$'),
+    ('5', '%'),
+    ('6', '^'),
+    ('7', '&'),
+    ('8', '*'),
+    ('9', '('),
+    ('0', ')'),
+];
+
+/// Punctuation keys and their shifted symbols
+const SYMBOLS: [(u8, char, char); 11] = [
+    (0x2D, '-', '_'),
+    (0x2E, '=', '+'),
+    (0x2F, '[', '{'),
+    (0x30, ']', '}'),
+    (0x31, '\\', '|'),
+    (0x33, ';', ':'),
+    (0x34, '\'', '"'),
+    (0x35, '`', '~'),
+    (0x36, ',', '<'),
+    (0x37, '.', '>'),
+    (0x38, '/', '?'),
+];
+
+/// Returns the keyboard usage of the key, or `None` for the modifier keys.
+pub fn usage(imprint: KeyImprint) -> Option<u8> {
+    KEY_MAP.iter().flatten().find_map(|x| match x {
+        KeyType::Normal(rule) if rule.imprint() == imprint => {
+            key_usage(rule.modified(false, false)).map(|(_, x)| x)
+        }
+        _ => None,
+    })
+}
+
+/// Returns the modifier bit of the key, or `None` for the other keys.
+///
+/// Fn has no bit as it is resolved on the device.
+pub fn modifier_bit(imprint: KeyImprint) -> Option<u8> {
+    match imprint {
+        KeyImprint::LeftCtrl => Some(LEFT_CTRL),
+        KeyImprint::LeftShift => Some(LEFT_SHIFT),
+        KeyImprint::LeftAlt => Some(LEFT_ALT),
+        KeyImprint::LeftOpt => Some(LEFT_GUI),
+        _ => None,
+    }
+}
+
+/// Returns the modifier bits of the held modifier keys.
+pub fn modifier_bits(modifiers: &Modifiers) -> u8 {
+    [
+        (modifiers.is_ctrl_pressed, LEFT_CTRL),
+        (modifiers.is_shift_pressed, LEFT_SHIFT),
+        (modifiers.is_alt_pressed, LEFT_ALT),
+        (modifiers.is_opt_pressed, LEFT_GUI),
+    ]
+    .iter()
+    .filter(|(is_pressed, _)| *is_pressed)
+    .fold(0, |acc, (_, bit)| acc | bit)
+}
+
+/// Returns the modifier bits and the usage that type the key, or `None`
+/// for the media keys and characters missing from the US layout.
+pub fn key_usage(key: Modified) -> Option<(u8, u8)> {
+    let usage = match key {
+        Modified::Graph(c) => return char_usage(c),
+        Modified::Enter => 0x28,
+        Modified::Escape => 0x29,
+        Modified::Backspace => 0x2A,
+        Modified::Tab => 0x2B,
+        Modified::Space => 0x2C,
+        Modified::Delete => 0x4C,
+        Modified::Home => 0x4A,
+        Modified::End => 0x4D,
+        Modified::RightCursor => 0x4F,
+        Modified::LeftCursor => 0x50,
+        Modified::DownCursor => 0x51,
+        Modified::UpCursor => 0x52,
+        Modified::NumLock => 0x53,
+        Modified::VolumeUp
+        | Modified::VolumeDown
+        | Modified::Mute
+        | Modified::PlayPause
+        | Modified::NextTrack
+        | Modified::PreviousTrack
+        | Modified::BrightnessUp
+        | Modified::BrightnessDown => return None,
+    };
+    Some((0, usage))
+}
+
+/// Returns the modifier bits and the usage that type the character.
+pub fn char_usage(c: char) -> Option<(u8, u8)> {
+    match c {
+        'a'..='z' => Some((0, 0x04 + (c as u8 - b'a'))),
+        'A'..='Z' => Some((LEFT_SHIFT, 0x04 + (c as u8 - b'A'))),
+        '\n' => Some((0, 0x28)),
+        '\t' => Some((0, 0x2B)),
+        ' ' => Some((0, 0x2C)),
+        _ => DIGITS
+            .iter()
+            .zip(0x1E..)
+            .map(|((normal, shifted), usage)| (usage, *normal, *shifted))
+            .chain(SYMBOLS)
+            .find_map(|(usage, normal, shifted)| match c {
+                _ if c == normal => Some((0, usage)),
+                _ if c == shifted => Some((LEFT_SHIFT, usage)),
+                _ => None,
+            }),
+    }
+}
+
+/// Returns the keyboard report, without the report ID, of the modifier
+/// bits and up to six usages.
+pub fn keyboard_report(modifiers: u8, usages: &[u8]) -> [u8; 8] {
+    let mut report = [0u8; 8];
+    report[0] = modifiers;
+    for (slot, usage) in report[2..].iter_mut().zip(usages) {
+        *slot = *usage;
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn letters_and_digits_follow_the_keyboard_page() {
+        assert_eq!(char_usage('a'), Some((0, 0x04)));
+        assert_eq!(char_usage('z'), Some((0, 0x1D)));
+        assert_eq!(char_usage('Q'), Some((LEFT_SHIFT, 0x14)));
+        assert_eq!(char_usage('1'), Some((0, 0x1E)));
+        assert_eq!(char_usage('0'), Some((0, 0x27)));
+        assert_eq!(char_usage('!'), Some((LEFT_SHIFT, 0x1E)));
+        assert_eq!(char_usage(')'), Some((LEFT_SHIFT, 0x27)));
+    }
+
+    #[test]
+    fn symbols_use_the_us_layout() {
+        assert_eq!(char_usage('-'), Some((0, 0x2D)));
+        assert_eq!(char_usage('_'), Some((LEFT_SHIFT, 0x2D)));
+        assert_eq!(char_usage(';'), Some((0, 0x33)));
+        assert_eq!(char_usage('"'), Some((LEFT_SHIFT, 0x34)));
+        assert_eq!(char_usage('?'), Some((LEFT_SHIFT, 0x38)));
+        assert_eq!(char_usage('\n'), Some((0, 0x28)));
+        assert_eq!(char_usage(' '), Some((0, 0x2C)));
+        assert_eq!(char_usage('é'), None);
+        assert_eq!(char_usage('£'), None);
+    }
+
+    #[test]
+    fn named_keys_have_usages_and_media_keys_do_not() {
+        assert_eq!(key_usage(Modified::Enter), Some((0, 0x28)));
+        assert_eq!(key_usage(Modified::Backspace), Some((0, 0x2A)));
+        assert_eq!(key_usage(Modified::UpCursor), Some((0, 0x52)));
+        assert_eq!(key_usage(Modified::Graph('A')), Some((LEFT_SHIFT, 0x04)));
+        assert_eq!(key_usage(Modified::VolumeUp), None);
+        assert_eq!(key_usage(Modified::BrightnessDown), None);
+    }
+
+    #[test]
+    fn every_normal_key_has_a_usage() {
+        for key in KEY_MAP.iter().flatten() {
+            match key {
+                KeyType::Normal(rule) => {
+                    assert!(usage(rule.imprint()).is_some(), "{:?}", rule.imprint());
+                    // the shifted character types with the same usage and Shift
+                    let shifted = key_usage(rule.modified(false, true));
+                    assert_eq!(shifted.map(|(_, x)| x), usage(rule.imprint()));
+                }
+                KeyType::Modifier(imprint) => assert_eq!(usage(*imprint), None),
+            }
+        }
+    }
+
+    #[test]
+    fn modifier_bits_skip_fn() {
+        assert_eq!(modifier_bit(KeyImprint::LeftCtrl), Some(LEFT_CTRL));
+        assert_eq!(modifier_bit(KeyImprint::LeftOpt), Some(LEFT_GUI));
+        assert_eq!(modifier_bit(KeyImprint::LeftFn), None);
+        assert_eq!(modifier_bit(KeyImprint::A), None);
+
+        let modifiers = Modifiers {
+            is_fn_pressed: true,
+            is_shift_pressed: true,
+            is_ctrl_pressed: true,
+            is_alt_pressed: false,
+            is_opt_pressed: true,
+        };
+        assert_eq!(modifier_bits(&modifiers), LEFT_SHIFT | LEFT_CTRL | LEFT_GUI);
+        assert_eq!(modifier_bits(&Modifiers::default()), 0);
+    }
+
+    #[test]
+    fn keyboard_report_keeps_six_usages() {
+        assert_eq!(
+            keyboard_report(LEFT_SHIFT, &[0x04, 0x05]),
+            [LEFT_SHIFT, 0, 0x04, 0x05, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            keyboard_report(0, &[1, 2, 3, 4, 5, 6, 7]),
+            [0, 0, 1, 2, 3, 4, 5, 6]
+        );
+    }
+}
@@ -0,0 +1,487 @@
+//! Key events to HID keyboard reports
+//!
+//! [`HidBridge`] keeps the state of a 6-key-rollover keyboard from the
+//! key event stream: the modifier bits and the held keys in the order
+//! they were pressed. With more than six keys held, the report is in the
+//! phantom state (ErrorRollOver in every slot) until a key is released,
+//! as the host would otherwise see keys released that are still held.
+//!
+//! The modifier byte is built from the modifiers carried by the events of
+//! the held keys, with the Shift bit of the shifted characters of the
+//! usage table, so the events of [`KeyboardState`], which has no events
+//! for the modifier keys, type Ctrl+C and `!` as such. The modifier key
+//! events of a [`Pipeline`] also set and clear their bits.
+//!
+//! Each change of the state queues a report, and the queue is sent to a
+//! [`KeyboardOutput`] at most once per report interval, so a key pressed
+//! and released between two reports still reaches the host as a press
+//! followed by a release.
+//!
+//! [`KeyboardState`]: crate::keyboard::KeyboardState
+//! [`Pipeline`]: crate::keyboard::pipeline::Pipeline
+use anyhow::Result;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use super::{key_usage, keyboard_report, modifier_bit, modifier_bits, usage};
+use crate::keyboard::pipeline::{KeyEvent, KeyEventKind};
+use crate::keyboard::KeyImprint;
+
+/// Keys reported at once
+const ROLLOVER: usize = 6;
+
+/// Usage sent in every slot while too many keys are held
+const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// Reports kept while the output is slower than the key events; the last
+/// one is replaced beyond, so the final state is always sent
+const QUEUE_CAPACITY: usize = 16;
+
+/// Backend sending the keyboard reports to the host
+pub trait KeyboardOutput {
+    /// Send the keyboard report without the report ID. Returns false if
+    /// the backend is busy and the report has to be sent again later.
+    fn send_report(&mut self, report: &[u8; 8]) -> Result<bool>;
+}
+
+/// Key held on the bridge
+#[derive(Debug, Clone, Copy)]
+struct HeldKey {
+    imprint: KeyImprint,
+    usage: u8,
+    /// Modifier bits of the press
+    modifiers: u8,
+}
+
+/// Bridge from the key events to the keyboard reports
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{ble::hid::BleHid, hid::bridge::HidBridge};
+///
+/// let mut hid = BleHid::start("Cardputer").unwrap();
+/// let mut bridge = HidBridge::new().with_interval(Duration::from_millis(15));
+/// let events = broadcast.subscribe();
+/// loop {
+///     bridge.handle_all(events.drain());
+///     bridge.flush(&mut hid).unwrap();
+///     thread::sleep(Duration::from_millis(1));
+/// }
+/// ```
+pub struct HidBridge {
+    interval: Duration,
+    /// Bits of the modifier keys held, from the modifier key events
+    modifiers: u8,
+    /// Held keys in the order they were pressed
+    held: Vec<HeldKey>,
+    /// Reports waiting to be sent, oldest first
+    queue: VecDeque<[u8; 8]>,
+    /// Last report queued, to skip the events that change nothing
+    last: [u8; 8],
+    sent: Option<Instant>,
+}
+
+impl Default for HidBridge {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(10),
+            modifiers: 0,
+            held: Vec::new(),
+            queue: VecDeque::new(),
+            last: [0; 8],
+            sent: None,
+        }
+    }
+}
+
+impl HidBridge {
+    /// Create new bridge with no key held.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the shortest time between two reports (10 ms).
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Returns the report of the current state.
+    pub fn report(&self) -> [u8; 8] {
+        let modifiers = self
+            .held
+            .iter()
+            .fold(self.modifiers, |acc, x| acc | x.modifiers);
+        if self.is_overflowed() {
+            return keyboard_report(modifiers, &[ERROR_ROLL_OVER; ROLLOVER]);
+        }
+        let usages: Vec<u8> = self.held.iter().map(|x| x.usage).collect();
+        keyboard_report(modifiers, &usages)
+    }
+
+    /// Returns true while more keys are held than a report can carry.
+    pub fn is_overflowed(&self) -> bool {
+        self.held.len() > ROLLOVER
+    }
+
+    /// Returns the number of reports waiting to be sent.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Update the state with the event. Repeated events are ignored, as
+    /// the host repeats the held keys itself, and so are the keys without
+    /// a usage (Fn and the media keys).
+    pub fn handle(&mut self, event: &KeyEvent) {
+        match event.kind {
+            KeyEventKind::Pressed => self.press(event),
+            KeyEventKind::Released => self.release(event.imprint),
+            KeyEventKind::Repeated => return,
+        }
+        self.queue_report();
+    }
+
+    /// Update the state with the events in order.
+    pub fn handle_all(&mut self, events: impl IntoIterator<Item = KeyEvent>) {
+        for event in events {
+            self.handle(&event);
+        }
+    }
+
+    /// Release every key, e.g. before the output is switched.
+    pub fn release_all(&mut self) {
+        self.modifiers = 0;
+        self.held.clear();
+        self.queue_report();
+    }
+
+    /// Send the oldest queued report if the interval has passed since the
+    /// last one. Returns true if a report was sent.
+    pub fn flush(&mut self, output: &mut impl KeyboardOutput) -> Result<bool> {
+        let Some(report) = self.queue.front() else {
+            return Ok(false);
+        };
+        if self.sent.is_some_and(|sent| sent.elapsed() < self.interval) {
+            return Ok(false);
+        }
+        if !output.send_report(report)? {
+            return Ok(false);
+        }
+        self.queue.pop_front();
+        self.sent = Some(Instant::now());
+        Ok(true)
+    }
+
+    fn press(&mut self, event: &KeyEvent) {
+        // only the events of a pipeline come for the modifier keys
+        if let Some(bit) = modifier_bit(event.imprint) {
+            self.modifiers |= bit;
+            return;
+        }
+        if self.held.iter().any(|x| x.imprint == event.imprint) {
+            return;
+        }
+        // the converted key follows the Fn layer, e.g. arrows
+        let typed = match event.key {
+            Some(key) => key_usage(key),
+            None => usage(event.imprint).map(|x| (0, x)),
+        };
+        if let Some((bits, usage)) = typed {
+            self.held.push(HeldKey {
+                imprint: event.imprint,
+                usage,
+                modifiers: bits | modifier_bits(&event.modifiers),
+            });
+        }
+    }
+
+    fn release(&mut self, imprint: KeyImprint) {
+        if let Some(bit) = modifier_bit(imprint) {
+            self.modifiers &= !bit;
+            return;
+        }
+        // the usage of the press is released even if Fn was released first
+        self.held.retain(|x| x.imprint != imprint);
+    }
+
+    fn queue_report(&mut self) {
+        let report = self.report();
+        if report == self.last {
+            return;
+        }
+        self.last = report;
+        if self.queue.len() < QUEUE_CAPACITY {
+            self.queue.push_back(report);
+        } else if let Some(x) = self.queue.back_mut() {
+            *x = report;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hid::{LEFT_CTRL, LEFT_SHIFT};
+    use crate::keyboard::pipeline::Modifiers;
+    use crate::keyboard::{KeyType, KeyboardScanner, KeyboardState, Modified, KEY_MAP};
+    use proptest::{collection::vec, prelude::*, sample::select};
+
+    /// Output recording the reports, busy for the first `busy` calls
+    #[derive(Default)]
+    struct Recorder {
+        reports: Vec<[u8; 8]>,
+        busy: usize,
+    }
+
+    impl KeyboardOutput for Recorder {
+        fn send_report(&mut self, report: &[u8; 8]) -> Result<bool> {
+            if self.busy > 0 {
+                self.busy -= 1;
+                return Ok(false);
+            }
+            self.reports.push(*report);
+            Ok(true)
+        }
+    }
+
+    /// Scanner returning the prepared scans in order
+    struct Scripted(Vec<Vec<KeyImprint>>);
+
+    impl KeyboardScanner for Scripted {
+        fn scan_pressed_keytypes(&mut self) -> Result<Vec<KeyType>> {
+            let imprints = self.0.remove(0);
+            Ok(KEY_MAP
+                .iter()
+                .flatten()
+                .filter(|x| imprints.contains(&x.imprint()))
+                .copied()
+                .collect())
+        }
+    }
+
+    fn event(kind: KeyEventKind, imprint: KeyImprint) -> KeyEvent {
+        KeyEvent::new(kind, imprint)
+    }
+
+    /// Feed the scans to a keyboard state and the bridge, and return the
+    /// queued reports.
+    fn reports_of_scans(scans: Vec<Vec<KeyImprint>>) -> Vec<[u8; 8]> {
+        let count = scans.len();
+        let mut scanner = Scripted(scans);
+        let mut state = KeyboardState::default();
+        let mut bridge = HidBridge::new().with_interval(Duration::ZERO);
+        for _ in 0..count {
+            state.update(&mut scanner).unwrap();
+            bridge.handle_all(state.drain_events());
+        }
+        bridge.queue.into_iter().collect()
+    }
+
+    #[test]
+    fn keyboard_state_events_carry_ctrl_and_shift() {
+        let reports = reports_of_scans(vec![
+            vec![KeyImprint::LeftCtrl],
+            vec![KeyImprint::LeftCtrl, KeyImprint::C],
+            vec![KeyImprint::LeftCtrl],
+            vec![KeyImprint::LeftShift, KeyImprint::One],
+            vec![],
+        ]);
+        assert_eq!(
+            reports,
+            vec![
+                [LEFT_CTRL, 0, 0x06, 0, 0, 0, 0, 0],
+                [0; 8],
+                [LEFT_SHIFT, 0, 0x1E, 0, 0, 0, 0, 0],
+                [0; 8],
+            ]
+        );
+    }
+
+    #[test]
+    fn fn_layer_keys_use_the_converted_usage() {
+        let reports = reports_of_scans(vec![
+            vec![KeyImprint::LeftFn, KeyImprint::SemiColon],
+            vec![KeyImprint::SemiColon],
+            vec![],
+        ]);
+        // the release follows the press even after Fn is released
+        assert_eq!(reports, vec![[0, 0, 0x52, 0, 0, 0, 0, 0], [0; 8]]);
+    }
+
+    #[test]
+    fn pipeline_modifier_events_set_their_bits() {
+        let mut bridge = HidBridge::new();
+        let mut ctrl = event(KeyEventKind::Pressed, KeyImprint::LeftCtrl);
+        ctrl.modifiers.is_ctrl_pressed = true;
+        bridge.handle(&ctrl);
+        assert_eq!(bridge.report(), [LEFT_CTRL, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut c = event(KeyEventKind::Pressed, KeyImprint::C);
+        c.modifiers.is_ctrl_pressed = true;
+        c.key = Some(Modified::Graph('c'));
+        bridge.handle(&c);
+        assert_eq!(bridge.report(), [LEFT_CTRL, 0, 0x06, 0, 0, 0, 0, 0]);
+
+        bridge.handle(&event(KeyEventKind::Released, KeyImprint::C));
+        bridge.handle(&event(KeyEventKind::Released, KeyImprint::LeftCtrl));
+        assert_eq!(bridge.report(), [0; 8]);
+        assert_eq!(bridge.pending(), 4);
+    }
+
+    #[test]
+    fn keys_keep_the_order_of_their_presses() {
+        let mut bridge = HidBridge::new();
+        for imprint in [KeyImprint::C, KeyImprint::A, KeyImprint::B] {
+            bridge.handle(&event(KeyEventKind::Pressed, imprint));
+        }
+        // pressed twice without a release
+        bridge.handle(&event(KeyEventKind::Pressed, KeyImprint::C));
+        assert_eq!(bridge.report(), [0, 0, 0x06, 0x04, 0x05, 0, 0, 0]);
+
+        bridge.handle(&event(KeyEventKind::Released, KeyImprint::A));
+        bridge.handle(&event(KeyEventKind::Pressed, KeyImprint::D));
+        assert_eq!(bridge.report(), [0, 0, 0x06, 0x05, 0x07, 0, 0, 0]);
+
+        // releases of keys not held change nothing
+        let pending = bridge.pending();
+        bridge.handle(&event(KeyEventKind::Released, KeyImprint::Z));
+        bridge.handle(&event(KeyEventKind::Repeated, KeyImprint::C));
+        assert_eq!(bridge.pending(), pending);
+    }
+
+    #[test]
+    fn seventh_key_overflows_until_released() {
+        let keys = [
+            KeyImprint::A,
+            KeyImprint::B,
+            KeyImprint::C,
+            KeyImprint::D,
+            KeyImprint::E,
+            KeyImprint::F,
+            KeyImprint::G,
+        ];
+        let mut bridge = HidBridge::new();
+        let mut shift = event(KeyEventKind::Pressed, KeyImprint::LeftShift);
+        shift.modifiers.is_shift_pressed = true;
+        bridge.handle(&shift);
+        for imprint in keys {
+            bridge.handle(&event(KeyEventKind::Pressed, imprint));
+        }
+        assert!(bridge.is_overflowed());
+        assert_eq!(bridge.report(), [LEFT_SHIFT, 0, 1, 1, 1, 1, 1, 1]);
+
+        bridge.handle(&event(KeyEventKind::Released, KeyImprint::A));
+        assert!(!bridge.is_overflowed());
+        assert_eq!(
+            bridge.report(),
+            [LEFT_SHIFT, 0, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A]
+        );
+    }
+
+    #[test]
+    fn media_keys_and_fn_have_no_usage() {
+        let mut bridge = HidBridge::new();
+        let mut mute = event(KeyEventKind::Pressed, KeyImprint::Space);
+        mute.key = Some(Modified::Mute);
+        bridge.handle(&mute);
+        bridge.handle(&event(KeyEventKind::Pressed, KeyImprint::LeftFn));
+        assert_eq!(bridge.report(), [0; 8]);
+        assert_eq!(bridge.pending(), 0);
+    }
+
+    #[test]
+    fn flush_waits_for_the_interval_and_the_output() {
+        let mut bridge = HidBridge::new().with_interval(Duration::from_millis(20));
+        let mut output = Recorder {
+            busy: 1,
+            ..Default::default()
+        };
+        // a tap between two reports still sends the press
+        bridge.handle(&event(KeyEventKind::Pressed, KeyImprint::A));
+        bridge.handle(&event(KeyEventKind::Released, KeyImprint::A));
+
+        assert!(!bridge.flush(&mut output).unwrap());
+        assert!(bridge.flush(&mut output).unwrap());
+        assert!(!bridge.flush(&mut output).unwrap());
+        assert_eq!(bridge.pending(), 1);
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(bridge.flush(&mut output).unwrap());
+        assert!(!bridge.flush(&mut output).unwrap());
+        assert_eq!(output.reports, vec![[0, 0, 0x04, 0, 0, 0, 0, 0], [0; 8]]);
+    }
+
+    #[test]
+    fn full_queue_keeps_the_last_state() {
+        let mut bridge = HidBridge::new();
+        for _ in 0..QUEUE_CAPACITY {
+            bridge.handle(&event(KeyEventKind::Pressed, KeyImprint::A));
+            bridge.handle(&event(KeyEventKind::Released, KeyImprint::A));
+        }
+        assert_eq!(bridge.pending(), QUEUE_CAPACITY);
+        assert_eq!(bridge.queue.back(), Some(&[0; 8]));
+
+        bridge.release_all();
+        bridge.handle(&event(KeyEventKind::Pressed, KeyImprint::B));
+        assert_eq!(bridge.pending(), QUEUE_CAPACITY);
+        assert_eq!(bridge.queue.back(), Some(&[0, 0, 0x05, 0, 0, 0, 0, 0]));
+    }
+
+    const KEYS: [KeyImprint; 10] = [
+        KeyImprint::A,
+        KeyImprint::B,
+        KeyImprint::C,
+        KeyImprint::D,
+        KeyImprint::E,
+        KeyImprint::F,
+        KeyImprint::G,
+        KeyImprint::H,
+        KeyImprint::LeftCtrl,
+        KeyImprint::LeftShift,
+    ];
+
+    proptest! {
+        #[test]
+        fn reports_follow_the_held_keys(
+            events in vec((any::<bool>(), select(KEYS.to_vec())), 0..80)
+        ) {
+            let mut bridge = HidBridge::new();
+            let mut held: Vec<KeyImprint> = Vec::new();
+            for (pressed, imprint) in events {
+                let kind = if pressed { KeyEventKind::Pressed } else { KeyEventKind::Released };
+                let mut x = event(kind, imprint);
+                x.modifiers = Modifiers::default();
+                bridge.handle(&x);
+                match (pressed, held.iter().position(|x| *x == imprint)) {
+                    (true, None) => held.push(imprint),
+                    (false, Some(i)) => {
+                        held.remove(i);
+                    }
+                    _ => {}
+                }
+            }
+            let normal: Vec<u8> = held
+                .iter()
+                .filter_map(|x| usage(*x))
+                .collect();
+            let bits = held
+                .iter()
+                .filter_map(|x| modifier_bit(*x))
+                .fold(0, |acc, x| acc | x);
+            let report = bridge.report();
+            prop_assert_eq!(report[0], bits);
+            if normal.len() > ROLLOVER {
+                prop_assert_eq!(&report[2..], &[ERROR_ROLL_OVER; ROLLOVER]);
+            } else {
+                prop_assert_eq!(&report[2..2 + normal.len()], &normal[..]);
+                prop_assert!(report[2 + normal.len()..].iter().all(|x| *x == 0));
+            }
+
+            bridge.release_all();
+            prop_assert_eq!(bridge.report(), [0; 8]);
+        }
+    }
+}
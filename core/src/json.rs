@@ -0,0 +1,208 @@
+//! Minimal JSON for flat request and response objects
+//!
+//! The REST API of the cardputer crate takes objects whose fields are
+//! strings, numbers, booleans or null; nested values are not supported.
+use anyhow::{anyhow, bail, Result};
+
+/// Value of a field of a flat object
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+/// Parse an object whose fields are strings, numbers, booleans or null.
+pub fn parse_object(text: &str) -> Result<Vec<(String, Value)>> {
+    let mut parser = Parser {
+        chars: text.trim().chars().peekable(),
+    };
+    parser.expect('{')?;
+    let mut fields = Vec::new();
+    if parser.skip_whitespace() == Some('}') {
+        parser.chars.next();
+        parser.end()?;
+        return Ok(fields);
+    }
+    loop {
+        parser.skip_whitespace();
+        let name = parser.string()?;
+        parser.expect(':')?;
+        fields.push((name, parser.value()?));
+        match parser.next_token() {
+            Some(',') => continue,
+            Some('}') => break,
+            x => bail!("expected ',' or '}}', found {:?}", x),
+        }
+    }
+    parser.end()?;
+    Ok(fields)
+}
+
+/// Returns the field of the parsed object.
+pub fn field<'a>(fields: &'a [(String, Value)], name: &str) -> Option<&'a Value> {
+    fields.iter().find(|(x, _)| x == name).map(|(_, x)| x)
+}
+
+/// Returns the text as a JSON string literal.
+pub fn string(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c if (c as u32) < 0x20 => literal.push_str(&format!("\\u{:04x}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) -> Option<char> {
+        while self.chars.next_if(|x| x.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    fn next_token(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.next()
+    }
+
+    fn end(&mut self) -> Result<()> {
+        match self.next_token() {
+            None => Ok(()),
+            Some(x) => bail!("unexpected {:?} after the object", x),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.next_token() {
+            Some(x) if x == expected => Ok(()),
+            x => bail!("expected {:?}, found {:?}", expected, x),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.skip_whitespace() {
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|x| x.is_ascii_digit() || "+-.eE".contains(*x))
+                {
+                    number.push(c);
+                }
+                Ok(Value::Number(number.parse()?))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut word = String::new();
+                while let Some(c) = self.chars.next_if(|x| x.is_ascii_alphabetic()) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "null" => Ok(Value::Null),
+                    _ => bail!("unexpected {:?}", word),
+                }
+            }
+            x => bail!("unsupported value starting with {:?}", x),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            match self
+                .chars
+                .next()
+                .ok_or_else(|| anyhow!("unterminated string"))?
+            {
+                '"' => return Ok(text),
+                '\\' => match self.chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('r') => text.push('\r'),
+                    Some('t') => text.push('\t'),
+                    Some('b') => text.push('\u{8}'),
+                    Some('f') => text.push('\u{c}'),
+                    Some('u') => {
+                        let code: String = self.chars.by_ref().take(4).collect();
+                        let code = u32::from_str_radix(&code, 16)?;
+                        text.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    Some(c) => text.push(c),
+                    None => bail!("unterminated string"),
+                },
+                c => text.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn parses_flat_objects() {
+        let fields =
+            parse_object(r#" { "a": "x\"é", "b" : -1.5e1, "c":true, "d": null } "#).unwrap();
+        assert_eq!(
+            field(&fields, "a"),
+            Some(&Value::String("x\"é".to_string()))
+        );
+        assert_eq!(field(&fields, "b"), Some(&Value::Number(-15.0)));
+        assert_eq!(field(&fields, "c"), Some(&Value::Bool(true)));
+        assert_eq!(field(&fields, "d"), Some(&Value::Null));
+        assert_eq!(field(&fields, "e"), None);
+        assert!(parse_object("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_trailing_garbage() {
+        assert!(parse_object(r#"{"a":1}x"#).is_err());
+        assert!(parse_object(r#"{"a":1}}"#).is_err());
+        assert!(parse_object(r#"{} {}"#).is_err());
+        assert!(parse_object(r#"{"a":1}  "#).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_objects() {
+        for text in [
+            "",
+            "[]",
+            r#"{"a"}"#,
+            r#"{"a":1,}"#,
+            r#"{"a":1 "b":2}"#,
+            r#"{"a":"x"#,
+            r#"{"a":yes}"#,
+            r#"{"a":{}}"#,
+            r#"{a:1}"#,
+        ] {
+            assert!(parse_object(text).is_err(), "{:?}", text);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn string_literals_parse_back(text in any::<String>()) {
+            let object = format!("{{\"text\":{}}}", string(&text));
+            let fields = parse_object(&object).unwrap();
+            prop_assert_eq!(field(&fields, "text"), Some(&Value::String(text)));
+        }
+    }
+}
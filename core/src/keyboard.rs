@@ -0,0 +1,864 @@
+//! Key types, the key map and the keyboard state of the Cardputer
+//!
+//! The 74HC138 decoder selects one of eight addresses with A0 to A2 and
+//! the seven input lines Y0 to Y6 read the keys of the address;
+//! [`key_at`] maps an address and an input line to the key.
+//!
+//! ### 4x14 keymap
+//!
+//! ```text
+//!  A2 A2 A0 |    Y0  |  Y1   |  Y2   |  Y3   |  Y4   |  Y5   |  Y6
+//!  H  -  -  |  L     | L     | L     | L     | L     | L     | L
+//!  L  -  -  |      L |     L |     L |     L |     L |     L |     L
+//! ----------+--------+-------+-------+-------+-------+-------+-------
+//!  -  H  H  |  `   1   2   3   4   5   6   7   8   9   0   -   =  DEL
+//!  -  H  L  | TAB  q   w   e   r   t   y   u   i   o   p   [   ]   \
+//!  -  L  H  | FN  SHT  a   s   d   f   g   h   j   k   l   ;   '  ENT
+//!  -  L  L  | CTL OPT ALT  z   x   c   v   b   n   m   ,   .   /  SPC
+//! ```
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub mod accessibility;
+pub mod broadcast;
+pub mod event_ring;
+pub mod idle;
+pub mod layer;
+pub mod pipeline;
+pub mod tca8418;
+pub mod typing_stats;
+pub use layer::{fn_key, numpad, Activator, Layer, Layers};
+use pipeline::{KeyEvent, KeyEventKind, Modifiers};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyImprint {
+    Backquote,
+    One,
+    Two,
+    Three,
+    Four,
+    Five,
+    Six,
+    Seven,
+    Eight,
+    Nine,
+    Zero,
+    Minus,
+    Equal,
+    Backspace,
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    OpenSquareBracket,
+    CloseSquareBracket,
+    Backslash,
+    LeftFn,
+    LeftShift,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    SemiColon,
+    Quote,
+    Enter,
+    LeftCtrl,
+    LeftOpt,
+    LeftAlt,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    Space,
+}
+impl KeyImprint {
+    /// Returns the imprint of the variant name, ignoring case (e.g. "a",
+    /// "Enter", "LeftShift").
+    pub fn from_name(name: &str) -> Option<Self> {
+        KEY_MAP
+            .iter()
+            .flatten()
+            .map(KeyType::imprint)
+            .find(|x| format!("{:?}", x).eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Modified {
+    Graph(char),
+    Escape,
+    Enter,
+    Space,
+    Tab,
+    LeftCursor,
+    DownCursor,
+    UpCursor,
+    RightCursor,
+    Home,
+    End,
+    Backspace,
+    Delete,
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    PlayPause,
+    NextTrack,
+    PreviousTrack,
+    BrightnessUp,
+    BrightnessDown,
+    NumLock,
+}
+/// Keys of [`Modified`] other than the graphic characters
+const NAMED_KEYS: [Modified; 21] = [
+    Modified::Escape,
+    Modified::Enter,
+    Modified::Space,
+    Modified::Tab,
+    Modified::LeftCursor,
+    Modified::DownCursor,
+    Modified::UpCursor,
+    Modified::RightCursor,
+    Modified::Home,
+    Modified::End,
+    Modified::Backspace,
+    Modified::Delete,
+    Modified::VolumeUp,
+    Modified::VolumeDown,
+    Modified::Mute,
+    Modified::PlayPause,
+    Modified::NextTrack,
+    Modified::PreviousTrack,
+    Modified::BrightnessUp,
+    Modified::BrightnessDown,
+    Modified::NumLock,
+];
+
+impl Modified {
+    /// Returns the key of the variant name ignoring case (e.g. "Enter",
+    /// "UpCursor"), or the graphic character of a single-character name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Some(Modified::Graph(c));
+        }
+        NAMED_KEYS
+            .into_iter()
+            .find(|x| format!("{:?}", x).eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the key as a byte for serial protocols.
+    ///
+    /// Control keys use their ASCII codes and the cursor keys use the codes
+    /// of the M5Stack CardKB (0xB4 left, 0xB5 up, 0xB6 down, 0xB7 right).
+    pub fn to_ascii(&self) -> Option<u8> {
+        match self {
+            Modified::Graph(c) if c.is_ascii() => Some(*c as u8),
+            Modified::Graph(_) => None,
+            Modified::Escape => Some(0x1B),
+            Modified::Enter => Some(0x0D),
+            Modified::Space => Some(0x20),
+            Modified::Tab => Some(0x09),
+            Modified::Backspace => Some(0x08),
+            Modified::Delete => Some(0x7F),
+            Modified::LeftCursor => Some(0xB4),
+            Modified::UpCursor => Some(0xB5),
+            Modified::DownCursor => Some(0xB6),
+            Modified::RightCursor => Some(0xB7),
+            Modified::Home
+            | Modified::End
+            | Modified::VolumeUp
+            | Modified::VolumeDown
+            | Modified::Mute
+            | Modified::PlayPause
+            | Modified::NextTrack
+            | Modified::PreviousTrack
+            | Modified::BrightnessUp
+            | Modified::BrightnessDown
+            | Modified::NumLock => None,
+        }
+    }
+}
+
+macro_rules! graph {
+    ($x:expr) => {
+        Modified::Graph($x)
+    };
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+/// Conversion rule
+pub struct ConversionRule(KeyImprint, Modified, Modified);
+impl ConversionRule {
+    /// Convert according to the state of Fn and Shift key
+    pub fn modified(&self, is_fn_pressed: bool, is_shift_pressed: bool) -> Modified {
+        if let Some(x) = fn_key(self.0).filter(|_| is_fn_pressed) {
+            return x;
+        }
+        if is_shift_pressed {
+            self.2
+        } else {
+            self.1
+        }
+    }
+
+    /// Returns the imprint of the key assigned to the rule
+    pub fn imprint(&self) -> KeyImprint {
+        self.0
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Define the type of key as modifier key and normal key
+pub enum KeyType {
+    Modifier(KeyImprint),
+    Normal(ConversionRule),
+}
+impl KeyType {
+    pub fn imprint(&self) -> KeyImprint {
+        match self {
+            KeyType::Modifier(x) => *x,
+            KeyType::Normal(x) => x.imprint(),
+        }
+    }
+}
+macro_rules! normal {
+    ($x:expr,$y:expr,$z:expr) => {
+        KeyType::Normal(ConversionRule($x, $y, $z))
+    };
+}
+
+const COLUMN_MAP: [[usize; 7]; 2] = [[1, 3, 5, 7, 9, 11, 13], [0, 2, 4, 6, 8, 10, 12]];
+/// Keys of the 4x14 keymap, from the bottom row
+pub const KEY_MAP: [[KeyType; 14]; 4] = [
+    [
+        KeyType::Modifier(KeyImprint::LeftCtrl),
+        KeyType::Modifier(KeyImprint::LeftOpt),
+        KeyType::Modifier(KeyImprint::LeftAlt),
+        normal!(KeyImprint::Z, graph!('z'), graph!('Z')),
+        normal!(KeyImprint::X, graph!('x'), graph!('X')),
+        normal!(KeyImprint::C, graph!('c'), graph!('C')),
+        normal!(KeyImprint::V, graph!('v'), graph!('V')),
+        normal!(KeyImprint::B, graph!('b'), graph!('B')),
+        normal!(KeyImprint::N, graph!('n'), graph!('N')),
+        normal!(KeyImprint::M, graph!('m'), graph!('M')),
+        normal!(KeyImprint::Comma, graph!(','), graph!('<')),
+        normal!(KeyImprint::Period, graph!('.'), graph!('>')),
+        normal!(KeyImprint::Slash, graph!('/'), graph!('?')),
+        normal!(KeyImprint::Space, Modified::Space, Modified::Space),
+    ],
+    [
+        KeyType::Modifier(KeyImprint::LeftFn),
+        KeyType::Modifier(KeyImprint::LeftShift),
+        normal!(KeyImprint::A, graph!('a'), graph!('A')),
+        normal!(KeyImprint::S, graph!('s'), graph!('S')),
+        normal!(KeyImprint::D, graph!('d'), graph!('D')),
+        normal!(KeyImprint::F, graph!('f'), graph!('F')),
+        normal!(KeyImprint::G, graph!('g'), graph!('G')),
+        normal!(KeyImprint::H, graph!('h'), graph!('H')),
+        normal!(KeyImprint::J, graph!('j'), graph!('J')),
+        normal!(KeyImprint::K, graph!('k'), graph!('K')),
+        normal!(KeyImprint::L, graph!('l'), graph!('L')),
+        normal!(KeyImprint::SemiColon, graph!(';'), graph!(':')),
+        normal!(KeyImprint::Quote, graph!('\''), graph!('"')),
+        normal!(KeyImprint::Enter, Modified::Enter, Modified::Enter),
+    ],
+    [
+        normal!(KeyImprint::Tab, Modified::Tab, Modified::Tab),
+        normal!(KeyImprint::Q, graph!('q'), graph!('Q')),
+        normal!(KeyImprint::W, graph!('w'), graph!('W')),
+        normal!(KeyImprint::E, graph!('e'), graph!('E')),
+        normal!(KeyImprint::R, graph!('r'), graph!('R')),
+        normal!(KeyImprint::T, graph!('t'), graph!('T')),
+        normal!(KeyImprint::Y, graph!('y'), graph!('Y')),
+        normal!(KeyImprint::U, graph!('u'), graph!('U')),
+        normal!(KeyImprint::I, graph!('i'), graph!('I')),
+        normal!(KeyImprint::O, graph!('o'), graph!('O')),
+        normal!(KeyImprint::P, graph!('p'), graph!('P')),
+        normal!(KeyImprint::OpenSquareBracket, graph!('['), graph!('{')),
+        normal!(KeyImprint::CloseSquareBracket, graph!(']'), graph!('}')),
+        normal!(KeyImprint::Backslash, graph!('\\'), graph!('|')),
+    ],
+    [
+        normal!(KeyImprint::Backquote, graph!('`'), graph!('~')),
+        normal!(KeyImprint::One, graph!('1'), graph!('!')),
+        normal!(KeyImprint::Two, graph!('2'), graph!('@')),
+        normal!(KeyImprint::Three, graph!('3'), graph!('#')),
+        normal!(KeyImprint::Four, graph!('4'), graph!('$')),
+        normal!(KeyImprint::Five, graph!('5'), graph!('%')),
+        normal!(KeyImprint::Six, graph!('6'), graph!('^')),
+        normal!(KeyImprint::Seven, graph!('7'), graph!('&')),
+        normal!(KeyImprint::Eight, graph!('8'), graph!('*')),
+        normal!(KeyImprint::Nine, graph!('9'), graph!('(')),
+        normal!(KeyImprint::Zero, graph!('0'), graph!(')')),
+        normal!(KeyImprint::Minus, graph!('-'), graph!('_')),
+        normal!(KeyImprint::Equal, graph!('='), graph!('+')),
+        normal!(
+            KeyImprint::Backspace,
+            Modified::Backspace,
+            Modified::Backspace
+        ),
+    ],
+];
+
+/// Keyboard scanner trait
+pub trait KeyboardScanner {
+    /// Scan the keyboard and return the Vector of KeyType.
+    fn scan_pressed_keytypes(&mut self) -> Result<Vec<KeyType>>;
+}
+
+/// Returns the key connected to the input line while the address is
+/// selected, or `None` outside the 8 addresses and 7 input lines.
+pub fn key_at(address: usize, input: usize) -> Option<KeyType> {
+    let (col, row) = if address < 4 {
+        (COLUMN_MAP[0].get(input)?, address)
+    } else {
+        (COLUMN_MAP[1].get(input)?, address - 4)
+    };
+    KEY_MAP.get(row)?.get(*col).copied()
+}
+
+/// Returns the pressed keys of the pressed state of each input line for
+/// each address.
+pub fn decode_matrix(matrix: &[[bool; 7]; 8]) -> Vec<KeyType> {
+    let mut keys: Vec<KeyType> = vec![];
+    for (i, inputs) in matrix.iter().enumerate() {
+        for (j, pressed) in inputs.iter().enumerate() {
+            if *pressed {
+                keys.extend(key_at(i, j));
+            }
+        }
+    }
+    keys
+}
+
+/// Structure that scans the keyboard and keeps track of state changes
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::keyboard::{Keyboard, KeyboardState};
+///
+/// let peripherals = Peripherals::take().unwrap();
+///
+/// let mut keyboard = Keyboard::new(
+///     peripherals.pins.gpio8,
+///     peripherals.pins.gpio9,
+///     peripherals.pins.gpio11,
+///     peripherals.pins.gpio13,
+///     peripherals.pins.gpio15,
+///     peripherals.pins.gpio3,
+///     peripherals.pins.gpio4,
+///     peripherals.pins.gpio5,
+///     peripherals.pins.gpio6,
+///     peripherals.pins.gpio7,
+/// )
+/// .unwrap();
+///
+/// let mut keyboard_state = KeyboardState::default();
+/// keyboard_state.update(&mut keyboard).unwrap();
+/// log::info!("{:?}", keyboard_state.pressed_keys());
+/// log::info!("{:?}", keyboard_state.released_keys());
+/// ```
+#[derive(Debug, Default)]
+pub struct KeyboardState {
+    is_fn_pressed: bool,
+    is_ctrl_pressed: bool,
+    is_shift_pressed: bool,
+    is_alt_pressed: bool,
+    is_opt_pressed: bool,
+    layers: Layers,
+
+    hold_keys: Vec<HeldKey>,
+    pressed_keys: Vec<HeldKey>,
+    released_keys: Vec<HeldKey>,
+    /// When each held key, modifiers included, was first seen pressed
+    held_since: HashMap<KeyImprint, Instant>,
+}
+
+/// Key held down, converted with the modifiers and layers of the moment
+/// it was pressed
+///
+/// Releasing Fn or Shift before the key does not change the key reported
+/// at its release.
+#[derive(Debug, Clone, Copy)]
+struct HeldKey {
+    rule: ConversionRule,
+    key: Modified,
+    modifiers: Modifiers,
+}
+
+impl KeyboardState {
+    /// Get the latest key state and update the Pressed/Released state
+    ///
+    /// Newly pressed keys are converted with the modifiers and layers of
+    /// this update and keep that conversion until released.
+    pub fn update(&mut self, keyboard: &mut impl KeyboardScanner) -> Result<()> {
+        let mut new_hold_keys: Vec<ConversionRule> = Vec::new();
+        let mut held: Vec<KeyImprint> = Vec::new();
+
+        self.pressed_keys.clear();
+        self.released_keys.clear();
+
+        self.is_fn_pressed = false;
+        self.is_ctrl_pressed = false;
+        self.is_shift_pressed = false;
+        self.is_alt_pressed = false;
+        self.is_opt_pressed = false;
+
+        for pressed in keyboard.scan_pressed_keytypes()?.iter() {
+            held.push(pressed.imprint());
+            match pressed {
+                KeyType::Modifier(KeyImprint::LeftFn) => self.is_fn_pressed = true,
+                KeyType::Modifier(KeyImprint::LeftCtrl) => self.is_ctrl_pressed = true,
+                KeyType::Modifier(KeyImprint::LeftShift) => self.is_shift_pressed = true,
+                KeyType::Modifier(KeyImprint::LeftAlt) => self.is_alt_pressed = true,
+                KeyType::Modifier(KeyImprint::LeftOpt) => self.is_opt_pressed = true,
+                KeyType::Normal(h) if self.layers.is_momentary_activator(h.imprint()) => {}
+                KeyType::Normal(h) => new_hold_keys.push(*h),
+                _ => {}
+            }
+        }
+        self.layers.update_momentary(&held);
+
+        for key in self.hold_keys.iter() {
+            if !new_hold_keys.contains(&key.rule) {
+                self.released_keys.push(*key);
+            }
+        }
+
+        let modifiers = self.modifiers();
+        let mut hold_keys = Vec::with_capacity(new_hold_keys.len());
+        for rule in new_hold_keys {
+            match self.hold_keys.iter().find(|x| x.rule == rule) {
+                Some(key) => hold_keys.push(*key),
+                None => {
+                    let key = HeldKey {
+                        rule,
+                        key: self.convert(&rule),
+                        modifiers,
+                    };
+                    hold_keys.push(key);
+                    self.pressed_keys.push(key);
+                }
+            }
+        }
+        self.hold_keys = hold_keys;
+
+        let now = Instant::now();
+        self.held_since = held
+            .iter()
+            .map(|x| (*x, self.held_since.get(x).copied().unwrap_or(now)))
+            .collect();
+
+        let pressed = self.pressed_keys();
+        self.layers.update_toggle(&pressed);
+
+        Ok(())
+    }
+
+    pub fn pressed_keys(&self) -> Vec<Modified> {
+        self.pressed_keys.iter().map(|x| x.key).collect()
+    }
+
+    /// Returns the keys released in the last update, as they were reported
+    /// when pressed.
+    pub fn released_keys(&self) -> Vec<Modified> {
+        self.released_keys.iter().map(|x| x.key).collect()
+    }
+
+    pub fn hold_keys(&self) -> Vec<Modified> {
+        self.hold_keys.iter().map(|x| x.key).collect()
+    }
+
+    /// Returns the imprints of the keys pressed in the last update except modifier keys
+    ///
+    /// The order is the same as [`KeyboardState::pressed_keys`].
+    pub fn pressed_imprints(&self) -> Vec<KeyImprint> {
+        self.pressed_keys.iter().map(|x| x.rule.imprint()).collect()
+    }
+
+    /// Returns the imprints of the held keys except modifier keys
+    pub fn hold_imprints(&self) -> Vec<KeyImprint> {
+        self.hold_keys.iter().map(|x| x.rule.imprint()).collect()
+    }
+
+    /// Returns true if the key, modifier keys included, was held at the
+    /// last update.
+    pub fn is_held(&self, imprint: KeyImprint) -> bool {
+        self.held_since.contains_key(&imprint)
+    }
+
+    /// Returns how long the key has been held, or `None` if it was not
+    /// held at the last update.
+    pub fn held_duration(&self, imprint: KeyImprint) -> Option<Duration> {
+        self.held_since.get(&imprint).map(Instant::elapsed)
+    }
+
+    pub fn is_fn_pressed(&self) -> bool {
+        self.is_fn_pressed
+    }
+
+    pub fn is_ctrl_pressed(&self) -> bool {
+        self.is_ctrl_pressed
+    }
+
+    pub fn is_shift_pressed(&self) -> bool {
+        self.is_shift_pressed
+    }
+
+    pub fn is_alt_pressed(&self) -> bool {
+        self.is_alt_pressed
+    }
+
+    pub fn is_opt_pressed(&self) -> bool {
+        self.is_opt_pressed
+    }
+
+    /// Returns the state of the modifier keys.
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            is_fn_pressed: self.is_fn_pressed,
+            is_shift_pressed: self.is_shift_pressed,
+            is_ctrl_pressed: self.is_ctrl_pressed,
+            is_alt_pressed: self.is_alt_pressed,
+            is_opt_pressed: self.is_opt_pressed,
+        }
+    }
+
+    /// Take the released and pressed keys of the last update as events
+    /// without allocating.
+    ///
+    /// The released keys come first. An event carries the modifiers and
+    /// the converted key of the press, so the release matches the press
+    /// even if a modifier was released first. After draining,
+    /// [`KeyboardState::pressed_keys`] and [`KeyboardState::released_keys`]
+    /// return nothing until the next update.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cardputer::keyboard::pipeline::KeyEventKind;
+    ///
+    /// keyboard_state.update(&mut keyboard).unwrap();
+    /// for event in keyboard_state.drain_events() {
+    ///     if event.kind == KeyEventKind::Pressed {
+    ///         log::info!("{:?}", event.key);
+    ///     }
+    /// }
+    /// ```
+    pub fn drain_events(&mut self) -> impl Iterator<Item = KeyEvent> + '_ {
+        // both events of a key carry the modifiers and key of its press
+        let event = |kind, key: HeldKey| KeyEvent {
+            kind,
+            imprint: key.rule.imprint(),
+            modifiers: key.modifiers,
+            key: Some(key.key),
+        };
+        self.released_keys
+            .drain(..)
+            .map(move |x| event(KeyEventKind::Released, x))
+            .chain(
+                self.pressed_keys
+                    .drain(..)
+                    .map(move |x| event(KeyEventKind::Pressed, x)),
+            )
+    }
+
+    /// Set the layers on top of the base layer.
+    pub fn with_layers(mut self, layers: Layers) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Returns the layers on top of the base layer.
+    pub fn layers(&self) -> &Layers {
+        &self.layers
+    }
+
+    /// Returns the layers to activate or deactivate them.
+    pub fn layers_mut(&mut self) -> &mut Layers {
+        &mut self.layers
+    }
+
+    /// Convert the key according to the Shift key and the active layers.
+    fn convert(&self, rule: &ConversionRule) -> Modified {
+        self.layers.convert(rule, self.is_shift_pressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::{collection::vec, prelude::*};
+
+    /// Scanner returning the prepared scans in order
+    struct Scripted(Vec<Vec<KeyImprint>>);
+
+    impl KeyboardScanner for Scripted {
+        fn scan_pressed_keytypes(&mut self) -> Result<Vec<KeyType>> {
+            let imprints = self.0.remove(0);
+            Ok(KEY_MAP
+                .iter()
+                .flatten()
+                .filter(|x| imprints.contains(&x.imprint()))
+                .copied()
+                .collect())
+        }
+    }
+
+    fn all_imprints() -> Vec<KeyImprint> {
+        KEY_MAP.iter().flatten().map(KeyType::imprint).collect()
+    }
+
+    #[test]
+    fn key_at_follows_the_keymap() {
+        let imprint = |address, input| key_at(address, input).unwrap().imprint();
+        assert_eq!(imprint(7, 0), KeyImprint::Backquote);
+        assert_eq!(imprint(3, 0), KeyImprint::One);
+        assert_eq!(imprint(3, 6), KeyImprint::Backspace);
+        assert_eq!(imprint(6, 0), KeyImprint::Tab);
+        assert_eq!(imprint(5, 0), KeyImprint::LeftFn);
+        assert_eq!(imprint(4, 0), KeyImprint::LeftCtrl);
+        assert_eq!(imprint(0, 6), KeyImprint::Space);
+        assert!(key_at(8, 0).is_none());
+        assert!(key_at(0, 7).is_none());
+    }
+
+    #[test]
+    fn key_at_reaches_every_key_once() {
+        let mut found: Vec<KeyImprint> = (0..8)
+            .flat_map(|address| (0..7).map(move |input| key_at(address, input).unwrap().imprint()))
+            .collect();
+        assert_eq!(found.len(), 56);
+        for imprint in all_imprints() {
+            let position = found.iter().position(|x| *x == imprint).unwrap();
+            found.remove(position);
+        }
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn decode_matrix_returns_the_pressed_keys() {
+        assert!(decode_matrix(&[[false; 7]; 8]).is_empty());
+        assert_eq!(decode_matrix(&[[true; 7]; 8]).len(), 56);
+
+        let mut matrix = [[false; 7]; 8];
+        matrix[3][0] = true;
+        matrix[1][0] = true;
+        let keys: Vec<KeyImprint> = decode_matrix(&matrix).iter().map(|x| x.imprint()).collect();
+        assert_eq!(keys, vec![KeyImprint::LeftShift, KeyImprint::One]);
+    }
+
+    #[test]
+    fn imprint_names_round_trip() {
+        for imprint in all_imprints() {
+            let name = format!("{:?}", imprint);
+            assert_eq!(KeyImprint::from_name(&name), Some(imprint));
+            assert_eq!(KeyImprint::from_name(&name.to_uppercase()), Some(imprint));
+        }
+        assert_eq!(KeyImprint::from_name("NoSuchKey"), None);
+    }
+
+    #[test]
+    fn modified_names_round_trip() {
+        assert_eq!(Modified::from_name("a"), Some(Modified::Graph('a')));
+        assert_eq!(Modified::from_name("enter"), Some(Modified::Enter));
+        for key in NAMED_KEYS {
+            assert_eq!(Modified::from_name(&format!("{:?}", key)), Some(key));
+        }
+        assert_eq!(Modified::from_name("NoSuchKey"), None);
+    }
+
+    #[test]
+    fn to_ascii_uses_control_and_cardkb_codes() {
+        assert_eq!(Modified::Graph('a').to_ascii(), Some(b'a'));
+        assert_eq!(Modified::Graph('é').to_ascii(), None);
+        assert_eq!(Modified::Enter.to_ascii(), Some(0x0D));
+        assert_eq!(Modified::UpCursor.to_ascii(), Some(0xB5));
+        assert_eq!(Modified::Mute.to_ascii(), None);
+    }
+
+    #[test]
+    fn conversion_rule_applies_fn_then_shift() {
+        let rule = ConversionRule(KeyImprint::SemiColon, graph!(';'), graph!(':'));
+        assert_eq!(rule.modified(false, false), graph!(';'));
+        assert_eq!(rule.modified(false, true), graph!(':'));
+        assert_eq!(rule.modified(true, false), Modified::UpCursor);
+        assert_eq!(rule.modified(true, true), Modified::UpCursor);
+
+        let rule = ConversionRule(KeyImprint::A, graph!('a'), graph!('A'));
+        assert_eq!(rule.modified(true, false), graph!('a'));
+    }
+
+    #[test]
+    fn state_reports_press_hold_and_release() {
+        let mut scanner = Scripted(vec![
+            vec![KeyImprint::A],
+            vec![KeyImprint::A],
+            vec![KeyImprint::LeftShift, KeyImprint::B],
+            vec![],
+        ]);
+        let mut state = KeyboardState::default();
+
+        state.update(&mut scanner).unwrap();
+        assert_eq!(state.pressed_keys(), vec![graph!('a')]);
+        assert_eq!(state.hold_keys(), vec![graph!('a')]);
+        assert!(state.released_keys().is_empty());
+
+        state.update(&mut scanner).unwrap();
+        assert!(state.pressed_keys().is_empty());
+        assert_eq!(state.hold_imprints(), vec![KeyImprint::A]);
+
+        state.update(&mut scanner).unwrap();
+        assert!(state.is_shift_pressed());
+        assert_eq!(state.pressed_keys(), vec![graph!('B')]);
+        assert_eq!(state.released_keys(), vec![graph!('a')]);
+
+        state.update(&mut scanner).unwrap();
+        assert!(!state.is_shift_pressed());
+        assert_eq!(state.released_keys(), vec![graph!('B')]);
+        assert!(state.hold_keys().is_empty());
+    }
+
+    #[test]
+    fn release_reports_the_key_as_pressed() {
+        let mut scanner = Scripted(vec![
+            vec![KeyImprint::LeftFn, KeyImprint::SemiColon],
+            vec![KeyImprint::SemiColon],
+            vec![],
+        ]);
+        let mut state = KeyboardState::default();
+
+        state.update(&mut scanner).unwrap();
+        assert_eq!(state.pressed_keys(), vec![Modified::UpCursor]);
+
+        state.update(&mut scanner).unwrap();
+        assert!(!state.is_fn_pressed());
+        assert_eq!(state.hold_keys(), vec![Modified::UpCursor]);
+
+        state.update(&mut scanner).unwrap();
+        let events: Vec<KeyEvent> = state.drain_events().collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, KeyEventKind::Released);
+        assert_eq!(events[0].key, Some(Modified::UpCursor));
+        assert!(events[0].modifiers.is_fn_pressed);
+    }
+
+    #[test]
+    fn state_tracks_held_keys() {
+        let mut scanner = Scripted(vec![
+            vec![KeyImprint::W, KeyImprint::LeftShift],
+            vec![KeyImprint::W],
+            vec![],
+        ]);
+        let mut state = KeyboardState::default();
+        assert!(!state.is_held(KeyImprint::W));
+        assert!(state.held_duration(KeyImprint::W).is_none());
+
+        state.update(&mut scanner).unwrap();
+        assert!(state.is_held(KeyImprint::W));
+        assert!(state.is_held(KeyImprint::LeftShift));
+        assert!(!state.is_held(KeyImprint::A));
+        let first = state.held_duration(KeyImprint::W).unwrap();
+
+        std::thread::sleep(Duration::from_millis(5));
+        state.update(&mut scanner).unwrap();
+        assert!(state.held_duration(KeyImprint::W).unwrap() >= first + Duration::from_millis(5));
+        assert!(!state.is_held(KeyImprint::LeftShift));
+
+        state.update(&mut scanner).unwrap();
+        assert!(!state.is_held(KeyImprint::W));
+    }
+
+    #[test]
+    fn drain_events_puts_releases_first() {
+        let mut scanner = Scripted(vec![vec![KeyImprint::A], vec![KeyImprint::B]]);
+        let mut state = KeyboardState::default();
+        state.update(&mut scanner).unwrap();
+        state.update(&mut scanner).unwrap();
+
+        let events: Vec<(KeyEventKind, KeyImprint)> =
+            state.drain_events().map(|x| (x.kind, x.imprint)).collect();
+        assert_eq!(
+            events,
+            vec![
+                (KeyEventKind::Released, KeyImprint::A),
+                (KeyEventKind::Pressed, KeyImprint::B)
+            ]
+        );
+        assert!(state.pressed_keys().is_empty());
+        assert!(state.released_keys().is_empty());
+    }
+
+    #[test]
+    fn presses_and_releases_pair_up() {
+        // pseudo-random scans over the whole keyboard
+        let imprints = all_imprints();
+        let mut seed = 0x2545_f491_u32;
+        let scans: Vec<Vec<KeyImprint>> = (0..500)
+            .map(|_| {
+                imprints
+                    .iter()
+                    .filter(|_| {
+                        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                        (seed >> 16) & 15 == 0
+                    })
+                    .copied()
+                    .collect()
+            })
+            .chain(std::iter::once(vec![]))
+            .collect();
+        let count = scans.len();
+        let mut scanner = Scripted(scans);
+        let mut state = KeyboardState::default();
+        let mut held: Vec<KeyImprint> = Vec::new();
+        for _ in 0..count {
+            state.update(&mut scanner).unwrap();
+            for event in state.drain_events() {
+                match event.kind {
+                    KeyEventKind::Pressed => {
+                        assert!(!held.contains(&event.imprint));
+                        held.push(event.imprint);
+                    }
+                    KeyEventKind::Released => {
+                        let position = held.iter().position(|x| *x == event.imprint);
+                        held.remove(position.expect("release without press"));
+                    }
+                    KeyEventKind::Repeated => unreachable!(),
+                }
+            }
+        }
+        assert!(held.is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn key_at_is_defined_only_on_the_matrix(address in 0usize..32, input in 0usize..32) {
+            prop_assert_eq!(key_at(address, input).is_some(), address < 8 && input < 7);
+        }
+
+        #[test]
+        fn matrix_scans_pair_press_and_release(data in vec(any::<u8>(), 0..400)) {
+            crate::fuzz::matrix_scans(&data);
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Accessibility filters for the input pipeline
+//!
+//! Slow keys accept a key only after it is held for a while, bounce keys
+//! ignore a key pressed again right after it was released, and sticky
+//! modifiers keep a modifier pressed alone active for the next key. The
+//! filters go after the [`Debouncer`](super::pipeline::Debouncer) and
+//! before the [`LayerMapper`](super::pipeline::LayerMapper).
+use std::time::{Duration, Instant};
+
+use super::pipeline::{KeyEvent, KeyEventKind, Scan, Stage};
+use super::{KeyImprint, KeyType, KEY_MAP};
+
+pub(crate) fn is_modifier(imprint: KeyImprint) -> bool {
+    KEY_MAP
+        .iter()
+        .flatten()
+        .any(|x| matches!(x, KeyType::Modifier(m) if *m == imprint))
+}
+
+/// Stage that accepts a key only after it is held for the time
+#[derive(Debug, Clone)]
+pub struct SlowKeys {
+    hold: Duration,
+    /// Keys held but not yet accepted, with the time of the press
+    pending: Vec<(KeyImprint, Instant)>,
+    accepted: Vec<KeyImprint>,
+}
+
+impl SlowKeys {
+    /// Create new filter with the hold time.
+    pub fn new(hold: Duration) -> Self {
+        Self {
+            hold,
+            pending: Vec::new(),
+            accepted: Vec::new(),
+        }
+    }
+}
+
+impl Stage for SlowKeys {
+    fn process(&mut self, scan: &mut Scan) {
+        let (pending, accepted) = (&mut self.pending, &mut self.accepted);
+        scan.events.retain(|event| match event.kind {
+            KeyEventKind::Pressed => {
+                pending.push((event.imprint, scan.time));
+                false
+            }
+            KeyEventKind::Released => {
+                pending.retain(|(x, _)| *x != event.imprint);
+                let was_accepted = accepted.contains(&event.imprint);
+                accepted.retain(|x| *x != event.imprint);
+                was_accepted
+            }
+            KeyEventKind::Repeated => accepted.contains(&event.imprint),
+        });
+        let hold = self.hold;
+        self.pending.retain(|(imprint, since)| {
+            if scan.time.duration_since(*since) < hold {
+                return true;
+            }
+            scan.events
+                .push(KeyEvent::new(KeyEventKind::Pressed, *imprint));
+            accepted.push(*imprint);
+            false
+        });
+        scan.held.retain(|x| self.accepted.contains(x));
+    }
+}
+
+/// Stage that ignores a key pressed again within the time after its release
+#[derive(Debug, Clone)]
+pub struct BounceKeys {
+    time: Duration,
+    released: Vec<(KeyImprint, Instant)>,
+    ignored: Vec<KeyImprint>,
+}
+
+impl BounceKeys {
+    /// Create new filter with the time.
+    pub fn new(time: Duration) -> Self {
+        Self {
+            time,
+            released: Vec::new(),
+            ignored: Vec::new(),
+        }
+    }
+}
+
+impl Stage for BounceKeys {
+    fn process(&mut self, scan: &mut Scan) {
+        let time = self.time;
+        self.released
+            .retain(|(_, at)| scan.time.duration_since(*at) < time);
+        let (released, ignored) = (&mut self.released, &mut self.ignored);
+        scan.events.retain(|event| {
+            let is_ignored = ignored.contains(&event.imprint);
+            match event.kind {
+                KeyEventKind::Pressed if released.iter().any(|(x, _)| *x == event.imprint) => {
+                    ignored.push(event.imprint);
+                    false
+                }
+                KeyEventKind::Released => {
+                    ignored.retain(|x| *x != event.imprint);
+                    released.retain(|(x, _)| *x != event.imprint);
+                    released.push((event.imprint, scan.time));
+                    !is_ignored
+                }
+                _ => !is_ignored,
+            }
+        });
+        scan.held.retain(|x| !self.ignored.contains(x));
+    }
+}
+
+/// Stage that keeps a modifier pressed and released alone active until
+/// the next key
+///
+/// Pressing the latched modifier again releases it.
+#[derive(Debug, Clone, Default)]
+pub struct StickyModifiers {
+    /// Modifiers physically held, and whether another key was pressed meanwhile
+    down: Vec<(KeyImprint, bool)>,
+    latched: Vec<KeyImprint>,
+}
+
+impl StickyModifiers {
+    /// Create new filter.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the latched modifiers.
+    pub fn latched(&self) -> &[KeyImprint] {
+        &self.latched
+    }
+}
+
+impl Stage for StickyModifiers {
+    fn process(&mut self, scan: &mut Scan) {
+        let mut is_used = false;
+        for event in scan.events.iter() {
+            match event.kind {
+                KeyEventKind::Pressed if is_modifier(event.imprint) => {
+                    let was_latched = self.latched.contains(&event.imprint);
+                    self.latched.retain(|x| *x != event.imprint);
+                    self.down.push((event.imprint, was_latched));
+                }
+                KeyEventKind::Pressed => {
+                    is_used = true;
+                    self.down.iter_mut().for_each(|(_, x)| *x = true);
+                }
+                KeyEventKind::Released if is_modifier(event.imprint) => {
+                    let combined = self
+                        .down
+                        .iter()
+                        .find(|(x, _)| *x == event.imprint)
+                        .is_some_and(|(_, x)| *x);
+                    self.down.retain(|(x, _)| *x != event.imprint);
+                    if !combined {
+                        self.latched.push(event.imprint);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for imprint in self.latched.iter() {
+            if !scan.held.contains(imprint) {
+                scan.held.push(*imprint);
+            }
+        }
+        if is_used {
+            self.latched.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use KeyEventKind::{Pressed, Released, Repeated};
+    use KeyImprint::{LeftCtrl, LeftShift, A, B};
+
+    /// Scan of the held keys with the events at the time after the start
+    fn scan(
+        start: Instant,
+        ms: u64,
+        held: &[KeyImprint],
+        events: &[(KeyEventKind, KeyImprint)],
+    ) -> Scan {
+        Scan {
+            time: start + Duration::from_millis(ms),
+            held: held.to_vec(),
+            events: events
+                .iter()
+                .map(|(kind, imprint)| KeyEvent::new(*kind, *imprint))
+                .collect(),
+        }
+    }
+
+    fn kinds(events: &[KeyEvent]) -> Vec<(KeyEventKind, KeyImprint)> {
+        events.iter().map(|x| (x.kind, x.imprint)).collect()
+    }
+
+    #[test]
+    fn slow_keys_accept_a_key_held_long_enough() {
+        let start = Instant::now();
+        let mut slow = SlowKeys::new(Duration::from_millis(50));
+
+        let mut x = scan(start, 0, &[A], &[(Pressed, A)]);
+        slow.process(&mut x);
+        assert!(x.events.is_empty());
+        assert!(x.held.is_empty());
+
+        let mut x = scan(start, 30, &[A], &[(Repeated, A)]);
+        slow.process(&mut x);
+        assert!(x.events.is_empty());
+
+        let mut x = scan(start, 50, &[A], &[]);
+        slow.process(&mut x);
+        assert_eq!(kinds(&x.events), vec![(Pressed, A)]);
+        assert_eq!(x.held, vec![A]);
+
+        let mut x = scan(start, 60, &[A], &[(Repeated, A)]);
+        slow.process(&mut x);
+        assert_eq!(kinds(&x.events), vec![(Repeated, A)]);
+
+        let mut x = scan(start, 70, &[], &[(Released, A)]);
+        slow.process(&mut x);
+        assert_eq!(kinds(&x.events), vec![(Released, A)]);
+    }
+
+    #[test]
+    fn slow_keys_drop_a_short_press() {
+        let start = Instant::now();
+        let mut slow = SlowKeys::new(Duration::from_millis(50));
+        let mut x = scan(start, 0, &[A], &[(Pressed, A)]);
+        slow.process(&mut x);
+        let mut x = scan(start, 40, &[], &[(Released, A)]);
+        slow.process(&mut x);
+        assert!(x.events.is_empty());
+        let mut x = scan(start, 100, &[], &[]);
+        slow.process(&mut x);
+        assert!(x.events.is_empty());
+    }
+
+    #[test]
+    fn bounce_keys_ignore_a_quick_second_press() {
+        let start = Instant::now();
+        let mut bounce = BounceKeys::new(Duration::from_millis(100));
+
+        let mut x = scan(start, 0, &[A], &[(Pressed, A)]);
+        bounce.process(&mut x);
+        assert_eq!(kinds(&x.events), vec![(Pressed, A)]);
+        let mut x = scan(start, 10, &[], &[(Released, A)]);
+        bounce.process(&mut x);
+        assert_eq!(kinds(&x.events), vec![(Released, A)]);
+
+        // the bounce and its release are dropped
+        let mut x = scan(start, 50, &[A], &[(Pressed, A)]);
+        bounce.process(&mut x);
+        assert!(x.events.is_empty());
+        assert!(x.held.is_empty());
+        let mut x = scan(start, 60, &[A], &[(Repeated, A)]);
+        bounce.process(&mut x);
+        assert!(x.events.is_empty());
+        let mut x = scan(start, 70, &[], &[(Released, A)]);
+        bounce.process(&mut x);
+        assert!(x.events.is_empty());
+
+        // other keys are not affected
+        let mut x = scan(start, 80, &[B], &[(Pressed, B)]);
+        bounce.process(&mut x);
+        assert_eq!(kinds(&x.events), vec![(Pressed, B)]);
+
+        let mut x = scan(start, 200, &[A, B], &[(Pressed, A)]);
+        bounce.process(&mut x);
+        assert_eq!(kinds(&x.events), vec![(Pressed, A)]);
+        assert_eq!(x.held, vec![A, B]);
+    }
+
+    #[test]
+    fn sticky_modifier_applies_to_the_next_key() {
+        let start = Instant::now();
+        let mut sticky = StickyModifiers::new();
+
+        let mut x = scan(start, 0, &[LeftShift], &[(Pressed, LeftShift)]);
+        sticky.process(&mut x);
+        let mut x = scan(start, 10, &[], &[(Released, LeftShift)]);
+        sticky.process(&mut x);
+        assert_eq!(sticky.latched(), &[LeftShift]);
+        assert_eq!(x.held, vec![LeftShift]);
+
+        let mut x = scan(start, 20, &[A], &[(Pressed, A)]);
+        sticky.process(&mut x);
+        assert_eq!(x.held, vec![A, LeftShift]);
+        assert!(sticky.latched().is_empty());
+
+        let mut x = scan(start, 30, &[A], &[]);
+        sticky.process(&mut x);
+        assert_eq!(x.held, vec![A]);
+    }
+
+    #[test]
+    fn sticky_modifier_is_not_latched_when_combined() {
+        let start = Instant::now();
+        let mut sticky = StickyModifiers::new();
+        let mut x = scan(start, 0, &[LeftCtrl], &[(Pressed, LeftCtrl)]);
+        sticky.process(&mut x);
+        let mut x = scan(start, 10, &[LeftCtrl, A], &[(Pressed, A)]);
+        sticky.process(&mut x);
+        let mut x = scan(start, 20, &[A], &[(Released, LeftCtrl)]);
+        sticky.process(&mut x);
+        assert!(sticky.latched().is_empty());
+        assert_eq!(x.held, vec![A]);
+    }
+
+    #[test]
+    fn pressing_a_latched_modifier_again_releases_it() {
+        let start = Instant::now();
+        let mut sticky = StickyModifiers::new();
+        for (ms, kind) in [(0, Pressed), (10, Released)] {
+            let mut x = scan(start, ms, &[], &[(kind, LeftShift)]);
+            sticky.process(&mut x);
+        }
+        assert_eq!(sticky.latched(), &[LeftShift]);
+
+        let mut x = scan(start, 20, &[LeftShift], &[(Pressed, LeftShift)]);
+        sticky.process(&mut x);
+        assert!(sticky.latched().is_empty());
+        let mut x = scan(start, 30, &[], &[(Released, LeftShift)]);
+        sticky.process(&mut x);
+        assert!(sticky.latched().is_empty());
+        assert!(x.held.is_empty());
+    }
+
+    proptest! {
+        /// A press of the duration scanned every 10 ms goes through the
+        /// slow keys only if held for the hold time, and then with its
+        /// release.
+        #[test]
+        fn slow_keys_pass_only_long_presses(hold in 1u64..100, held_for in 1u64..200) {
+            let start = Instant::now();
+            let mut slow = SlowKeys::new(Duration::from_millis(hold));
+            let mut events = Vec::new();
+            let mut pressed_at = None;
+            let mut ms = 0;
+            while ms < held_for {
+                let kind = if ms == 0 { vec![(Pressed, A)] } else { vec![] };
+                let mut x = scan(start, ms, &[A], &kind);
+                slow.process(&mut x);
+                if pressed_at.is_none() && !x.events.is_empty() {
+                    pressed_at = Some(ms);
+                }
+                events.extend(kinds(&x.events));
+                ms += 10;
+            }
+            let mut x = scan(start, held_for, &[], &[(Released, A)]);
+            slow.process(&mut x);
+            events.extend(kinds(&x.events));
+
+            let accepted = (0..held_for).step_by(10).any(|x| x >= hold);
+            if accepted {
+                prop_assert_eq!(events, vec![(Pressed, A), (Released, A)]);
+                prop_assert!(pressed_at.is_some_and(|x| x >= hold));
+            } else {
+                prop_assert!(events.is_empty());
+            }
+        }
+
+        /// Whatever the timing, the bounce keys let a press through only
+        /// with its release.
+        #[test]
+        fn bounce_keys_pair_presses_and_releases(gaps in proptest::collection::vec(0u64..200, 1..10)) {
+            let start = Instant::now();
+            let mut bounce = BounceKeys::new(Duration::from_millis(100));
+            let mut ms = 0;
+            let mut events = Vec::new();
+            for gap in gaps {
+                for (kind, held) in [(Pressed, vec![A]), (Released, vec![])] {
+                    let mut x = scan(start, ms, &held, &[(kind, A)]);
+                    bounce.process(&mut x);
+                    events.extend(kinds(&x.events));
+                    ms += gap;
+                }
+            }
+            prop_assert!(!events.is_empty());
+            for pair in events.chunks(2) {
+                prop_assert_eq!(pair, &[(Pressed, A), (Released, A)][..]);
+            }
+        }
+    }
+}
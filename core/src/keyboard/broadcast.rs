@@ -0,0 +1,217 @@
+//! Fan-out of key events to several subscribers
+//!
+//! The keyboard task publishes each event once and every component, e.g.
+//! the status bar, the hotkey registry, the focused screen and the HID
+//! bridge, receives it from its own [`Subscription`]. Each subscription has
+//! a bounded [`EventRing`]; when a subscriber stalls, its oldest events are
+//! overwritten and counted without affecting the others.
+//!
+//! [`Broadcast`] carries other events the same way, e.g. the
+//! [`ActivityEvent`](super::idle::ActivityEvent)s of the idle detection.
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::event_ring::EventRing;
+use super::pipeline::KeyEvent;
+
+/// Publisher of key events to the subscriptions
+pub type KeyEventBroadcast = Broadcast<KeyEvent>;
+
+/// Publisher of events to the subscriptions
+///
+/// Each subscription queues `N` events, a power of two. Clones publish to
+/// the same subscriptions.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer_core::keyboard::broadcast::KeyEventBroadcast;
+///
+/// let broadcast = KeyEventBroadcast::new();
+/// let status_bar = broadcast.subscribe();
+/// let hotkeys = broadcast.subscribe();
+///
+/// let publisher = broadcast.clone();
+/// thread::spawn(move || loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     publisher.publish_all(keyboard_state.drain_events());
+///     thread::sleep(Duration::from_millis(10));
+/// });
+///
+/// while let Some(event) = hotkeys.recv_timeout(Duration::from_millis(100)) {
+///     log::info!("{:?}", event);
+/// }
+/// ```
+pub struct Broadcast<T, const N: usize = 16> {
+    /// The lock also makes the publishers the single producer of each ring
+    queues: Arc<Mutex<Vec<Arc<Queue<T, N>>>>>,
+}
+
+impl<T: Copy, const N: usize> Broadcast<T, N> {
+    /// Create new publisher without subscriptions.
+    pub fn new() -> Self {
+        Self {
+            queues: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Returns a new subscription receiving the events published from now.
+    pub fn subscribe(&self) -> Subscription<T, N> {
+        let queue = Arc::new(Queue {
+            ring: EventRing::new(),
+            waiting: Mutex::new(()),
+            ready: Condvar::new(),
+        });
+        lock(&self.queues).push(queue.clone());
+        Subscription { queue }
+    }
+
+    /// Returns the number of live subscriptions.
+    pub fn subscriber_count(&self) -> usize {
+        let mut queues = lock(&self.queues);
+        queues.retain(|x| Arc::strong_count(x) > 1);
+        queues.len()
+    }
+
+    /// Send the event to every subscription. Dropped subscriptions are
+    /// removed.
+    pub fn publish(&self, event: T) {
+        let mut queues = lock(&self.queues);
+        queues.retain(|x| Arc::strong_count(x) > 1);
+        for queue in queues.iter() {
+            queue.push(event);
+        }
+    }
+
+    /// Send the events to every subscription in order.
+    pub fn publish_all(&self, events: impl IntoIterator<Item = T>) {
+        for event in events {
+            self.publish(event);
+        }
+    }
+}
+
+impl<T: Copy, const N: usize> Default for Broadcast<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Clone for Broadcast<T, N> {
+    fn clone(&self) -> Self {
+        Self {
+            queues: self.queues.clone(),
+        }
+    }
+}
+
+struct Queue<T, const N: usize> {
+    ring: EventRing<T, N>,
+    /// Held while waking the subscriber, so a wake-up is not lost
+    waiting: Mutex<()>,
+    ready: Condvar,
+}
+
+impl<T: Copy, const N: usize> Queue<T, N> {
+    fn push(&self, event: T) {
+        self.ring.push(event);
+        let _waiting = lock(&self.waiting);
+        self.ready.notify_one();
+    }
+}
+
+/// Receiver of the published events
+///
+/// Dropping it unsubscribes.
+pub struct Subscription<T = KeyEvent, const N: usize = 16> {
+    queue: Arc<Queue<T, N>>,
+}
+
+impl<T: Copy, const N: usize> Subscription<T, N> {
+    /// Returns the oldest queued event without waiting.
+    pub fn try_recv(&self) -> Option<T> {
+        self.queue.ring.pop()
+    }
+
+    /// Wait for an event up to the timeout.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        let waiting = lock(&self.queue.waiting);
+        let _waiting = self
+            .queue
+            .ready
+            .wait_timeout_while(waiting, timeout, |_| self.queue.ring.is_empty())
+            .unwrap_or_else(|e| e.into_inner());
+        self.queue.ring.pop()
+    }
+
+    /// Take all queued events.
+    pub fn drain(&self) -> Vec<T> {
+        std::iter::from_fn(|| self.queue.ring.pop()).collect()
+    }
+
+    /// Returns the number of events dropped because the queue was full.
+    pub fn overflows(&self) -> u32 {
+        self.queue.ring.overflow_count()
+    }
+}
+
+/// Lock the mutex even if a thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_subscription_receives_the_events() {
+        let broadcast = Broadcast::<u32, 4>::new();
+        let first = broadcast.subscribe();
+        broadcast.publish(1);
+        let second = broadcast.subscribe();
+        broadcast.clone().publish_all([2, 3]);
+        assert_eq!(first.drain(), vec![1, 2, 3]);
+        assert_eq!(second.try_recv(), Some(2));
+        assert_eq!(second.drain(), vec![3]);
+        assert_eq!(first.try_recv(), None);
+    }
+
+    #[test]
+    fn stalled_subscription_drops_its_oldest_events() {
+        let broadcast = Broadcast::<u32, 4>::new();
+        let stalled = broadcast.subscribe();
+        let reader = broadcast.subscribe();
+        for x in 0..6 {
+            broadcast.publish(x);
+            assert_eq!(reader.try_recv(), Some(x));
+        }
+        assert_eq!(stalled.overflows(), 2);
+        assert_eq!(stalled.drain(), vec![2, 3, 4, 5]);
+        assert_eq!(reader.overflows(), 0);
+    }
+
+    #[test]
+    fn dropped_subscriptions_are_removed() {
+        let broadcast = Broadcast::<u32>::new();
+        let kept = broadcast.subscribe();
+        drop(broadcast.subscribe());
+        assert_eq!(broadcast.subscriber_count(), 1);
+        broadcast.publish(1);
+        assert_eq!(kept.try_recv(), Some(1));
+    }
+
+    #[test]
+    fn waiting_subscriber_wakes_up_on_publish() {
+        let broadcast = Broadcast::<u32>::new();
+        let subscription = broadcast.subscribe();
+        assert_eq!(subscription.recv_timeout(Duration::from_millis(1)), None);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(10));
+                broadcast.publish(7);
+            });
+            assert_eq!(subscription.recv_timeout(Duration::from_secs(10)), Some(7));
+        });
+    }
+}
@@ -0,0 +1,255 @@
+//! Fixed-capacity ring buffer for key events
+//!
+//! Passes events from a keyboard task or ISR to a consumer without locks
+//! or allocation. When the consumer stalls, the oldest events are
+//! overwritten and counted, so memory stays bounded. The capacity is a
+//! power of two, so the slot of an index is found with a mask and the
+//! indices wrap around without a jump.
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Lock-free ring buffer for one producer and one consumer
+///
+/// # Examples
+///
+/// ```
+/// use cardputer_core::keyboard::{event_ring::EventRing, pipeline::{KeyEvent, Pipeline}};
+///
+/// static EVENTS: EventRing<KeyEvent, 32> = EventRing::new();
+///
+/// thread::spawn(move || {
+///     let mut pipeline = Pipeline::standard();
+///     loop {
+///         for event in pipeline.update(&mut keyboard).unwrap() {
+///             EVENTS.push(event);
+///         }
+///         thread::sleep(Duration::from_millis(5));
+///     }
+/// });
+/// while let Some(event) = EVENTS.pop() {
+///     log::info!("{:?}", event);
+/// }
+/// ```
+pub struct EventRing<T, const N: usize> {
+    slots: UnsafeCell<[MaybeUninit<T>; N]>,
+    /// Index of the oldest event, advanced by the consumer and by the
+    /// producer when it overwrites
+    head: AtomicU32,
+    /// Index of the next event to write, advanced by the producer only
+    tail: AtomicU32,
+    overflows: AtomicU32,
+}
+
+// SAFETY: a slot is read only after the producer published it through
+// `tail`, and a read overlapping an overwrite is discarded because the
+// producer moves `head` past the slot before writing it.
+unsafe impl<T: Copy + Send, const N: usize> Sync for EventRing<T, N> {}
+
+impl<T: Copy, const N: usize> EventRing<T, N> {
+    /// Stops the build of a buffer whose capacity is not a power of two
+    const POWER_OF_TWO: () = assert!(N.is_power_of_two(), "capacity must be a power of two");
+
+    /// Create new empty buffer.
+    pub const fn new() -> Self {
+        let () = Self::POWER_OF_TWO;
+        Self {
+            // SAFETY: an array of `MaybeUninit` needs no initialization
+            slots: UnsafeCell::new(unsafe { MaybeUninit::uninit().assume_init() }),
+            head: AtomicU32::new(0),
+            tail: AtomicU32::new(0),
+            overflows: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns the number of events the buffer holds.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Append the event, overwriting the oldest one if the buffer is full.
+    ///
+    /// Must be called from a single producer, which may be an ISR.
+    pub fn push(&self, event: T) {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) as usize >= N
+            && self
+                .head
+                .compare_exchange(
+                    head,
+                    head.wrapping_add(1),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_ok()
+        {
+            self.overflows.fetch_add(1, Ordering::Relaxed);
+        }
+        // SAFETY: only the producer writes, and the slot is no longer
+        // between `head` and `tail`
+        unsafe {
+            let slot = (self.slots.get() as *mut MaybeUninit<T>).add(Self::slot(tail));
+            core::ptr::write_volatile(slot, MaybeUninit::new(event));
+        }
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Remove and return the oldest event.
+    ///
+    /// Must be called from a single consumer.
+    pub fn pop(&self) -> Option<T> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            if head == self.tail.load(Ordering::Acquire) {
+                return None;
+            }
+            // SAFETY: the slot was published by `tail`; if the producer
+            // overwrites it meanwhile, the exchange below fails
+            let event = unsafe {
+                let slot = (self.slots.get() as *const MaybeUninit<T>).add(Self::slot(head));
+                core::ptr::read_volatile(slot)
+            };
+            if self
+                .head
+                .compare_exchange(
+                    head,
+                    head.wrapping_add(1),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_ok()
+            {
+                // SAFETY: the slot was fully written before it was published
+                return Some(unsafe { event.assume_init() });
+            }
+        }
+    }
+
+    /// Returns the number of events in the buffer.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail.wrapping_sub(head) as usize).min(N)
+    }
+
+    /// Returns true if the buffer has no events.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of events overwritten before being read.
+    pub fn overflow_count(&self) -> u32 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+
+    /// Returns the slot of the index.
+    const fn slot(index: u32) -> usize {
+        index as usize & (N - 1)
+    }
+}
+
+impl<T: Copy, const N: usize> Default for EventRing<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::{collection::vec, prelude::*};
+    use std::collections::VecDeque;
+
+    #[test]
+    fn events_come_out_in_order() {
+        let ring = EventRing::<u32, 4>::new();
+        assert!(ring.is_empty());
+        assert_eq!(ring.capacity(), 4);
+        ring.push(1);
+        ring.push(2);
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.pop(), Some(1));
+        ring.push(3);
+        assert_eq!(ring.pop(), Some(2));
+        assert_eq!(ring.pop(), Some(3));
+        assert_eq!(ring.pop(), None);
+        assert_eq!(ring.overflow_count(), 0);
+    }
+
+    #[test]
+    fn full_buffer_overwrites_the_oldest() {
+        let ring = EventRing::<u32, 4>::new();
+        for x in 0..6 {
+            ring.push(x);
+        }
+        assert_eq!(ring.len(), 4);
+        assert_eq!(ring.overflow_count(), 2);
+        let events: Vec<_> = std::iter::from_fn(|| ring.pop()).collect();
+        assert_eq!(events, vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn indices_wrap_around() {
+        let ring = EventRing::<u32, 8>::new();
+        ring.head.store(u32::MAX - 2, Ordering::Relaxed);
+        ring.tail.store(u32::MAX - 2, Ordering::Relaxed);
+        for x in 0..10 {
+            ring.push(x);
+        }
+        assert_eq!(ring.len(), 8);
+        assert_eq!(ring.overflow_count(), 2);
+        let events: Vec<_> = std::iter::from_fn(|| ring.pop()).collect();
+        assert_eq!(events, (2..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn consumer_on_another_thread_sees_each_event_once() {
+        const COUNT: u32 = 100_000;
+        let ring = EventRing::<u32, 8>::new();
+        let mut received = Vec::new();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for x in 0..COUNT {
+                    ring.push(x);
+                }
+                ring.push(u32::MAX);
+            });
+            loop {
+                match ring.pop() {
+                    Some(u32::MAX) => break,
+                    Some(x) => received.push(x),
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+        assert!(received.windows(2).all(|x| x[0] < x[1]));
+        assert_eq!(received.len() as u32 + ring.overflow_count(), COUNT);
+    }
+
+    proptest! {
+        /// The buffer behaves as a queue dropping its oldest event when
+        /// full.
+        #[test]
+        fn matches_a_bounded_queue(ops in vec(prop::option::of(any::<u8>()), 0..100)) {
+            let ring = EventRing::<u8, 4>::new();
+            let mut model = VecDeque::new();
+            let mut overflows = 0;
+            for op in ops {
+                match op {
+                    Some(x) => {
+                        if model.len() == 4 {
+                            model.pop_front();
+                            overflows += 1;
+                        }
+                        model.push_back(x);
+                        ring.push(x);
+                    }
+                    None => prop_assert_eq!(ring.pop(), model.pop_front()),
+                }
+                prop_assert_eq!(ring.len(), model.len());
+            }
+            prop_assert_eq!(ring.overflow_count(), overflows);
+        }
+    }
+}
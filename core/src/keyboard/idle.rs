@@ -0,0 +1,249 @@
+//! Detection of keyboard inactivity
+//!
+//! [`IdleDetector`] watches the key input and publishes
+//! [`ActivityEvent::Idle`] each time the input has stopped for one of its
+//! thresholds, then [`ActivityEvent::Active`] when it resumes. The
+//! auto-dim, the screensaver and the power management subscribe to these
+//! events instead of each keeping the time of the last key.
+use std::time::{Duration, Instant};
+
+use super::broadcast::{Broadcast, Subscription};
+use super::pipeline::{KeyEvent, Modifiers};
+use super::KeyboardState;
+
+/// Change of the keyboard activity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityEvent {
+    /// No input for the threshold
+    Idle(Duration),
+    /// Input again after an idle event
+    Active,
+}
+
+/// Detector of the periods without key input
+///
+/// # Examples
+///
+/// ```
+/// use cardputer_core::keyboard::idle::{ActivityEvent, IdleDetector};
+///
+/// let mut idle = IdleDetector::new()
+///     .with_threshold(Duration::from_secs(30))
+///     .with_threshold(Duration::from_secs(120));
+///
+/// let events = idle.subscribe();
+/// thread::spawn(move || loop {
+///     match events.recv_timeout(Duration::from_secs(1)) {
+///         Some(ActivityEvent::Idle(x)) if x >= Duration::from_secs(120) => {
+///             power.sleep(&mut display, &mut backlight).unwrap()
+///         }
+///         Some(ActivityEvent::Idle(_)) => backlight.ramp_to(20, ramp).unwrap(),
+///         Some(ActivityEvent::Active) => power.wake(&mut display, &mut backlight).unwrap(),
+///         None => {}
+///     }
+/// });
+///
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     idle.update(&keyboard_state);
+///     thread::sleep(Duration::from_millis(10));
+/// }
+/// ```
+pub struct IdleDetector {
+    /// Thresholds in increasing order
+    thresholds: Vec<Duration>,
+    last_activity: Instant,
+    /// Number of thresholds passed since the last activity
+    passed: usize,
+    broadcast: Broadcast<ActivityEvent>,
+}
+
+impl IdleDetector {
+    /// Create new detector without thresholds, counting from now.
+    pub fn new() -> Self {
+        Self {
+            thresholds: Vec::new(),
+            last_activity: Instant::now(),
+            passed: 0,
+            broadcast: Broadcast::new(),
+        }
+    }
+
+    /// Add a time without input after which an idle event is published.
+    pub fn with_threshold(mut self, threshold: Duration) -> Self {
+        if let Err(i) = self.thresholds.binary_search(&threshold) {
+            self.thresholds.insert(i, threshold);
+        }
+        self
+    }
+
+    /// Returns a new subscription receiving the events published from now.
+    pub fn subscribe(&self) -> Subscription<ActivityEvent> {
+        self.broadcast.subscribe()
+    }
+
+    /// Returns the time since the last input.
+    pub fn idle_time(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    /// Returns true once the first threshold has passed, until the next
+    /// input.
+    pub fn is_idle(&self) -> bool {
+        self.passed > 0
+    }
+
+    /// Check the state of the last update, where any key pressed, held or
+    /// released is an input, and the time.
+    pub fn update(&mut self, state: &KeyboardState) -> Vec<ActivityEvent> {
+        let input = !state.pressed_keys().is_empty()
+            || !state.released_keys().is_empty()
+            || !state.hold_imprints().is_empty()
+            || state.modifiers() != Modifiers::default();
+        let mut events = Vec::new();
+        if input {
+            events.extend(self.activity());
+        }
+        events.extend(self.poll());
+        events
+    }
+
+    /// Take the key event as an input, e.g. for a subscriber of the key
+    /// event broadcast.
+    pub fn observe(&mut self, _event: &KeyEvent) -> Option<ActivityEvent> {
+        self.activity()
+    }
+
+    /// Record an input, from the keyboard or another source, and publish
+    /// the active event if the detector was idle.
+    pub fn activity(&mut self) -> Option<ActivityEvent> {
+        self.activity_at(Instant::now())
+    }
+
+    fn activity_at(&mut self, now: Instant) -> Option<ActivityEvent> {
+        self.last_activity = now;
+        if self.passed == 0 {
+            return None;
+        }
+        self.passed = 0;
+        self.broadcast.publish(ActivityEvent::Active);
+        Some(ActivityEvent::Active)
+    }
+
+    /// Check the time and publish an idle event for each threshold passed
+    /// since the last call.
+    pub fn poll(&mut self) -> Vec<ActivityEvent> {
+        self.poll_at(Instant::now())
+    }
+
+    fn poll_at(&mut self, now: Instant) -> Vec<ActivityEvent> {
+        let idle = now.saturating_duration_since(self.last_activity);
+        let mut events = Vec::new();
+        while let Some(threshold) = self.thresholds.get(self.passed).copied() {
+            if idle < threshold {
+                break;
+            }
+            self.passed += 1;
+            let event = ActivityEvent::Idle(threshold);
+            self.broadcast.publish(event);
+            events.push(event);
+        }
+        events
+    }
+}
+
+impl Default for IdleDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(x: u64) -> Duration {
+        Duration::from_secs(x)
+    }
+
+    #[test]
+    fn thresholds_are_sorted_and_unique() {
+        let idle = IdleDetector::new()
+            .with_threshold(secs(120))
+            .with_threshold(secs(30))
+            .with_threshold(secs(120));
+        assert_eq!(idle.thresholds, vec![secs(30), secs(120)]);
+    }
+
+    #[test]
+    fn each_threshold_is_published_once() {
+        let mut idle = IdleDetector::new()
+            .with_threshold(secs(30))
+            .with_threshold(secs(120));
+        let events = idle.subscribe();
+        let start = idle.last_activity;
+        assert!(idle.poll_at(start + secs(29)).is_empty());
+        assert!(!idle.is_idle());
+        assert_eq!(
+            idle.poll_at(start + secs(30)),
+            vec![ActivityEvent::Idle(secs(30))]
+        );
+        assert!(idle.is_idle());
+        assert!(idle.poll_at(start + secs(60)).is_empty());
+        assert_eq!(
+            idle.poll_at(start + secs(120)),
+            vec![ActivityEvent::Idle(secs(120))]
+        );
+        assert!(idle.poll_at(start + secs(600)).is_empty());
+        assert_eq!(
+            events.drain(),
+            vec![
+                ActivityEvent::Idle(secs(30)),
+                ActivityEvent::Idle(secs(120))
+            ]
+        );
+    }
+
+    #[test]
+    fn late_poll_publishes_all_passed_thresholds() {
+        let mut idle = IdleDetector::new()
+            .with_threshold(secs(30))
+            .with_threshold(secs(120));
+        let start = idle.last_activity;
+        assert_eq!(
+            idle.poll_at(start + secs(200)),
+            vec![
+                ActivityEvent::Idle(secs(30)),
+                ActivityEvent::Idle(secs(120))
+            ]
+        );
+    }
+
+    #[test]
+    fn activity_resets_the_thresholds() {
+        let mut idle = IdleDetector::new().with_threshold(secs(30));
+        let events = idle.subscribe();
+        let start = idle.last_activity;
+        assert_eq!(idle.activity_at(start + secs(10)), None);
+        assert!(idle.poll_at(start + secs(39)).is_empty());
+        idle.poll_at(start + secs(40));
+        assert_eq!(
+            idle.activity_at(start + secs(50)),
+            Some(ActivityEvent::Active)
+        );
+        assert!(!idle.is_idle());
+        assert!(idle.poll_at(start + secs(79)).is_empty());
+        assert_eq!(
+            idle.poll_at(start + secs(80)),
+            vec![ActivityEvent::Idle(secs(30))]
+        );
+        assert_eq!(
+            events.drain(),
+            vec![
+                ActivityEvent::Idle(secs(30)),
+                ActivityEvent::Active,
+                ActivityEvent::Idle(secs(30))
+            ]
+        );
+    }
+}
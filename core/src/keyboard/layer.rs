@@ -0,0 +1,366 @@
+//! Keymap layers on top of the base layer
+use super::{ConversionRule, KeyImprint, Modified};
+
+/// Keys of the Fn layer
+const FN_RULES: [(KeyImprint, Modified); 15] = [
+    (KeyImprint::SemiColon, Modified::UpCursor),
+    (KeyImprint::Period, Modified::DownCursor),
+    (KeyImprint::Slash, Modified::RightCursor),
+    (KeyImprint::Comma, Modified::LeftCursor),
+    (KeyImprint::Backquote, Modified::Escape),
+    (KeyImprint::Backspace, Modified::Delete),
+    (KeyImprint::Equal, Modified::VolumeUp),
+    (KeyImprint::Minus, Modified::VolumeDown),
+    (KeyImprint::Space, Modified::Mute),
+    (KeyImprint::P, Modified::PlayPause),
+    (KeyImprint::CloseSquareBracket, Modified::NextTrack),
+    (KeyImprint::OpenSquareBracket, Modified::PreviousTrack),
+    (KeyImprint::Zero, Modified::BrightnessUp),
+    (KeyImprint::Nine, Modified::BrightnessDown),
+    (KeyImprint::N, Modified::NumLock),
+];
+
+/// Keys of the numeric keypad layer, following the embedded keypad of laptops
+const NUMPAD_RULES: [(KeyImprint, Modified); 15] = [
+    (KeyImprint::Seven, Modified::Graph('7')),
+    (KeyImprint::Eight, Modified::Graph('8')),
+    (KeyImprint::Nine, Modified::Graph('9')),
+    (KeyImprint::Zero, Modified::Graph('/')),
+    (KeyImprint::U, Modified::Graph('4')),
+    (KeyImprint::I, Modified::Graph('5')),
+    (KeyImprint::O, Modified::Graph('6')),
+    (KeyImprint::P, Modified::Graph('*')),
+    (KeyImprint::J, Modified::Graph('1')),
+    (KeyImprint::K, Modified::Graph('2')),
+    (KeyImprint::L, Modified::Graph('3')),
+    (KeyImprint::SemiColon, Modified::Graph('-')),
+    (KeyImprint::M, Modified::Graph('0')),
+    (KeyImprint::Period, Modified::Graph('.')),
+    (KeyImprint::Slash, Modified::Graph('+')),
+];
+
+fn lookup(rules: &[(KeyImprint, Modified)], imprint: KeyImprint) -> Option<Modified> {
+    rules.iter().find(|(x, _)| *x == imprint).map(|(_, x)| *x)
+}
+
+/// Returns the key of the Fn layer assigned to the imprint.
+pub fn fn_key(imprint: KeyImprint) -> Option<Modified> {
+    lookup(&FN_RULES, imprint)
+}
+
+/// Returns the key of the numeric keypad layer assigned to the imprint.
+///
+/// The layout follows the embedded keypad of laptops: 7 8 9 0 / U I O P /
+/// J K L ; / M . act as 7 8 9 / 4 5 6 * / 1 2 3 - / 0 . and the slash as +.
+pub fn numpad(imprint: KeyImprint) -> Option<Modified> {
+    lookup(&NUMPAD_RULES, imprint)
+}
+
+/// How a layer is activated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activator {
+    /// Active while the key is held. A normal key used as the activator
+    /// is not reported as pressed.
+    Momentary(KeyImprint),
+    /// Toggled each time a key converted to the value is pressed.
+    Toggle(Modified),
+}
+
+/// Layer that overrides the keys of the lower layers
+///
+/// Keys without a rule fall through to the next active layer and finally
+/// to the base layer of the key map.
+#[derive(Debug, Clone)]
+pub struct Layer {
+    activator: Activator,
+    rules: Vec<(KeyImprint, Modified)>,
+    is_active: bool,
+}
+
+impl Layer {
+    /// Create new empty layer.
+    pub fn new(activator: Activator) -> Self {
+        Self {
+            activator,
+            rules: Vec::new(),
+            is_active: false,
+        }
+    }
+
+    /// The default layer 1 activated by holding Fn.
+    pub fn fn_layer() -> Self {
+        Self::new(Activator::Momentary(KeyImprint::LeftFn)).with_rules(&FN_RULES)
+    }
+
+    /// The numeric keypad layer toggled by Fn+N.
+    pub fn numpad_layer() -> Self {
+        Self::new(Activator::Toggle(Modified::NumLock)).with_rules(&NUMPAD_RULES)
+    }
+
+    /// Assign the key to the imprint in this layer.
+    pub fn with_rule(mut self, imprint: KeyImprint, key: Modified) -> Self {
+        self.rules.retain(|(x, _)| *x != imprint);
+        self.rules.push((imprint, key));
+        self
+    }
+
+    /// Assign the keys to the imprints in this layer.
+    pub fn with_rules(self, rules: &[(KeyImprint, Modified)]) -> Self {
+        rules.iter().fold(self, |layer, (imprint, key)| {
+            layer.with_rule(*imprint, *key)
+        })
+    }
+
+    /// Returns the activator of the layer.
+    pub fn activator(&self) -> Activator {
+        self.activator
+    }
+
+    /// Returns the key assigned to the imprint in this layer.
+    pub fn get(&self, imprint: KeyImprint) -> Option<Modified> {
+        lookup(&self.rules, imprint)
+    }
+
+    /// Returns true if the layer is active.
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+}
+
+/// Stack of layers on top of the base layer
+///
+/// The layers are numbered from 1 in the order they are added, and a
+/// lower-numbered active layer takes precedence over the higher ones.
+/// The default stack has the Fn layer as layer 1 and the numeric keypad
+/// layer as layer 2.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::keyboard::{Activator, KeyImprint, KeyboardState, Layer, Layers, Modified};
+///
+/// // hold Opt for the cursor keys on I/J/K/L
+/// let layers = Layers::default().with_layer(
+///     Layer::new(Activator::Momentary(KeyImprint::LeftOpt))
+///         .with_rule(KeyImprint::I, Modified::UpCursor)
+///         .with_rule(KeyImprint::J, Modified::LeftCursor)
+///         .with_rule(KeyImprint::K, Modified::DownCursor)
+///         .with_rule(KeyImprint::L, Modified::RightCursor),
+/// );
+/// let mut keyboard_state = KeyboardState::default().with_layers(layers);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Layers {
+    layers: Vec<Layer>,
+}
+
+impl Layers {
+    /// Create new stack without layers.
+    pub fn new() -> Self {
+        Self { layers: Vec::new() }
+    }
+
+    /// Add the layer on top of the stack.
+    pub fn with_layer(mut self, layer: Layer) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Returns the layer of the number, starting from 1.
+    pub fn layer(&self, number: usize) -> Option<&Layer> {
+        self.layers.get(number.checked_sub(1)?)
+    }
+
+    /// Returns true if the layer of the number is active.
+    pub fn is_active(&self, number: usize) -> bool {
+        self.layer(number).is_some_and(|x| x.is_active)
+    }
+
+    /// Activate or deactivate the layer of the number.
+    pub fn set_active(&mut self, number: usize, active: bool) {
+        if let Some(layer) = number.checked_sub(1).and_then(|x| self.layers.get_mut(x)) {
+            layer.is_active = active;
+        }
+    }
+
+    /// Returns the key assigned to the imprint in the active layers.
+    pub fn get(&self, imprint: KeyImprint) -> Option<Modified> {
+        self.layers
+            .iter()
+            .filter(|x| x.is_active)
+            .find_map(|x| x.get(imprint))
+    }
+
+    /// Convert the key by the active layers or the base layer.
+    pub fn convert(&self, rule: &ConversionRule, is_shift_pressed: bool) -> Modified {
+        self.get(rule.imprint())
+            .unwrap_or_else(|| rule.modified(false, is_shift_pressed))
+    }
+
+    /// Returns true if the imprint activates a layer while held.
+    pub fn is_momentary_activator(&self, imprint: KeyImprint) -> bool {
+        self.layers
+            .iter()
+            .any(|x| x.activator == Activator::Momentary(imprint))
+    }
+
+    /// Update the momentary layers from the held keys including modifier keys.
+    pub(crate) fn update_momentary(&mut self, held: &[KeyImprint]) {
+        for layer in self.layers.iter_mut() {
+            if let Activator::Momentary(imprint) = layer.activator {
+                layer.is_active = held.contains(&imprint);
+            }
+        }
+    }
+
+    /// Toggle the layers activated by the pressed keys.
+    pub(crate) fn update_toggle(&mut self, pressed: &[Modified]) {
+        for layer in self.layers.iter_mut() {
+            if let Activator::Toggle(key) = layer.activator {
+                if pressed.contains(&key) {
+                    layer.is_active = !layer.is_active;
+                }
+            }
+        }
+    }
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self::new()
+            .with_layer(Layer::fn_layer())
+            .with_layer(Layer::numpad_layer())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::{prelude::*, sample::select};
+
+    fn rule(imprint: KeyImprint, normal: char, shifted: char) -> ConversionRule {
+        ConversionRule(imprint, Modified::Graph(normal), Modified::Graph(shifted))
+    }
+
+    #[test]
+    fn fn_and_numpad_tables_follow_the_keycaps() {
+        assert_eq!(fn_key(KeyImprint::SemiColon), Some(Modified::UpCursor));
+        assert_eq!(fn_key(KeyImprint::N), Some(Modified::NumLock));
+        assert_eq!(fn_key(KeyImprint::A), None);
+        assert_eq!(numpad(KeyImprint::U), Some(Modified::Graph('4')));
+        assert_eq!(numpad(KeyImprint::Slash), Some(Modified::Graph('+')));
+        assert_eq!(numpad(KeyImprint::A), None);
+    }
+
+    #[test]
+    fn with_rule_replaces_the_rule_of_the_imprint() {
+        let layer = Layer::new(Activator::Momentary(KeyImprint::LeftOpt))
+            .with_rule(KeyImprint::I, Modified::UpCursor)
+            .with_rule(KeyImprint::I, Modified::Home);
+        assert_eq!(layer.get(KeyImprint::I), Some(Modified::Home));
+        assert_eq!(layer.get(KeyImprint::J), None);
+        assert!(!layer.is_active());
+    }
+
+    #[test]
+    fn convert_falls_through_to_the_base_layer() {
+        let mut layers = Layers::default();
+        let semicolon = rule(KeyImprint::SemiColon, ';', ':');
+        let a = rule(KeyImprint::A, 'a', 'A');
+
+        assert_eq!(layers.convert(&semicolon, false), Modified::Graph(';'));
+        assert_eq!(layers.convert(&semicolon, true), Modified::Graph(':'));
+
+        layers.update_momentary(&[KeyImprint::LeftFn]);
+        assert!(layers.is_active(1));
+        assert_eq!(layers.convert(&semicolon, false), Modified::UpCursor);
+        assert_eq!(layers.convert(&a, true), Modified::Graph('A'));
+
+        layers.update_momentary(&[]);
+        assert!(!layers.is_active(1));
+        assert_eq!(layers.convert(&semicolon, false), Modified::Graph(';'));
+    }
+
+    #[test]
+    fn lower_layers_take_precedence() {
+        let mut layers = Layers::new()
+            .with_layer(
+                Layer::new(Activator::Momentary(KeyImprint::LeftOpt))
+                    .with_rule(KeyImprint::I, Modified::UpCursor),
+            )
+            .with_layer(
+                Layer::new(Activator::Momentary(KeyImprint::LeftAlt))
+                    .with_rule(KeyImprint::I, Modified::Home)
+                    .with_rule(KeyImprint::K, Modified::End),
+            );
+        layers.update_momentary(&[KeyImprint::LeftOpt, KeyImprint::LeftAlt]);
+        assert_eq!(layers.get(KeyImprint::I), Some(Modified::UpCursor));
+        assert_eq!(layers.get(KeyImprint::K), Some(Modified::End));
+
+        layers.set_active(1, false);
+        assert_eq!(layers.get(KeyImprint::I), Some(Modified::Home));
+    }
+
+    #[test]
+    fn toggle_layers_flip_on_each_press() {
+        let mut layers = Layers::default();
+        let m = rule(KeyImprint::M, 'm', 'M');
+
+        layers.update_toggle(&[Modified::NumLock]);
+        assert!(layers.is_active(2));
+        assert_eq!(layers.convert(&m, false), Modified::Graph('0'));
+
+        // momentary updates leave the toggled layer alone
+        layers.update_momentary(&[]);
+        assert!(layers.is_active(2));
+
+        layers.update_toggle(&[Modified::Graph('x')]);
+        assert!(layers.is_active(2));
+        layers.update_toggle(&[Modified::NumLock]);
+        assert!(!layers.is_active(2));
+        assert_eq!(layers.convert(&m, false), Modified::Graph('m'));
+    }
+
+    #[test]
+    fn layer_numbers_start_from_one() {
+        let mut layers = Layers::default();
+        assert!(layers.layer(0).is_none());
+        assert!(layers.layer(3).is_none());
+        assert_eq!(
+            layers.layer(1).map(Layer::activator),
+            Some(Activator::Momentary(KeyImprint::LeftFn))
+        );
+        layers.set_active(0, true);
+        layers.set_active(3, true);
+        assert!(!layers.is_active(0) && !layers.is_active(1) && !layers.is_active(2));
+        assert!(layers.is_momentary_activator(KeyImprint::LeftFn));
+        assert!(!layers.is_momentary_activator(KeyImprint::N));
+    }
+
+    proptest! {
+        #[test]
+        fn momentary_layer_follows_its_activator(
+            held in proptest::collection::vec(select(vec![
+                KeyImprint::LeftFn,
+                KeyImprint::LeftShift,
+                KeyImprint::A,
+                KeyImprint::N,
+            ]), 0..4)
+        ) {
+            let mut layers = Layers::default();
+            layers.update_momentary(&held);
+            prop_assert_eq!(layers.is_active(1), held.contains(&KeyImprint::LeftFn));
+            prop_assert!(!layers.is_active(2));
+        }
+
+        #[test]
+        fn toggling_twice_restores_the_layer(initial: bool, presses in 0usize..6) {
+            let mut layers = Layers::default();
+            layers.set_active(2, initial);
+            for _ in 0..presses {
+                layers.update_toggle(&[Modified::NumLock]);
+            }
+            prop_assert_eq!(layers.is_active(2), initial ^ (presses % 2 == 1));
+        }
+    }
+}
@@ -0,0 +1,823 @@
+//! Key processing pipeline built from pluggable stages
+//!
+//! ```text
+//! scanner -> Debouncer -> Repeater -> LayerMapper -> events
+//! ```
+//!
+//! Each stage implements [`Stage`] and works on a [`Scan`], which carries
+//! the held keys and the events produced by the previous stages. Custom
+//! stages can be inserted anywhere, e.g. a decoder that turns Morse input
+//! on the space key into characters after the debouncer.
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::{ConversionRule, KeyImprint, KeyType, KeyboardScanner, Layers, Modified, KEY_MAP};
+
+/// Kind of a key event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEventKind {
+    Pressed,
+    Released,
+    /// Generated by the repeater while the key is held
+    Repeated,
+}
+
+/// State of the modifier keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub is_fn_pressed: bool,
+    pub is_shift_pressed: bool,
+    pub is_ctrl_pressed: bool,
+    pub is_alt_pressed: bool,
+    pub is_opt_pressed: bool,
+}
+
+impl Modifiers {
+    /// Collect the state of the modifier keys from the held keys.
+    pub fn from_held(held: &[KeyImprint]) -> Self {
+        Self {
+            is_fn_pressed: held.contains(&KeyImprint::LeftFn),
+            is_shift_pressed: held.contains(&KeyImprint::LeftShift),
+            is_ctrl_pressed: held.contains(&KeyImprint::LeftCtrl),
+            is_alt_pressed: held.contains(&KeyImprint::LeftAlt),
+            is_opt_pressed: held.contains(&KeyImprint::LeftOpt),
+        }
+    }
+
+    /// Returns the state as bits: 0 Fn, 1 Shift, 2 Ctrl, 3 Alt, 4 Opt.
+    pub fn bits(&self) -> u8 {
+        [
+            self.is_fn_pressed,
+            self.is_shift_pressed,
+            self.is_ctrl_pressed,
+            self.is_alt_pressed,
+            self.is_opt_pressed,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, x)| acc | ((*x as u8) << i))
+    }
+}
+
+/// Key event passed between the stages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub kind: KeyEventKind,
+    pub imprint: KeyImprint,
+    /// State of the modifier keys when the event occurred
+    pub modifiers: Modifiers,
+    /// Converted key, filled by [`LayerMapper`]; `None` for modifier keys
+    pub key: Option<Modified>,
+}
+
+impl KeyEvent {
+    /// Create new unconverted event.
+    pub fn new(kind: KeyEventKind, imprint: KeyImprint) -> Self {
+        Self {
+            kind,
+            imprint,
+            modifiers: Modifiers::default(),
+            key: None,
+        }
+    }
+}
+
+/// Data passed through the stages for each scan of the keyboard
+#[derive(Debug, Clone)]
+pub struct Scan {
+    /// Time of the scan
+    pub time: Instant,
+    /// Keys held including modifier keys
+    pub held: Vec<KeyImprint>,
+    /// Events produced so far
+    pub events: Vec<KeyEvent>,
+}
+
+/// Stage of the pipeline
+pub trait Stage {
+    /// Process the scan, modifying the held keys and the events as needed.
+    fn process(&mut self, scan: &mut Scan);
+}
+
+/// Stage that ignores changes shorter than the debounce time and emits
+/// the pressed and released events of the stable key state
+#[derive(Debug, Clone)]
+pub struct Debouncer {
+    time: Duration,
+    stable: Vec<KeyImprint>,
+    candidate: Vec<KeyImprint>,
+    since: Option<Instant>,
+}
+
+impl Debouncer {
+    /// Create new debouncer. A zero time only detects the changes.
+    pub fn new(time: Duration) -> Self {
+        Self {
+            time,
+            stable: Vec::new(),
+            candidate: Vec::new(),
+            since: None,
+        }
+    }
+}
+
+impl Default for Debouncer {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(5))
+    }
+}
+
+impl Stage for Debouncer {
+    fn process(&mut self, scan: &mut Scan) {
+        if scan.held != self.candidate || self.since.is_none() {
+            self.candidate = scan.held.clone();
+            self.since = Some(scan.time);
+        }
+        let settled = self
+            .since
+            .is_some_and(|x| scan.time.duration_since(x) >= self.time);
+        if settled && self.candidate != self.stable {
+            for key in self.stable.iter().filter(|x| !self.candidate.contains(x)) {
+                scan.events
+                    .push(KeyEvent::new(KeyEventKind::Released, *key));
+            }
+            for key in self.candidate.iter().filter(|x| !self.stable.contains(x)) {
+                scan.events.push(KeyEvent::new(KeyEventKind::Pressed, *key));
+            }
+            self.stable = self.candidate.clone();
+        }
+        scan.held = self.stable.clone();
+    }
+}
+
+/// Repeat timing of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Typematic {
+    /// The key does not repeat.
+    Off,
+    /// The key repeats after the delay at the interval.
+    Repeat { delay: Duration, interval: Duration },
+}
+
+impl Typematic {
+    /// Repeat after the delay at the interval.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the interval is zero.
+    pub fn new(delay: Duration, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "zero repeat interval");
+        Typematic::Repeat { delay, interval }
+    }
+}
+
+/// Class of keys sharing a repeat timing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyClass {
+    /// A to Z
+    Letter,
+    /// 0 to 9
+    Digit,
+    /// The cursor keys of the Fn layer (; , . /) while Fn is held
+    Cursor,
+}
+
+impl KeyClass {
+    /// Returns the class of the key pressed with the held keys.
+    pub fn of(imprint: KeyImprint, held: &[KeyImprint]) -> Option<Self> {
+        let cursors = [
+            KeyImprint::SemiColon,
+            KeyImprint::Comma,
+            KeyImprint::Period,
+            KeyImprint::Slash,
+        ];
+        if cursors.contains(&imprint) && held.contains(&KeyImprint::LeftFn) {
+            return Some(KeyClass::Cursor);
+        }
+        match rule_of(imprint)?.modified(false, false) {
+            Modified::Graph(c) if c.is_ascii_alphabetic() => Some(KeyClass::Letter),
+            Modified::Graph(c) if c.is_ascii_digit() => Some(KeyClass::Digit),
+            _ => None,
+        }
+    }
+}
+
+/// Stage that repeats the last pressed normal key while it is held
+///
+/// The timing can be overridden per key or per [`KeyClass`], the key
+/// taking precedence over its class.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::keyboard::pipeline::{KeyClass, Repeater, Typematic};
+/// use cardputer::keyboard::KeyImprint;
+/// use std::time::Duration;
+///
+/// let repeater = Repeater::default()
+///     .with_class(KeyClass::Letter, Typematic::Off)
+///     .with_class(KeyClass::Cursor, Typematic::new(Duration::from_millis(300), Duration::from_millis(30)))
+///     .with_key(KeyImprint::Backspace, Typematic::new(Duration::from_millis(400), Duration::from_millis(80)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Repeater {
+    default: Typematic,
+    keys: Vec<(KeyImprint, Typematic)>,
+    classes: Vec<(KeyClass, Typematic)>,
+    /// Key, time of the next repeat and interval
+    repeating: Option<(KeyImprint, Instant, Duration)>,
+}
+
+impl Repeater {
+    /// Create new repeater that starts after the delay and repeats at the interval.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the interval is zero.
+    pub fn new(delay: Duration, interval: Duration) -> Self {
+        Self {
+            default: Typematic::new(delay, interval),
+            keys: Vec::new(),
+            classes: Vec::new(),
+            repeating: None,
+        }
+    }
+
+    /// Set the timing of the keys without an override.
+    pub fn with_default(mut self, typematic: Typematic) -> Self {
+        self.default = typematic;
+        self
+    }
+
+    /// Override the timing of the key.
+    pub fn with_key(mut self, imprint: KeyImprint, typematic: Typematic) -> Self {
+        self.keys.retain(|(x, _)| *x != imprint);
+        self.keys.push((imprint, typematic));
+        self
+    }
+
+    /// Override the timing of the class of keys.
+    pub fn with_class(mut self, class: KeyClass, typematic: Typematic) -> Self {
+        self.classes.retain(|(x, _)| *x != class);
+        self.classes.push((class, typematic));
+        self
+    }
+
+    /// Returns the timing of the key pressed with the held keys.
+    pub fn typematic(&self, imprint: KeyImprint, held: &[KeyImprint]) -> Typematic {
+        let class = KeyClass::of(imprint, held);
+        let key = self.keys.iter().find(|(x, _)| *x == imprint);
+        if let Some((_, typematic)) = key {
+            return *typematic;
+        }
+        self.classes
+            .iter()
+            .find(|(x, _)| Some(*x) == class)
+            .map_or(self.default, |(_, x)| *x)
+    }
+}
+
+impl Default for Repeater {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500), Duration::from_millis(50))
+    }
+}
+
+impl Stage for Repeater {
+    fn process(&mut self, scan: &mut Scan) {
+        for event in scan.events.iter() {
+            match (event.kind, self.repeating) {
+                (KeyEventKind::Pressed, _) if rule_of(event.imprint).is_some() => {
+                    self.repeating = match self.typematic(event.imprint, &scan.held) {
+                        // built directly without Typematic::new
+                        Typematic::Off
+                        | Typematic::Repeat {
+                            interval: Duration::ZERO,
+                            ..
+                        } => None,
+                        Typematic::Repeat { delay, interval } => {
+                            Some((event.imprint, scan.time + delay, interval))
+                        }
+                    };
+                }
+                (KeyEventKind::Released, Some((key, _, _))) if key == event.imprint => {
+                    self.repeating = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some((key, next, interval)) = self.repeating.as_mut() {
+            if scan.time >= *next {
+                scan.events
+                    .push(KeyEvent::new(KeyEventKind::Repeated, *key));
+                *next += *interval;
+            }
+        }
+    }
+}
+
+/// Stage that converts the events by the Shift key and the keymap layers
+///
+/// A key is converted when it is pressed; its repeats and its release
+/// report the same key and modifiers even if the modifiers or the layers
+/// changed meanwhile.
+#[derive(Debug, Clone, Default)]
+pub struct LayerMapper {
+    layers: Layers,
+    /// Converted key and modifiers of each pressed key
+    pressed: HashMap<KeyImprint, (Option<Modified>, Modifiers)>,
+}
+
+impl LayerMapper {
+    /// Create new mapper with the layers.
+    pub fn new(layers: Layers) -> Self {
+        Self {
+            layers,
+            pressed: HashMap::new(),
+        }
+    }
+
+    /// Returns the layers to activate or deactivate them.
+    pub fn layers_mut(&mut self) -> &mut Layers {
+        &mut self.layers
+    }
+}
+
+impl Stage for LayerMapper {
+    fn process(&mut self, scan: &mut Scan) {
+        let modifiers = Modifiers::from_held(&scan.held);
+        self.layers.update_momentary(&scan.held);
+
+        let layers = &self.layers;
+        // normal keys used as momentary activators are not reported
+        scan.events.retain(|x| {
+            !(layers.is_momentary_activator(x.imprint) && rule_of(x.imprint).is_some())
+        });
+        let mut pressed = Vec::new();
+        for event in scan.events.iter_mut() {
+            let convert = || {
+                let key =
+                    rule_of(event.imprint).map(|x| layers.convert(&x, modifiers.is_shift_pressed));
+                (key, modifiers)
+            };
+            let (key, modifiers) = match event.kind {
+                KeyEventKind::Pressed => {
+                    let converted = convert();
+                    self.pressed.insert(event.imprint, converted);
+                    converted
+                }
+                KeyEventKind::Repeated => {
+                    *self.pressed.entry(event.imprint).or_insert_with(convert)
+                }
+                KeyEventKind::Released => {
+                    self.pressed.remove(&event.imprint).unwrap_or_else(convert)
+                }
+            };
+            event.key = key;
+            event.modifiers = modifiers;
+            if let (KeyEventKind::Pressed, Some(key)) = (event.kind, event.key) {
+                pressed.push(key);
+            }
+        }
+        self.layers.update_toggle(&pressed);
+    }
+}
+
+/// Pipeline of stages fed by a keyboard scanner
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::keyboard::pipeline::{Debouncer, LayerMapper, Pipeline, Repeater};
+///
+/// let mut pipeline = Pipeline::new()
+///     .with_stage(Debouncer::default())
+///     .with_stage(MorseDecoder::new()) // user-defined stage
+///     .with_stage(Repeater::default())
+///     .with_stage(LayerMapper::default());
+/// loop {
+///     for event in pipeline.update(&mut keyboard).unwrap() {
+///         log::info!("{:?}", event);
+///     }
+/// }
+/// ```
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage + Send>>,
+}
+
+impl Pipeline {
+    /// Create new pipeline without stages.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The standard pipeline of debouncer, repeater and layer mapper.
+    pub fn standard() -> Self {
+        Self::new()
+            .with_stage(Debouncer::default())
+            .with_stage(Repeater::default())
+            .with_stage(LayerMapper::default())
+    }
+
+    /// Append the stage to the end of the pipeline.
+    pub fn with_stage(mut self, stage: impl Stage + Send + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Scan the keyboard, pass the result through the stages and return the events.
+    pub fn update(&mut self, scanner: &mut impl KeyboardScanner) -> Result<Vec<KeyEvent>> {
+        let mut scan = Scan {
+            time: Instant::now(),
+            held: scanner
+                .scan_pressed_keytypes()?
+                .iter()
+                .map(|x| x.imprint())
+                .collect(),
+            events: Vec::new(),
+        };
+        for stage in self.stages.iter_mut() {
+            stage.process(&mut scan);
+        }
+        Ok(scan.events)
+    }
+}
+
+/// Returns the conversion rule of the normal key.
+fn rule_of(imprint: KeyImprint) -> Option<ConversionRule> {
+    KEY_MAP.iter().flatten().find_map(|x| match x {
+        KeyType::Normal(rule) if rule.imprint() == imprint => Some(*rule),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::{Activator, Layer};
+    use proptest::{collection::vec, prelude::*, sample::subsequence};
+
+    /// Scan of the held keys at the time after the start
+    fn scan(start: Instant, ms: u64, held: &[KeyImprint]) -> Scan {
+        Scan {
+            time: start + Duration::from_millis(ms),
+            held: held.to_vec(),
+            events: Vec::new(),
+        }
+    }
+
+    fn kinds(events: &[KeyEvent]) -> Vec<(KeyEventKind, KeyImprint)> {
+        events.iter().map(|x| (x.kind, x.imprint)).collect()
+    }
+
+    /// Press A, then hold it with a later scan at each time.
+    fn repeat_times(repeater: &mut Repeater, held: &[KeyImprint], times: &[u64]) -> Vec<u64> {
+        let start = Instant::now();
+        let mut first = scan(start, 0, held);
+        first
+            .events
+            .push(KeyEvent::new(KeyEventKind::Pressed, KeyImprint::A));
+        repeater.process(&mut first);
+        times
+            .iter()
+            .filter(|ms| {
+                let mut next = scan(start, **ms, held);
+                repeater.process(&mut next);
+                next.events
+                    .iter()
+                    .any(|x| x.kind == KeyEventKind::Repeated && x.imprint == KeyImprint::A)
+            })
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn debouncer_waits_for_a_stable_state() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(5));
+
+        let mut x = scan(start, 0, &[KeyImprint::A]);
+        debouncer.process(&mut x);
+        assert!(x.events.is_empty());
+        assert!(x.held.is_empty());
+
+        // a glitch restarts the wait
+        let mut x = scan(start, 3, &[]);
+        debouncer.process(&mut x);
+        let mut x = scan(start, 4, &[KeyImprint::A]);
+        debouncer.process(&mut x);
+        let mut x = scan(start, 8, &[KeyImprint::A]);
+        debouncer.process(&mut x);
+        assert!(x.events.is_empty());
+
+        let mut x = scan(start, 9, &[KeyImprint::A]);
+        debouncer.process(&mut x);
+        assert_eq!(
+            kinds(&x.events),
+            vec![(KeyEventKind::Pressed, KeyImprint::A)]
+        );
+        assert_eq!(x.held, vec![KeyImprint::A]);
+    }
+
+    #[test]
+    fn zero_debounce_reports_releases_before_presses() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::ZERO);
+        let mut x = scan(start, 0, &[KeyImprint::A]);
+        debouncer.process(&mut x);
+        assert_eq!(
+            kinds(&x.events),
+            vec![(KeyEventKind::Pressed, KeyImprint::A)]
+        );
+
+        let mut x = scan(start, 1, &[KeyImprint::B]);
+        debouncer.process(&mut x);
+        assert_eq!(
+            kinds(&x.events),
+            vec![
+                (KeyEventKind::Released, KeyImprint::A),
+                (KeyEventKind::Pressed, KeyImprint::B)
+            ]
+        );
+    }
+
+    #[test]
+    fn repeater_starts_after_the_delay_at_the_interval() {
+        let mut repeater = Repeater::new(Duration::from_millis(500), Duration::from_millis(50));
+        let times = repeat_times(
+            &mut repeater,
+            &[KeyImprint::A],
+            &[100, 499, 500, 520, 550, 600],
+        );
+        assert_eq!(times, vec![500, 550, 600]);
+    }
+
+    #[test]
+    fn repeater_stops_on_release() {
+        let start = Instant::now();
+        let mut repeater = Repeater::new(Duration::ZERO, Duration::from_millis(10));
+        let mut x = scan(start, 0, &[KeyImprint::A]);
+        x.events
+            .push(KeyEvent::new(KeyEventKind::Pressed, KeyImprint::A));
+        repeater.process(&mut x);
+        assert_eq!(x.events.len(), 2);
+
+        let mut x = scan(start, 20, &[]);
+        x.events
+            .push(KeyEvent::new(KeyEventKind::Released, KeyImprint::A));
+        repeater.process(&mut x);
+        assert_eq!(
+            kinds(&x.events),
+            vec![(KeyEventKind::Released, KeyImprint::A)]
+        );
+    }
+
+    #[test]
+    fn repeater_ignores_modifier_keys() {
+        let start = Instant::now();
+        let mut repeater = Repeater::new(Duration::ZERO, Duration::from_millis(10));
+        let mut x = scan(start, 0, &[KeyImprint::LeftShift]);
+        x.events
+            .push(KeyEvent::new(KeyEventKind::Pressed, KeyImprint::LeftShift));
+        repeater.process(&mut x);
+        assert_eq!(x.events.len(), 1);
+    }
+
+    #[test]
+    fn key_overrides_take_precedence_over_classes() {
+        let slow = Typematic::new(Duration::from_millis(800), Duration::from_millis(100));
+        let repeater = Repeater::default()
+            .with_class(KeyClass::Letter, Typematic::Off)
+            .with_key(KeyImprint::A, slow);
+        assert_eq!(repeater.typematic(KeyImprint::A, &[]), slow);
+        assert_eq!(repeater.typematic(KeyImprint::B, &[]), Typematic::Off);
+        assert_eq!(
+            repeater.typematic(KeyImprint::One, &[]),
+            Typematic::new(Duration::from_millis(500), Duration::from_millis(50))
+        );
+        let mut repeater = repeater;
+        assert_eq!(
+            repeat_times(&mut repeater, &[KeyImprint::A], &[600, 800]),
+            vec![800]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "zero repeat interval")]
+    fn zero_interval_is_rejected() {
+        Repeater::new(Duration::from_millis(500), Duration::ZERO);
+    }
+
+    #[test]
+    fn zero_interval_variant_does_not_repeat() {
+        let zero = Typematic::Repeat {
+            delay: Duration::ZERO,
+            interval: Duration::ZERO,
+        };
+        let mut repeater = Repeater::default().with_key(KeyImprint::A, zero);
+        assert!(repeat_times(&mut repeater, &[KeyImprint::A], &[0, 1, 2]).is_empty());
+    }
+
+    #[test]
+    fn key_classes() {
+        assert_eq!(KeyClass::of(KeyImprint::Q, &[]), Some(KeyClass::Letter));
+        assert_eq!(KeyClass::of(KeyImprint::Seven, &[]), Some(KeyClass::Digit));
+        assert_eq!(KeyClass::of(KeyImprint::Comma, &[]), None);
+        assert_eq!(
+            KeyClass::of(KeyImprint::Comma, &[KeyImprint::LeftFn]),
+            Some(KeyClass::Cursor)
+        );
+    }
+
+    #[test]
+    fn layer_mapper_converts_with_shift_and_fn() {
+        let start = Instant::now();
+        let mut mapper = LayerMapper::default();
+        let mut x = scan(start, 0, &[KeyImprint::LeftShift, KeyImprint::A]);
+        x.events
+            .push(KeyEvent::new(KeyEventKind::Pressed, KeyImprint::A));
+        x.events
+            .push(KeyEvent::new(KeyEventKind::Pressed, KeyImprint::LeftShift));
+        mapper.process(&mut x);
+        assert_eq!(x.events[0].key, Some(Modified::Graph('A')));
+        assert!(x.events[0].modifiers.is_shift_pressed);
+        assert_eq!(x.events[1].key, None);
+
+        let mut x = scan(start, 10, &[KeyImprint::LeftFn, KeyImprint::SemiColon]);
+        x.events
+            .push(KeyEvent::new(KeyEventKind::Pressed, KeyImprint::SemiColon));
+        mapper.process(&mut x);
+        assert_eq!(x.events[0].key, Some(Modified::UpCursor));
+    }
+
+    #[test]
+    fn layer_mapper_releases_the_key_it_pressed() {
+        let start = Instant::now();
+        let mut mapper = LayerMapper::default();
+        let mut events = Vec::new();
+        for (ms, held, kind) in [
+            (
+                0,
+                &[KeyImprint::LeftFn, KeyImprint::SemiColon][..],
+                KeyEventKind::Pressed,
+            ),
+            // Fn is released first
+            (10, &[KeyImprint::SemiColon][..], KeyEventKind::Repeated),
+            (20, &[][..], KeyEventKind::Released),
+        ] {
+            let mut x = scan(start, ms, held);
+            x.events.push(KeyEvent::new(kind, KeyImprint::SemiColon));
+            mapper.process(&mut x);
+            events.push((x.events[0].key, x.events[0].modifiers.is_fn_pressed));
+        }
+        assert_eq!(events, [(Some(Modified::UpCursor), true); 3]);
+
+        // the next press is converted again
+        let mut x = scan(start, 30, &[KeyImprint::SemiColon]);
+        x.events
+            .push(KeyEvent::new(KeyEventKind::Pressed, KeyImprint::SemiColon));
+        mapper.process(&mut x);
+        assert_eq!(x.events[0].key, Some(Modified::Graph(';')));
+    }
+
+    #[test]
+    fn layer_mapper_toggles_the_numpad() {
+        let start = Instant::now();
+        let mut mapper = LayerMapper::default();
+        let press = |mapper: &mut LayerMapper, ms, held: &[KeyImprint], key| {
+            let mut x = scan(start, ms, held);
+            x.events.push(KeyEvent::new(KeyEventKind::Pressed, key));
+            mapper.process(&mut x);
+            x.events[0].key
+        };
+        assert_eq!(
+            press(
+                &mut mapper,
+                0,
+                &[KeyImprint::LeftFn, KeyImprint::N],
+                KeyImprint::N
+            ),
+            Some(Modified::NumLock)
+        );
+        assert_eq!(
+            press(&mut mapper, 10, &[KeyImprint::U], KeyImprint::U),
+            Some(Modified::Graph('4'))
+        );
+        press(
+            &mut mapper,
+            20,
+            &[KeyImprint::LeftFn, KeyImprint::N],
+            KeyImprint::N,
+        );
+        assert_eq!(
+            press(&mut mapper, 30, &[KeyImprint::U], KeyImprint::U),
+            Some(Modified::Graph('u'))
+        );
+    }
+
+    #[test]
+    fn normal_key_activators_are_not_reported() {
+        let layers = Layers::new().with_layer(
+            Layer::new(Activator::Momentary(KeyImprint::Space))
+                .with_rule(KeyImprint::J, Modified::LeftCursor),
+        );
+        let mut mapper = LayerMapper::new(layers);
+        let mut x = scan(Instant::now(), 0, &[KeyImprint::Space, KeyImprint::J]);
+        x.events
+            .push(KeyEvent::new(KeyEventKind::Pressed, KeyImprint::Space));
+        x.events
+            .push(KeyEvent::new(KeyEventKind::Pressed, KeyImprint::J));
+        mapper.process(&mut x);
+        assert_eq!(
+            kinds(&x.events),
+            vec![(KeyEventKind::Pressed, KeyImprint::J)]
+        );
+        assert_eq!(x.events[0].key, Some(Modified::LeftCursor));
+    }
+
+    const KEYS: [KeyImprint; 6] = [
+        KeyImprint::A,
+        KeyImprint::B,
+        KeyImprint::SemiColon,
+        KeyImprint::LeftFn,
+        KeyImprint::LeftShift,
+        KeyImprint::Space,
+    ];
+
+    /// Scans of random held keys at random steps, ending with none held
+    fn scans() -> impl Strategy<Value = Vec<(u64, Vec<KeyImprint>)>> {
+        vec((0u64..10, subsequence(KEYS.to_vec(), 0..4)), 0..60).prop_map(|mut scans| {
+            scans.push((100, Vec::new()));
+            scans
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn debouncer_pairs_presses_and_releases(scans in scans(), ms in 0u64..8) {
+            let start = Instant::now();
+            let mut debouncer = Debouncer::new(Duration::from_millis(ms));
+            let mut held: Vec<KeyImprint> = Vec::new();
+            let mut time = 0;
+            // the last scan is repeated so the empty state settles
+            for (step, keys) in scans.iter().chain(scans.last()) {
+                time += step;
+                let mut x = scan(start, time, keys);
+                debouncer.process(&mut x);
+                for event in x.events {
+                    match event.kind {
+                        KeyEventKind::Pressed => {
+                            prop_assert!(!held.contains(&event.imprint));
+                            held.push(event.imprint);
+                        }
+                        KeyEventKind::Released => {
+                            let i = held.iter().position(|x| *x == event.imprint);
+                            prop_assert!(i.is_some());
+                            held.remove(i.unwrap());
+                        }
+                        KeyEventKind::Repeated => prop_assert!(false),
+                    }
+                }
+                held.sort_by_key(|x| format!("{:?}", x));
+                let mut stable = x.held.clone();
+                stable.sort_by_key(|x| format!("{:?}", x));
+                prop_assert_eq!(&held, &stable);
+            }
+            prop_assert!(held.is_empty());
+        }
+
+        #[test]
+        fn standard_stages_repeat_only_held_keys(scans in scans()) {
+            let start = Instant::now();
+            let mut stages: Vec<Box<dyn Stage>> = vec![
+                Box::new(Debouncer::new(Duration::ZERO)),
+                Box::new(Repeater::new(Duration::from_millis(20), Duration::from_millis(5))),
+                Box::new(LayerMapper::default()),
+            ];
+            let mut time = 0;
+            for (step, keys) in scans {
+                time += step;
+                let mut x = scan(start, time, &keys);
+                for stage in stages.iter_mut() {
+                    stage.process(&mut x);
+                }
+                for event in x.events {
+                    if event.kind == KeyEventKind::Repeated {
+                        prop_assert!(keys.contains(&event.imprint));
+                    }
+                    // every normal key is converted, modifier keys are not
+                    prop_assert_eq!(event.key.is_some(), rule_of(event.imprint).is_some());
+                    // repeats and releases keep the modifiers of the press
+                    if event.kind == KeyEventKind::Pressed {
+                        prop_assert_eq!(
+                            event.modifiers.is_shift_pressed,
+                            keys.contains(&KeyImprint::LeftShift)
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Key event decoding of the TCA8418 keypad controller
+//!
+//! The controller of the Cardputer ADV reports each press and release as
+//! an event byte: bit 7 is set on press and bits 0 to 6 hold the key
+//! number, counted from 1 with ten numbers per row of the controller.
+//! [`HeldKeys`] replays the events to keep the set of held keys.
+use super::{KeyType, KEY_MAP};
+
+/// Rows and columns of the matrix wired on the ADV
+pub const ROWS: u8 = 7;
+pub const COLUMNS: u8 = 8;
+
+/// Decoded key event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub pressed: bool,
+    /// Row of the controller
+    pub row: u8,
+    /// Column of the controller
+    pub column: u8,
+}
+
+impl Event {
+    /// Decode the event byte of the FIFO.
+    pub fn decode(event: u8) -> Self {
+        let number = (event & 0x7F).saturating_sub(1);
+        Self {
+            pressed: event & 0x80 != 0,
+            row: number / 10,
+            column: number % 10,
+        }
+    }
+
+    /// Returns the key of the event, or `None` outside the wired matrix.
+    pub fn key(&self) -> Option<KeyType> {
+        key_at(self.row, self.column)
+    }
+}
+
+/// Returns the key at the row and the column of the controller.
+///
+/// Each row of the controller covers two columns of the key map and each
+/// half of its columns the four rows.
+pub fn key_at(row: u8, column: u8) -> Option<KeyType> {
+    if row >= ROWS || column >= COLUMNS {
+        return None;
+    }
+    let x = row as usize * 2 + (column > 3) as usize;
+    let y = column as usize % 4;
+    Some(KEY_MAP[y][x])
+}
+
+/// Held keys kept from the press and release events
+#[derive(Debug, Clone, Default)]
+pub struct HeldKeys {
+    /// Held keys as (row, column) of the controller, in pressing order
+    held: Vec<(u8, u8)>,
+}
+
+impl HeldKeys {
+    /// Create new empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply the event.
+    pub fn apply(&mut self, event: Event) {
+        let position = (event.row, event.column);
+        self.held.retain(|x| *x != position);
+        if event.pressed {
+            self.held.push(position);
+        }
+    }
+
+    /// Forget the held keys.
+    pub fn clear(&mut self) {
+        self.held.clear();
+    }
+
+    /// Returns the held keys of the wired matrix.
+    pub fn keys(&self) -> Vec<KeyType> {
+        self.held
+            .iter()
+            .filter_map(|(row, column)| key_at(*row, *column))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::KeyImprint;
+    use proptest::{collection::vec, prelude::*};
+
+    fn imprint(row: u8, column: u8) -> KeyImprint {
+        key_at(row, column).unwrap().imprint()
+    }
+
+    #[test]
+    fn decode_splits_the_key_number() {
+        assert_eq!(
+            Event::decode(0x81),
+            Event {
+                pressed: true,
+                row: 0,
+                column: 0
+            }
+        );
+        assert_eq!(
+            Event::decode(0x0C),
+            Event {
+                pressed: false,
+                row: 1,
+                column: 1
+            }
+        );
+    }
+
+    #[test]
+    fn key_at_follows_the_keymap() {
+        assert_eq!(imprint(0, 0), KeyImprint::LeftCtrl);
+        assert_eq!(imprint(0, 3), KeyImprint::Backquote);
+        assert_eq!(imprint(0, 4), KeyImprint::LeftOpt);
+        assert_eq!(imprint(6, 7), KeyImprint::Backspace);
+        assert!(key_at(ROWS, 0).is_none());
+        assert!(key_at(0, COLUMNS).is_none());
+    }
+
+    #[test]
+    fn key_at_reaches_every_key_once() {
+        let mut found: Vec<KeyImprint> = (0..ROWS)
+            .flat_map(|row| (0..COLUMNS).map(move |column| imprint(row, column)))
+            .collect();
+        for imprint in KEY_MAP.iter().flatten().map(KeyType::imprint) {
+            let position = found.iter().position(|x| *x == imprint).unwrap();
+            found.remove(position);
+        }
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn every_event_byte_decodes() {
+        for byte in 0..=u8::MAX {
+            let event = Event::decode(byte);
+            assert_eq!(event.pressed, byte & 0x80 != 0);
+            assert_eq!(
+                event.key().is_some(),
+                event.row < ROWS && event.column < COLUMNS
+            );
+        }
+    }
+
+    #[test]
+    fn held_keys_follow_the_events() {
+        let press = |row, column| Event {
+            pressed: true,
+            row,
+            column,
+        };
+        let release = |row, column| Event {
+            pressed: false,
+            row,
+            column,
+        };
+        let imprints = |held: &HeldKeys| -> Vec<KeyImprint> {
+            held.keys().iter().map(|x| x.imprint()).collect()
+        };
+
+        let mut held = HeldKeys::new();
+        held.apply(press(0, 3));
+        held.apply(press(0, 3));
+        held.apply(press(6, 7));
+        assert_eq!(
+            imprints(&held),
+            vec![KeyImprint::Backquote, KeyImprint::Backspace]
+        );
+
+        held.apply(release(0, 3));
+        held.apply(release(1, 1));
+        assert_eq!(imprints(&held), vec![KeyImprint::Backspace]);
+
+        // keys outside the matrix are tracked but not reported
+        held.apply(press(9, 9));
+        assert_eq!(imprints(&held), vec![KeyImprint::Backspace]);
+
+        held.clear();
+        assert!(held.keys().is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn held_keys_are_unique(events in vec(any::<u8>(), 0..200)) {
+            let mut held = HeldKeys::new();
+            for event in events {
+                held.apply(Event::decode(event));
+            }
+            let imprints: Vec<KeyImprint> = held.keys().iter().map(|x| x.imprint()).collect();
+            for (i, imprint) in imprints.iter().enumerate() {
+                prop_assert!(!imprints[i + 1..].contains(imprint));
+            }
+        }
+
+        #[test]
+        fn event_streams_pair_press_and_release(
+            data in vec(prop_oneof![Just(0u8), any::<u8>()], 0..400)
+        ) {
+            crate::fuzz::tca8418_events(&data);
+        }
+    }
+}
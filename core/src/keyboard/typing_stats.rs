@@ -0,0 +1,314 @@
+//! Typing statistics for practicing on the keyboard
+//!
+//! [`TypingStats`] counts the presses of each key, the characters typed
+//! and the corrections made with Backspace or Delete, and measures the
+//! time spent typing to give the speed in words per minute. Pauses longer
+//! than [`IDLE_GAP`] are not counted as typing time, so the speed does not
+//! drop while the keyboard is left alone.
+//!
+//! [`TypingStats::to_text`] writes the statistics as lines of a name and
+//! a number, which the cardputer crate saves in a store so a session can
+//! be continued after a reboot:
+//!
+//! ```text
+//! characters 1532
+//! corrections 87
+//! active_ms 412950
+//! A 96
+//! Space 281
+//! ```
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use super::accessibility::is_modifier;
+use super::{KeyImprint, KeyboardState, Modified, KEY_MAP};
+
+/// Longest pause between two keys counted as typing time
+pub const IDLE_GAP: Duration = Duration::from_secs(5);
+
+/// Characters in a word for the speed, by convention
+const WORD_LENGTH: f32 = 5.0;
+
+/// Statistics of the keys typed
+///
+/// # Examples
+///
+/// ```
+/// use cardputer_core::keyboard::typing_stats::TypingStats;
+///
+/// let mut stats = TypingStats::from_text(&saved).unwrap_or_default();
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     stats.record(&keyboard_state);
+///     if keyboard_state.pressed_keys().contains(&Modified::Escape) {
+///         break;
+///     }
+/// }
+/// println!("{:.0} wpm", stats.wpm());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypingStats {
+    /// Presses of each key, including the modifiers
+    counts: HashMap<KeyImprint, u32>,
+    characters: u32,
+    corrections: u32,
+    /// Typing time without the long pauses
+    active: Duration,
+    /// Time of the last key
+    last: Option<Instant>,
+    /// Modifier keys held at the last update
+    modifiers: Vec<KeyImprint>,
+}
+
+impl TypingStats {
+    /// Create new empty statistics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the keys pressed in the last update, and the modifier keys
+    /// held since then.
+    pub fn record(&mut self, state: &KeyboardState) {
+        let now = Instant::now();
+        let modifiers: Vec<KeyImprint> = KEY_MAP
+            .iter()
+            .flatten()
+            .map(|x| x.imprint())
+            .filter(|x| is_modifier(*x) && state.is_held(*x))
+            .collect();
+        for imprint in &modifiers {
+            if !self.modifiers.contains(imprint) {
+                self.press(*imprint, now);
+            }
+        }
+        self.modifiers = modifiers;
+        for (imprint, key) in state
+            .pressed_imprints()
+            .into_iter()
+            .zip(state.pressed_keys())
+        {
+            self.record_key(imprint, key, now);
+        }
+    }
+
+    /// Record a key pressed at the time.
+    pub fn record_key(&mut self, imprint: KeyImprint, key: Modified, at: Instant) {
+        self.press(imprint, at);
+        match key {
+            Modified::Graph(_) | Modified::Space | Modified::Enter | Modified::Tab => {
+                self.characters += 1
+            }
+            Modified::Backspace | Modified::Delete => self.corrections += 1,
+            _ => {}
+        }
+    }
+
+    /// Count a press of the key, modifier keys included, at the time.
+    fn press(&mut self, imprint: KeyImprint, at: Instant) {
+        *self.counts.entry(imprint).or_default() += 1;
+        if let Some(last) = self.last {
+            let gap = at.saturating_duration_since(last);
+            if gap <= IDLE_GAP {
+                self.active += gap;
+            }
+        }
+        self.last = Some(at);
+    }
+
+    /// Returns the presses of the key.
+    pub fn count(&self, imprint: KeyImprint) -> u32 {
+        self.counts.get(&imprint).copied().unwrap_or(0)
+    }
+
+    /// Returns the keys pressed with their counts, the most pressed first.
+    pub fn most_pressed(&self) -> Vec<(KeyImprint, u32)> {
+        let mut counts: Vec<(KeyImprint, u32)> =
+            self.counts.iter().map(|(k, v)| (*k, *v)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| name(a.0).cmp(&name(b.0))));
+        counts
+    }
+
+    /// Returns the presses of all the keys.
+    pub fn total_presses(&self) -> u32 {
+        self.counts.values().sum()
+    }
+
+    /// Returns the characters typed, including spaces and line feeds.
+    pub fn characters(&self) -> u32 {
+        self.characters
+    }
+
+    /// Returns the presses of Backspace and Delete.
+    pub fn corrections(&self) -> u32 {
+        self.corrections
+    }
+
+    /// Returns the ratio of corrections to characters typed.
+    pub fn correction_rate(&self) -> f32 {
+        match self.characters {
+            0 => 0.0,
+            x => self.corrections as f32 / x as f32,
+        }
+    }
+
+    /// Returns the time spent typing.
+    pub fn active_time(&self) -> Duration {
+        self.active
+    }
+
+    /// Returns the speed in words of five characters per minute.
+    pub fn wpm(&self) -> f32 {
+        let minutes = self.active.as_secs_f32() / 60.0;
+        if minutes <= 0.0 {
+            return 0.0;
+        }
+        self.characters as f32 / WORD_LENGTH / minutes
+    }
+
+    /// Clear the statistics.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns the statistics in the text format.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "characters {}", self.characters);
+        let _ = writeln!(text, "corrections {}", self.corrections);
+        let _ = writeln!(text, "active_ms {}", self.active.as_millis());
+        for (imprint, count) in self.most_pressed() {
+            let _ = writeln!(text, "{} {}", name(imprint), count);
+        }
+        text
+    }
+
+    /// Parse the text format of [`TypingStats::to_text`].
+    pub fn from_text(text: &str) -> Result<Self> {
+        let mut stats = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parse = || -> Option<(&str, u64)> {
+                let (name, value) = line.split_once(' ')?;
+                Some((name, value.trim().parse().ok()?))
+            };
+            let (name, value) =
+                parse().ok_or_else(|| anyhow!("line {}: invalid entry", number + 1))?;
+            let count = u32::try_from(value).unwrap_or(u32::MAX);
+            match name {
+                "characters" => stats.characters = count,
+                "corrections" => stats.corrections = count,
+                "active_ms" => stats.active = Duration::from_millis(value),
+                _ => {
+                    let imprint = KeyImprint::from_name(name)
+                        .ok_or_else(|| anyhow!("line {}: unknown key {}", number + 1, name))?;
+                    stats.counts.insert(imprint, count);
+                }
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// Returns the name of the key in the text format.
+fn name(imprint: KeyImprint) -> String {
+    format!("{:?}", imprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::KeyType;
+    use crate::keyboard::KeyboardScanner;
+    use anyhow::Result;
+    use proptest::prelude::*;
+
+    struct Keys(Vec<KeyType>);
+
+    impl KeyboardScanner for Keys {
+        fn scan_pressed_keytypes(&mut self) -> Result<Vec<KeyType>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn key(imprint: KeyImprint) -> KeyType {
+        *KEY_MAP
+            .iter()
+            .flatten()
+            .find(|x| x.imprint() == imprint)
+            .unwrap()
+    }
+
+    #[test]
+    fn counts_characters_corrections_and_time() {
+        let mut stats = TypingStats::new();
+        let start = Instant::now();
+        let ms = |x| start + Duration::from_millis(x);
+        stats.record_key(KeyImprint::A, Modified::Graph('a'), ms(0));
+        stats.record_key(KeyImprint::Space, Modified::Space, ms(200));
+        stats.record_key(KeyImprint::Backspace, Modified::Backspace, ms(400));
+        stats.record_key(KeyImprint::A, Modified::Graph('A'), ms(10_000));
+        assert_eq!(stats.count(KeyImprint::A), 2);
+        assert_eq!(stats.total_presses(), 4);
+        assert_eq!(stats.characters(), 3);
+        assert_eq!(stats.corrections(), 1);
+        // the pause longer than IDLE_GAP is not counted
+        assert_eq!(stats.active_time(), Duration::from_millis(400));
+        assert_eq!(stats.most_pressed()[0], (KeyImprint::A, 2));
+    }
+
+    #[test]
+    fn counts_modifier_presses_once() {
+        let mut stats = TypingStats::new();
+        let mut state = KeyboardState::default();
+        let shift = key(KeyImprint::LeftShift);
+        for keys in [
+            vec![shift],
+            vec![shift, key(KeyImprint::A)],
+            vec![],
+            vec![shift],
+        ] {
+            state.update(&mut Keys(keys)).unwrap();
+            stats.record(&state);
+        }
+        assert_eq!(stats.count(KeyImprint::LeftShift), 2);
+        assert_eq!(stats.count(KeyImprint::A), 1);
+        assert_eq!(stats.characters(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_text() {
+        assert!(TypingStats::from_text("characters").is_err());
+        assert!(TypingStats::from_text("characters x").is_err());
+        assert!(TypingStats::from_text("NoSuchKey 1").is_err());
+        assert_eq!(TypingStats::from_text("\n").unwrap(), TypingStats::new());
+    }
+
+    proptest! {
+        #[test]
+        fn text_round_trips(
+            presses in prop::collection::vec((0usize..56, 0u64..8000, any::<bool>()), 0..64),
+        ) {
+            let mut stats = TypingStats::new();
+            let mut at = Instant::now();
+            for (index, gap, character) in presses {
+                let imprint = KEY_MAP.iter().flatten().nth(index).unwrap().imprint();
+                at += Duration::from_millis(gap);
+                let key = if character { Modified::Graph('x') } else { Modified::Delete };
+                stats.record_key(imprint, key, at);
+            }
+            let parsed = TypingStats::from_text(&stats.to_text()).unwrap();
+            prop_assert_eq!(parsed.to_text(), stats.to_text());
+            prop_assert_eq!(parsed.most_pressed(), stats.most_pressed());
+            prop_assert_eq!(parsed.characters(), stats.characters());
+            prop_assert_eq!(parsed.corrections(), stats.corrections());
+            prop_assert_eq!(parsed.active_time(), stats.active_time());
+        }
+    }
+}
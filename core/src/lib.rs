@@ -0,0 +1,28 @@
+//! Hardware-independent logic of the [cardputer] crate
+//!
+//! The key map, the decoding of the keyboard matrix and of the TCA8418
+//! events, the conversion rules, the keyboard state, the HID usage table,
+//! the frame stream format, the color conversions and the text layout do not depend on esp-idf-hal, so they
+//! build and are tested on the host:
+//!
+//! ```text
+//! cd core && cargo test
+//! ```
+//!
+//! The cardputer crate re-exports them in its `keyboard`, `hid`,
+//! `frame_stream`, `color` and `widget` modules, and its REST API parses the requests with
+//! [`json`]. The [`frame_stream::FrameDecoder`] is meant
+//! for desktop programs, which depend on this crate only. The
+//! [`fuzz`] module, enabled by the `fuzzing` feature, drives the decoding
+//! with arbitrary input for fuzzers.
+//!
+//! [cardputer]: https://github.com/syurazo/cardputer
+pub mod color;
+pub mod crc;
+pub mod frame_stream;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+pub mod hid;
+pub mod json;
+pub mod keyboard;
+pub mod widget;
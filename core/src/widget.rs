@@ -0,0 +1,2 @@
+//! Layout of the widgets of the cardputer crate
+pub mod text_layout;
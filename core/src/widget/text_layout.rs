@@ -0,0 +1,468 @@
+//! Layout of styled text in wrapped and aligned lines
+//!
+//! A text is given as [`Span`]s, each with its color, font and emphasis.
+//! [`TextLayout`] breaks it into [`LineBox`]es no wider than the width,
+//! at spaces when possible, and aligns each line to the left, the center
+//! or the right. Line feeds start new lines. Lines of mixed fonts share
+//! the baseline and are as high as their largest font.
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoFont, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    text::{Alignment, Baseline, Text},
+};
+
+/// Style of a span
+#[derive(Debug, Clone, Copy)]
+pub struct SpanStyle {
+    pub color: Rgb565,
+    pub font: &'static MonoFont<'static>,
+    /// Drawn twice, one pixel apart, as the mono fonts have no bold face
+    pub emphasis: bool,
+}
+
+impl SpanStyle {
+    /// Returns the advance of a character.
+    fn char_width(&self) -> u32 {
+        self.font.character_size.width + self.font.character_spacing
+    }
+}
+
+impl Default for SpanStyle {
+    fn default() -> Self {
+        Self {
+            color: Rgb565::WHITE,
+            font: &FONT_6X10,
+            emphasis: false,
+        }
+    }
+}
+
+impl PartialEq for SpanStyle {
+    fn eq(&self, other: &Self) -> bool {
+        self.color == other.color
+            && core::ptr::eq(self.font, other.font)
+            && self.emphasis == other.emphasis
+    }
+}
+
+/// Text in one style
+///
+/// # Examples
+///
+/// ```
+/// use cardputer_core::widget::text_layout::Span;
+///
+/// let spans = [
+///     Span::new("Battery "),
+///     Span::new("low").with_color(Rgb565::RED).with_emphasis(),
+/// ];
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Span<'a> {
+    pub text: &'a str,
+    pub style: SpanStyle,
+}
+
+impl<'a> Span<'a> {
+    /// Create new span in the default style.
+    pub fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            style: SpanStyle::default(),
+        }
+    }
+
+    /// Set the color (white).
+    pub fn with_color(mut self, color: Rgb565) -> Self {
+        self.style.color = color;
+        self
+    }
+
+    /// Set the font (6x10).
+    pub fn with_font(mut self, font: &'static MonoFont<'static>) -> Self {
+        self.style.font = font;
+        self
+    }
+
+    /// Emphasize the text (not emphasized).
+    pub fn with_emphasis(mut self) -> Self {
+        self.style.emphasis = true;
+        self
+    }
+}
+
+/// Part of a line in one style
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fragment {
+    pub text: String,
+    /// Position from the left of the layout
+    pub x: i32,
+    pub style: SpanStyle,
+}
+
+/// Line of text placed by [`TextLayout`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineBox {
+    pub fragments: Vec<Fragment>,
+    /// Position of the top from the top of the layout
+    pub y: i32,
+    /// Width of the text without the trailing spaces
+    pub width: u32,
+    pub height: u32,
+    /// Distance from the top to the baseline
+    pub baseline: u32,
+}
+
+impl LineBox {
+    /// Draw the line with the layout placed at the origin.
+    pub fn draw_at<D>(&self, origin: Point, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        for fragment in &self.fragments {
+            let position = origin + Point::new(fragment.x, self.y + self.baseline as i32);
+            let style = MonoTextStyle::new(fragment.style.font, fragment.style.color);
+            Text::with_baseline(&fragment.text, position, style, Baseline::Alphabetic)
+                .draw(target)?;
+            if fragment.style.emphasis {
+                Text::with_baseline(
+                    &fragment.text,
+                    position + Point::new(1, 0),
+                    style,
+                    Baseline::Alphabetic,
+                )
+                .draw(target)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Line breaking and alignment of spans
+///
+/// # Examples
+///
+/// ```
+/// use cardputer_core::widget::text_layout::{Span, TextLayout};
+/// use embedded_graphics::text::Alignment;
+///
+/// let paragraph = TextLayout::new(200)
+///     .with_alignment(Alignment::Center)
+///     .paragraph(
+///         &[
+///             Span::new("Update available: "),
+///             Span::new("v1.2.0").with_color(Rgb565::GREEN).with_emphasis(),
+///         ],
+///         Point::new(20, 40),
+///     );
+/// paragraph.draw(&mut fb).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextLayout {
+    width: u32,
+    alignment: Alignment,
+    line_spacing: u32,
+}
+
+impl TextLayout {
+    /// Create new layout of the width in pixels.
+    pub fn new(width: u32) -> Self {
+        Self {
+            width,
+            alignment: Alignment::Left,
+            line_spacing: 0,
+        }
+    }
+
+    /// Set the alignment of the lines (left).
+    pub fn with_alignment(mut self, alignment: Alignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Set the space between the lines in pixels (0).
+    pub fn with_line_spacing(mut self, line_spacing: u32) -> Self {
+        self.line_spacing = line_spacing;
+        self
+    }
+
+    /// Returns the width in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Break the spans into lines.
+    ///
+    /// A word wider than the layout is broken between characters. Spaces
+    /// at the start of a wrapped line are dropped; those after a line feed
+    /// are kept for indentation.
+    pub fn layout(&self, spans: &[Span]) -> Vec<LineBox> {
+        let mut builder = Builder {
+            layout: self,
+            lines: Vec::new(),
+            fragments: Vec::new(),
+            x: 0,
+            y: 0,
+            wrapped: false,
+            style: spans.first().map(|x| x.style).unwrap_or_default(),
+        };
+        for span in spans {
+            builder.style = span.style;
+            let mut rest = span.text;
+            while let Some(c) = rest.chars().next() {
+                let end = rest
+                    .find(|x: char| x == '\n' || x.is_whitespace() != c.is_whitespace())
+                    .unwrap_or(rest.len())
+                    .max(c.len_utf8());
+                let (piece, next) = rest.split_at(end);
+                rest = next;
+                if c == '\n' {
+                    builder.wrapped = false;
+                    builder.break_line();
+                } else if c.is_whitespace() {
+                    builder.push_spaces(piece);
+                } else {
+                    builder.push_word(piece);
+                }
+            }
+        }
+        let ends_with_line_feed = spans
+            .iter()
+            .rev()
+            .find(|x| !x.text.is_empty())
+            .is_some_and(|x| x.text.ends_with('\n'));
+        if !builder.fragments.is_empty() || builder.lines.is_empty() || ends_with_line_feed {
+            builder.break_line();
+        }
+        builder.lines
+    }
+
+    /// Lay out the spans with the top left corner at the position.
+    pub fn paragraph(&self, spans: &[Span], top_left: Point) -> Paragraph {
+        Paragraph {
+            lines: self.layout(spans),
+            top_left,
+            width: self.width,
+        }
+    }
+}
+
+/// State of [`TextLayout::layout`]
+struct Builder<'l> {
+    layout: &'l TextLayout,
+    lines: Vec<LineBox>,
+    fragments: Vec<Fragment>,
+    /// Width of the current line
+    x: u32,
+    y: i32,
+    /// The current line continues the previous one
+    wrapped: bool,
+    style: SpanStyle,
+}
+
+impl Builder<'_> {
+    fn push_word(&mut self, word: &str) {
+        let char_width = self.style.char_width().max(1);
+        let mut rest = word;
+        while !rest.is_empty() {
+            let fits = (self.layout.width.saturating_sub(self.x) / char_width) as usize;
+            let count = rest.chars().count();
+            if count <= fits {
+                self.append(rest);
+                return;
+            }
+            if self.x > 0 && (fits == 0 || count as u32 * char_width <= self.layout.width) {
+                // the word fits on the next line, or starts it
+                self.wrap();
+                continue;
+            }
+            // longer than a line: fill this one, at least one character
+            let end = rest
+                .char_indices()
+                .nth(fits.max(1))
+                .map(|(i, _)| i)
+                .unwrap_or(rest.len());
+            let (head, tail) = rest.split_at(end);
+            self.append(head);
+            rest = tail;
+            if !rest.is_empty() {
+                self.wrap();
+            }
+        }
+    }
+
+    fn push_spaces(&mut self, spaces: &str) {
+        if self.wrapped && self.x == 0 {
+            return;
+        }
+        // kept even past the width; they are trimmed at the break
+        self.append(spaces);
+    }
+
+    fn append(&mut self, text: &str) {
+        let width = text.chars().count() as u32 * self.style.char_width();
+        match self.fragments.last_mut() {
+            Some(last) if last.style == self.style => last.text.push_str(text),
+            _ => self.fragments.push(Fragment {
+                text: text.to_string(),
+                x: self.x as i32,
+                style: self.style,
+            }),
+        }
+        self.x += width;
+    }
+
+    fn wrap(&mut self) {
+        self.break_line();
+        self.wrapped = true;
+    }
+
+    fn break_line(&mut self) {
+        let mut fragments = std::mem::take(&mut self.fragments);
+        // trailing spaces take no room
+        while let Some(last) = fragments.last_mut() {
+            let trimmed = last.text.trim_end().len();
+            let removed = last.text[trimmed..].chars().count() as u32;
+            self.x -= removed * last.style.char_width();
+            last.text.truncate(trimmed);
+            if !last.text.is_empty() {
+                break;
+            }
+            fragments.pop();
+        }
+        let width = self.x;
+
+        let fonts = || fragments.iter().map(|x| x.style.font);
+        let baseline = fonts()
+            .map(|x| x.baseline)
+            .max()
+            .unwrap_or(self.style.font.baseline);
+        let descent = fonts()
+            .map(|x| x.character_size.height - x.baseline)
+            .max()
+            .unwrap_or(self.style.font.character_size.height - self.style.font.baseline);
+
+        let shift = match self.layout.alignment {
+            Alignment::Left => 0,
+            Alignment::Center => self.layout.width.saturating_sub(width) / 2,
+            Alignment::Right => self.layout.width.saturating_sub(width),
+        };
+        for fragment in fragments.iter_mut() {
+            fragment.x += shift as i32;
+        }
+
+        let height = baseline + descent;
+        self.lines.push(LineBox {
+            fragments,
+            y: self.y,
+            width,
+            height,
+            baseline,
+        });
+        self.y += (height + self.layout.line_spacing) as i32;
+        self.x = 0;
+    }
+}
+
+/// Laid out text at a position
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paragraph {
+    pub lines: Vec<LineBox>,
+    pub top_left: Point,
+    width: u32,
+}
+
+impl Paragraph {
+    /// Returns the size taken by the lines.
+    pub fn size(&self) -> Size {
+        let height = self
+            .lines
+            .last()
+            .map(|x| x.y as u32 + x.height)
+            .unwrap_or(0);
+        Size::new(self.width, height)
+    }
+}
+
+impl Drawable for Paragraph {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        for line in &self.lines {
+            line.draw_at(self.top_left, target)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::mono_font::ascii::FONT_10X20;
+
+    fn texts(lines: &[LineBox]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|x| x.fragments.iter().map(|x| x.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn wraps_at_spaces() {
+        // 6 pixels per character of the default font: 10 per line
+        let lines = TextLayout::new(60).layout(&[Span::new("the quick brown fox")]);
+        assert_eq!(texts(&lines), ["the quick", "brown fox"]);
+        assert_eq!(lines[0].width, 54);
+        assert_eq!(lines[1].y, lines[0].height as i32);
+    }
+
+    #[test]
+    fn breaks_words_longer_than_a_line() {
+        let lines = TextLayout::new(24).layout(&[Span::new("ab abcdefghij")]);
+        assert_eq!(texts(&lines), ["ab a", "bcde", "fghi", "j"]);
+        let lines = TextLayout::new(3).layout(&[Span::new("ab")]);
+        assert_eq!(texts(&lines), ["a", "b"]);
+    }
+
+    #[test]
+    fn counts_multibyte_characters_once() {
+        let lines = TextLayout::new(24).layout(&[Span::new("éèêë àâ")]);
+        assert_eq!(texts(&lines), ["éèêë", "àâ"]);
+        assert_eq!(lines[0].width, 24);
+        let lines = TextLayout::new(12).layout(&[Span::new("日本語")]);
+        assert_eq!(texts(&lines), ["日本", "語"]);
+    }
+
+    #[test]
+    fn keeps_indentation_after_line_feeds() {
+        let lines = TextLayout::new(60).layout(&[Span::new("a\n  b\n")]);
+        assert_eq!(texts(&lines), ["a", "  b", ""]);
+        assert_eq!(TextLayout::new(60).layout(&[]).len(), 1);
+    }
+
+    #[test]
+    fn aligns_lines() {
+        let spans = [Span::new("ab")];
+        let x = |alignment| {
+            TextLayout::new(60).with_alignment(alignment).layout(&spans)[0].fragments[0].x
+        };
+        assert_eq!(x(Alignment::Left), 0);
+        assert_eq!(x(Alignment::Center), 24);
+        assert_eq!(x(Alignment::Right), 48);
+    }
+
+    #[test]
+    fn mixed_fonts_share_the_baseline() {
+        let spans = [Span::new("a "), Span::new("b").with_font(&FONT_10X20)];
+        let lines = TextLayout::new(100).with_line_spacing(2).layout(&spans);
+        assert_eq!(lines[0].fragments[1].x, 12);
+        assert_eq!(lines[0].baseline, FONT_10X20.baseline);
+        assert_eq!(lines[0].height, FONT_10X20.character_size.height);
+        assert_eq!(lines[0].width, 22);
+    }
+}
@@ -0,0 +1,213 @@
+//! Audio pipeline routing an input to an output through a gain and effects
+//!
+//! The built-in microphone and speaker share GPIO43 (the microphone clock
+//! and the speaker word select), so they cannot be opened together; route
+//! the microphone to another [`AudioSink`] or a [`SignalGenerator`] to the
+//! speaker, or implement the traits for external I2S devices.
+use anyhow::{bail, Result};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::{generator::SignalGenerator, microphone::Microphone, speaker::Speaker};
+
+const DEFAULT_BLOCK_SIZE: usize = 256;
+
+/// Source of 16-bit mono samples
+pub trait AudioSource {
+    fn sample_rate(&self) -> u32;
+    /// Fill the buffer, blocking until the samples are available.
+    fn read(&mut self, samples: &mut [i16]) -> Result<()>;
+}
+
+/// Destination of 16-bit mono samples
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+    /// Queue the samples, blocking while the output is full.
+    fn write(&mut self, samples: &[i16]) -> Result<()>;
+}
+
+impl AudioSource for Microphone<'_> {
+    fn sample_rate(&self) -> u32 {
+        Microphone::sample_rate(self)
+    }
+
+    fn read(&mut self, samples: &mut [i16]) -> Result<()> {
+        Microphone::read(self, samples)
+    }
+}
+
+impl AudioSource for SignalGenerator {
+    fn sample_rate(&self) -> u32 {
+        SignalGenerator::sample_rate(self)
+    }
+
+    fn read(&mut self, samples: &mut [i16]) -> Result<()> {
+        self.fill(samples);
+        Ok(())
+    }
+}
+
+impl AudioSink for Speaker<'_> {
+    fn sample_rate(&self) -> u32 {
+        Speaker::sample_rate(self)
+    }
+
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        self.play(samples)
+    }
+}
+
+/// Effect applied in place to each block of samples
+pub trait Effect: Send {
+    fn process(&mut self, samples: &mut [i16]);
+}
+
+impl<F: FnMut(&mut [i16]) + Send> Effect for F {
+    fn process(&mut self, samples: &mut [i16]) {
+        self(samples)
+    }
+}
+
+/// Echo effect mixing in the signal from a fixed time earlier
+pub struct Delay {
+    buffer: Vec<i16>,
+    position: usize,
+    feedback: f32,
+    mix: f32,
+}
+
+impl Delay {
+    /// Create new delay of the duration at the sample rate, with half feedback and mix.
+    pub fn new(delay: Duration, sample_rate: u32) -> Self {
+        let len = (delay.as_micros() * sample_rate as u128 / 1_000_000).max(1) as usize;
+        Self {
+            buffer: vec![0; len],
+            position: 0,
+            feedback: 0.5,
+            mix: 0.5,
+        }
+    }
+
+    /// Set how much of the delayed signal is fed back, from 0.0 to below 1.0.
+    pub fn with_feedback(mut self, feedback: f32) -> Self {
+        self.feedback = feedback.clamp(0.0, 0.95);
+        self
+    }
+
+    /// Set the level of the delayed signal in the output, from 0.0 to 1.0.
+    pub fn with_mix(mut self, mix: f32) -> Self {
+        self.mix = mix.clamp(0.0, 1.0);
+        self
+    }
+}
+
+impl Effect for Delay {
+    fn process(&mut self, samples: &mut [i16]) {
+        for sample in samples {
+            let delayed = self.buffer[self.position] as f32;
+            let input = *sample as f32;
+            self.buffer[self.position] = (input + delayed * self.feedback) as i16;
+            *sample = (input + delayed * self.mix) as i16;
+            self.position = (self.position + 1) % self.buffer.len();
+        }
+    }
+}
+
+/// Pipeline from an [`AudioSource`] to an [`AudioSink`]
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::audio_pipeline::{AudioPipeline, Delay};
+///
+/// let mut pipeline = AudioPipeline::new()
+///     .with_gain_db(-6.0)
+///     .with_effect(Delay::new(Duration::from_millis(200), speaker.sample_rate()))
+///     .with_effect(|samples: &mut [i16]| samples.iter_mut().for_each(|x| *x = x.clamp(-8000, 8000)));
+/// let stop = AtomicBool::new(false);
+/// pipeline.run(&mut source, &mut speaker, &stop).unwrap();
+/// ```
+pub struct AudioPipeline {
+    gain: f32,
+    block_size: usize,
+    effects: Vec<Box<dyn Effect>>,
+}
+
+impl Default for AudioPipeline {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            block_size: DEFAULT_BLOCK_SIZE,
+            effects: Vec::new(),
+        }
+    }
+}
+
+impl AudioPipeline {
+    /// Create new pipeline at unity gain without effects.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the gain in dB.
+    pub fn with_gain_db(mut self, db: f32) -> Self {
+        self.set_gain_db(db);
+        self
+    }
+
+    /// Set the number of samples moved at a time (256). Smaller blocks
+    /// lower the latency.
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Append an effect, applied after the gain and the previous effects.
+    pub fn with_effect(mut self, effect: impl Effect + 'static) -> Self {
+        self.effects.push(Box::new(effect));
+        self
+    }
+
+    /// Change the gain in dB.
+    pub fn set_gain_db(&mut self, db: f32) {
+        self.gain = 10f32.powf(db / 20.0);
+    }
+
+    /// Apply the gain and the effects to the samples in place.
+    pub fn process(&mut self, samples: &mut [i16]) {
+        if self.gain != 1.0 {
+            for sample in samples.iter_mut() {
+                // the cast saturates
+                *sample = (*sample as f32 * self.gain) as i16;
+            }
+        }
+        for effect in self.effects.iter_mut() {
+            effect.process(samples);
+        }
+    }
+
+    /// Move blocks from the source to the sink until the flag is set.
+    pub fn run<I: AudioSource, O: AudioSink>(
+        &mut self,
+        source: &mut I,
+        sink: &mut O,
+        stop: &AtomicBool,
+    ) -> Result<()> {
+        if source.sample_rate() != sink.sample_rate() {
+            bail!(
+                "sample rates differ: {} Hz and {} Hz",
+                source.sample_rate(),
+                sink.sample_rate()
+            );
+        }
+        let mut block = vec![0i16; self.block_size];
+        while !stop.load(Ordering::Relaxed) {
+            source.read(&mut block)?;
+            self.process(&mut block);
+            sink.write(&block)?;
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,31 @@
 //! LCD backlight controller
+//!
+//! The backlight is either switched on and off through the GPIO, or
+//! dimmed by PWM with an LEDC channel, which also allows ramping the
+//! brightness, e.g. when the display wakes (see
+//! [`display::power`](crate::display::power)).
 use anyhow::Result;
 use esp_idf_hal::{
     gpio::{Gpio38, Level, Output, PinDriver},
+    ledc::{config::TimerConfig, LedcChannel, LedcDriver, LedcTimer, LedcTimerDriver},
     peripheral::Peripheral,
+    prelude::*,
 };
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// PWM frequency, above the audible range
+const PWM_FREQUENCY: u32 = 25_000;
+
+/// Interval between the steps of a ramp
+const RAMP_STEP: Duration = Duration::from_millis(10);
+
+enum Driver<'a> {
+    Switch(PinDriver<'a, Gpio38, Output>),
+    Pwm(LedcDriver<'a>),
+}
 
 /// Backlight controller
 ///
@@ -18,26 +40,142 @@ use esp_idf_hal::{
 /// backlight.on().unwrap();
 /// ```
 pub struct Backlight<'a> {
-    driver: PinDriver<'a, Gpio38, Output>,
+    driver: Driver<'a>,
+    /// Brightness in percent while on
+    brightness: u8,
+    /// Brightness in percent output now
+    level: u8,
 }
 
 impl<'a> Backlight<'a> {
-    /// Create new controller.
+    /// Create new controller switching the backlight on and off.
     pub fn new(gpio: impl Peripheral<P = Gpio38> + 'a) -> Result<Backlight<'a>> {
         let driver = PinDriver::output(gpio)?;
 
-        Ok(Self { driver })
+        Ok(Self {
+            driver: Driver::Switch(driver),
+            brightness: 100,
+            level: 0,
+        })
+    }
+
+    /// Create new controller dimming the backlight with the LEDC timer and
+    /// channel. The backlight is off until turned on.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use cardputer::backlight::Backlight;
+    ///
+    /// let mut backlight = Backlight::with_pwm(
+    ///     peripherals.pins.gpio38,
+    ///     peripherals.ledc.timer0,
+    ///     peripherals.ledc.channel0,
+    /// )
+    /// .unwrap();
+    /// backlight.set_brightness(60).unwrap();
+    /// backlight.ramp_on(Duration::from_millis(300)).unwrap();
+    /// ```
+    pub fn with_pwm<T, C>(
+        gpio: impl Peripheral<P = Gpio38> + 'a,
+        timer: impl Peripheral<P = T> + 'a,
+        channel: impl Peripheral<P = C> + 'a,
+    ) -> Result<Backlight<'a>>
+    where
+        T: LedcTimer,
+        C: LedcChannel,
+    {
+        let timer = LedcTimerDriver::new(timer, &TimerConfig::new().frequency(PWM_FREQUENCY.Hz()))?;
+        let mut driver = LedcDriver::new(channel, timer, gpio)?;
+        driver.set_duty(0)?;
+
+        Ok(Self {
+            driver: Driver::Pwm(driver),
+            brightness: 100,
+            level: 0,
+        })
     }
 
     /// Turn on the backlight.
     pub fn on(&mut self) -> Result<()> {
-        self.driver.set_level(Level::High)?;
-        Ok(())
+        self.output(self.brightness)
     }
 
     /// Turn off the backlight.
     pub fn off(&mut self) -> Result<()> {
-        self.driver.set_level(Level::Low)?;
+        self.output(0)
+    }
+
+    /// Returns true while the backlight is on.
+    pub fn is_on(&self) -> bool {
+        self.level > 0
+    }
+
+    /// Returns true if the brightness can be changed.
+    pub fn is_dimmable(&self) -> bool {
+        matches!(self.driver, Driver::Pwm(_))
+    }
+
+    /// Returns the brightness in percent while on.
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Set the brightness in percent, from 1 to 100, while on (100).
+    /// Applied at once if the backlight is on. Without PWM, any brightness
+    /// is full.
+    pub fn set_brightness(&mut self, percent: u8) -> Result<()> {
+        self.brightness = percent.clamp(1, 100);
+        if self.is_on() {
+            self.output(self.brightness)?;
+        }
+        Ok(())
+    }
+
+    /// Change the output gradually to the brightness in percent over the
+    /// duration, blocking until done. Without PWM, switches at once.
+    pub fn ramp_to(&mut self, percent: u8, duration: Duration) -> Result<()> {
+        let target = percent.min(100);
+        if !self.is_dimmable() {
+            return self.output(target);
+        }
+        let from = self.level as f32;
+        let started = Instant::now();
+        loop {
+            let progress = match duration.as_secs_f32() {
+                x if x > 0.0 => (started.elapsed().as_secs_f32() / x).min(1.0),
+                _ => 1.0,
+            };
+            self.output((from + (target as f32 - from) * progress).round() as u8)?;
+            if progress >= 1.0 {
+                return Ok(());
+            }
+            thread::sleep(RAMP_STEP);
+        }
+    }
+
+    /// Turn on gradually over the duration.
+    pub fn ramp_on(&mut self, duration: Duration) -> Result<()> {
+        self.ramp_to(self.brightness, duration)
+    }
+
+    /// Turn off gradually over the duration.
+    pub fn ramp_off(&mut self, duration: Duration) -> Result<()> {
+        self.ramp_to(0, duration)
+    }
+
+    fn output(&mut self, percent: u8) -> Result<()> {
+        match &mut self.driver {
+            Driver::Switch(x) => {
+                x.set_level(if percent > 0 { Level::High } else { Level::Low })?;
+            }
+            Driver::Pwm(x) => {
+                // perceived brightness is closer to linear with a square curve
+                let duty = x.get_max_duty() as u64 * (percent as u64).pow(2) / 10_000;
+                x.set_duty(duty as u32)?;
+            }
+        }
+        self.level = percent;
         Ok(())
     }
 }
@@ -0,0 +1,12 @@
+//! Bluetooth LE peripheral modes (`ble` feature)
+//!
+//! Built on the NimBLE host of ESP-IDF, which has to be enabled in the
+//! sdkconfig of the application:
+//!
+//! ```text
+//! CONFIG_BT_ENABLED=y
+//! CONFIG_BT_BLUEDROID_ENABLED=n
+//! CONFIG_BT_NIMBLE_ENABLED=y
+//! ```
+pub mod hid;
+pub mod nus;
@@ -0,0 +1,159 @@
+//! HID over GATT: the Cardputer as a Bluetooth keyboard with media keys
+use anyhow::{anyhow, Result};
+use esp32_nimble::{
+    enums::{AuthReq, SecurityIOCap},
+    utilities::mutex::Mutex,
+    BLECharacteristic, BLEDevice, BLEHIDDevice,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::hid::{
+    bridge::KeyboardOutput, consumer_report, keyboard_report, ConsumerUsage, CONSUMER_REPORT_ID,
+    KEYBOARD_REPORT_ID, REPORT_DESCRIPTOR,
+};
+use crate::media::MediaKey;
+
+/// GAP appearance of a keyboard
+const APPEARANCE_KEYBOARD: u16 = 0x03C1;
+/// Espressif vendor ID, USB-IF source
+const VENDOR_ID: u16 = 0x303A;
+const PRODUCT_ID: u16 = 0x8001;
+const VERSION: u16 = 0x0100;
+
+/// HID keyboard peripheral
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{ble::hid::BleHid, media::MediaShortcuts};
+///
+/// let hid = BleHid::start("Cardputer").unwrap();
+/// let mut media = MediaShortcuts::new().forward_to(hid.media_hook());
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     let keys = media.process(keyboard_state.pressed_keys());
+/// }
+/// ```
+pub struct BleHid {
+    keyboard: Arc<Mutex<BLECharacteristic>>,
+    consumer: Arc<Mutex<BLECharacteristic>>,
+    is_connected: Arc<AtomicBool>,
+}
+
+impl BleHid {
+    /// Register the HID service and start advertising with the device name.
+    ///
+    /// The host pairs with "Just Works" bonding.
+    pub fn start(name: &str) -> Result<Self> {
+        let device = BLEDevice::take();
+        BLEDevice::set_device_name(name).map_err(|e| anyhow!("{:?}", e))?;
+        device
+            .security()
+            .set_auth(AuthReq::all())
+            .set_io_cap(SecurityIOCap::NoInputNoOutput);
+
+        let is_connected = Arc::new(AtomicBool::new(false));
+        let server = device.get_server();
+        {
+            let is_connected = is_connected.clone();
+            server.on_connect(move |_, _| is_connected.store(true, Ordering::Relaxed));
+        }
+        {
+            let is_connected = is_connected.clone();
+            server.on_disconnect(move |_, _| is_connected.store(false, Ordering::Relaxed));
+        }
+
+        let mut hid = BLEHIDDevice::new(server);
+        hid.manufacturer("M5Stack");
+        hid.pnp(0x02, VENDOR_ID, PRODUCT_ID, VERSION);
+        hid.hid_info(0x00, 0x01);
+        hid.report_map(REPORT_DESCRIPTOR);
+        hid.set_battery_level(100);
+        let keyboard = hid.input_report(KEYBOARD_REPORT_ID);
+        let consumer = hid.input_report(CONSUMER_REPORT_ID);
+
+        device
+            .get_advertising()
+            .name(name)
+            .appearance(APPEARANCE_KEYBOARD)
+            .add_service_uuid(hid.hid_service().lock().uuid())
+            .scan_response(false)
+            .start()
+            .map_err(|e| anyhow!("{:?}", e))?;
+
+        Ok(Self {
+            keyboard,
+            consumer,
+            is_connected,
+        })
+    }
+
+    /// Returns true if a host is connected.
+    pub fn is_connected(&self) -> bool {
+        self.is_connected.load(Ordering::Relaxed)
+    }
+
+    /// Send a keyboard report: modifier bits and up to six key usages, as
+    /// returned by the [usage table](crate::hid::key_usage).
+    /// Dropped when no host is connected.
+    pub fn send_keyboard(&mut self, modifiers: u8, keys: &[u8]) -> Result<()> {
+        if keys.len() > 6 {
+            return Err(anyhow!("too many keys: {}", keys.len()));
+        }
+        if !self.is_connected() {
+            return Ok(());
+        }
+        self.keyboard
+            .lock()
+            .set_value(&keyboard_report(modifiers, keys))
+            .notify();
+        Ok(())
+    }
+
+    /// Press the consumer usage, or release it with `None`.
+    pub fn send_consumer(&mut self, usage: Option<ConsumerUsage>) {
+        send_consumer(&self.consumer, &self.is_connected, usage);
+    }
+
+    /// Press and release the consumer usage.
+    pub fn click_consumer(&mut self, usage: ConsumerUsage) {
+        self.send_consumer(Some(usage));
+        self.send_consumer(None);
+    }
+
+    /// Returns a hook for [`MediaShortcuts::forward_to`](crate::media::MediaShortcuts::forward_to)
+    /// that sends the media keys as consumer-control clicks.
+    pub fn media_hook(&self) -> impl FnMut(MediaKey) + Send + 'static {
+        let consumer = self.consumer.clone();
+        let is_connected = self.is_connected.clone();
+        move |key| {
+            send_consumer(&consumer, &is_connected, Some(key.into()));
+            send_consumer(&consumer, &is_connected, None);
+        }
+    }
+}
+
+impl KeyboardOutput for BleHid {
+    fn send_report(&mut self, report: &[u8; 8]) -> Result<bool> {
+        if self.is_connected() {
+            self.keyboard.lock().set_value(report).notify();
+        }
+        Ok(true)
+    }
+}
+
+fn send_consumer(
+    characteristic: &Mutex<BLECharacteristic>,
+    is_connected: &AtomicBool,
+    usage: Option<ConsumerUsage>,
+) {
+    if is_connected.load(Ordering::Relaxed) {
+        characteristic
+            .lock()
+            .set_value(&consumer_report(usage))
+            .notify();
+    }
+}
@@ -0,0 +1,146 @@
+//! Nordic UART Service: a serial terminal over BLE
+//!
+//! Phone apps such as nRF Connect or Serial Bluetooth Terminal connect to
+//! the service and act as a remote terminal: typed keys are sent to the
+//! phone and text from the phone is received as lines.
+use anyhow::{anyhow, Result};
+use esp32_nimble::{
+    utilities::mutex::Mutex, uuid128, BLECharacteristic, BLEDevice, NimbleProperties,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+};
+
+use crate::keyboard::{KeyboardState, Modified};
+
+/// Payload of a notification at the default MTU
+const CHUNK_SIZE: usize = 20;
+
+/// NUS peripheral
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::ble::nus::NusTerminal;
+///
+/// let mut terminal = NusTerminal::start("Cardputer").unwrap();
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     terminal.send_keys(&keyboard_state).unwrap();
+///     while let Some(line) = terminal.read_line() {
+///         log::info!("phone: {}", line);
+///     }
+/// }
+/// ```
+pub struct NusTerminal {
+    tx: Arc<Mutex<BLECharacteristic>>,
+    received: mpsc::Receiver<Vec<u8>>,
+    is_connected: Arc<AtomicBool>,
+    /// Received bytes not yet returned as a line
+    pending: Vec<u8>,
+}
+
+impl NusTerminal {
+    /// Register the service and start advertising with the device name.
+    pub fn start(name: &str) -> Result<Self> {
+        let device = BLEDevice::take();
+        BLEDevice::set_device_name(name).map_err(|e| anyhow!("{:?}", e))?;
+
+        let is_connected = Arc::new(AtomicBool::new(false));
+        let server = device.get_server();
+        {
+            let is_connected = is_connected.clone();
+            server.on_connect(move |_, _| is_connected.store(true, Ordering::Relaxed));
+        }
+        {
+            let is_connected = is_connected.clone();
+            server.on_disconnect(move |_, _| is_connected.store(false, Ordering::Relaxed));
+        }
+
+        let service_uuid = uuid128!("6E400001-B5A3-F393-E0A9-E50E24DCCA9E");
+        let service = server.create_service(service_uuid);
+        let (sender, received) = mpsc::channel();
+        service
+            .lock()
+            .create_characteristic(
+                uuid128!("6E400002-B5A3-F393-E0A9-E50E24DCCA9E"),
+                NimbleProperties::WRITE | NimbleProperties::WRITE_NO_RSP,
+            )
+            .lock()
+            .on_write(move |args| {
+                let _ = sender.send(args.recv_data.to_vec());
+            });
+        let tx = service.lock().create_characteristic(
+            uuid128!("6E400003-B5A3-F393-E0A9-E50E24DCCA9E"),
+            NimbleProperties::NOTIFY,
+        );
+
+        device
+            .get_advertising()
+            .name(name)
+            .add_service_uuid(service_uuid)
+            .start()
+            .map_err(|e| anyhow!("{:?}", e))?;
+
+        Ok(Self {
+            tx,
+            received,
+            is_connected,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Returns true if a central is connected.
+    pub fn is_connected(&self) -> bool {
+        self.is_connected.load(Ordering::Relaxed)
+    }
+
+    /// Send the bytes. Dropped when no central is connected.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        if !self.is_connected() {
+            return Ok(());
+        }
+        for chunk in data.chunks(CHUNK_SIZE) {
+            self.tx.lock().set_value(chunk).notify();
+        }
+        Ok(())
+    }
+
+    /// Send the text.
+    pub fn write_str(&mut self, text: &str) -> Result<()> {
+        self.write(text.as_bytes())
+    }
+
+    /// Send the keys pressed in the last update as ASCII, like a terminal keyboard.
+    pub fn send_keys(&mut self, state: &KeyboardState) -> Result<()> {
+        let keys: Vec<u8> = state
+            .pressed_keys()
+            .iter()
+            .filter_map(Modified::to_ascii)
+            .collect();
+        self.write(&keys)
+    }
+
+    /// Returns the bytes received since the last call, including those of
+    /// incomplete lines.
+    pub fn read(&mut self) -> Vec<u8> {
+        self.receive();
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Returns the next complete line without the line ending.
+    pub fn read_line(&mut self) -> Option<String> {
+        self.receive();
+        let end = self.pending.iter().position(|x| *x == b'\n')?;
+        let line: Vec<u8> = self.pending.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line);
+        Some(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    fn receive(&mut self) {
+        while let Ok(data) = self.received.try_recv() {
+            self.pending.extend(data);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Board facade over the hardware variants
+//!
+//! The original Cardputer scans its keyboard through a 74HC138 decoder on
+//! GPIO, while the Cardputer ADV uses a TCA8418 keypad controller on I2C
+//! (GPIO8/9, the same pins). [`Board`] creates the display, backlight,
+//! keyboard and battery monitor with the pins and drivers of the
+//! [`Variant`], so one application can target both boards. Both variants
+//! measure the battery through the divider on GPIO10.
+//!
+//! [`Board::detect`] probes the hardware at boot instead, so one flashed
+//! image runs on either board.
+//!
+//! The display calibration saved in the store passed to the constructors
+//! is applied when the display is created.
+//!
+//! [`Board::into_shared`] wraps the devices in [`BoardCell`]s, so several
+//! threads can borrow the display, the backlight or the audio devices in
+//! turn instead of moving them all into one closure.
+use anyhow::Result;
+use esp_idf_hal::{
+    delay::TickType,
+    gpio::{
+        Gpio0, Gpio1, Gpio12, Gpio14, Gpio2, Gpio21, Gpio39, Gpio40, Gpio41, Gpio42, Gpio43,
+        Gpio46, InputPin, OutputPin,
+    },
+    i2c::{I2c, I2cConfig, I2cDriver, I2C0},
+    i2s::I2S0,
+    peripheral::Peripheral,
+    peripherals::Peripherals,
+    prelude::*,
+    rmt::CHANNEL0,
+    spi::SPI3,
+};
+use std::{
+    sync::{Arc, Mutex, MutexGuard, TryLockError},
+    time::Duration,
+};
+
+use crate::backlight::Backlight;
+use crate::display::{self, DisplayDriver};
+use crate::keyboard::{
+    tca8418::{Tca8418Config, Tca8418Keyboard, TimeoutI2c},
+    KeyType, Keyboard, KeyboardScanner,
+};
+use crate::power::PowerMonitor;
+use crate::storage::Store;
+
+/// Time the keypad controller of the ADV is given to acknowledge the probe
+const PROBE_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Hardware variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// Original Cardputer with the decoder-scanned keyboard
+    Cardputer,
+    /// Cardputer ADV with the TCA8418 keypad controller
+    CardputerAdv,
+}
+
+impl Variant {
+    /// Returns the product name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::Cardputer => "Cardputer",
+            Variant::CardputerAdv => "Cardputer ADV",
+        }
+    }
+
+    /// Probe the internal I2C bus (SDA GPIO8, SCL GPIO9) for the keypad
+    /// controller of the ADV. On the original Cardputer these pins drive
+    /// the decoder, so nothing acknowledges; a bus held low times out
+    /// instead of blocking.
+    ///
+    /// Pass the peripherals by reference to create the devices afterwards.
+    /// Neither board has a power management chip to probe; both measure
+    /// the battery through the same ADC divider.
+    pub fn detect<I2C: I2c>(
+        i2c: impl Peripheral<P = I2C>,
+        sda: impl Peripheral<P = impl InputPin + OutputPin>,
+        scl: impl Peripheral<P = impl InputPin + OutputPin>,
+    ) -> Result<Self> {
+        let config = I2cConfig::new().baudrate(100.kHz().into());
+        let mut driver = I2cDriver::new(i2c, sda, scl, &config)?;
+        let address = Tca8418Keyboard::<I2cDriver>::ADDRESS;
+        Ok(
+            if driver
+                .write(address, &[], TickType::from(PROBE_TIMEOUT).ticks())
+                .is_ok()
+            {
+                Variant::CardputerAdv
+            } else {
+                Variant::Cardputer
+            },
+        )
+    }
+}
+
+/// Keyboard driver of the variant
+pub enum BoardKeyboard<'a> {
+    Matrix(Keyboard<'a>),
+    Tca8418(Tca8418Keyboard<TimeoutI2c<'a>>),
+}
+
+impl KeyboardScanner for BoardKeyboard<'_> {
+    fn scan_pressed_keytypes(&mut self) -> Result<Vec<KeyType>> {
+        match self {
+            BoardKeyboard::Matrix(x) => x.scan_pressed_keytypes(),
+            BoardKeyboard::Tca8418(x) => x.scan_pressed_keytypes(),
+        }
+    }
+}
+
+/// Peripherals not used by the [`Board`], for the other modules
+pub struct Spare {
+    /// G0 button
+    pub button: Gpio0,
+    /// Grove port: I2C controller, SDA and SCL
+    pub grove: (I2C0, Gpio2, Gpio1),
+    /// SD card slot: SPI host, SCK, MISO, MOSI and CS
+    pub sd: (SPI3, Gpio40, Gpio39, Gpio14, Gpio12),
+    /// I2S of the speaker and the microphone, and GPIO41, 42, 43 and 46
+    pub audio: (I2S0, Gpio41, Gpio42, Gpio43, Gpio46),
+    /// RMT channel and GPIO of the status LED
+    pub led: (CHANNEL0, Gpio21),
+}
+
+/// Devices of the board
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::board::Board;
+/// use cardputer::keyboard::KeyboardState;
+///
+/// let peripherals = Peripherals::take().unwrap();
+/// let mut board = Board::detect(peripherals, &store).unwrap();
+/// println!("running on {}", board.variant.name());
+/// board.backlight.on().unwrap();
+///
+/// let mut keyboard_state = KeyboardState::default();
+/// loop {
+///     keyboard_state.update(&mut board.keyboard).unwrap();
+///     // draw on board.display
+/// }
+/// ```
+pub struct Board {
+    pub variant: Variant,
+    pub display: DisplayDriver<'static>,
+    pub backlight: Backlight<'static>,
+    pub keyboard: BoardKeyboard<'static>,
+    pub power: PowerMonitor<'static>,
+    pub spare: Spare,
+}
+
+impl Board {
+    /// Detect the variant and create its devices.
+    pub fn detect(peripherals: Peripherals, store: &impl Store) -> Result<Self> {
+        Self::detect_with_config(peripherals, &Tca8418Config::default(), store)
+    }
+
+    /// Detect the variant and create its devices, with the bus settings
+    /// of the keypad controller of the ADV.
+    pub fn detect_with_config(
+        mut peripherals: Peripherals,
+        config: &Tca8418Config,
+        store: &impl Store,
+    ) -> Result<Self> {
+        let variant = Variant::detect(
+            &mut peripherals.i2c1,
+            &mut peripherals.pins.gpio8,
+            &mut peripherals.pins.gpio9,
+        )?;
+        Self::new_with_config(peripherals, variant, config, store)
+    }
+
+    /// Create the devices of the variant.
+    pub fn new(peripherals: Peripherals, variant: Variant, store: &impl Store) -> Result<Self> {
+        Self::new_with_config(peripherals, variant, &Tca8418Config::default(), store)
+    }
+
+    /// Create the devices of the variant, with the bus settings of the
+    /// keypad controller of the ADV.
+    pub fn new_with_config(
+        peripherals: Peripherals,
+        variant: Variant,
+        config: &Tca8418Config,
+        store: &impl Store,
+    ) -> Result<Self> {
+        let pins = peripherals.pins;
+        let display = display::build(
+            peripherals.spi2,
+            pins.gpio36,
+            pins.gpio35,
+            pins.gpio37,
+            pins.gpio34,
+            pins.gpio33,
+            store,
+        )?;
+        let backlight = Backlight::with_pwm(
+            pins.gpio38,
+            peripherals.ledc.timer0,
+            peripherals.ledc.channel0,
+        )?;
+        let keyboard = match variant {
+            Variant::Cardputer => BoardKeyboard::Matrix(Keyboard::new(
+                pins.gpio8,
+                pins.gpio9,
+                pins.gpio11,
+                pins.gpio13,
+                pins.gpio15,
+                pins.gpio3,
+                pins.gpio4,
+                pins.gpio5,
+                pins.gpio6,
+                pins.gpio7,
+            )?),
+            Variant::CardputerAdv => BoardKeyboard::Tca8418(Tca8418Keyboard::with_config(
+                peripherals.i2c1,
+                pins.gpio8,
+                pins.gpio9,
+                config,
+            )?),
+        };
+        let power = PowerMonitor::new(peripherals.adc1, pins.gpio10)?;
+        Ok(Self {
+            variant,
+            display,
+            backlight,
+            keyboard,
+            power,
+            spare: Spare {
+                button: pins.gpio0,
+                grove: (peripherals.i2c0, pins.gpio2, pins.gpio1),
+                sd: (
+                    peripherals.spi3,
+                    pins.gpio40,
+                    pins.gpio39,
+                    pins.gpio14,
+                    pins.gpio12,
+                ),
+                audio: (
+                    peripherals.i2s0,
+                    pins.gpio41,
+                    pins.gpio42,
+                    pins.gpio43,
+                    pins.gpio46,
+                ),
+                led: (peripherals.rmt.channel0, pins.gpio21),
+            },
+        })
+    }
+
+    /// Wrap the devices for sharing between threads. The keyboard stays
+    /// owned, as one task usually scans it.
+    pub fn into_shared(self) -> SharedBoard {
+        SharedBoard {
+            variant: self.variant,
+            display: BoardCell::new(self.display),
+            backlight: BoardCell::new(self.backlight),
+            keyboard: self.keyboard,
+            power: BoardCell::new(self.power),
+            spare: self.spare,
+        }
+    }
+}
+
+/// Device shared between threads
+///
+/// Clones refer to the same device. A thread holds the device while the
+/// guard of [`BoardCell::lock`] lives, or for the closure of
+/// [`BoardCell::with`]; the others wait. A panic while holding the device
+/// does not make it unusable.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::board::{Board, BoardCell};
+/// use cardputer::speaker::Speaker;
+///
+/// let board = Board::detect(Peripherals::take().unwrap(), &store).unwrap().into_shared();
+/// let (i2s, bclk, dout, ws, _) = board.spare.audio;
+/// let speaker = BoardCell::new(Speaker::new(i2s, bclk, ws, dout, 16000).unwrap());
+///
+/// let backlight = board.backlight.clone();
+/// thread::spawn(move || loop {
+///     thread::sleep(Duration::from_secs(30));
+///     backlight.with(|x| x.off()).unwrap();
+/// });
+///
+/// let display = board.display.clone();
+/// thread::spawn(move || loop {
+///     fb.flush(&mut *display.lock()).unwrap();
+///     thread::sleep(Duration::from_millis(50));
+/// });
+/// ```
+pub struct BoardCell<T> {
+    device: Arc<Mutex<T>>,
+}
+
+impl<T> BoardCell<T> {
+    /// Wrap the device.
+    pub fn new(device: T) -> Self {
+        Self {
+            device: Arc::new(Mutex::new(device)),
+        }
+    }
+
+    /// Borrow the device until the guard is dropped, waiting while another
+    /// thread holds it.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.device.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Borrow the device if no other thread holds it.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        match self.device.try_lock() {
+            Ok(x) => Some(x),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// Run the closure with the device borrowed.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.lock())
+    }
+}
+
+impl<T> Clone for BoardCell<T> {
+    fn clone(&self) -> Self {
+        Self {
+            device: self.device.clone(),
+        }
+    }
+}
+
+/// Devices of the board shared between threads, created by
+/// [`Board::into_shared`]
+pub struct SharedBoard {
+    pub variant: Variant,
+    pub display: BoardCell<DisplayDriver<'static>>,
+    pub backlight: BoardCell<Backlight<'static>>,
+    pub keyboard: BoardKeyboard<'static>,
+    pub power: BoardCell<PowerMonitor<'static>>,
+    pub spare: Spare,
+}
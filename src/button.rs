@@ -0,0 +1,138 @@
+//! Front G0 button (GPIO0) with click, double-click and long-press gestures
+use anyhow::Result;
+use esp_idf_hal::{
+    gpio::{Gpio0, Input, PinDriver, Pull},
+    peripheral::Peripheral,
+};
+use std::time::{Duration, Instant};
+
+const DEBOUNCE: Duration = Duration::from_millis(20);
+const DOUBLE_CLICK: Duration = Duration::from_millis(300);
+const LONG_PRESS: Duration = Duration::from_millis(800);
+
+/// Gesture of the button
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEvent {
+    /// Pressed and released once, reported after the double-click interval
+    Click,
+    DoubleClick,
+    /// Held for the long-press time, reported while still held
+    LongPress,
+}
+
+/// G0 button driver
+///
+/// Call [`update`](Self::update) periodically like
+/// [`KeyboardState::update`](crate::keyboard::KeyboardState::update), e.g.
+/// from the same timer, and send the events through the same channel as
+/// the keys.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::button::{Button, ButtonEvent};
+///
+/// let mut button = Button::new(peripherals.pins.gpio0).unwrap();
+/// loop {
+///     for event in button.update().unwrap() {
+///         match event {
+///             ButtonEvent::Click => { /* back */ }
+///             ButtonEvent::DoubleClick => { /* home */ }
+///             ButtonEvent::LongPress => { /* menu */ }
+///         }
+///     }
+///     thread::sleep(Duration::from_millis(10));
+/// }
+/// ```
+pub struct Button<'a> {
+    pin: PinDriver<'a, Gpio0, Input>,
+    double_click: Duration,
+    long_press: Duration,
+    /// Debounced state
+    is_pressed: bool,
+    /// Raw state and when it last changed
+    raw: (bool, Instant),
+    pressed_at: Instant,
+    is_long_pressed: bool,
+    pending_click: Option<Instant>,
+}
+
+impl<'a> Button<'a> {
+    /// Create new driver.
+    pub fn new(gpio: impl Peripheral<P = Gpio0> + 'a) -> Result<Self> {
+        let mut pin = PinDriver::input(gpio)?;
+        pin.set_pull(Pull::Up)?;
+        let now = Instant::now();
+        Ok(Self {
+            pin,
+            double_click: DOUBLE_CLICK,
+            long_press: LONG_PRESS,
+            is_pressed: false,
+            raw: (false, now),
+            pressed_at: now,
+            is_long_pressed: false,
+            pending_click: None,
+        })
+    }
+
+    /// Set the maximum interval between the clicks of a double-click (300 ms).
+    ///
+    /// A single click is reported this long after the release.
+    pub fn with_double_click(mut self, interval: Duration) -> Self {
+        self.double_click = interval;
+        self
+    }
+
+    /// Set the hold time of a long press (800 ms).
+    pub fn with_long_press(mut self, time: Duration) -> Self {
+        self.long_press = time;
+        self
+    }
+
+    /// Returns true if the button is held down (debounced).
+    pub fn is_pressed(&self) -> bool {
+        self.is_pressed
+    }
+
+    /// Read the button and return the recognized gestures.
+    pub fn update(&mut self) -> Result<Vec<ButtonEvent>> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+
+        let raw = self.pin.is_low();
+        if raw != self.raw.0 {
+            self.raw = (raw, now);
+        }
+        if raw != self.is_pressed && now.duration_since(self.raw.1) >= DEBOUNCE {
+            self.is_pressed = raw;
+            if raw {
+                self.pressed_at = now;
+                self.is_long_pressed = false;
+            } else if !self.is_long_pressed {
+                match self.pending_click.take() {
+                    Some(_) => events.push(ButtonEvent::DoubleClick),
+                    None => self.pending_click = Some(now),
+                }
+            }
+        }
+
+        if self.is_pressed
+            && !self.is_long_pressed
+            && now.duration_since(self.pressed_at) >= self.long_press
+        {
+            self.is_long_pressed = true;
+            if self.pending_click.take().is_some() {
+                events.push(ButtonEvent::Click);
+            }
+            events.push(ButtonEvent::LongPress);
+        }
+        if self.pending_click.is_some_and(|released| {
+            !self.is_pressed && now.duration_since(released) > self.double_click
+        }) {
+            self.pending_click = None;
+            events.push(ButtonEvent::Click);
+        }
+
+        Ok(events)
+    }
+}
@@ -0,0 +1,351 @@
+//! Peer-to-peer text chat over ESP-NOW
+//!
+//! Devices announce their nickname by broadcast, so peers are found
+//! without pairing. Messages are sent to each known peer and resent until
+//! acknowledged. Each packet carries a random session ID chosen at start,
+//! so the message IDs reused by a rebooted peer are not taken for copies.
+//!
+//! WiFi has to be started in station mode on the same channel on all
+//! devices; a connection to an access point is not needed.
+use anyhow::{bail, Result};
+use esp_idf_svc::espnow::{EspNow, PeerInfo, BROADCAST};
+use std::{
+    collections::VecDeque,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+/// MAC address of a device
+pub type Mac = [u8; 6];
+
+/// Longest message in bytes, the ESP-NOW payload limit less the header
+pub const MAX_TEXT_LEN: usize = 250 - HEADER_LEN;
+
+const MAGIC: [u8; 2] = *b"CC";
+const HEADER_LEN: usize = 7;
+const HELLO: u8 = 0;
+const MESSAGE: u8 = 1;
+const ACK: u8 = 2;
+
+const HELLO_INTERVAL: Duration = Duration::from_secs(2);
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_RETRIES: u32 = 3;
+/// Messages remembered to drop the copies resent after a lost ACK
+const RECENT_SIZE: usize = 32;
+
+/// Device found on the air
+#[derive(Debug, Clone, PartialEq)]
+pub struct Peer {
+    pub mac: Mac,
+    pub name: String,
+    pub last_seen: Instant,
+}
+
+/// Event returned by [`Chat::poll`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatEvent {
+    PeerJoined(Peer),
+    /// Not heard from for 10 seconds
+    PeerLeft(Peer),
+    Message {
+        from: Peer,
+        text: String,
+    },
+    /// The message with the ID returned by [`Chat::send`] was acknowledged
+    Delivered {
+        id: u16,
+        to: Peer,
+    },
+    /// The message was not acknowledged after the retries, or the peer
+    /// left before acknowledging it
+    Failed {
+        id: u16,
+        to: Peer,
+    },
+    /// Sending a packet or registering a peer failed; the chat goes on
+    Error(String),
+}
+
+struct Pending {
+    id: u16,
+    mac: Mac,
+    packet: Vec<u8>,
+    sent: Instant,
+    retries: u32,
+}
+
+/// Chat endpoint
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{chat::{Chat, ChatEvent}, widget::{console::Console, line_editor::LineEditor}};
+///
+/// // start WiFi in station mode first
+/// let mut chat = Chat::start("alice").unwrap();
+/// let mut console = Console::new(Rectangle::new(Point::zero(), Size::new(240, 120)));
+/// let mut editor = LineEditor::new(Point::new(0, 122), 240);
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     if let Some(line) = editor.update(&keyboard_state) {
+///         chat.send(&line).unwrap();
+///         console.push_line(&format!("me: {}", line));
+///     }
+///     for event in chat.poll().unwrap() {
+///         match event {
+///             ChatEvent::Message { from, text } => {
+///                 console.push_line(&format!("{}: {}", from.name, text))
+///             }
+///             ChatEvent::Failed { to, .. } => {
+///                 console.push_colored_line(&format!("not delivered to {}", to.name), Rgb565::RED)
+///             }
+///             _ => {}
+///         }
+///     }
+///     console.draw(&mut fb).unwrap();
+///     editor.draw(&mut fb).unwrap();
+///     fb.flush(&mut display).unwrap();
+/// }
+/// ```
+pub struct Chat<'a> {
+    espnow: EspNow<'a>,
+    name: String,
+    received: mpsc::Receiver<(Mac, Vec<u8>)>,
+    peers: Vec<Peer>,
+    pending: Vec<Pending>,
+    /// Sender, session and ID of the messages received lately
+    recent: VecDeque<(Mac, u16, u16)>,
+    session: u16,
+    next_id: u16,
+    last_hello: Option<Instant>,
+}
+
+impl<'a> Chat<'a> {
+    /// Start ESP-NOW and announce the nickname.
+    pub fn start(name: &str) -> Result<Self> {
+        let espnow = EspNow::take()?;
+        espnow.add_peer(PeerInfo {
+            peer_addr: BROADCAST,
+            ..Default::default()
+        })?;
+        let (sender, received) = mpsc::channel();
+        espnow.register_recv_cb(move |mac: &[u8], data: &[u8]| {
+            if let Ok(mac) = mac.try_into() {
+                let _ = sender.send((mac, data.to_vec()));
+            }
+        })?;
+
+        Ok(Self {
+            espnow,
+            name: name.to_string(),
+            received,
+            peers: Vec::new(),
+            pending: Vec::new(),
+            recent: VecDeque::new(),
+            // SAFETY: esp_random has no preconditions; it is random once
+            // the radio is on, which EspNow::take requires
+            session: unsafe { esp_idf_hal::sys::esp_random() } as u16,
+            next_id: 0,
+            last_hello: None,
+        })
+    }
+
+    /// Returns the nickname.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the peers heard from recently.
+    pub fn peers(&self) -> &[Peer] {
+        &self.peers
+    }
+
+    /// Send the text to all peers and return the message ID used in the
+    /// delivery events.
+    pub fn send(&mut self, text: &str) -> Result<u16> {
+        if text.len() > MAX_TEXT_LEN {
+            bail!("message too long: {} bytes", text.len());
+        }
+        if self.peers.is_empty() {
+            bail!("no peers");
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let packet = packet(MESSAGE, self.session, id, text.as_bytes());
+        for peer in self.peers.iter() {
+            // a lost packet is resent by poll like an unacknowledged one
+            let _ = self.espnow.send(peer.mac, &packet);
+            self.pending.push(Pending {
+                id,
+                mac: peer.mac,
+                packet: packet.clone(),
+                sent: Instant::now(),
+                retries: 0,
+            });
+        }
+        Ok(id)
+    }
+
+    /// Handle the received packets, resend unacknowledged messages and
+    /// announce the nickname. Call it in the main loop. The sends are best
+    /// effort: their failures are returned as [`ChatEvent::Error`].
+    pub fn poll(&mut self) -> Result<Vec<ChatEvent>> {
+        let mut events = Vec::new();
+        let now = Instant::now();
+
+        let is_hello_due = match self.last_hello {
+            Some(x) => now - x >= HELLO_INTERVAL,
+            None => true,
+        };
+        if is_hello_due {
+            let hello = packet(HELLO, self.session, 0, self.name.as_bytes());
+            if let Err(e) = self.espnow.send(BROADCAST, &hello) {
+                events.push(ChatEvent::Error(format!("hello: {:?}", e)));
+            }
+            self.last_hello = Some(now);
+        }
+
+        while let Ok((mac, data)) = self.received.try_recv() {
+            if data.len() < HEADER_LEN || data[..2] != MAGIC {
+                continue;
+            }
+            let session = u16::from_le_bytes([data[3], data[4]]);
+            let id = u16::from_le_bytes([data[5], data[6]]);
+            let payload = String::from_utf8_lossy(&data[HEADER_LEN..]).to_string();
+            match data[2] {
+                HELLO => {
+                    if let Some(event) = self.see(mac, Some(payload)) {
+                        events.push(event);
+                    }
+                }
+                MESSAGE => {
+                    if let Some(event) = self.see(mac, None) {
+                        events.push(event);
+                    }
+                    // a copy resent after a lost ACK is acknowledged again
+                    if !self.recent.contains(&(mac, session, id)) {
+                        self.recent.push_back((mac, session, id));
+                        if self.recent.len() > RECENT_SIZE {
+                            self.recent.pop_front();
+                        }
+                        events.push(ChatEvent::Message {
+                            from: self.peer(mac),
+                            text: payload,
+                        });
+                    }
+                    // the ACK echoes the session of the message
+                    if let Err(e) = self.espnow.send(mac, &packet(ACK, session, id, &[])) {
+                        events.push(ChatEvent::Error(format!("ack: {:?}", e)));
+                    }
+                }
+                ACK if session == self.session => {
+                    if let Some(i) = self.pending.iter().position(|x| x.mac == mac && x.id == id) {
+                        self.pending.remove(i);
+                        events.push(ChatEvent::Delivered {
+                            id,
+                            to: self.peer(mac),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut i = 0;
+        while i < self.pending.len() {
+            let pending = &mut self.pending[i];
+            if now - pending.sent < RETRY_INTERVAL {
+                i += 1;
+            } else if pending.retries < MAX_RETRIES {
+                pending.retries += 1;
+                pending.sent = now;
+                if let Err(e) = self.espnow.send(pending.mac, &pending.packet) {
+                    events.push(ChatEvent::Error(format!("resend: {:?}", e)));
+                }
+                i += 1;
+            } else {
+                let pending = self.pending.remove(i);
+                events.push(ChatEvent::Failed {
+                    id: pending.id,
+                    to: self.peer(pending.mac),
+                });
+            }
+        }
+
+        let (alive, left): (Vec<_>, Vec<_>) = self
+            .peers
+            .drain(..)
+            .partition(|x| now - x.last_seen < PEER_TIMEOUT);
+        self.peers = alive;
+        for peer in left {
+            if let Err(e) = self.espnow.del_peer(peer.mac) {
+                events.push(ChatEvent::Error(format!("remove peer: {:?}", e)));
+            }
+            let (failed, pending) = self.pending.drain(..).partition(|x| x.mac == peer.mac);
+            self.pending = pending;
+            for x in failed {
+                events.push(ChatEvent::Failed {
+                    id: x.id,
+                    to: peer.clone(),
+                });
+            }
+            events.push(ChatEvent::PeerLeft(peer));
+        }
+
+        Ok(events)
+    }
+
+    /// Record a packet from the device, registering it as a peer when new.
+    /// Returns the error event if the device cannot be registered.
+    fn see(&mut self, mac: Mac, name: Option<String>) -> Option<ChatEvent> {
+        if let Some(peer) = self.peers.iter_mut().find(|x| x.mac == mac) {
+            peer.last_seen = Instant::now();
+            if let Some(name) = name {
+                peer.name = name;
+            }
+            return None;
+        }
+        let registered = self.espnow.peer_exists(mac).and_then(|exists| {
+            if exists {
+                return Ok(());
+            }
+            self.espnow.add_peer(PeerInfo {
+                peer_addr: mac,
+                ..Default::default()
+            })
+        });
+        if let Err(e) = registered {
+            return Some(ChatEvent::Error(format!("add peer: {:?}", e)));
+        }
+        let peer = Peer {
+            mac,
+            name: name.unwrap_or_else(|| format!("{:02X}{:02X}", mac[4], mac[5])),
+            last_seen: Instant::now(),
+        };
+        self.peers.push(peer.clone());
+        Some(ChatEvent::PeerJoined(peer))
+    }
+
+    fn peer(&self, mac: Mac) -> Peer {
+        self.peers
+            .iter()
+            .find(|x| x.mac == mac)
+            .cloned()
+            .unwrap_or_else(|| Peer {
+                mac,
+                name: format!("{:02X}{:02X}", mac[4], mac[5]),
+                last_seen: Instant::now(),
+            })
+    }
+}
+
+fn packet(kind: u8, session: u16, id: u16, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+    packet.extend_from_slice(&MAGIC);
+    packet.push(kind);
+    packet.extend_from_slice(&session.to_le_bytes());
+    packet.extend_from_slice(&id.to_le_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
@@ -0,0 +1,67 @@
+//! Text clipboard shared across widgets
+//!
+//! The clipboard is global, so text copied in one widget can be pasted
+//! into another. Call [`persist`] once at startup to keep the contents in
+//! NVS across reboots.
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvs;
+use std::sync::Mutex;
+
+/// NVS key of the clipboard contents
+const NVS_KEY: &str = "clipboard";
+
+static CLIPBOARD: Mutex<String> = Mutex::new(String::new());
+static STORAGE: Mutex<Option<EspDefaultNvs>> = Mutex::new(None);
+
+/// Replace the clipboard contents with the text.
+pub fn copy(text: &str) {
+    let mut clipboard = CLIPBOARD.lock().unwrap();
+    clipboard.clear();
+    clipboard.push_str(text);
+    save(&clipboard);
+}
+
+/// Returns a copy of the clipboard contents.
+pub fn paste() -> String {
+    CLIPBOARD.lock().unwrap().clone()
+}
+
+/// Returns true if the clipboard is empty.
+pub fn is_empty() -> bool {
+    CLIPBOARD.lock().unwrap().is_empty()
+}
+
+/// Empty the clipboard.
+pub fn clear() {
+    copy("");
+}
+
+/// Restore the clipboard from NVS and save every later change to it.
+///
+/// # Examples
+///
+/// ```
+/// use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
+///
+/// let nvs = EspDefaultNvs::new(EspDefaultNvsPartition::take().unwrap(), "cardputer", true).unwrap();
+/// cardputer::clipboard::persist(nvs).unwrap();
+/// ```
+pub fn persist(nvs: EspDefaultNvs) -> Result<()> {
+    if let Some(len) = nvs.str_len(NVS_KEY)? {
+        let mut buf = vec![0u8; len];
+        if let Some(text) = nvs.get_str(NVS_KEY, &mut buf)? {
+            let mut clipboard = CLIPBOARD.lock().unwrap();
+            clipboard.clear();
+            clipboard.push_str(text);
+        }
+    }
+    *STORAGE.lock().unwrap() = Some(nvs);
+    Ok(())
+}
+
+fn save(text: &str) {
+    if let Some(nvs) = STORAGE.lock().unwrap().as_mut() {
+        // the clipboard stays usable in RAM even if NVS is full
+        let _ = nvs.set_str(NVS_KEY, text);
+    }
+}
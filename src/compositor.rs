@@ -0,0 +1,192 @@
+//! Frame buffer shared by components drawing from several tasks
+//!
+//! Each component (the status bar, a console, the app screen) claims a
+//! rectangle of the screen from the [`Compositor`] and gets a
+//! [`RegionLease`]. The regions of the leases never overlap: a claim that
+//! overlaps a held region fails. Drawing through a lease is translated to
+//! the region and clipped to it, and flushing a lease transfers only its
+//! region, so a task cannot overwrite or send the pixels of another one.
+//! The region is free again when the lease is dropped.
+use anyhow::{anyhow, bail, Result};
+use core::fmt::Debug;
+use embedded_graphics::{
+    draw_target::Cropped, pixelcolor::Rgb565, prelude::*, primitives::Rectangle,
+};
+use std::sync::{Arc, Mutex};
+
+use crate::framebuffer::FrameBuffer;
+
+/// Region held by a lease
+struct Claim {
+    id: u32,
+    name: String,
+    area: Rectangle,
+    /// Drawn since the last flush
+    dirty: bool,
+}
+
+struct Shared {
+    fb: FrameBuffer,
+    claims: Vec<Claim>,
+    next_id: u32,
+}
+
+impl Shared {
+    fn claim_mut(&mut self, id: u32) -> Option<&mut Claim> {
+        self.claims.iter_mut().find(|x| x.id == id)
+    }
+}
+
+/// Owner of the frame buffer and of the leases of its regions
+///
+/// Cloned handles share the same frame buffer.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{compositor::Compositor, framebuffer::FrameBuffer};
+///
+/// let compositor = Compositor::new(FrameBuffer::new());
+/// let status = compositor
+///     .claim("status", Rectangle::new(Point::zero(), Size::new(240, 12)))
+///     .unwrap();
+/// let app = compositor
+///     .claim("app", Rectangle::new(Point::new(0, 12), Size::new(240, 123)))
+///     .unwrap();
+///
+/// thread::spawn(move || loop {
+///     status
+///         .draw(|target| status_bar.draw(target))
+///         .unwrap();
+///     thread::sleep(Duration::from_secs(1));
+/// });
+///
+/// loop {
+///     // (0, 0) is the top left corner of the region
+///     app.draw(|target| target.clear(Rgb565::BLACK)).unwrap();
+///     compositor.flush(&mut display).unwrap();
+///     thread::sleep(Duration::from_millis(20));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Compositor {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Compositor {
+    /// Create new compositor of the frame buffer, without leases.
+    pub fn new(fb: FrameBuffer) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Shared {
+                fb,
+                claims: Vec::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// Claim the area for the component of the name. The parts outside
+    /// the display are ignored. Fails if the area is empty or overlaps a
+    /// region already claimed.
+    pub fn claim(&self, name: &str, area: Rectangle) -> Result<RegionLease> {
+        let mut shared = self.shared.lock().map_err(|e| anyhow!("{:?}", e))?;
+        let area = area.intersection(&shared.fb.bounding_box());
+        if area.is_zero_sized() {
+            bail!("{}: empty region", name);
+        }
+        if let Some(x) = shared
+            .claims
+            .iter()
+            .find(|x| !x.area.intersection(&area).is_zero_sized())
+        {
+            bail!("{}: region overlaps the region of {}", name, x.name);
+        }
+        let id = shared.next_id;
+        shared.next_id += 1;
+        shared.claims.push(Claim {
+            id,
+            name: name.to_string(),
+            area,
+            dirty: false,
+        });
+        Ok(RegionLease {
+            shared: self.shared.clone(),
+            id,
+            area,
+        })
+    }
+
+    /// Returns the names and areas of the claimed regions.
+    pub fn regions(&self) -> Vec<(String, Rectangle)> {
+        self.shared.lock().map_or(Vec::new(), |shared| {
+            shared
+                .claims
+                .iter()
+                .map(|x| (x.name.clone(), x.area))
+                .collect()
+        })
+    }
+
+    /// Transfer the regions drawn since their last flush to the display.
+    pub fn flush<D>(&self, display: &mut D) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        let mut shared = self.shared.lock().map_err(|e| anyhow!("{:?}", e))?;
+        let Shared { fb, claims, .. } = &mut *shared;
+        for claim in claims.iter_mut().filter(|x| x.dirty) {
+            fb.flush_area(&claim.area, display)?;
+            claim.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+/// Exclusive right to draw a region of the [`Compositor`]
+///
+/// Releases the region when dropped.
+pub struct RegionLease {
+    shared: Arc<Mutex<Shared>>,
+    id: u32,
+    area: Rectangle,
+}
+
+impl RegionLease {
+    /// Returns the area of the region on the screen.
+    pub fn area(&self) -> Rectangle {
+        self.area
+    }
+
+    /// Call `f` with a draw target of the region, where (0, 0) is the top
+    /// left corner of the region and the pixels outside are dropped. The
+    /// other tasks wait for the frame buffer meanwhile.
+    pub fn draw<R>(&self, f: impl FnOnce(&mut Cropped<'_, FrameBuffer>) -> R) -> Result<R> {
+        let mut shared = self.shared.lock().map_err(|e| anyhow!("{:?}", e))?;
+        if let Some(claim) = shared.claim_mut(self.id) {
+            claim.dirty = true;
+        }
+        Ok(f(&mut shared.fb.cropped(&self.area)))
+    }
+
+    /// Transfer the region to the display now, whether it was drawn or not.
+    pub fn flush<D>(&self, display: &mut D) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        let mut shared = self.shared.lock().map_err(|e| anyhow!("{:?}", e))?;
+        shared.fb.flush_area(&self.area, display)?;
+        if let Some(claim) = shared.claim_mut(self.id) {
+            claim.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RegionLease {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        shared.claims.retain(|x| x.id != self.id);
+    }
+}
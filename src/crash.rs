@@ -0,0 +1,220 @@
+//! Crash detection and core dump retrieval
+//!
+//! With `CONFIG_ESP_COREDUMP_ENABLE_TO_FLASH=y` and a `coredump` partition
+//! in the partition table, ESP-IDF writes a core dump to flash when the
+//! firmware crashes. At the next boot [`CrashReport::check`] tells whether
+//! the previous run ended in a crash, the dump can be copied to a
+//! [`Store`] with [`save_core_dump`], and the report can be shown as a
+//! dialog.
+//!
+//! The saved dump is decoded on a computer with
+//! `espcoredump.py info_corefile -t raw -c <dump> <elf>`.
+use anyhow::Result;
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::PrimitiveStyleBuilder};
+use esp_idf_hal::sys::{
+    esp, esp_core_dump_image_check, esp_core_dump_image_erase, esp_core_dump_image_get,
+    esp_flash_read, esp_reset_reason, esp_reset_reason_t_ESP_RST_BROWNOUT,
+    esp_reset_reason_t_ESP_RST_DEEPSLEEP, esp_reset_reason_t_ESP_RST_EXT,
+    esp_reset_reason_t_ESP_RST_INT_WDT, esp_reset_reason_t_ESP_RST_PANIC,
+    esp_reset_reason_t_ESP_RST_POWERON, esp_reset_reason_t_ESP_RST_SW,
+    esp_reset_reason_t_ESP_RST_TASK_WDT, esp_reset_reason_t_ESP_RST_WDT, ESP_OK,
+};
+
+use crate::storage::Store;
+use crate::widget::text_layout::{Span, TextLayout};
+
+/// Size of the chunks read from flash
+const CHUNK_SIZE: usize = 4096;
+
+/// Why the chip was reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetReason {
+    PowerOn,
+    /// Reset pin
+    External,
+    /// `esp_restart`
+    Software,
+    Panic,
+    InterruptWatchdog,
+    TaskWatchdog,
+    /// Other watchdogs
+    Watchdog,
+    DeepSleep,
+    Brownout,
+    Other(u32),
+}
+
+impl ResetReason {
+    /// Returns true if the reset was caused by a failure.
+    pub fn is_crash(&self) -> bool {
+        matches!(
+            self,
+            ResetReason::Panic
+                | ResetReason::InterruptWatchdog
+                | ResetReason::TaskWatchdog
+                | ResetReason::Watchdog
+                | ResetReason::Brownout
+        )
+    }
+
+    /// Returns the description shown to the user.
+    pub fn description(&self) -> &'static str {
+        match self {
+            ResetReason::PowerOn => "power on",
+            ResetReason::External => "reset button",
+            ResetReason::Software => "restart",
+            ResetReason::Panic => "panic or exception",
+            ResetReason::InterruptWatchdog => "interrupt watchdog",
+            ResetReason::TaskWatchdog => "task watchdog",
+            ResetReason::Watchdog => "watchdog",
+            ResetReason::DeepSleep => "wake from deep sleep",
+            ResetReason::Brownout => "brownout (low battery?)",
+            ResetReason::Other(_) => "unknown",
+        }
+    }
+}
+
+/// Return why the chip was reset.
+pub fn reset_reason() -> ResetReason {
+    #[allow(non_upper_case_globals)]
+    match unsafe { esp_reset_reason() } {
+        esp_reset_reason_t_ESP_RST_POWERON => ResetReason::PowerOn,
+        esp_reset_reason_t_ESP_RST_EXT => ResetReason::External,
+        esp_reset_reason_t_ESP_RST_SW => ResetReason::Software,
+        esp_reset_reason_t_ESP_RST_PANIC => ResetReason::Panic,
+        esp_reset_reason_t_ESP_RST_INT_WDT => ResetReason::InterruptWatchdog,
+        esp_reset_reason_t_ESP_RST_TASK_WDT => ResetReason::TaskWatchdog,
+        esp_reset_reason_t_ESP_RST_WDT => ResetReason::Watchdog,
+        esp_reset_reason_t_ESP_RST_DEEPSLEEP => ResetReason::DeepSleep,
+        esp_reset_reason_t_ESP_RST_BROWNOUT => ResetReason::Brownout,
+        other => ResetReason::Other(other),
+    }
+}
+
+/// Returns the size in bytes of the core dump in flash, or `None` if there
+/// is no valid dump.
+pub fn core_dump_size() -> Option<usize> {
+    if unsafe { esp_core_dump_image_check() } != ESP_OK {
+        return None;
+    }
+    let (mut address, mut size) = (0, 0);
+    esp!(unsafe { esp_core_dump_image_get(&mut address, &mut size) }).ok()?;
+    Some(size)
+}
+
+/// Copy the core dump from flash to the key of the store and erase it from
+/// flash. Returns the size of the dump, or `None` if there is none.
+///
+/// The dump is held in RAM while copied; it is usually 20 to 100 KiB.
+pub fn save_core_dump(store: &mut impl Store, key: &str) -> Result<Option<usize>> {
+    if unsafe { esp_core_dump_image_check() } != ESP_OK {
+        return Ok(None);
+    }
+    let (mut address, mut size) = (0, 0);
+    esp!(unsafe { esp_core_dump_image_get(&mut address, &mut size) })?;
+    let mut data = vec![0u8; size];
+    for (i, chunk) in data.chunks_mut(CHUNK_SIZE).enumerate() {
+        esp!(unsafe {
+            esp_flash_read(
+                core::ptr::null_mut(),
+                chunk.as_mut_ptr().cast(),
+                (address + i * CHUNK_SIZE) as u32,
+                chunk.len() as u32,
+            )
+        })?;
+    }
+    store.write(key, &data)?;
+    erase_core_dump()?;
+    Ok(Some(size))
+}
+
+/// Erase the core dump from flash.
+pub fn erase_core_dump() -> Result<()> {
+    esp!(unsafe { esp_core_dump_image_erase() })?;
+    Ok(())
+}
+
+/// Report of the previous run ending in a crash, drawn as a dialog
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::crash::{self, CrashReport};
+///
+/// if let Some(mut report) = CrashReport::check() {
+///     if let Ok(Some(_)) = crash::save_core_dump(&mut card.store(), "core.dmp") {
+///         report = report.with_saved_as("core.dmp");
+///     }
+///     report.draw(&mut fb).unwrap();
+///     fb.flush(&mut display).unwrap();
+///     // wait for a key
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    /// Reason of the last reset
+    pub reason: ResetReason,
+    /// Size of the core dump in flash
+    pub core_dump_size: Option<usize>,
+    /// Where the core dump was saved
+    pub saved_as: Option<String>,
+}
+
+impl CrashReport {
+    /// Returns the report if the last reset was caused by a failure or a
+    /// core dump is in flash.
+    pub fn check() -> Option<Self> {
+        let reason = reset_reason();
+        let core_dump_size = core_dump_size();
+        (reason.is_crash() || core_dump_size.is_some()).then_some(Self {
+            reason,
+            core_dump_size,
+            saved_as: None,
+        })
+    }
+
+    /// Mention where the core dump was saved.
+    pub fn with_saved_as(mut self, name: impl Into<String>) -> Self {
+        self.saved_as = Some(name.into());
+        self
+    }
+}
+
+impl Drawable for CrashReport {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let area = target.bounding_box().offset(-12);
+        area.into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(Rgb565::new(8, 8, 8))
+                .stroke_color(Rgb565::RED)
+                .stroke_width(2)
+                .build(),
+        )
+        .draw(target)?;
+
+        let reason = format!("Reason: {}\n", self.reason.description());
+        let core_dump = match (&self.saved_as, self.core_dump_size) {
+            (Some(name), _) => format!("Core dump saved: {}\n", name),
+            (None, Some(size)) => format!("Core dump in flash: {} bytes\n", size),
+            (None, None) => String::new(),
+        };
+        let spans = [
+            Span::new("Previous crash detected\n")
+                .with_color(Rgb565::RED)
+                .with_emphasis(),
+            Span::new(&reason),
+            Span::new(&core_dump),
+            Span::new("Press any key").with_color(Rgb565::CSS_GRAY),
+        ];
+        TextLayout::new(area.size.width.saturating_sub(16))
+            .with_line_spacing(4)
+            .paragraph(&spans, area.top_left + Point::new(8, 8))
+            .draw(target)
+    }
+}
@@ -0,0 +1,721 @@
+//! Factory and field diagnostics of the whole device
+//!
+//! [`run_all`] walks through the hardware one device at a time and returns
+//! a [`Report`] of pass/fail results:
+//!
+//! - keyboard: guided test asking for every key in turn
+//! - display: solid colors and a checkerboard, confirmed by the user
+//! - backlight: a short blink, confirmed by the user
+//! - speaker: a 1 kHz tone, confirmed by the user
+//! - microphone: loopback of the speaker tone, compared to the silence
+//! - SD card: write, read back and delete a test file
+//! - battery: voltage within the range of a Li-ion cell
+//! - Grove: scan of the I2C bus
+//!
+//! Confirmations are answered with Y (or Enter) and N on the keyboard. A
+//! device not given to [`Devices`] is skipped. Each result can be written
+//! to an output as it is known, e.g. the console, so the report is also
+//! available over the serial port.
+use anyhow::{anyhow, Result};
+use core::fmt::{self, Debug};
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use std::{
+    io::Write,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::backlight::Backlight;
+use crate::board::BoardKeyboard;
+use crate::generator::SignalGenerator;
+use crate::i2c_bus::I2cBus;
+use crate::keyboard::{self, KeyImprint, KeyboardScanner, SelfTestPrompt, KEY_MAP};
+use crate::microphone::Microphone;
+use crate::power::PowerMonitor;
+use crate::speaker::Speaker;
+use crate::storage::{sd::SdCard, Store};
+
+/// Time given to press each key of the guided keyboard test
+const KEY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time given to answer a confirmation
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(15);
+/// Time each display pattern is shown
+const PATTERN_TIME: Duration = Duration::from_millis(700);
+/// Frequency of the test tone in Hz
+const TONE_FREQUENCY: f32 = 1000.0;
+/// Length of the speaker and loopback tones
+const TONE_TIME: Duration = Duration::from_millis(800);
+/// Minimum ratio of the microphone level with the tone to the silence
+const LOOPBACK_RATIO: f32 = 4.0;
+/// Range of the battery voltage (mV) of a healthy Li-ion cell
+const BATTERY_RANGE: (u32, u32) = (3000, 4400);
+/// Name of the file written to the SD card
+const SD_TEST_FILE: &str = "diag.tmp";
+/// Interval between the keyboard scans
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Outcome of a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+    /// The device was not given or the check could not be confirmed
+    Skipped,
+}
+
+impl Status {
+    /// Returns the label printed in the report.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Status::Pass => "PASS",
+            Status::Fail => "FAIL",
+            Status::Skipped => "SKIP",
+        }
+    }
+
+    fn color(&self) -> Rgb565 {
+        match self {
+            Status::Pass => Rgb565::GREEN,
+            Status::Fail => Rgb565::RED,
+            Status::Skipped => Rgb565::CSS_GRAY,
+        }
+    }
+}
+
+/// Result of one device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    /// Name of the device, e.g. "keyboard"
+    pub name: &'static str,
+    pub status: Status,
+    /// What was measured or what failed
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    /// Returns a failed result with the error as the detail.
+    fn error(name: &'static str, error: anyhow::Error) -> Self {
+        Self::new(name, Status::Fail, format!("{:#}", error))
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:<10} {}",
+            self.status.label(),
+            self.name,
+            self.detail
+        )
+    }
+}
+
+/// Results of [`run_all`] in the order of the checks
+///
+/// Printed with `{}`, it gives one line per device and a summary.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub results: Vec<CheckResult>,
+}
+
+impl Report {
+    /// Returns true if no check failed.
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|x| x.status != Status::Fail)
+    }
+
+    /// Returns the number of results with the status.
+    pub fn count(&self, status: Status) -> usize {
+        self.results.iter().filter(|x| x.status == status).count()
+    }
+
+    /// Returns the one-line summary, e.g. "7 passed, 1 failed, 0 skipped".
+    pub fn summary(&self) -> String {
+        format!(
+            "{} passed, {} failed, {} skipped",
+            self.count(Status::Pass),
+            self.count(Status::Fail),
+            self.count(Status::Skipped)
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            writeln!(f, "{}", result)?;
+        }
+        write!(f, "{}", self.summary())
+    }
+}
+
+impl Drawable for Report {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.clear(Rgb565::BLACK)?;
+        let origin = target.bounding_box().top_left + Point::new(4, 4);
+        let white = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        for (i, result) in self.results.iter().enumerate() {
+            let position = origin + Point::new(0, i as i32 * 12);
+            let style = MonoTextStyle::new(&FONT_6X10, result.status.color());
+            let next = Text::with_baseline(result.status.label(), position, style, Baseline::Top)
+                .draw(target)?;
+            let line = format!(" {:<10} {}", result.name, result.detail);
+            Text::with_baseline(&line, next, white, Baseline::Top).draw(target)?;
+        }
+        let position = origin + Point::new(0, self.results.len() as i32 * 12 + 4);
+        let color = if self.is_ok() {
+            Rgb565::GREEN
+        } else {
+            Rgb565::RED
+        };
+        Text::with_baseline(
+            &self.summary(),
+            position,
+            MonoTextStyle::new(&FONT_6X10, color),
+            Baseline::Top,
+        )
+        .draw(target)?;
+        Ok(())
+    }
+}
+
+/// Devices to be checked by [`run_all`]
+///
+/// The display and the backlight are required to guide the user; the
+/// checks of the others are skipped unless given. For the loopback the
+/// microphone needs I2S0 (PDM input), so create the speaker on I2S1.
+pub struct Devices<'a, 'd, D> {
+    pub display: &'a mut D,
+    pub backlight: &'a mut Backlight<'d>,
+    pub keyboard: Option<&'a mut BoardKeyboard<'d>>,
+    pub speaker: Option<&'a mut Speaker<'d>>,
+    pub microphone: Option<&'a mut Microphone<'d>>,
+    pub sd_card: Option<&'a SdCard<'d>>,
+    pub power: Option<&'a mut PowerMonitor<'d>>,
+    pub grove: Option<&'a I2cBus<'d>>,
+    pub output: Option<&'a mut dyn Write>,
+}
+
+impl<'a, 'd, D> Devices<'a, 'd, D> {
+    /// Create new set of the display and the backlight only.
+    pub fn new(display: &'a mut D, backlight: &'a mut Backlight<'d>) -> Self {
+        Self {
+            display,
+            backlight,
+            keyboard: None,
+            speaker: None,
+            microphone: None,
+            sd_card: None,
+            power: None,
+            grove: None,
+            output: None,
+        }
+    }
+
+    /// Check the keyboard and use it for the confirmations (none).
+    pub fn with_keyboard(mut self, keyboard: &'a mut BoardKeyboard<'d>) -> Self {
+        self.keyboard = Some(keyboard);
+        self
+    }
+
+    /// Check the speaker (none).
+    pub fn with_speaker(mut self, speaker: &'a mut Speaker<'d>) -> Self {
+        self.speaker = Some(speaker);
+        self
+    }
+
+    /// Check the microphone, with the speaker for the loopback (none).
+    pub fn with_microphone(mut self, microphone: &'a mut Microphone<'d>) -> Self {
+        self.microphone = Some(microphone);
+        self
+    }
+
+    /// Check the mounted SD card (none).
+    pub fn with_sd_card(mut self, sd_card: &'a SdCard<'d>) -> Self {
+        self.sd_card = Some(sd_card);
+        self
+    }
+
+    /// Check the battery voltage (none).
+    pub fn with_power(mut self, power: &'a mut PowerMonitor<'d>) -> Self {
+        self.power = Some(power);
+        self
+    }
+
+    /// Scan the Grove I2C bus (none).
+    pub fn with_grove(mut self, grove: &'a I2cBus<'d>) -> Self {
+        self.grove = Some(grove);
+        self
+    }
+
+    /// Write each result and the summary to the output as a line, e.g.
+    /// `std::io::stdout()` for the console (none).
+    pub fn with_output(mut self, output: &'a mut dyn Write) -> Self {
+        self.output = Some(output);
+        self
+    }
+}
+
+/// Run every check in turn, showing the report on the display at the end.
+///
+/// Each result is written to the output of the devices as soon as it is
+/// known, followed by the summary. Only a failure to draw on the display is returned as an
+/// error; failures of the devices are in the report.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::board::Board;
+/// use cardputer::diagnostics::{self, Devices};
+/// use cardputer::i2c_bus::I2cBus;
+///
+/// let mut board = Board::detect(Peripherals::take().unwrap(), &store).unwrap();
+/// let (i2c, sda, scl) = board.spare.grove;
+/// let grove = I2cBus::new(grove::build(i2c, sda, scl, 100.kHz().into()).unwrap());
+///
+/// let mut console = std::io::stdout();
+/// let devices = Devices::new(&mut board.display, &mut board.backlight)
+///     .with_keyboard(&mut board.keyboard)
+///     .with_power(&mut board.power)
+///     .with_grove(&grove)
+///     .with_output(&mut console);
+/// let report = diagnostics::run_all(devices).unwrap();
+/// if !report.is_ok() {
+///     // keep the report on screen
+/// }
+/// ```
+pub fn run_all<D>(devices: Devices<'_, '_, D>) -> Result<Report>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: Debug,
+{
+    let Devices {
+        display,
+        backlight,
+        mut keyboard,
+        mut speaker,
+        microphone,
+        sd_card,
+        power,
+        grove,
+        mut output,
+    } = devices;
+    let mut report = Report::default();
+    let mut record = |result: CheckResult| {
+        if let Some(output) = output.as_deref_mut() {
+            let _ = writeln!(output, "{}", result);
+        }
+        report.results.push(result);
+    };
+
+    let _ = backlight.on();
+    record(match keyboard.as_deref_mut() {
+        Some(keyboard) => check_keyboard(display, keyboard)?,
+        None => CheckResult::new("keyboard", Status::Skipped, "not given"),
+    });
+    record(check_display(display, keyboard.as_deref_mut())?);
+    record(check_backlight(
+        display,
+        backlight,
+        keyboard.as_deref_mut(),
+    )?);
+    record(match speaker.as_deref_mut() {
+        Some(speaker) => check_speaker(display, speaker, keyboard)?,
+        None => CheckResult::new("speaker", Status::Skipped, "not given"),
+    });
+    record(match microphone {
+        Some(microphone) => check_microphone(display, microphone, speaker)?,
+        None => CheckResult::new("microphone", Status::Skipped, "not given"),
+    });
+    record(match sd_card {
+        Some(sd_card) => check_sd_card(sd_card),
+        None => CheckResult::new("sd card", Status::Skipped, "not given"),
+    });
+    record(match power {
+        Some(power) => check_battery(power),
+        None => CheckResult::new("battery", Status::Skipped, "not given"),
+    });
+    record(match grove {
+        Some(grove) => check_grove(grove),
+        None => CheckResult::new("grove", Status::Skipped, "not given"),
+    });
+
+    if let Some(output) = output {
+        let _ = writeln!(output, "{}", report.summary());
+    }
+    report.draw(display).map_err(|e| anyhow!("{:?}", e))?;
+    Ok(report)
+}
+
+/// Clear the display and show the lines of the instruction.
+fn show<D>(display: &mut D, lines: &[&str]) -> Result<()>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: Debug,
+{
+    display
+        .clear(Rgb565::BLACK)
+        .map_err(|e| anyhow!("{:?}", e))?;
+    let origin = display.bounding_box().top_left + Point::new(8, 8);
+    let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+    for (i, line) in lines.iter().enumerate() {
+        let position = origin + Point::new(0, i as i32 * 14);
+        Text::with_baseline(line, position, style, Baseline::Top)
+            .draw(display)
+            .map_err(|e| anyhow!("{:?}", e))?;
+    }
+    Ok(())
+}
+
+/// Ask the question and wait for Y or Enter (true) or N (false). Returns
+/// `None` without a keyboard or an answer within the timeout.
+fn confirm<D>(
+    display: &mut D,
+    keyboard: Option<&mut BoardKeyboard>,
+    question: &str,
+) -> Result<Option<bool>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: Debug,
+{
+    let Some(keyboard) = keyboard else {
+        return Ok(None);
+    };
+    show(display, &[question, "", "Y: yes  N: no"])?;
+    wait_released(keyboard);
+    let started = Instant::now();
+    while started.elapsed() < CONFIRM_TIMEOUT {
+        for key in keyboard.scan_pressed_keytypes().unwrap_or_default() {
+            match key.imprint() {
+                KeyImprint::Y | KeyImprint::Enter => return Ok(Some(true)),
+                KeyImprint::N => return Ok(Some(false)),
+                _ => {}
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(None)
+}
+
+/// Wait up to the key timeout for all keys to be released.
+fn wait_released(keyboard: &mut impl KeyboardScanner) {
+    let started = Instant::now();
+    while started.elapsed() < KEY_TIMEOUT
+        && !keyboard
+            .scan_pressed_keytypes()
+            .unwrap_or_default()
+            .is_empty()
+    {
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Returns the result of a check confirmed by the user.
+fn confirmed(name: &'static str, answer: Option<bool>, detail: &str) -> CheckResult {
+    match answer {
+        Some(true) => CheckResult::new(name, Status::Pass, detail),
+        Some(false) => CheckResult::new(name, Status::Fail, format!("{} not seen", detail)),
+        None => CheckResult::new(name, Status::Skipped, "not confirmed"),
+    }
+}
+
+fn check_keyboard<D>(display: &mut D, keyboard: &mut BoardKeyboard) -> Result<CheckResult>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: Debug,
+{
+    const NAME: &str = "keyboard";
+    let dead_keys = match keyboard {
+        // the matrix test also finds the stuck keys and the broken lines
+        BoardKeyboard::Matrix(keyboard) => {
+            let mut error = None;
+            let report = keyboard::self_test(
+                keyboard,
+                |prompt| {
+                    let result = match prompt {
+                        SelfTestPrompt::ReleaseAll => show(display, &["Release all keys"]),
+                        SelfTestPrompt::Press(key) => {
+                            show(display, &["Press the key", &format!("{:?}", key)])
+                        }
+                    };
+                    if let Err(e) = result {
+                        error.get_or_insert(e);
+                    }
+                },
+                KEY_TIMEOUT,
+            );
+            if let Some(e) = error {
+                return Err(e);
+            }
+            let report = match report {
+                Ok(x) => x,
+                Err(e) => return Ok(CheckResult::error(NAME, e)),
+            };
+            if !report.stuck_keys.is_empty() {
+                let detail = format!("stuck {:?}", report.stuck_keys);
+                return Ok(CheckResult::new(NAME, Status::Fail, detail));
+            }
+            report.dead_keys
+        }
+        BoardKeyboard::Tca8418(_) => {
+            let mut dead_keys = Vec::new();
+            for imprint in KEY_MAP.iter().flatten().map(|x| x.imprint()) {
+                show(display, &["Press the key", &format!("{:?}", imprint)])?;
+                wait_released(keyboard);
+                let started = Instant::now();
+                let mut pressed = false;
+                while !pressed && started.elapsed() < KEY_TIMEOUT {
+                    pressed = keyboard
+                        .scan_pressed_keytypes()
+                        .unwrap_or_default()
+                        .iter()
+                        .any(|x| x.imprint() == imprint);
+                    thread::sleep(POLL_INTERVAL);
+                }
+                if !pressed {
+                    dead_keys.push(imprint);
+                }
+            }
+            dead_keys
+        }
+    };
+    Ok(if dead_keys.is_empty() {
+        CheckResult::new(NAME, Status::Pass, "all keys")
+    } else {
+        CheckResult::new(NAME, Status::Fail, format!("dead {:?}", dead_keys))
+    })
+}
+
+fn check_display<D>(display: &mut D, keyboard: Option<&mut BoardKeyboard>) -> Result<CheckResult>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: Debug,
+{
+    let colors = [
+        Rgb565::RED,
+        Rgb565::GREEN,
+        Rgb565::BLUE,
+        Rgb565::WHITE,
+        Rgb565::BLACK,
+    ];
+    for color in colors {
+        display.clear(color).map_err(|e| anyhow!("{:?}", e))?;
+        thread::sleep(PATTERN_TIME);
+    }
+
+    let area = display.bounding_box();
+    let size = 8;
+    for y in (0..area.size.height).step_by(size as usize) {
+        for x in (0..area.size.width).step_by(size as usize) {
+            let color = if (x / size + y / size) & 1 == 0 {
+                Rgb565::WHITE
+            } else {
+                Rgb565::BLACK
+            };
+            Rectangle::new(
+                area.top_left + Point::new(x as i32, y as i32),
+                Size::new(size, size),
+            )
+            .into_styled(PrimitiveStyle::with_fill(color))
+            .draw(display)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        }
+    }
+    thread::sleep(PATTERN_TIME * 2);
+
+    let answer = confirm(
+        display,
+        keyboard,
+        "Were the colors and the checkerboard clean?",
+    )?;
+    Ok(confirmed("display", answer, "colors and checkerboard"))
+}
+
+fn check_backlight<D>(
+    display: &mut D,
+    backlight: &mut Backlight,
+    keyboard: Option<&mut BoardKeyboard>,
+) -> Result<CheckResult>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: Debug,
+{
+    const NAME: &str = "backlight";
+    show(display, &["Watch the screen blink"])?;
+    thread::sleep(PATTERN_TIME);
+    for _ in 0..2 {
+        let result = backlight.off().and_then(|_| {
+            thread::sleep(PATTERN_TIME);
+            backlight.on()
+        });
+        if let Err(e) = result {
+            let _ = backlight.on();
+            return Ok(CheckResult::error(NAME, e));
+        }
+        thread::sleep(PATTERN_TIME);
+    }
+    let answer = confirm(display, keyboard, "Did the screen blink twice?")?;
+    Ok(confirmed(NAME, answer, "blink"))
+}
+
+/// Returns the samples of the test tone at the sample rate.
+fn tone(sample_rate: u32) -> Vec<i16> {
+    let mut samples = vec![0; (sample_rate as u128 * TONE_TIME.as_millis() / 1000) as usize];
+    SignalGenerator::new(sample_rate)
+        .with_frequency(TONE_FREQUENCY)
+        .with_amplitude(0.5)
+        .fill(&mut samples);
+    samples
+}
+
+fn check_speaker<D>(
+    display: &mut D,
+    speaker: &mut Speaker,
+    keyboard: Option<&mut BoardKeyboard>,
+) -> Result<CheckResult>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: Debug,
+{
+    const NAME: &str = "speaker";
+    show(display, &["Listen to the speaker"])?;
+    if let Err(e) = speaker.play(&tone(speaker.sample_rate())) {
+        return Ok(CheckResult::error(NAME, e));
+    }
+    let answer = confirm(display, keyboard, "Did you hear a tone?")?;
+    Ok(confirmed(NAME, answer, "1 kHz tone"))
+}
+
+/// Returns the RMS level of the samples.
+fn rms(samples: &[i16]) -> f32 {
+    let sum: f32 = samples.iter().map(|x| (*x as f32).powi(2)).sum();
+    (sum / samples.len().max(1) as f32).sqrt()
+}
+
+fn check_microphone<D>(
+    display: &mut D,
+    microphone: &mut Microphone,
+    speaker: Option<&mut Speaker>,
+) -> Result<CheckResult>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: Debug,
+{
+    const NAME: &str = "microphone";
+    show(display, &["Testing the microphone", "Keep quiet"])?;
+    // blocks of 20 ms, so the speaker and the microphone stay in step
+    let mut block = vec![0; microphone.sample_rate() as usize / 50];
+    let blocks = TONE_TIME.as_millis() as usize / 20;
+
+    let mut silence = 0.0;
+    for _ in 0..blocks {
+        if let Err(e) = microphone.read(&mut block) {
+            return Ok(CheckResult::error(NAME, e));
+        }
+        silence += rms(&block) / blocks as f32;
+    }
+    let Some(speaker) = speaker else {
+        return Ok(if silence > 0.0 {
+            let detail = format!("noise {:.0}, no loopback", silence);
+            CheckResult::new(NAME, Status::Pass, detail)
+        } else {
+            CheckResult::new(NAME, Status::Fail, "no signal")
+        });
+    };
+
+    let tone = tone(speaker.sample_rate());
+    let mut level = 0.0;
+    for chunk in tone
+        .chunks(speaker.sample_rate() as usize / 50)
+        .take(blocks)
+    {
+        let result = speaker
+            .play(chunk)
+            .and_then(|_| microphone.read(&mut block));
+        if let Err(e) = result {
+            return Ok(CheckResult::error(NAME, e));
+        }
+        level += rms(&block) / blocks as f32;
+    }
+    let detail = format!("loopback {:.0} / silence {:.0}", level, silence);
+    Ok(if level > silence.max(1.0) * LOOPBACK_RATIO {
+        CheckResult::new(NAME, Status::Pass, detail)
+    } else {
+        CheckResult::new(NAME, Status::Fail, detail)
+    })
+}
+
+fn check_sd_card(sd_card: &SdCard) -> CheckResult {
+    const NAME: &str = "sd card";
+    if !sd_card.is_mounted() {
+        return CheckResult::new(NAME, Status::Fail, "not mounted");
+    }
+    let data: Vec<u8> = (0..=255).collect();
+    let mut store = sd_card.store();
+    let result = store
+        .write(SD_TEST_FILE, &data)
+        .and_then(|_| store.read(SD_TEST_FILE))
+        .and_then(|read| {
+            store.remove(SD_TEST_FILE)?;
+            Ok(read)
+        });
+    match result {
+        Ok(Some(read)) if read == data => {
+            let detail = format!("{} MB", sd_card.capacity() / 1_000_000);
+            CheckResult::new(NAME, Status::Pass, detail)
+        }
+        Ok(_) => CheckResult::new(NAME, Status::Fail, "read back differs"),
+        Err(e) => CheckResult::error(NAME, e),
+    }
+}
+
+fn check_battery(power: &mut PowerMonitor) -> CheckResult {
+    const NAME: &str = "battery";
+    match power.battery_voltage() {
+        Ok(voltage) => {
+            let detail = format!("{} mV", voltage);
+            let (min, max) = BATTERY_RANGE;
+            if (min..=max).contains(&voltage) {
+                CheckResult::new(NAME, Status::Pass, detail)
+            } else {
+                CheckResult::new(NAME, Status::Fail, detail)
+            }
+        }
+        Err(e) => CheckResult::error(NAME, e),
+    }
+}
+
+fn check_grove(grove: &I2cBus) -> CheckResult {
+    let addresses = grove.scan();
+    let detail = if addresses.is_empty() {
+        "no device".to_string()
+    } else {
+        addresses
+            .iter()
+            .map(|x| format!("0x{:02x}", x))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    CheckResult::new("grove", Status::Pass, detail)
+}
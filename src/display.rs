@@ -1,6 +1,17 @@
 //! Create and initialize ST7789 display driver
+//!
+//! Hardware revisions or hand-wired boards with another controller can use
+//! [`build_with_model`] with a [`Panel`]. Support for controllers other than
+//! the ST7789 is enabled by the `st7735s`, `ili9341` and `ili9342c` features.
+//!
+//! The window offset of the panel is corrected by the per-unit
+//! [`calibration`], which [`build`] loads from the store. ST7789 features the driver does not cover,
+//! e.g. the idle mode, are reached with the raw [`command`]s, and the
+//! sleep mode is sequenced with the backlight by [`power`].
 use anyhow::{anyhow, Result};
+use core::ops::{Deref, DerefMut};
 use display_interface_spi::SPIInterfaceNoCS;
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
 use esp_idf_hal::{
     delay::Delay,
     gpio::{AnyIOPin, Output, PinDriver},
@@ -9,28 +20,183 @@ use esp_idf_hal::{
     prelude::*,
     spi::{config::DriverConfig, SpiAnyPins, SpiConfig, SpiDeviceDriver, SpiDriver},
 };
-use mipidsi::{models::ST7789, options::Orientation, Builder, ColorInversion, Display};
+use mipidsi::{
+    models::{Model, ST7789},
+    options::Orientation,
+    Builder, ColorInversion, Display, ModelOptions,
+};
+
+pub mod calibration;
+pub mod command;
+pub mod power;
+
+use crate::storage::Store;
+use calibration::Calibration;
+use command::{CommandInterface, CommandQueue};
 
-type Drawable<'a> = Display<
-    SPIInterfaceNoCS<SpiDeviceDriver<'a, SpiDriver<'a>>, PinDriver<'a, Gpio34, Output>>,
-    ST7789,
+/// mipidsi driver of the display
+type Driver<'a, M> = Display<
+    CommandInterface<
+        SPIInterfaceNoCS<SpiDeviceDriver<'a, SpiDriver<'a>>, PinDriver<'a, Gpio34, Output>>,
+    >,
+    M,
     PinDriver<'a, Gpio33, Output>,
 >;
 
+/// Display driver created by [`build`] and [`build_with_model`]
+///
+/// Dereferences to the mipidsi [`Display`], and keeps the queue of the
+/// raw [`command`]s sent through its interface.
+pub struct DisplayDriver<'a, M: Model = ST7789> {
+    inner: Driver<'a, M>,
+    commands: CommandQueue,
+}
+
+impl<'a, M: Model> Deref for DisplayDriver<'a, M> {
+    type Target = Driver<'a, M>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<M: Model> DerefMut for DisplayDriver<'_, M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<M: Model> OriginDimensions for DisplayDriver<'_, M> {
+    fn size(&self) -> Size {
+        self.inner.size()
+    }
+}
+
+impl<M: Model> DrawTarget for DisplayDriver<'_, M> {
+    type Color = M::ColorFormat;
+    type Error = mipidsi::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.inner.draw_iter(pixels)
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        self.inner.fill_contiguous(area, colors)
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.inner.fill_solid(area, color)
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.inner.clear(color)
+    }
+}
+
 /// Display width
 pub const DISPLAY_SIZE_WIDTH: u16 = 240;
 /// Display height
 pub const DISPLAY_SIZE_HEIGHT: u16 = 135;
 
+/// Panel controller with the settings of its wiring on the board
+///
+/// Implement it for a model of mipidsi to use a panel not supported by
+/// the crate.
+pub trait Panel: Model<ColorFormat = Rgb565> + Sized {
+    /// Create the model.
+    fn model() -> Self;
+
+    /// Returns whether the colors are inverted.
+    fn color_inversion() -> ColorInversion;
+
+    /// Returns the offset of the visible area in the controller memory.
+    fn window_offset(options: &ModelOptions) -> (u16, u16);
+}
+
+impl Panel for ST7789 {
+    fn model() -> Self {
+        ST7789
+    }
+
+    fn color_inversion() -> ColorInversion {
+        ColorInversion::Inverted
+    }
+
+    fn window_offset(_: &ModelOptions) -> (u16, u16) {
+        (40, 53)
+    }
+}
+
+#[cfg(feature = "st7735s")]
+impl Panel for mipidsi::models::ST7735s {
+    fn model() -> Self {
+        mipidsi::models::ST7735s
+    }
+
+    fn color_inversion() -> ColorInversion {
+        ColorInversion::Normal
+    }
+
+    fn window_offset(_: &ModelOptions) -> (u16, u16) {
+        (0, 0)
+    }
+}
+
+#[cfg(feature = "ili9341")]
+impl Panel for mipidsi::models::ILI9341Rgb565 {
+    fn model() -> Self {
+        mipidsi::models::ILI9341Rgb565
+    }
+
+    fn color_inversion() -> ColorInversion {
+        ColorInversion::Normal
+    }
+
+    fn window_offset(_: &ModelOptions) -> (u16, u16) {
+        (0, 0)
+    }
+}
+
+#[cfg(feature = "ili9342c")]
+impl Panel for mipidsi::models::ILI9342CRgb565 {
+    fn model() -> Self {
+        mipidsi::models::ILI9342CRgb565
+    }
+
+    fn color_inversion() -> ColorInversion {
+        ColorInversion::Inverted
+    }
+
+    fn window_offset(_: &ModelOptions) -> (u16, u16) {
+        (0, 0)
+    }
+}
+
+/// Returns the offset of the panel with the calibration.
+fn window_offset<M: Panel>(options: &ModelOptions) -> (u16, u16) {
+    calibration::corrected(M::window_offset(options))
+}
+
 /// Create and initialize display driver
 ///
+/// The [`calibration`] saved in the store is applied before the window is
+/// placed. Without one, or if it cannot be read, the default offset of the
+/// panel is used, so the display still comes up to be calibrated again.
+///
 /// # Examples
 ///
 /// ```
 /// use embedded_graphics::pixelcolor::Rgb565;
-/// use cardputer::display;
+/// use cardputer::{display, storage::nvs::NvsStore};
 ///
 /// let peripherals = Peripherals::take().unwrap();
+/// let store = NvsStore::new(EspDefaultNvs::new(partition, "config", true).unwrap());
 ///
 /// let mut display = display::build(
 ///     peripherals.spi2,
@@ -39,6 +205,7 @@ pub const DISPLAY_SIZE_HEIGHT: u16 = 135;
 ///     peripherals.pins.gpio37,
 ///     peripherals.pins.gpio34,
 ///     peripherals.pins.gpio33,
+///     &store,
 /// )
 /// .unwrap();
 /// display.clear(Rgb565::WHITE).unwrap();
@@ -50,10 +217,54 @@ pub fn build<'a, SPI>(
     cs: impl Peripheral<P = Gpio37> + 'a,
     rs: impl Peripheral<P = Gpio34> + 'a,
     rst: impl Peripheral<P = Gpio33> + 'a,
-) -> Result<Drawable<'a>>
+    store: &impl Store,
+) -> Result<DisplayDriver<'a>>
 where
     SPI: SpiAnyPins,
 {
+    build_with_model(spi, sck, dc, cs, rs, rst, store)
+}
+
+/// Create and initialize display driver for the panel controller
+///
+/// The offsets, orientation and size are the same as [`build`], so the
+/// frame buffers and widgets work unchanged. Both apply the
+/// [`calibration`] saved in the store.
+///
+/// # Examples
+///
+/// ```
+/// use mipidsi::models::ILI9341Rgb565;
+/// use cardputer::display;
+///
+/// let mut display = display::build_with_model::<_, ILI9341Rgb565>(
+///     peripherals.spi2,
+///     peripherals.pins.gpio36,
+///     peripherals.pins.gpio35,
+///     peripherals.pins.gpio37,
+///     peripherals.pins.gpio34,
+///     peripherals.pins.gpio33,
+///     &store,
+/// )
+/// .unwrap();
+/// ```
+pub fn build_with_model<'a, SPI, M>(
+    spi: impl Peripheral<P = SPI> + 'a,
+    sck: impl Peripheral<P = Gpio36> + 'a,
+    dc: impl Peripheral<P = Gpio35> + 'a,
+    cs: impl Peripheral<P = Gpio37> + 'a,
+    rs: impl Peripheral<P = Gpio34> + 'a,
+    rst: impl Peripheral<P = Gpio33> + 'a,
+    store: &impl Store,
+) -> Result<DisplayDriver<'a, M>>
+where
+    SPI: SpiAnyPins,
+    M: Panel,
+{
+    if let Ok(Some(calibration)) = Calibration::load(store) {
+        calibration.apply();
+    }
+
     let spi_config = SpiConfig::new().baudrate(80.MHz().into());
     let device_config = DriverConfig::new();
     let spi = SpiDeviceDriver::new_single(
@@ -68,19 +279,24 @@ where
 
     let rs = PinDriver::output(rs)?;
     let rst = PinDriver::output(rst)?;
-    let mut drawable = Builder::st7789(SPIInterfaceNoCS::new(spi, rs))
-        .with_invert_colors(ColorInversion::Inverted)
-        .with_display_size(DISPLAY_SIZE_WIDTH, DISPLAY_SIZE_HEIGHT)
-        .with_window_offset_handler(|_| (40, 53))
-        .init(&mut Delay::new_default(), Some(rst))
-        .map_err(|e| anyhow!("{:?}", e))?;
+    let commands = CommandQueue::default();
+    let mut drawable = Builder::with_model(
+        CommandInterface::new(SPIInterfaceNoCS::new(spi, rs), commands.clone()),
+        M::model(),
+    )
+    .with_invert_colors(M::color_inversion())
+    .with_display_size(DISPLAY_SIZE_WIDTH, DISPLAY_SIZE_HEIGHT)
+    .with_window_offset_handler(window_offset::<M>)
+    .with_orientation(Orientation::Landscape(true))
+    .init(&mut Delay::new_default(), Some(rst))
+    .map_err(|e| anyhow!("{:?}", e))?;
 
-    drawable
-        .set_orientation(Orientation::Landscape(true))
-        .map_err(|e| anyhow!("{:?}", e))?;
     drawable
         .set_scroll_offset(0)
         .map_err(|e| anyhow!("{:?}", e))?;
 
-    Ok(drawable)
+    Ok(DisplayDriver {
+        inner: drawable,
+        commands,
+    })
 }
@@ -0,0 +1,214 @@
+//! Per-unit correction of the window offset
+//!
+//! The visible area of the panels is not placed at exactly the same
+//! position in the controller memory on every unit, which leaves a line of
+//! garbage at one edge and cuts the opposite one. [`calibrate`] lets the
+//! user move a border with the arrow keys until it is aligned with the
+//! edges of the panel, and saves the correction in a [`Store`], e.g. an
+//! [`NvsStore`]. [`build`] loads and applies it from the same store at
+//! boot, before placing the window.
+//!
+//! [`build`]: super::build
+//! [`NvsStore`]: crate::storage::nvs::NvsStore
+use anyhow::{anyhow, Result};
+use core::fmt::Debug;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+};
+use std::{
+    sync::atomic::{AtomicI16, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::keyboard::{KeyImprint, KeyboardScanner};
+use crate::storage::Store;
+
+/// Store key of the correction
+pub const CALIBRATION_KEY: &str = "display_offset";
+
+/// Largest correction in pixels in each direction
+pub const MAX_CORRECTION: i16 = 32;
+
+/// Interval between the keyboard scans
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+static X: AtomicI16 = AtomicI16::new(0);
+static Y: AtomicI16 = AtomicI16::new(0);
+
+/// Correction in pixels added to the window offset of the panel
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    pub x: i16,
+    pub y: i16,
+}
+
+impl Calibration {
+    /// Create new correction, limited to [`MAX_CORRECTION`].
+    pub fn new(x: i16, y: i16) -> Self {
+        Self {
+            x: x.clamp(-MAX_CORRECTION, MAX_CORRECTION),
+            y: y.clamp(-MAX_CORRECTION, MAX_CORRECTION),
+        }
+    }
+
+    /// Returns the correction in effect.
+    pub fn current() -> Self {
+        Self {
+            x: X.load(Ordering::Relaxed),
+            y: Y.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Put the correction in effect from the next drawing on.
+    pub fn apply(&self) {
+        X.store(self.x, Ordering::Relaxed);
+        Y.store(self.y, Ordering::Relaxed);
+    }
+
+    /// Read the correction from the store, saved as the two offsets
+    /// separated by a space. Returns `None` if none is saved.
+    pub fn load(store: &impl Store) -> Result<Option<Self>> {
+        let Some(text) = store.read_string(CALIBRATION_KEY)? else {
+            return Ok(None);
+        };
+        match text.split_whitespace().collect::<Vec<_>>()[..] {
+            [x, y] => Ok(Some(Self::new(x.parse()?, y.parse()?))),
+            _ => Err(anyhow!("invalid display calibration: {:?}", text)),
+        }
+    }
+
+    /// Write the correction to the store for the next boots.
+    pub fn save(&self, store: &mut impl Store) -> Result<()> {
+        store.write(CALIBRATION_KEY, format!("{} {}", self.x, self.y).as_bytes())
+    }
+
+    /// Delete the saved correction, going back to the default offset of
+    /// the panel at the next boot.
+    pub fn erase(store: &mut impl Store) -> Result<()> {
+        store.remove(CALIBRATION_KEY)?;
+        Ok(())
+    }
+}
+
+/// Returns the offset of the panel with the correction.
+pub(crate) fn corrected((x, y): (u16, u16)) -> (u16, u16) {
+    let correction = Calibration::current();
+    (
+        x.saturating_add_signed(correction.x),
+        y.saturating_add_signed(correction.y),
+    )
+}
+
+/// Let the user align a border with the edges of the panel and save the
+/// correction.
+///
+/// The arrow keys (`;`, `.`, `,` and `/`) move the border by one pixel,
+/// Backspace goes back to the default offset, Enter saves the correction
+/// to the store and returns it, and Esc (`` ` ``) restores the previous correction and
+/// returns `None`.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::display::{self, calibration};
+///
+/// let mut display = display::build(/* ... */).unwrap();
+/// if let Some(calibration) = calibration::calibrate(&mut display, &mut keyboard, &mut store).unwrap() {
+///     println!("saved {:?}", calibration);
+/// }
+/// ```
+pub fn calibrate<D>(
+    display: &mut D,
+    keyboard: &mut impl KeyboardScanner,
+    store: &mut impl Store,
+) -> Result<Option<Calibration>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: Debug,
+{
+    let previous = Calibration::current();
+    let mut calibration = previous;
+    let mut pressed = Vec::new();
+    draw(display, calibration).map_err(|e| anyhow!("{:?}", e))?;
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let keys: Vec<KeyImprint> = keyboard
+            .scan_pressed_keytypes()?
+            .iter()
+            .map(|x| x.imprint())
+            .collect();
+        let new_keys: Vec<KeyImprint> = keys
+            .iter()
+            .filter(|x| !pressed.contains(*x))
+            .copied()
+            .collect();
+        pressed = keys;
+
+        for key in new_keys {
+            let (x, y) = (calibration.x, calibration.y);
+            calibration = match key {
+                KeyImprint::SemiColon => Calibration::new(x, y - 1),
+                KeyImprint::Period => Calibration::new(x, y + 1),
+                KeyImprint::Comma => Calibration::new(x - 1, y),
+                KeyImprint::Slash => Calibration::new(x + 1, y),
+                KeyImprint::Backspace => Calibration::default(),
+                KeyImprint::Enter => {
+                    calibration.save(store)?;
+                    return Ok(Some(calibration));
+                }
+                KeyImprint::Backquote => {
+                    previous.apply();
+                    return Ok(None);
+                }
+                _ => continue,
+            };
+        }
+        if calibration != Calibration::current() {
+            calibration.apply();
+            draw(display, calibration).map_err(|e| anyhow!("{:?}", e))?;
+        }
+    }
+}
+
+/// Draw the border on the outermost pixels with the instructions.
+fn draw<D>(display: &mut D, calibration: Calibration) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    display.clear(Rgb565::BLACK)?;
+    let area = display.bounding_box();
+    area.into_styled(PrimitiveStyle::with_stroke(Rgb565::WHITE, 1))
+        .draw(display)?;
+    Rectangle::new(
+        area.top_left + Point::new(2, 2),
+        area.size - Size::new(4, 4),
+    )
+    .into_styled(PrimitiveStyle::with_stroke(Rgb565::RED, 1))
+    .draw(display)?;
+
+    let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+    let lines = [
+        "Align the white border".to_string(),
+        "with the panel edges".to_string(),
+        String::new(),
+        "Arrows: move  Del: reset".to_string(),
+        "Enter: save  Esc: cancel".to_string(),
+        format!("Offset {:+} {:+}", calibration.x, calibration.y),
+    ];
+    let center = area.center() - Point::new(0, lines.len() as i32 * 6);
+    for (i, line) in lines.iter().enumerate() {
+        Text::with_alignment(
+            line,
+            center + Point::new(0, i as i32 * 12),
+            style,
+            Alignment::Center,
+        )
+        .draw(display)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,258 @@
+//! Raw ST7789 commands for features the driver does not cover
+//!
+//! [`SendCommand::send_command`] sends one of the [`Command`]s known to be
+//! safe: they change how the panel refreshes or renders the picture, but
+//! not the state tracked by the driver (address window, orientation,
+//! pixel format) nor the power settings that could harm the panel. Sleep
+//! in and out need delays around them and are sent by
+//! [`power`](super::power).
+//!
+//! The driver does not give access to its interface, so the display is
+//! built over a [`CommandInterface`] sharing a queue with the
+//! [`DisplayDriver`]. A command is queued, then the driver is made to send
+//! its orientation again: the interface sends the queued commands in its
+//! place, followed by a NOP, so the MADCTL register is left untouched.
+//!
+//! The interface also stages the pixels written by the driver, e.g. by
+//! [`FrameBuffer::flush`], in buffers of the
+//! [`shared_dma_pool`](crate::memory::shared_dma_pool), so they go out in
+//! long DMA transfers instead of one short transfer per 64 pixels.
+//!
+//! [`FrameBuffer::flush`]: crate::framebuffer::FrameBuffer::flush
+use anyhow::{anyhow, bail, Result};
+use display_interface::{DataFormat, DisplayError, WriteOnlyDataCommand};
+use mipidsi::models::Model;
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use super::DisplayDriver;
+use crate::memory;
+
+/// Command queued by [`SendCommand::send_command`]
+struct Pending {
+    code: u8,
+    params: Vec<u8>,
+    /// Time the controller needs before the next command
+    delay: Duration,
+}
+
+/// NOP, sent in place of the command of the driver flushing the queue
+const NOP: u8 = 0x00;
+
+#[derive(Default)]
+struct Queue {
+    commands: Vec<Pending>,
+    /// The next command of the driver only flushes the queue
+    flushing: bool,
+    /// The parameters of the command of the driver are dropped
+    dropping: bool,
+}
+
+/// Queue of the commands shared by a [`DisplayDriver`] and its interface
+#[derive(Clone, Default)]
+pub(crate) struct CommandQueue(Arc<Mutex<Queue>>);
+
+impl CommandQueue {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// ST7789 command that can be sent with [`SendCommand::send_command`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// PTLON: show only the partial area, the rest is black
+    PartialModeOn,
+    /// NORON: leave the partial mode
+    NormalModeOn,
+    /// INVOFF: stop inverting the colors
+    InversionOff,
+    /// INVON: invert the colors
+    InversionOn,
+    /// GAMSET: select a predefined gamma curve (1 byte: 1, 2, 4 or 8)
+    GammaSet,
+    /// DISPOFF: blank the panel, keeping the frame memory
+    DisplayOff,
+    /// DISPON: show the frame memory again
+    DisplayOn,
+    /// PTLAR: rows of the partial area (4 bytes: start and end, big endian)
+    PartialArea,
+    /// IDMOFF: leave the idle mode
+    IdleModeOff,
+    /// IDMON: show 8 colors only, to save power
+    IdleModeOn,
+    /// PORCTRL: porch setting (5 bytes)
+    PorchControl,
+    /// FRCTRL1: frame rate in the idle and partial modes (3 bytes)
+    FrameRateControl1,
+    /// FRCTRL2: frame rate in the normal mode (1 byte, 0x0F for 60 Hz)
+    FrameRateControl2,
+    /// PVGAMCTRL: positive voltage gamma curve (14 bytes)
+    PositiveGamma,
+    /// NVGAMCTRL: negative voltage gamma curve (14 bytes)
+    NegativeGamma,
+}
+
+impl Command {
+    /// Returns the command code.
+    pub fn code(&self) -> u8 {
+        match self {
+            Command::PartialModeOn => 0x12,
+            Command::NormalModeOn => 0x13,
+            Command::InversionOff => 0x20,
+            Command::InversionOn => 0x21,
+            Command::GammaSet => 0x26,
+            Command::DisplayOff => 0x28,
+            Command::DisplayOn => 0x29,
+            Command::PartialArea => 0x30,
+            Command::IdleModeOff => 0x38,
+            Command::IdleModeOn => 0x39,
+            Command::PorchControl => 0xB2,
+            Command::FrameRateControl1 => 0xB3,
+            Command::FrameRateControl2 => 0xC6,
+            Command::PositiveGamma => 0xE0,
+            Command::NegativeGamma => 0xE1,
+        }
+    }
+
+    /// Returns the number of parameter bytes.
+    pub fn param_len(&self) -> usize {
+        match self {
+            Command::GammaSet | Command::FrameRateControl2 => 1,
+            Command::FrameRateControl1 => 3,
+            Command::PartialArea => 4,
+            Command::PorchControl => 5,
+            Command::PositiveGamma | Command::NegativeGamma => 14,
+            _ => 0,
+        }
+    }
+}
+
+/// Sending of raw commands to the display
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::display::{self, command::{Command, SendCommand}};
+///
+/// let mut display = display::build(/* ... */).unwrap();
+/// // 8 colors at 39 Hz while nothing happens on the screen
+/// display.send_command(Command::FrameRateControl2, &[0x1F]).unwrap();
+/// display.send_command(Command::IdleModeOn, &[]).unwrap();
+/// ```
+pub trait SendCommand {
+    /// Send the command with its parameters. Fails without sending
+    /// anything if the number of parameters is not the one of the command.
+    fn send_command(&mut self, command: Command, params: &[u8]) -> Result<()>;
+}
+
+impl SendCommand for DisplayDriver<'_> {
+    fn send_command(&mut self, command: Command, params: &[u8]) -> Result<()> {
+        if params.len() != command.param_len() {
+            bail!(
+                "{:?} takes {} parameter bytes, not {}",
+                command,
+                command.param_len(),
+                params.len()
+            );
+        }
+        send_raw(self, command.code(), params, Duration::ZERO)
+    }
+}
+
+/// Send any command with its parameters, and wait for the delay before
+/// the next command.
+pub(crate) fn send_raw<M: Model>(
+    display: &mut DisplayDriver<'_, M>,
+    code: u8,
+    params: &[u8],
+    delay: Duration,
+) -> Result<()> {
+    {
+        let mut queue = display.commands.lock();
+        queue.commands.push(Pending {
+            code,
+            params: params.to_vec(),
+            delay,
+        });
+        queue.flushing = true;
+    }
+    // the interface replaces the MADCTL of the driver with the queue and
+    // a NOP; the orientation is the one set by the builder
+    let orientation = display.inner.orientation();
+    let result = display
+        .inner
+        .set_orientation(orientation)
+        .map_err(|e| anyhow!("{:?}", e));
+    display.commands.lock().flushing = false;
+    result
+}
+
+/// Display interface that sends the commands queued by
+/// [`SendCommand::send_command`] before the commands of the driver
+pub struct CommandInterface<DI> {
+    inner: DI,
+    queue: CommandQueue,
+}
+
+impl<DI> CommandInterface<DI> {
+    pub(crate) fn new(inner: DI, queue: CommandQueue) -> Self {
+        Self { inner, queue }
+    }
+}
+
+impl<DI: WriteOnlyDataCommand> CommandInterface<DI> {
+    /// Send the pixels as big endian bytes, a DMA buffer at a time.
+    fn send_pixels(&mut self, pixels: &mut dyn Iterator<Item = u16>) -> Result<(), DisplayError> {
+        let mut buffer = memory::shared_dma_pool().acquire();
+        loop {
+            let mut len = 0;
+            for (dst, pixel) in buffer.chunks_exact_mut(2).zip(&mut *pixels) {
+                dst.copy_from_slice(&pixel.to_be_bytes());
+                len += 2;
+            }
+            if len == 0 {
+                return Ok(());
+            }
+            self.inner.send_data(DataFormat::U8(&buffer[..len]))?;
+        }
+    }
+}
+
+impl<DI: WriteOnlyDataCommand> WriteOnlyDataCommand for CommandInterface<DI> {
+    fn send_commands(&mut self, cmd: DataFormat<'_>) -> Result<(), DisplayError> {
+        let (pending, flushing) = {
+            let mut queue = self.queue.lock();
+            let flushing = std::mem::take(&mut queue.flushing);
+            queue.dropping = flushing;
+            (std::mem::take(&mut queue.commands), flushing)
+        };
+        for command in pending {
+            self.inner.send_commands(DataFormat::U8(&[command.code]))?;
+            if !command.params.is_empty() {
+                self.inner.send_data(DataFormat::U8(&command.params))?;
+            }
+            if !command.delay.is_zero() {
+                thread::sleep(command.delay);
+            }
+        }
+        if flushing {
+            self.inner.send_commands(DataFormat::U8(&[NOP]))
+        } else {
+            self.inner.send_commands(cmd)
+        }
+    }
+
+    fn send_data(&mut self, buf: DataFormat<'_>) -> Result<(), DisplayError> {
+        if std::mem::take(&mut self.queue.lock().dropping) {
+            return Ok(());
+        }
+        match buf {
+            DataFormat::U16BEIter(pixels) => self.send_pixels(pixels),
+            buf => self.inner.send_data(buf),
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Panel sleep and wake in step with the backlight
+//!
+//! Turning the backlight on while the panel is still asleep, or before it
+//! has shown the frame memory, flashes the screen white. [`DisplayPower`]
+//! orders the steps: going to sleep, the backlight is ramped down before
+//! the panel is turned off; waking, the panel leaves the sleep mode and
+//! warms up, the frame can be redrawn, and only then is the backlight
+//! ramped up.
+//!
+//! The sleep and display commands are sent as raw
+//! [`command`](super::command)s, so the orientation set by
+//! [`build`](super::build) is kept across sleep and wake.
+//!
+//! At boot the panel is initialized by [`build`](super::build) with the
+//! backlight off: draw the first frame before ramping it on.
+//!
+//! # Examples
+//!
+//! ```
+//! use cardputer::display::power::DisplayPower;
+//!
+//! let mut board = Board::detect(peripherals, &store).unwrap();
+//! fb.flush(&mut board.display).unwrap();
+//! board.backlight.ramp_on(Duration::from_millis(200)).unwrap();
+//!
+//! let mut power = DisplayPower::new();
+//! loop {
+//!     if idle_for > Duration::from_secs(30) && !power.is_sleeping() {
+//!         power.sleep(&mut board.display, &mut board.backlight).unwrap();
+//!     }
+//!     if key_pressed && power.is_sleeping() {
+//!         power.wake(&mut board.display, &mut board.backlight).unwrap();
+//!     }
+//! }
+//! ```
+use anyhow::Result;
+use mipidsi::models::Model;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{command::send_raw, DisplayDriver};
+use crate::backlight::Backlight;
+
+/// SLPIN, DISPOFF, DISPON and SLPOUT
+const SLEEP_IN: u8 = 0x10;
+const SLEEP_OUT: u8 = 0x11;
+const DISPLAY_OFF: u8 = 0x28;
+const DISPLAY_ON: u8 = 0x29;
+
+/// Time the controller needs after sleep in or out before the next command
+const COMMAND_DELAY: Duration = Duration::from_millis(5);
+
+/// Shortest time between sleep in and sleep out
+const SLEEP_INTERVAL: Duration = Duration::from_millis(120);
+
+/// Sequencer of the panel sleep mode and the backlight
+#[derive(Debug, Clone)]
+pub struct DisplayPower {
+    ramp: Duration,
+    warm_up: Duration,
+    sleeping: bool,
+    /// Time of the last sleep in or out
+    changed: Option<Instant>,
+}
+
+impl Default for DisplayPower {
+    fn default() -> Self {
+        Self {
+            ramp: Duration::from_millis(200),
+            warm_up: Duration::from_millis(120),
+            sleeping: false,
+            changed: None,
+        }
+    }
+}
+
+impl DisplayPower {
+    /// Create new sequencer for an awake panel.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the duration of the backlight ramps (200 ms).
+    pub fn with_ramp(mut self, ramp: Duration) -> Self {
+        self.ramp = ramp;
+        self
+    }
+
+    /// Set the time the panel needs after leaving the sleep mode before it
+    /// is shown (120 ms).
+    pub fn with_warm_up(mut self, warm_up: Duration) -> Self {
+        self.warm_up = warm_up;
+        self
+    }
+
+    /// Returns true while the panel is in the sleep mode.
+    pub fn is_sleeping(&self) -> bool {
+        self.sleeping
+    }
+
+    /// Ramp the backlight off, then turn the panel off and put it in the
+    /// sleep mode. The frame memory is kept.
+    pub fn sleep<M: Model>(
+        &mut self,
+        display: &mut DisplayDriver<'_, M>,
+        backlight: &mut Backlight,
+    ) -> Result<()> {
+        if self.sleeping {
+            return Ok(());
+        }
+        backlight.ramp_off(self.ramp)?;
+        self.wait_interval();
+        send_raw(display, DISPLAY_OFF, &[], Duration::ZERO)?;
+        send_raw(display, SLEEP_IN, &[], COMMAND_DELAY)?;
+        self.sleeping = true;
+        self.changed = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Wake the panel and ramp the backlight on once it shows the frame
+    /// memory.
+    pub fn wake<M: Model>(
+        &mut self,
+        display: &mut DisplayDriver<'_, M>,
+        backlight: &mut Backlight,
+    ) -> Result<()> {
+        self.wake_with(display, backlight, |_| Ok(()))
+    }
+
+    /// Wake the panel, call `redraw` to update the frame memory while the
+    /// panel warms up, and ramp the backlight on once the frame is shown.
+    pub fn wake_with<'a, M: Model>(
+        &mut self,
+        display: &mut DisplayDriver<'a, M>,
+        backlight: &mut Backlight,
+        redraw: impl FnOnce(&mut DisplayDriver<'a, M>) -> Result<()>,
+    ) -> Result<()> {
+        if !self.sleeping {
+            return Ok(());
+        }
+        // the backlight may have been turned on directly
+        backlight.off()?;
+        self.wait_interval();
+        send_raw(display, SLEEP_OUT, &[], COMMAND_DELAY)?;
+        let woke = Instant::now();
+        self.sleeping = false;
+        self.changed = Some(woke);
+
+        redraw(display)?;
+        thread::sleep(self.warm_up.saturating_sub(woke.elapsed()));
+        send_raw(display, DISPLAY_ON, &[], Duration::ZERO)?;
+        backlight.ramp_on(self.ramp)
+    }
+
+    /// Wait until sleep in or out may be sent again.
+    fn wait_interval(&self) {
+        if let Some(changed) = self.changed {
+            thread::sleep(SLEEP_INTERVAL.saturating_sub(changed.elapsed()));
+        }
+    }
+}
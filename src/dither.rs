@@ -0,0 +1,192 @@
+//! Ordered dithering from RGB888 to RGB565
+//!
+//! RGB565 has only 32 levels of red and blue, so smooth gradients and
+//! photos show bands. A 4x4 Bayer matrix spreads the lost bits over
+//! neighbouring pixels, which the small panel blends back together.
+use embedded_graphics::{
+    pixelcolor::{Rgb565, Rgb888},
+    prelude::*,
+    primitives::Rectangle,
+};
+
+use crate::color;
+
+/// 4x4 Bayer threshold matrix
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Conversion from RGB888 to RGB565
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dithering {
+    /// Nearest color
+    #[default]
+    None,
+    /// 4x4 Bayer ordered dithering
+    Ordered,
+}
+
+impl Dithering {
+    /// Convert the color of the pixel at the position.
+    pub fn convert(&self, color: Rgb888, x: i32, y: i32) -> Rgb565 {
+        match self {
+            Dithering::None => color.into(),
+            Dithering::Ordered => ordered(color, x, y),
+        }
+    }
+}
+
+/// Convert the color of the pixel at the position with ordered dithering.
+pub fn ordered(color: Rgb888, x: i32, y: i32) -> Rgb565 {
+    let threshold = BAYER[(y & 3) as usize][(x & 3) as usize] as u16;
+    let channel = |value: u8, bits: u32| {
+        let step = 1u16 << (8 - bits);
+        let value = value as u16 + threshold * step / 16;
+        (value.min(255) >> (8 - bits)) as u8
+    };
+    Rgb565::new(
+        channel(color.r(), 5),
+        channel(color.g(), 6),
+        channel(color.b(), 5),
+    )
+}
+
+/// Draw target accepting RGB888 that dithers into an RGB565 target
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::dither::{DitherTarget, Dithering};
+///
+/// let bmp: Bmp<Rgb888> = Bmp::from_slice(include_bytes!("photo.bmp")).unwrap();
+/// Image::new(&bmp, Point::zero())
+///     .draw(&mut DitherTarget::new(&mut fb, Dithering::Ordered))
+///     .unwrap();
+/// ```
+pub struct DitherTarget<'a, D> {
+    target: &'a mut D,
+    dithering: Dithering,
+}
+
+impl<'a, D> DitherTarget<'a, D>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    /// Wrap the target.
+    pub fn new(target: &'a mut D, dithering: Dithering) -> Self {
+        Self { target, dithering }
+    }
+}
+
+impl<D> Dimensions for DitherTarget<'_, D>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<D> DrawTarget for DitherTarget<'_, D>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    type Color = Rgb888;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let dithering = self.dithering;
+        self.target.draw_iter(
+            pixels.into_iter().map(|Pixel(point, color)| {
+                Pixel(point, dithering.convert(color, point.x, point.y))
+            }),
+        )
+    }
+}
+
+/// Direction of a [`Gradient`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Direction {
+    /// From the left edge to the right edge
+    #[default]
+    Horizontal,
+    /// From the top edge to the bottom edge
+    Vertical,
+}
+
+/// Linear gradient filling a rectangle, dithered by default
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::dither::{Direction, Gradient};
+///
+/// Gradient::new(fb.bounding_box(), Rgb888::new(0, 0, 64), Rgb888::new(0, 96, 160))
+///     .with_direction(Direction::Vertical)
+///     .draw(&mut fb)
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gradient {
+    area: Rectangle,
+    start: Rgb888,
+    end: Rgb888,
+    direction: Direction,
+    dithering: Dithering,
+}
+
+impl Gradient {
+    /// Create new gradient from the start color to the end color.
+    pub fn new(area: Rectangle, start: Rgb888, end: Rgb888) -> Self {
+        Self {
+            area,
+            start,
+            end,
+            direction: Direction::default(),
+            dithering: Dithering::Ordered,
+        }
+    }
+
+    /// Set the direction (horizontal).
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Set the dithering (ordered).
+    pub fn with_dithering(mut self, dithering: Dithering) -> Self {
+        self.dithering = dithering;
+        self
+    }
+
+    /// Returns the color at the position from 0 to `length - 1`.
+    fn color_at(&self, position: u32, length: u32) -> Rgb888 {
+        let t = position as f32 / (length.max(2) - 1) as f32;
+        color::lerp(self.start, self.end, t)
+    }
+}
+
+impl Drawable for Gradient {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let top_left = self.area.top_left;
+        let Size { width, height } = self.area.size;
+        let colors = (0..height).flat_map(|y| {
+            (0..width).map(move |x| {
+                let color = match self.direction {
+                    Direction::Horizontal => self.color_at(x, width),
+                    Direction::Vertical => self.color_at(y, height),
+                };
+                let point = top_left + Point::new(x as i32, y as i32);
+                self.dithering.convert(color, point.x, point.y)
+            })
+        });
+        target.fill_contiguous(&self.area, colors)
+    }
+}
@@ -0,0 +1,220 @@
+//! Input focus of nested widgets
+//!
+//! The widgets that take text input (an editor, a dialog over it) push
+//! themselves on a [`Focus`] stack, and the top one has the focus.
+//! [`KeyRouter`] splits the keys of each keyboard update: the chords of
+//! the global [`HotkeyRegistry`] fire first, whatever has the focus; the
+//! media keys and NumLock go to every widget; the other keys only reach
+//! the focused widget. The modifier state is given to all of them, so an
+//! unfocused widget can still show it.
+//!
+//! The [`ScreenManager`](crate::screen::ScreenManager) keeps one stack for
+//! all its screens and drops the focus of the widgets of a screen when the
+//! screen is popped or replaced.
+use crate::hotkey::HotkeyRegistry;
+use crate::keyboard::{pipeline::Modifiers, KeyboardState, Modified};
+
+/// Identifier of a widget on the focus stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FocusId(u32);
+
+struct Entry {
+    id: FocusId,
+    name: String,
+    /// Number of screens on the stack when the widget took the focus
+    depth: usize,
+}
+
+/// Stack of the widgets that took the focus, the top one having it
+#[derive(Default)]
+pub struct Focus {
+    entries: Vec<Entry>,
+    next_id: u32,
+    /// Number of screens of the screen manager
+    depth: usize,
+}
+
+impl Focus {
+    /// Create new stack without focused widget.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give the focus to a new widget of the name, e.g. a dialog opened
+    /// over an editor.
+    pub fn push(&mut self, name: &str) -> FocusId {
+        let id = FocusId(self.next_id);
+        self.next_id += 1;
+        self.entries.push(Entry {
+            id,
+            name: name.to_string(),
+            depth: self.depth,
+        });
+        id
+    }
+
+    /// Remove the widget from the stack. If it had the focus, the widget
+    /// below it gets it back.
+    pub fn release(&mut self, id: FocusId) {
+        self.entries.retain(|x| x.id != id);
+    }
+
+    /// Move the widget to the top of the stack. Returns false if it is
+    /// not on the stack.
+    pub fn raise(&mut self, id: FocusId) -> bool {
+        let Some(i) = self.entries.iter().position(|x| x.id == id) else {
+            return false;
+        };
+        let entry = self.entries.remove(i);
+        self.entries.push(entry);
+        true
+    }
+
+    /// Returns the widget with the focus.
+    pub fn focused(&self) -> Option<FocusId> {
+        self.entries.last().map(|x| x.id)
+    }
+
+    /// Returns the name of the widget with the focus.
+    pub fn focused_name(&self) -> Option<&str> {
+        self.entries.last().map(|x| x.name.as_str())
+    }
+
+    /// Returns true if the widget has the focus.
+    pub fn has_focus(&self, id: FocusId) -> bool {
+        self.focused() == Some(id)
+    }
+
+    /// Set the number of screens, dropping the widgets of the screens
+    /// removed.
+    pub(crate) fn set_depth(&mut self, depth: usize) {
+        self.entries.retain(|x| x.depth <= depth);
+        self.depth = depth;
+    }
+}
+
+/// Returns true if the key reaches the widgets without the focus.
+fn is_global(key: Modified) -> bool {
+    matches!(
+        key,
+        Modified::VolumeUp
+            | Modified::VolumeDown
+            | Modified::Mute
+            | Modified::PlayPause
+            | Modified::NextTrack
+            | Modified::PreviousTrack
+            | Modified::BrightnessUp
+            | Modified::BrightnessDown
+            | Modified::NumLock
+    )
+}
+
+/// Keys of one keyboard update split by the focus
+#[derive(Debug, Clone, Default)]
+pub struct RoutedKeys {
+    focused: Option<FocusId>,
+    text: Vec<Modified>,
+    global: Vec<Modified>,
+    modifiers: Modifiers,
+}
+
+impl RoutedKeys {
+    /// Returns the pressed keys delivered to the widget: all of them if it
+    /// has the focus, only the global ones otherwise.
+    pub fn keys_for(&self, id: FocusId) -> Vec<Modified> {
+        if self.focused == Some(id) {
+            self.all()
+        } else {
+            self.global.clone()
+        }
+    }
+
+    /// Returns the pressed keys delivered to the widgets outside of the
+    /// focus stack: all of them while no widget has the focus.
+    pub fn unfocused(&self) -> Vec<Modified> {
+        if self.focused.is_none() {
+            self.all()
+        } else {
+            self.global.clone()
+        }
+    }
+
+    /// Returns the media keys and NumLock, delivered to every widget.
+    pub fn global(&self) -> &[Modified] {
+        &self.global
+    }
+
+    /// Returns the state of the modifier keys.
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    /// Returns the widget the keys were routed to.
+    pub fn focused(&self) -> Option<FocusId> {
+        self.focused
+    }
+
+    fn all(&self) -> Vec<Modified> {
+        let mut keys = self.text.clone();
+        keys.extend(&self.global);
+        keys
+    }
+}
+
+/// Router of the keys to the global hotkeys and the focused widget
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::focus::{Focus, KeyRouter};
+/// use cardputer::hotkey::{Chord, HotkeyRegistry};
+///
+/// let mut hotkeys = HotkeyRegistry::new();
+/// hotkeys.register(Chord::new(KeyImprint::S).ctrl(), || save());
+/// let mut router = KeyRouter::new(hotkeys);
+///
+/// let mut focus = Focus::new();
+/// let editor = focus.push("editor");
+/// let dialog = focus.push("find");
+///
+/// keyboard_state.update(&mut keyboard).unwrap();
+/// let keys = router.route(&keyboard_state, &focus);
+/// find_dialog.handle(&keys.keys_for(dialog));
+/// // only the media keys while the dialog is open
+/// editor_widget.handle(&keys.keys_for(editor));
+/// if keys.keys_for(dialog).contains(&Modified::Escape) {
+///     focus.release(dialog);
+/// }
+/// ```
+#[derive(Default)]
+pub struct KeyRouter {
+    hotkeys: HotkeyRegistry,
+}
+
+impl KeyRouter {
+    /// Create new router firing the hotkeys of the registry.
+    pub fn new(hotkeys: HotkeyRegistry) -> Self {
+        Self { hotkeys }
+    }
+
+    /// Returns the registry of the global hotkeys.
+    pub fn hotkeys(&mut self) -> &mut HotkeyRegistry {
+        &mut self.hotkeys
+    }
+
+    /// Fire the hotkeys of the last update and split the other pressed
+    /// keys for the widget with the focus.
+    pub fn route(&mut self, state: &KeyboardState, focus: &Focus) -> RoutedKeys {
+        let (global, text) = self
+            .hotkeys
+            .dispatch(state)
+            .into_iter()
+            .partition(|x| is_global(*x));
+        RoutedKeys {
+            focused: focus.focused(),
+            text,
+            global,
+            modifiers: state.modifiers(),
+        }
+    }
+}
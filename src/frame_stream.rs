@@ -0,0 +1,100 @@
+//! Frame buffer streaming to a host over USB serial
+//!
+//! For UI regression tests: the device sends each changed frame, and a
+//! program on the desktop decodes them with [`FrameDecoder`] and compares
+//! them with golden images.
+//!
+//! The format and the decoder are defined in the hardware-independent
+//! `cardputer-core` crate, which builds on the desktop, and re-exported
+//! here.
+use anyhow::Result;
+use std::time::{Duration, Instant};
+
+use crate::color;
+use crate::display::{DISPLAY_SIZE_HEIGHT, DISPLAY_SIZE_WIDTH};
+use crate::framebuffer::FrameBuffer;
+use crate::xmodem::Port;
+pub use cardputer_core::frame_stream::{encode, Frame, FrameDecoder, MAGIC};
+
+/// Streamer of the frame buffer
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::frame_stream::FrameStreamer;
+/// use cardputer::usb_serial::UsbSerial;
+///
+/// let mut usb = UsbSerial::new().unwrap();
+/// let mut streamer = FrameStreamer::new().with_max_fps(5);
+/// loop {
+///     draw(&mut fb);
+///     fb.flush(&mut display).unwrap();
+///     streamer.send(&fb, &mut usb).unwrap();
+/// }
+/// ```
+pub struct FrameStreamer {
+    sequence: u32,
+    /// Raw pixels of the last frame sent
+    previous: Vec<u16>,
+    current: Vec<u16>,
+    min_interval: Duration,
+    last_sent: Option<Instant>,
+}
+
+impl FrameStreamer {
+    /// Create new streamer without rate limit.
+    pub fn new() -> Self {
+        Self {
+            sequence: 0,
+            previous: Vec::new(),
+            current: Vec::new(),
+            min_interval: Duration::ZERO,
+            last_sent: None,
+        }
+    }
+
+    /// Set the maximum number of frames sent per second.
+    pub fn with_max_fps(mut self, fps: u32) -> Self {
+        self.min_interval = Duration::from_secs(1) / fps.max(1);
+        self
+    }
+
+    /// Returns the sequence number of the next frame.
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Send the frame buffer if it changed since the last frame sent.
+    /// Skipped when called faster than the maximum rate.
+    pub fn send(&mut self, fb: &FrameBuffer, port: &mut impl Port) -> Result<()> {
+        if self
+            .last_sent
+            .is_some_and(|x| x.elapsed() < self.min_interval)
+        {
+            return Ok(());
+        }
+        self.current.clear();
+        self.current
+            .extend(fb.pixels().iter().map(|x| color::to_raw(*x)));
+        if self.previous == self.current {
+            return Ok(());
+        }
+        let frame = encode(
+            self.sequence,
+            DISPLAY_SIZE_WIDTH,
+            DISPLAY_SIZE_HEIGHT,
+            &self.current,
+        );
+        port.write_all(&frame)?;
+        self.sequence = self.sequence.wrapping_add(1);
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.last_sent = Some(Instant::now());
+        Ok(())
+    }
+}
+
+impl Default for FrameStreamer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,369 @@
+//! Off-screen frame buffer of the display size
+use anyhow::{anyhow, Result};
+use core::convert::Infallible;
+use core::fmt::Debug;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyleBuilder},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::Rectangle,
+    text::{Baseline, Text},
+};
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use crate::display::{DISPLAY_SIZE_HEIGHT, DISPLAY_SIZE_WIDTH};
+use crate::memory::{Buffer, Placement};
+use crate::power::CpuBoost;
+
+/// Frame buffer that holds a whole screen in RAM
+///
+/// Draw into the buffer with embedded-graphics and transfer it to the
+/// display at once with [`FrameBuffer::flush`].
+///
+/// # Examples
+///
+/// ```
+/// use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
+/// use cardputer::{display, framebuffer::FrameBuffer};
+///
+/// let mut display = display::build(/* ... */).unwrap();
+///
+/// let mut fb = FrameBuffer::new();
+/// fb.clear(Rgb565::BLUE).unwrap();
+/// fb.flush(&mut display).unwrap();
+/// ```
+pub struct FrameBuffer {
+    pixels: Buffer<Rgb565>,
+    /// Updated by the flushes, which only read the pixels
+    stats: Cell<DisplayStats>,
+}
+
+impl FrameBuffer {
+    /// Create new frame buffer filled with black, in PSRAM if available.
+    pub fn new() -> Self {
+        let len = DISPLAY_SIZE_WIDTH as usize * DISPLAY_SIZE_HEIGHT as usize;
+        Self {
+            pixels: Buffer::new(len, Rgb565::BLACK, Placement::Large),
+            stats: Cell::new(DisplayStats::default()),
+        }
+    }
+
+    /// Returns the pixels in row-major order.
+    pub fn pixels(&self) -> &[Rgb565] {
+        &self.pixels
+    }
+
+    /// Returns the pixels in row-major order.
+    pub fn pixels_mut(&mut self) -> &mut [Rgb565] {
+        &mut self.pixels
+    }
+
+    /// Transfer the whole buffer to the display. A [`DisplayDriver`]
+    /// sends the pixels through the shared DMA pool.
+    ///
+    /// [`DisplayDriver`]: crate::display::DisplayDriver
+    pub fn flush<D>(&self, display: &mut D) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        let _boost = CpuBoost::new();
+        let started = Instant::now();
+        display
+            .fill_contiguous(&self.bounding_box(), self.pixels.iter().copied())
+            .map_err(|e| anyhow!("{:?}", e))?;
+
+        self.record(self.pixels.len(), started.elapsed());
+        Ok(())
+    }
+
+    /// Transfer the area of the buffer to the display. The parts outside
+    /// the display are ignored.
+    pub fn flush_area<D>(&self, area: &Rectangle, display: &mut D) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        let area = area.intersection(&self.bounding_box());
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+        let _boost = CpuBoost::new();
+        let started = Instant::now();
+        let width = DISPLAY_SIZE_WIDTH as usize;
+        let (x, y) = (area.top_left.x as usize, area.top_left.y as usize);
+        let (w, h) = (area.size.width as usize, area.size.height as usize);
+        let colors = (y..y + h).flat_map(|row| self.pixels[row * width + x..][..w].iter().copied());
+        display
+            .fill_contiguous(&area, colors)
+            .map_err(|e| anyhow!("{:?}", e))?;
+
+        self.record(w * h, started.elapsed());
+        Ok(())
+    }
+
+    /// Returns the statistics of the flushes since the creation or the last reset.
+    pub fn stats(&self) -> DisplayStats {
+        self.stats.get()
+    }
+
+    /// Clear the statistics.
+    pub fn reset_stats(&mut self) {
+        self.stats.set(DisplayStats::default());
+    }
+
+    /// Count a flush of the pixels.
+    fn record(&self, pixels: usize, elapsed: Duration) {
+        let mut stats = self.stats.get();
+        stats.add_flush(pixels, elapsed);
+        self.stats.set(stats);
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        let (x, y) = (point.x, point.y);
+        if x < 0 || y < 0 || x >= DISPLAY_SIZE_WIDTH as i32 || y >= DISPLAY_SIZE_HEIGHT as i32 {
+            return None;
+        }
+        Some(y as usize * DISPLAY_SIZE_WIDTH as usize + x as usize)
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        Size::new(DISPLAY_SIZE_WIDTH as u32, DISPLAY_SIZE_HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let Some(i) = self.index(point) {
+                self.pixels[i] = color;
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        let Some(bottom_right) = area.bottom_right() else {
+            return Ok(());
+        };
+        let width = DISPLAY_SIZE_WIDTH as usize;
+        let (left, right) = (area.top_left.x as usize, bottom_right.x as usize);
+        for y in area.top_left.y as usize..=bottom_right.y as usize {
+            self.pixels[y * width + left..=y * width + right].fill(color);
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.pixels.fill(color);
+        Ok(())
+    }
+}
+
+/// Width of [`LowResFrameBuffer`]
+pub const LOW_RES_WIDTH: u16 = DISPLAY_SIZE_WIDTH / 2;
+/// Height of [`LowResFrameBuffer`]
+pub const LOW_RES_HEIGHT: u16 = DISPLAY_SIZE_HEIGHT / 2;
+
+/// Quarter-size frame buffer shown pixel-doubled
+///
+/// Apps draw at 120x67 and [`LowResFrameBuffer::flush`] scales each pixel
+/// to 2x2 on the display, for a quarter of the RAM and fill cost of
+/// [`FrameBuffer`]. The odd last line of the display repeats the last row.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::framebuffer::LowResFrameBuffer;
+///
+/// let mut fb = LowResFrameBuffer::new();
+/// Rectangle::new(Point::new(10, 10), Size::new(8, 8))
+///     .into_styled(PrimitiveStyle::with_fill(Rgb565::RED))
+///     .draw(&mut fb)
+///     .unwrap();
+/// fb.flush(&mut display).unwrap();
+/// ```
+pub struct LowResFrameBuffer {
+    pixels: Buffer<Rgb565>,
+    /// Updated by the flushes, which only read the pixels
+    stats: Cell<DisplayStats>,
+}
+
+impl LowResFrameBuffer {
+    /// Create new frame buffer filled with black.
+    pub fn new() -> Self {
+        let len = LOW_RES_WIDTH as usize * LOW_RES_HEIGHT as usize;
+        Self {
+            pixels: Buffer::new(len, Rgb565::BLACK, Placement::Internal),
+            stats: Cell::new(DisplayStats::default()),
+        }
+    }
+
+    /// Returns the pixels in row-major order.
+    pub fn pixels(&self) -> &[Rgb565] {
+        &self.pixels
+    }
+
+    /// Returns the pixels in row-major order.
+    pub fn pixels_mut(&mut self) -> &mut [Rgb565] {
+        &mut self.pixels
+    }
+
+    /// Transfer the buffer to the whole display, doubling each pixel.
+    pub fn flush<D>(&self, display: &mut D) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        let _boost = CpuBoost::new();
+        let started = Instant::now();
+        let (width, height) = (LOW_RES_WIDTH as usize, LOW_RES_HEIGHT as usize);
+        let pixels = &self.pixels;
+        let doubled = (0..DISPLAY_SIZE_HEIGHT as usize).flat_map(|y| {
+            let row = (y / 2).min(height - 1) * width;
+            (0..DISPLAY_SIZE_WIDTH as usize).map(move |x| pixels[row + x / 2])
+        });
+        let area = Rectangle::new(
+            Point::zero(),
+            Size::new(DISPLAY_SIZE_WIDTH as u32, DISPLAY_SIZE_HEIGHT as u32),
+        );
+        display
+            .fill_contiguous(&area, doubled)
+            .map_err(|e| anyhow!("{:?}", e))?;
+
+        let mut stats = self.stats.get();
+        stats.add_flush(
+            area.size.width as usize * area.size.height as usize,
+            started.elapsed(),
+        );
+        self.stats.set(stats);
+        Ok(())
+    }
+
+    /// Returns the statistics of the flushes since the creation or the last reset.
+    pub fn stats(&self) -> DisplayStats {
+        self.stats.get()
+    }
+
+    /// Clear the statistics.
+    pub fn reset_stats(&mut self) {
+        self.stats.set(DisplayStats::default());
+    }
+}
+
+impl Default for LowResFrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginDimensions for LowResFrameBuffer {
+    fn size(&self) -> Size {
+        Size::new(LOW_RES_WIDTH as u32, LOW_RES_HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for LowResFrameBuffer {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let (width, height) = (LOW_RES_WIDTH as i32, LOW_RES_HEIGHT as i32);
+        for Pixel(point, color) in pixels {
+            if (0..width).contains(&point.x) && (0..height).contains(&point.y) {
+                self.pixels[(point.y * width + point.x) as usize] = color;
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.pixels.fill(color);
+        Ok(())
+    }
+}
+
+/// Performance counters of the display transfers
+///
+/// Can be drawn as a debug overlay in the top-left corner.
+///
+/// # Examples
+///
+/// ```
+/// let stats = fb.stats();
+/// stats.draw(&mut fb).unwrap();
+/// fb.flush(&mut display).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DisplayStats {
+    /// Number of frames flushed
+    pub frames: u32,
+    /// Number of bytes transferred to the display
+    pub bytes: u64,
+    /// Total time spent in flushing
+    pub total_flush_time: Duration,
+    /// Time spent in the last flush
+    pub last_flush_time: Duration,
+}
+
+impl DisplayStats {
+    /// Returns the average time spent in a flush.
+    pub fn average_flush_time(&self) -> Duration {
+        self.total_flush_time
+            .checked_div(self.frames)
+            .unwrap_or_default()
+    }
+
+    /// Count a flush of the pixels.
+    fn add_flush(&mut self, pixels: usize, elapsed: Duration) {
+        self.frames += 1;
+        self.bytes += pixels as u64 * 2;
+        self.total_flush_time += elapsed;
+        self.last_flush_time = elapsed;
+    }
+}
+
+impl Drawable for DisplayStats {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X10)
+            .text_color(Rgb565::YELLOW)
+            .background_color(Rgb565::BLACK)
+            .build();
+        let text = format!(
+            "{}f {}KB {:.1}ms",
+            self.frames,
+            self.bytes / 1024,
+            self.average_flush_time().as_secs_f32() * 1000.0
+        );
+        Text::with_baseline(&text, Point::zero(), style, Baseline::Top).draw(target)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+//! Gamepad-style input abstraction over the keyboard
+use crate::keyboard::{KeyImprint, KeyboardState};
+
+/// Logical gamepad button
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Start,
+}
+
+impl Button {
+    /// All buttons
+    pub const ALL: [Button; 7] = [
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+        Button::A,
+        Button::B,
+        Button::Start,
+    ];
+
+    fn mask(&self) -> u8 {
+        1 << (*self as u8)
+    }
+}
+
+/// Map from keys to gamepad buttons with per-frame button state
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::gamepad::{Button, InputMap};
+///
+/// let mut input = InputMap::wasd();
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     input.update(&keyboard_state);
+///     if input.is_pressed(Button::Left) {
+///         x -= 1;
+///     }
+///     if input.is_just_pressed(Button::A) {
+///         jump();
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct InputMap {
+    bindings: Vec<(KeyImprint, Button)>,
+    current: u8,
+    previous: u8,
+}
+
+impl InputMap {
+    /// Create new map without bindings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// W/A/S/D for the directions, J for A, K for B and Enter for Start.
+    pub fn wasd() -> Self {
+        Self::new()
+            .with_binding(KeyImprint::W, Button::Up)
+            .with_binding(KeyImprint::S, Button::Down)
+            .with_binding(KeyImprint::A, Button::Left)
+            .with_binding(KeyImprint::D, Button::Right)
+            .with_binding(KeyImprint::J, Button::A)
+            .with_binding(KeyImprint::K, Button::B)
+            .with_binding(KeyImprint::Enter, Button::Start)
+    }
+
+    /// The Fn-arrow keys (; . , /) for the directions, Z for A, X for B
+    /// and Enter for Start. Fn does not need to be held.
+    pub fn arrows() -> Self {
+        Self::new()
+            .with_binding(KeyImprint::SemiColon, Button::Up)
+            .with_binding(KeyImprint::Period, Button::Down)
+            .with_binding(KeyImprint::Comma, Button::Left)
+            .with_binding(KeyImprint::Slash, Button::Right)
+            .with_binding(KeyImprint::Z, Button::A)
+            .with_binding(KeyImprint::X, Button::B)
+            .with_binding(KeyImprint::Enter, Button::Start)
+    }
+
+    /// Bind the key to the button. A button can be bound to several keys.
+    pub fn with_binding(mut self, key: KeyImprint, button: Button) -> Self {
+        self.bindings.push((key, button));
+        self
+    }
+
+    /// Remove all bindings of the key.
+    pub fn unbind(&mut self, key: KeyImprint) {
+        self.bindings.retain(|(k, _)| *k != key);
+    }
+
+    /// Update the button state from the keyboard state. Call once per frame.
+    pub fn update(&mut self, state: &KeyboardState) {
+        let mut held = state.hold_imprints();
+        for (imprint, pressed) in [
+            (KeyImprint::LeftFn, state.is_fn_pressed()),
+            (KeyImprint::LeftShift, state.is_shift_pressed()),
+            (KeyImprint::LeftCtrl, state.is_ctrl_pressed()),
+            (KeyImprint::LeftOpt, state.is_opt_pressed()),
+            (KeyImprint::LeftAlt, state.is_alt_pressed()),
+        ] {
+            if pressed {
+                held.push(imprint);
+            }
+        }
+
+        self.previous = self.current;
+        self.current = self
+            .bindings
+            .iter()
+            .filter(|(key, _)| held.contains(key))
+            .fold(0, |acc, (_, button)| acc | button.mask());
+    }
+
+    /// Returns true while the button is held.
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.current & button.mask() != 0
+    }
+
+    /// Returns true if the button was pressed in this frame.
+    pub fn is_just_pressed(&self, button: Button) -> bool {
+        self.current & !self.previous & button.mask() != 0
+    }
+
+    /// Returns true if the button was released in this frame.
+    pub fn is_just_released(&self, button: Button) -> bool {
+        !self.current & self.previous & button.mask() != 0
+    }
+
+    /// Returns the buttons being held.
+    pub fn pressed_buttons(&self) -> Vec<Button> {
+        Button::ALL
+            .into_iter()
+            .filter(|x| self.is_pressed(*x))
+            .collect()
+    }
+}
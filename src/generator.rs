@@ -0,0 +1,220 @@
+//! Signal generator on the speaker
+//!
+//! Produces continuous waveforms for testing external circuits or as a toy
+//! function generator. The phase is kept across calls, so the output has
+//! no discontinuities between buffers.
+use anyhow::Result;
+use std::{
+    f32::consts::PI,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::speaker::Speaker;
+
+/// Number of samples generated per write to the speaker
+const BLOCK_SIZE: usize = 256;
+
+/// Waveform of the generator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Triangle,
+    /// White noise; the frequency is ignored
+    Noise,
+}
+
+/// How the frequency changes during a sweep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepMode {
+    Linear,
+    /// Same time per octave
+    Logarithmic,
+}
+
+/// Signal generator
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::generator::{SignalGenerator, SweepMode, Waveform};
+///
+/// let mut generator = SignalGenerator::new(speaker.sample_rate())
+///     .with_waveform(Waveform::Square)
+///     .with_frequency(1000.0)
+///     .with_amplitude(0.5);
+/// generator.play(&mut speaker, Duration::from_secs(1)).unwrap();
+///
+/// generator.set_waveform(Waveform::Sine);
+/// generator
+///     .sweep(&mut speaker, 20.0, 8000.0, Duration::from_secs(10), SweepMode::Logarithmic)
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct SignalGenerator {
+    sample_rate: u32,
+    waveform: Waveform,
+    frequency: f32,
+    amplitude: f32,
+    /// Position in the period, from 0 to 1
+    phase: f32,
+    /// State of the xorshift noise source
+    seed: u32,
+}
+
+impl SignalGenerator {
+    /// Create new generator of a 440 Hz sine at full amplitude.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            waveform: Waveform::Sine,
+            frequency: 440.0,
+            amplitude: 1.0,
+            phase: 0.0,
+            seed: 0x2545_F491,
+        }
+    }
+
+    /// Set the waveform.
+    pub fn with_waveform(mut self, waveform: Waveform) -> Self {
+        self.waveform = waveform;
+        self
+    }
+
+    /// Set the frequency in Hz.
+    pub fn with_frequency(mut self, frequency: f32) -> Self {
+        self.set_frequency(frequency);
+        self
+    }
+
+    /// Set the amplitude from 0.0 to 1.0 of full scale.
+    pub fn with_amplitude(mut self, amplitude: f32) -> Self {
+        self.set_amplitude(amplitude);
+        self
+    }
+
+    /// Returns the sample rate in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the waveform.
+    pub fn waveform(&self) -> Waveform {
+        self.waveform
+    }
+
+    /// Change the waveform.
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    /// Returns the frequency in Hz.
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    /// Change the frequency, limited to the Nyquist frequency.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency.clamp(0.0, self.sample_rate as f32 / 2.0);
+    }
+
+    /// Returns the amplitude.
+    pub fn amplitude(&self) -> f32 {
+        self.amplitude
+    }
+
+    /// Change the amplitude, clamped to 0.0 to 1.0.
+    pub fn set_amplitude(&mut self, amplitude: f32) {
+        self.amplitude = amplitude.clamp(0.0, 1.0);
+    }
+
+    /// Fill the buffer with the next samples.
+    pub fn fill(&mut self, output: &mut [i16]) {
+        let step = self.frequency / self.sample_rate as f32;
+        for sample in output {
+            *sample = (self.next_value() * self.amplitude * i16::MAX as f32) as i16;
+            self.phase = (self.phase + step).fract();
+        }
+    }
+
+    /// Play the signal for the duration.
+    pub fn play(&mut self, speaker: &mut Speaker, duration: Duration) -> Result<()> {
+        let mut remaining = self.samples(duration);
+        let mut block = [0i16; BLOCK_SIZE];
+        while remaining > 0 {
+            let len = remaining.min(BLOCK_SIZE);
+            self.fill(&mut block[..len]);
+            speaker.play(&block[..len])?;
+            remaining -= len;
+        }
+        Ok(())
+    }
+
+    /// Play the signal until the flag is set.
+    pub fn play_until(&mut self, speaker: &mut Speaker, stop: &AtomicBool) -> Result<()> {
+        let mut block = [0i16; BLOCK_SIZE];
+        while !stop.load(Ordering::Relaxed) {
+            self.fill(&mut block);
+            speaker.play(&block)?;
+        }
+        Ok(())
+    }
+
+    /// Sweep the frequency from `from` to `to` Hz over the duration.
+    ///
+    /// The frequency is left at `to` afterwards.
+    pub fn sweep(
+        &mut self,
+        speaker: &mut Speaker,
+        from: f32,
+        to: f32,
+        duration: Duration,
+        mode: SweepMode,
+    ) -> Result<()> {
+        let total = self.samples(duration).max(1);
+        let mut block = [0i16; BLOCK_SIZE];
+        let mut done = 0;
+        while done < total {
+            let position = done as f32 / total as f32;
+            let frequency = match mode {
+                SweepMode::Linear => from + (to - from) * position,
+                SweepMode::Logarithmic => {
+                    from.max(1.0) * (to.max(1.0) / from.max(1.0)).powf(position)
+                }
+            };
+            self.set_frequency(frequency);
+            let len = (total - done).min(BLOCK_SIZE);
+            self.fill(&mut block[..len]);
+            speaker.play(&block[..len])?;
+            done += len;
+        }
+        self.set_frequency(to);
+        Ok(())
+    }
+
+    fn samples(&self, duration: Duration) -> usize {
+        (duration.as_micros() * self.sample_rate as u128 / 1_000_000) as usize
+    }
+
+    /// Returns the value at the current phase from -1.0 to 1.0.
+    fn next_value(&mut self) -> f32 {
+        match self.waveform {
+            Waveform::Sine => (2.0 * PI * self.phase).sin(),
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Noise => {
+                self.seed ^= self.seed << 13;
+                self.seed ^= self.seed >> 17;
+                self.seed ^= self.seed << 5;
+                self.seed as i32 as f32 / i32::MAX as f32
+            }
+        }
+    }
+}
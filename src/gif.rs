@@ -0,0 +1,538 @@
+//! Animated GIF playback
+//!
+//! Decodes GIF87a/GIF89a data frame by frame, composing each frame onto
+//! an internal canvas with the disposal method requested by the file, and
+//! draws the canvas onto any `DrawTarget<Color = Rgb565>` (the display
+//! itself or a [`FrameBuffer`](crate::framebuffer::FrameBuffer)).
+use anyhow::{anyhow, bail, Result};
+use core::fmt::Debug;
+use embedded_graphics::{
+    pixelcolor::{Rgb565, Rgb888},
+    prelude::*,
+    primitives::Rectangle,
+};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::dither::Dithering;
+use crate::memory::{Buffer, Placement};
+
+/// Delay used for frames that request no delay, as web browsers do
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// Largest canvas in pixels, 2 MiB in RGB565
+pub const MAX_CANVAS_PIXELS: usize = 1024 * 1024;
+
+/// How the canvas is placed on the draw target
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Fit {
+    /// Draw in the original size, centered and cropped to the target
+    #[default]
+    Crop,
+    /// Scale to the largest size that fits the target, keeping the aspect ratio
+    Scale,
+}
+
+/// Disposal method of a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Disposal {
+    Keep,
+    Background,
+    Previous,
+}
+
+/// Area of the previous frame waiting to be disposed
+struct PendingDisposal {
+    method: Disposal,
+    area: Rectangle,
+    saved: Vec<Rgb565>,
+}
+
+/// Animated GIF player
+///
+/// # Examples
+///
+/// ```
+/// use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
+/// use cardputer::{display, framebuffer::FrameBuffer, gif::{Fit, Gif}};
+///
+/// let mut display = display::build(/* ... */).unwrap();
+/// let mut fb = FrameBuffer::new();
+///
+/// let mut gif = Gif::new(include_bytes!("animation.gif")).unwrap();
+/// gif.set_fit(Fit::Scale);
+/// while let Some(delay) = gif.next_frame().unwrap() {
+///     gif.draw(&mut fb).unwrap();
+///     fb.flush(&mut display).unwrap();
+///     std::thread::sleep(delay);
+/// }
+/// ```
+///
+/// Data on the SD card can be played after reading it into memory
+/// with `std::fs::read`.
+pub struct Gif<'a> {
+    data: &'a [u8],
+    start: usize,
+    position: usize,
+    width: u16,
+    height: u16,
+    global_palette: Vec<Rgb888>,
+    background: Rgb565,
+    dithering: Dithering,
+    canvas: Buffer<Rgb565>,
+    fit: Fit,
+    repetitions: Option<u16>,
+    played: u16,
+    pending: Option<PendingDisposal>,
+}
+
+/// Graphic control extension of the next image
+#[derive(Debug, Clone, Copy)]
+struct GraphicControl {
+    disposal: Disposal,
+    delay: Duration,
+    transparent: Option<u8>,
+}
+
+impl Default for GraphicControl {
+    fn default() -> Self {
+        Self {
+            disposal: Disposal::Keep,
+            delay: DEFAULT_FRAME_DELAY,
+            transparent: None,
+        }
+    }
+}
+
+impl<'a> Gif<'a> {
+    /// Parse the header of the GIF data.
+    ///
+    /// Fails if the canvas is empty or larger than [`MAX_CANVAS_PIXELS`],
+    /// before allocating it.
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        if data.len() < 13 || (&data[0..6] != b"GIF87a" && &data[0..6] != b"GIF89a") {
+            bail!("not a GIF image");
+        }
+        let width = u16::from_le_bytes([data[6], data[7]]);
+        let height = u16::from_le_bytes([data[8], data[9]]);
+        let pixels = width as usize * height as usize;
+        if pixels == 0 || pixels > MAX_CANVAS_PIXELS {
+            bail!("unsupported GIF canvas size {}x{}", width, height);
+        }
+        let flags = data[10];
+        let background_index = data[11];
+
+        let mut position = 13;
+        let global_palette = if flags & 0x80 != 0 {
+            let len = 3 << ((flags & 0x07) + 1);
+            let table = data
+                .get(position..position + len)
+                .ok_or_else(|| anyhow!("truncated global color table"))?;
+            position += len;
+            palette(table)
+        } else {
+            Vec::new()
+        };
+        let background = global_palette
+            .get(background_index as usize)
+            .map_or(Rgb565::BLACK, |x| (*x).into());
+
+        Ok(Self {
+            data,
+            start: position,
+            position,
+            width,
+            height,
+            global_palette,
+            background,
+            dithering: Dithering::None,
+            canvas: Buffer::try_new(pixels, background, Placement::Large)?,
+            fit: Fit::default(),
+            repetitions: None,
+            played: 0,
+            pending: None,
+        })
+    }
+
+    /// Returns the width of the animation.
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    /// Returns the height of the animation.
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Set how the animation is placed on the draw target.
+    pub fn set_fit(&mut self, fit: Fit) {
+        self.fit = fit;
+    }
+
+    /// Set the conversion of the 24-bit palette colors, applied from the next frame.
+    ///
+    /// Ordered dithering reduces banding in photos and gradients.
+    pub fn set_dithering(&mut self, dithering: Dithering) {
+        self.dithering = dithering;
+    }
+
+    /// Compose the next frame onto the canvas and return its display time.
+    ///
+    /// Returns `None` when the animation has finished, honoring the loop
+    /// count stored in the file (plays once when there is none), or when
+    /// the file has no frame to repeat.
+    pub fn next_frame(&mut self) -> Result<Option<Duration>> {
+        let mut control = GraphicControl::default();
+        let mut rewound = false;
+        loop {
+            match self.read_u8()? {
+                0x21 => {
+                    let label = self.read_u8()?;
+                    let block = self.read_sub_blocks()?;
+                    match label {
+                        0xf9 if block.len() >= 4 => control = graphic_control(&block),
+                        0xff if block.starts_with(b"NETSCAPE2.0") && block.len() >= 14 => {
+                            self.repetitions = Some(u16::from_le_bytes([block[12], block[13]]));
+                        }
+                        _ => {}
+                    }
+                }
+                0x2c => {
+                    self.read_image(&control)?;
+                    return Ok(Some(control.delay));
+                }
+                0x3b => {
+                    self.played = self.played.saturating_add(1);
+                    let again = match self.repetitions {
+                        Some(0) => true,
+                        Some(n) => self.played <= n,
+                        None => false,
+                    };
+                    // a whole pass since the rewind found no image
+                    if !again || rewound {
+                        return Ok(None);
+                    }
+                    self.rewind();
+                    rewound = true;
+                }
+                x => bail!("unexpected block 0x{:02x}", x),
+            }
+        }
+    }
+
+    /// Restart the animation from the first frame.
+    pub fn rewind(&mut self) {
+        self.position = self.start;
+        self.pending = None;
+        self.canvas.fill(self.background);
+    }
+
+    /// Draw the current canvas onto the target.
+    pub fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let (src_w, src_h) = (self.width as u32, self.height as u32);
+        let target_size = target.bounding_box().size;
+        let (dst_w, dst_h) = (target_size.width, target_size.height);
+        if src_w == 0 || src_h == 0 || dst_w == 0 || dst_h == 0 {
+            return Ok(());
+        }
+
+        let (out_w, out_h, span_w, span_h) = match self.fit {
+            Fit::Crop => {
+                let (w, h) = (src_w.min(dst_w), src_h.min(dst_h));
+                (w, h, w, h)
+            }
+            Fit::Scale => {
+                if src_w * dst_h <= dst_w * src_h {
+                    ((src_w * dst_h / src_h).max(1), dst_h, src_w, src_h)
+                } else {
+                    (dst_w, (src_h * dst_w / src_w).max(1), src_w, src_h)
+                }
+            }
+        };
+        let (src_x, src_y) = ((src_w - span_w) / 2, (src_h - span_h) / 2);
+        let area = Rectangle::new(
+            target.bounding_box().top_left
+                + Point::new(((dst_w - out_w) / 2) as i32, ((dst_h - out_h) / 2) as i32),
+            Size::new(out_w, out_h),
+        );
+
+        let colors = (0..out_h).flat_map(move |y| {
+            let row = (src_y + y * span_h / out_h) as usize * self.width as usize;
+            (0..out_w).map(move |x| self.canvas[row + (src_x + x * span_w / out_w) as usize])
+        });
+        target.fill_contiguous(&area, colors)
+    }
+
+    /// Play the animation on the target until it finishes.
+    pub fn play<D>(&mut self, target: &mut D) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        while let Some(delay) = self.next_frame()? {
+            let started = Instant::now();
+            self.draw(target).map_err(|e| anyhow!("{:?}", e))?;
+            if let Some(rest) = delay.checked_sub(started.elapsed()) {
+                thread::sleep(rest);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.position)
+            .ok_or_else(|| anyhow!("unexpected end of data"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or_else(|| anyhow!("unexpected end of data"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn read_sub_blocks(&mut self) -> Result<Vec<u8>> {
+        let mut block = Vec::new();
+        loop {
+            let len = self.read_u8()? as usize;
+            if len == 0 {
+                return Ok(block);
+            }
+            block.extend_from_slice(self.read_bytes(len)?);
+        }
+    }
+
+    fn read_image(&mut self, control: &GraphicControl) -> Result<()> {
+        let descriptor = self.read_bytes(9)?;
+        let left = u16::from_le_bytes([descriptor[0], descriptor[1]]);
+        let top = u16::from_le_bytes([descriptor[2], descriptor[3]]);
+        let width = u16::from_le_bytes([descriptor[4], descriptor[5]]);
+        let height = u16::from_le_bytes([descriptor[6], descriptor[7]]);
+        let flags = descriptor[8];
+        let interlaced = flags & 0x40 != 0;
+
+        let local_palette = if flags & 0x80 != 0 {
+            let len = 3 << ((flags & 0x07) + 1);
+            Some(palette(self.read_bytes(len)?))
+        } else {
+            None
+        };
+        let min_code_size = self.read_u8()?;
+        let data = self.read_sub_blocks()?;
+
+        self.dispose();
+
+        let area = Rectangle::new(
+            Point::new(left as i32, top as i32),
+            Size::new(width as u32, height as u32),
+        )
+        .intersection(&Rectangle::new(
+            Point::zero(),
+            Size::new(self.width as u32, self.height as u32),
+        ));
+        let saved = if control.disposal == Disposal::Previous {
+            self.area_pixels(&area)
+        } else {
+            Vec::new()
+        };
+        self.pending = Some(PendingDisposal {
+            method: control.disposal,
+            area,
+            saved,
+        });
+
+        if width == 0 {
+            return Ok(());
+        }
+        let palette = local_palette.as_ref().unwrap_or(&self.global_palette);
+        let dithering = self.dithering;
+        let canvas_width = self.width as usize;
+        let mut index = 0usize;
+        decode_lzw(&data, min_code_size, |color_index| {
+            let row = index / width as usize;
+            let col = index % width as usize;
+            index += 1;
+            if row >= height as usize || control.transparent == Some(color_index) {
+                return;
+            }
+            let row = if interlaced {
+                interlaced_row(row, height as usize)
+            } else {
+                row
+            };
+            let (x, y) = (left as usize + col, top as usize + row);
+            if x >= canvas_width || y >= self.height as usize {
+                return;
+            }
+            if let Some(color) = palette.get(color_index as usize) {
+                self.canvas[y * canvas_width + x] = dithering.convert(*color, x as i32, y as i32);
+            }
+        })
+    }
+
+    /// Apply the disposal method of the previous frame.
+    fn dispose(&mut self) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        match pending.method {
+            Disposal::Keep => {}
+            Disposal::Background => {
+                let background = self.background;
+                for (x, y) in area_points(&pending.area) {
+                    self.canvas[y * self.width as usize + x] = background;
+                }
+            }
+            Disposal::Previous => {
+                for ((x, y), color) in area_points(&pending.area).zip(pending.saved) {
+                    self.canvas[y * self.width as usize + x] = color;
+                }
+            }
+        }
+    }
+
+    fn area_pixels(&self, area: &Rectangle) -> Vec<Rgb565> {
+        area_points(area)
+            .map(|(x, y)| self.canvas[y * self.width as usize + x])
+            .collect()
+    }
+}
+
+fn area_points(area: &Rectangle) -> impl Iterator<Item = (usize, usize)> {
+    let (left, top) = (area.top_left.x as usize, area.top_left.y as usize);
+    let (width, height) = (area.size.width as usize, area.size.height as usize);
+    (top..top + height).flat_map(move |y| (left..left + width).map(move |x| (x, y)))
+}
+
+fn palette(table: &[u8]) -> Vec<Rgb888> {
+    table
+        .chunks_exact(3)
+        .map(|rgb| Rgb888::new(rgb[0], rgb[1], rgb[2]))
+        .collect()
+}
+
+fn graphic_control(block: &[u8]) -> GraphicControl {
+    let flags = block[0];
+    let centis = u16::from_le_bytes([block[1], block[2]]);
+    GraphicControl {
+        disposal: match (flags >> 2) & 0x07 {
+            2 => Disposal::Background,
+            3 => Disposal::Previous,
+            _ => Disposal::Keep,
+        },
+        delay: if centis < 2 {
+            DEFAULT_FRAME_DELAY
+        } else {
+            Duration::from_millis(centis as u64 * 10)
+        },
+        transparent: (flags & 0x01 != 0).then_some(block[3]),
+    }
+}
+
+/// Convert the n-th decoded row of an interlaced image to its position.
+fn interlaced_row(n: usize, height: usize) -> usize {
+    let mut n = n;
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        let rows = (height + step - 1 - start) / step;
+        if n < rows {
+            return start + n * step;
+        }
+        n -= rows;
+    }
+    n
+}
+
+/// Decode LZW compressed image data and pass each color index to `emit`.
+fn decode_lzw(data: &[u8], min_code_size: u8, mut emit: impl FnMut(u8)) -> Result<()> {
+    const MAX_CODES: usize = 4096;
+
+    if !(1..=11).contains(&min_code_size) {
+        bail!("invalid LZW code size {}", min_code_size);
+    }
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut prefix = vec![0u16; MAX_CODES];
+    let mut suffix = vec![0u8; MAX_CODES];
+    let mut stack: Vec<u8> = Vec::with_capacity(MAX_CODES);
+
+    let mut code_size = min_code_size as u32 + 1;
+    let mut next = clear + 2;
+    let mut previous: Option<u16> = None;
+
+    let mut bytes = data.iter();
+    let mut bits = 0u32;
+    let mut bit_count = 0u32;
+
+    loop {
+        while bit_count < code_size {
+            let Some(byte) = bytes.next() else {
+                return Ok(());
+            };
+            bits |= (*byte as u32) << bit_count;
+            bit_count += 8;
+        }
+        let code = (bits & ((1 << code_size) - 1)) as u16;
+        bits >>= code_size;
+        bit_count -= code_size;
+
+        if code == clear {
+            code_size = min_code_size as u32 + 1;
+            next = clear + 2;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            return Ok(());
+        }
+
+        let Some(prev) = previous else {
+            if code > clear {
+                bail!("invalid LZW code {}", code);
+            }
+            emit(code as u8);
+            previous = Some(code);
+            continue;
+        };
+
+        let string = match code {
+            c if c < next => c,
+            c if c == next => prev,
+            c => bail!("invalid LZW code {}", c),
+        };
+        stack.clear();
+        let mut c = string;
+        while c > clear {
+            stack.push(suffix[c as usize]);
+            c = prefix[c as usize];
+        }
+        stack.push(c as u8);
+        let first = c as u8;
+
+        stack.iter().rev().for_each(|&index| emit(index));
+        if code == next {
+            emit(first);
+        }
+
+        if (next as usize) < MAX_CODES {
+            prefix[next as usize] = prev;
+            suffix[next as usize] = first;
+            next += 1;
+            if next as u32 == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        }
+        previous = Some(code);
+    }
+}
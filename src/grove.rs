@@ -5,9 +5,50 @@ use esp_idf_hal::{
     i2c::I2C0,
     i2c::{I2cConfig, I2cDriver},
     peripheral::Peripheral,
+    sys::{esp, i2c_port_t, i2c_reset_tx_fifo},
     units::Hertz,
 };
 
+pub mod at;
+pub mod cardkb;
+pub mod lora;
+pub mod monitor;
+pub mod onewire;
+pub mod serial;
+pub mod sht30;
+pub mod slave;
+
+#[cfg(feature = "async")]
+mod async_i2c;
+#[cfg(feature = "async")]
+pub use async_i2c::AsyncI2c;
+
+/// Port number of I2C0 in the ESP-IDF driver
+const I2C0_PORT: i2c_port_t = 0;
+
+/// Address of the status register of I2C0 (I2C_SR_REG)
+const I2C0_STATUS: usize = 0x6001_3008;
+
+/// Returns the number of bytes waiting in the transmit FIFO of I2C0 in
+/// slave mode, to know when the master has read them.
+///
+/// The driver moves a written byte to the FIFO from its interrupt, which
+/// runs before the write returns, so the count includes it.
+pub(crate) fn tx_fifo_len() -> usize {
+    // SAFETY: the status register of I2C0 is always mapped, and reading it
+    // has no side effect
+    let status = unsafe { core::ptr::read_volatile(I2C0_STATUS as *const u32) };
+    // TXFIFO_CNT, bits 18 to 23
+    ((status >> 18) & 0x3F) as usize
+}
+
+/// Drop the bytes waiting in the transmit FIFO of I2C0 in slave mode.
+pub(crate) fn reset_tx_fifo() -> Result<()> {
+    // SAFETY: only called with the slave driver of I2C0 installed
+    esp!(unsafe { i2c_reset_tx_fifo(I2C0_PORT) })?;
+    Ok(())
+}
+
 pub fn build<'a>(
     i2c: impl Peripheral<P = I2C0> + 'a,
     sda: impl Peripheral<P = Gpio2> + 'a,
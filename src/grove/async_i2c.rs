@@ -0,0 +1,116 @@
+//! Async wrapper of the Grove I2C driver
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use embedded_hal_async::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use esp_idf_hal::{
+    delay::BLOCK,
+    i2c::{I2cDriver, I2cError},
+    sys::{EspError, ESP_FAIL},
+};
+
+/// Grove I2C driver implementing `embedded_hal_async::i2c::I2c`
+///
+/// ESP-IDF has no interrupt-driven completion for the legacy I2C driver,
+/// so each transaction yields to the executor once and then runs to
+/// completion. Transfers to sensors take well under a millisecond, which
+/// keeps other tasks responsive.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::{self, AsyncI2c};
+/// use esp_idf_hal::prelude::*;
+///
+/// let i2c = grove::build(
+///     peripherals.i2c0,
+///     peripherals.pins.gpio2,
+///     peripherals.pins.gpio1,
+///     400.kHz().into(),
+/// )
+/// .unwrap();
+/// let mut sensor = Sht4x::new(AsyncI2c::new(i2c));
+/// let measurement = sensor.measure().await.unwrap();
+/// ```
+pub struct AsyncI2c<'a> {
+    driver: I2cDriver<'a>,
+}
+
+impl<'a> AsyncI2c<'a> {
+    /// Wrap the driver built by [`super::build`].
+    pub fn new(driver: I2cDriver<'a>) -> Self {
+        Self { driver }
+    }
+
+    /// Returns the wrapped driver.
+    pub fn release(self) -> I2cDriver<'a> {
+        self.driver
+    }
+}
+
+impl ErrorType for AsyncI2c<'_> {
+    type Error = I2cError;
+}
+
+impl I2c for AsyncI2c<'_> {
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        YieldNow(false).await;
+        self.driver.read(address, read, BLOCK).map_err(to_i2c_err)
+    }
+
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        YieldNow(false).await;
+        self.driver.write(address, write, BLOCK).map_err(to_i2c_err)
+    }
+
+    async fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        YieldNow(false).await;
+        self.driver
+            .write_read(address, write, read, BLOCK)
+            .map_err(to_i2c_err)
+    }
+
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        YieldNow(false).await;
+        self.driver
+            .transaction(address, operations, BLOCK)
+            .map_err(to_i2c_err)
+    }
+}
+
+/// Same conversion as the blocking embedded-hal implementation of the driver
+fn to_i2c_err(err: EspError) -> I2cError {
+    if err.code() == ESP_FAIL {
+        I2cError::new(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown), err)
+    } else {
+        I2cError::other(err)
+    }
+}
+
+/// Future that returns pending once to let the executor run other tasks
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! AT command transport on the Grove UART
+//!
+//! Sends a command terminated with CR LF and collects the response lines
+//! until the final result code. Lines that start with a registered prefix
+//! are unsolicited result codes (URC) and go to their callback instead,
+//! also while a command is waiting for its response.
+use anyhow::{bail, Result};
+use esp_idf_hal::{
+    delay::{TickType, NON_BLOCK},
+    gpio::{InputPin, OutputPin},
+    peripheral::Peripheral,
+    uart::{config::Config, Uart, UartDriver},
+    units::Hertz,
+};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Urc {
+    prefix: String,
+    callback: Box<dyn FnMut(&str) + Send>,
+}
+
+/// AT command modem on the Grove UART
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::at::AtModem;
+/// use esp_idf_hal::prelude::*;
+///
+/// let mut modem = AtModem::new(
+///     peripherals.uart1,
+///     peripherals.pins.gpio2,
+///     peripherals.pins.gpio1,
+///     115200.Hz(),
+/// )
+/// .unwrap();
+/// modem.on_urc("+CMTI:", |line| log::info!("new SMS: {}", line));
+/// for line in modem.command("AT+CSQ").unwrap() {
+///     log::info!("{}", line);
+/// }
+/// ```
+pub struct AtModem<'a> {
+    uart: UartDriver<'a>,
+    timeout: Duration,
+    urcs: Vec<Urc>,
+    line: Vec<u8>,
+    lines: VecDeque<String>,
+}
+
+impl<'a> AtModem<'a> {
+    /// Create new modem with the TX and RX pins of the Grove port (GPIO1 and GPIO2).
+    pub fn new<UART: Uart>(
+        uart: impl Peripheral<P = UART> + 'a,
+        tx: impl Peripheral<P = impl OutputPin> + 'a,
+        rx: impl Peripheral<P = impl InputPin> + 'a,
+        baudrate: Hertz,
+    ) -> Result<Self> {
+        let config = Config::new().baudrate(baudrate);
+        let uart = UartDriver::new(
+            uart,
+            tx,
+            rx,
+            Option::<esp_idf_hal::gpio::AnyInputPin>::None,
+            Option::<esp_idf_hal::gpio::AnyOutputPin>::None,
+            &config,
+        )?;
+        Ok(Self {
+            uart,
+            timeout: DEFAULT_TIMEOUT,
+            urcs: Vec::new(),
+            line: Vec::new(),
+            lines: VecDeque::new(),
+        })
+    }
+
+    /// Set the default response timeout (1 s).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Register a callback for the unsolicited result codes starting with the prefix.
+    pub fn on_urc(&mut self, prefix: &str, callback: impl FnMut(&str) + Send + 'static) {
+        self.urcs.push(Urc {
+            prefix: prefix.to_string(),
+            callback: Box::new(callback),
+        });
+    }
+
+    /// Send the command and return the response lines before `OK`.
+    ///
+    /// Fails on `ERROR`, `+CME ERROR` or `+CMS ERROR` and on timeout.
+    pub fn command(&mut self, command: &str) -> Result<Vec<String>> {
+        let mut lines = self.request(command, self.timeout, |line| line == "OK")?;
+        lines.pop();
+        Ok(lines)
+    }
+
+    /// Send the command and return the response lines up to and including
+    /// the line for which `is_final` returns true.
+    ///
+    /// For modules that do not answer with the standard result codes.
+    pub fn request(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+        is_final: impl Fn(&str) -> bool,
+    ) -> Result<Vec<String>> {
+        self.poll()?;
+        self.uart.write(command.as_bytes())?;
+        self.uart.write(b"\r\n")?;
+
+        let deadline = Instant::now() + timeout;
+        let mut lines = Vec::new();
+        while let Some(line) = self.read_line(deadline)? {
+            // skip the echo
+            if line == command || self.dispatch(&line) {
+                continue;
+            }
+            if line.contains("ERROR") {
+                bail!("{}: {}", command, line);
+            }
+            let done = is_final(&line);
+            lines.push(line);
+            if done {
+                return Ok(lines);
+            }
+        }
+        bail!("{}: timeout", command)
+    }
+
+    /// Read the received lines and pass the unsolicited result codes to their callbacks.
+    ///
+    /// Other lines are discarded.
+    pub fn poll(&mut self) -> Result<()> {
+        while let Some(line) = self.read_line(Instant::now())? {
+            self.dispatch(&line);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the line was an unsolicited result code.
+    fn dispatch(&mut self, line: &str) -> bool {
+        match self
+            .urcs
+            .iter_mut()
+            .find(|urc| line.starts_with(&urc.prefix))
+        {
+            Some(urc) => {
+                (urc.callback)(line);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the next non-empty line, or `None` if none was completed until the deadline.
+    fn read_line(&mut self, deadline: Instant) -> Result<Option<String>> {
+        let mut buf = [0u8; 64];
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Ok(Some(line));
+            }
+            let now = Instant::now();
+            // wait for the first byte only, the driver blocks until the buffer is full
+            let len = if now < deadline {
+                self.uart
+                    .read(&mut buf[..1], TickType::from(deadline - now).ticks())?
+            } else {
+                self.uart.read(&mut buf, NON_BLOCK)?
+            };
+            if len == 0 {
+                return Ok(None);
+            }
+            for byte in &buf[..len] {
+                if *byte == b'\n' {
+                    let line = String::from_utf8_lossy(&self.line).trim().to_string();
+                    self.line.clear();
+                    if !line.is_empty() {
+                        self.lines.push_back(line);
+                    }
+                } else {
+                    self.line.push(*byte);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Emulation and reading of the M5Stack CardKB unit
+//!
+//! The CardKB is an I2C slave at address 0x5F; each one-byte read
+//! returns the next key as ASCII, with 0xB4 to 0xB7 for the cursor keys,
+//! or 0 if no key was pressed. Existing M5 projects that read the CardKB
+//! work unchanged with the Cardputer connected to their Grove port, and
+//! [`CardKbReader`] reads a CardKB plugged into the Cardputer.
+use anyhow::{anyhow, Result};
+use embedded_hal::blocking::i2c::Read;
+use esp_idf_hal::{
+    delay::NON_BLOCK,
+    gpio::{Gpio1, Gpio2},
+    i2c::{I2cSlaveConfig, I2cSlaveDriver, I2C0},
+    peripheral::Peripheral,
+};
+
+use std::collections::VecDeque;
+
+use super::tx_fifo_len;
+use crate::keyboard::{KeyboardState, Modified};
+
+/// Size of the driver buffers
+const BUFFER_SIZE: usize = 32;
+
+/// Maximum number of keys waiting for the master
+const KEY_QUEUE_SIZE: usize = 32;
+
+/// CardKB-compatible I2C slave on the Grove port
+///
+/// Exactly one byte is kept in the transmit FIFO of the I2C controller: the
+/// next key, or 0 when none is waiting. Once the master has read it, the
+/// next key or a 0 takes its place, so every read gets a valid answer as
+/// from a real CardKB. Call [`CardKb::push_keys`] often enough to top the
+/// FIFO up between the reads of the master.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::cardkb::CardKb;
+///
+/// let mut cardkb = CardKb::new(
+///     peripherals.i2c0,
+///     peripherals.pins.gpio2,
+///     peripherals.pins.gpio1,
+/// )
+/// .unwrap();
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     cardkb.push_keys(&keyboard_state).unwrap();
+/// }
+/// ```
+pub struct CardKb<'a> {
+    driver: I2cSlaveDriver<'a>,
+    keys: VecDeque<u8>,
+}
+
+impl<'a> CardKb<'a> {
+    /// I2C address of the CardKB
+    pub const ADDRESS: u8 = 0x5F;
+
+    /// Create new emulator.
+    pub fn new(
+        i2c: impl Peripheral<P = I2C0> + 'a,
+        sda: impl Peripheral<P = Gpio2> + 'a,
+        scl: impl Peripheral<P = Gpio1> + 'a,
+    ) -> Result<Self> {
+        let config = I2cSlaveConfig::new()
+            .sda_enable_pullup(true)
+            .scl_enable_pullup(true)
+            .rx_buffer_length(BUFFER_SIZE)
+            .tx_buffer_length(BUFFER_SIZE);
+        let mut driver = I2cSlaveDriver::new(i2c, sda, scl, Self::ADDRESS, &config)?;
+        driver.write(&[0], NON_BLOCK)?;
+        Ok(Self {
+            driver,
+            keys: VecDeque::new(),
+        })
+    }
+
+    /// Queue the keys pressed in the last update for the master, and top
+    /// the transmit FIFO up if the master has read it.
+    ///
+    /// The oldest keys are dropped when the master does not read them.
+    pub fn push_keys(&mut self, state: &KeyboardState) -> Result<()> {
+        for key in state.pressed_keys().iter().filter_map(Modified::to_ascii) {
+            if self.keys.len() == KEY_QUEUE_SIZE {
+                self.keys.pop_front();
+            }
+            self.keys.push_back(key);
+        }
+        self.poll()
+    }
+
+    /// Top the transmit FIFO up with the next key, or 0, if the master has
+    /// read it.
+    pub fn poll(&mut self) -> Result<()> {
+        if tx_fifo_len() > 0 {
+            return Ok(());
+        }
+        let code = self.keys.front().copied().unwrap_or(0);
+        if self.driver.write(&[code], NON_BLOCK)? == 1 {
+            self.keys.pop_front();
+        }
+        Ok(())
+    }
+}
+
+/// Reader of a CardKB connected to the Grove port
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::cardkb::CardKbReader;
+///
+/// let mut cardkb = CardKbReader::new(bus.acquire());
+/// while let Some(key) = cardkb.read_key().unwrap() {
+///     log::info!("{:?}", key);
+/// }
+/// ```
+pub struct CardKbReader<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C, E> CardKbReader<I2C>
+where
+    I2C: Read<Error = E>,
+    E: core::fmt::Debug,
+{
+    pub fn new(i2c: I2C) -> Self {
+        Self { i2c }
+    }
+
+    /// Release the I2C driver.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// Returns the next key, or `None` if no key was pressed. Codes without
+    /// a key are skipped.
+    pub fn read_key(&mut self) -> Result<Option<Modified>> {
+        for _ in 0..BUFFER_SIZE {
+            let mut code = [0u8];
+            self.i2c
+                .read(CardKb::ADDRESS, &mut code)
+                .map_err(|e| anyhow!("{:?}", e))?;
+            if code[0] == 0 {
+                return Ok(None);
+            }
+            if let Some(key) = from_code(code[0]) {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Returns the key of a code sent by the CardKB, the inverse of
+/// [`Modified::to_ascii`].
+fn from_code(code: u8) -> Option<Modified> {
+    match code {
+        0x1B => Some(Modified::Escape),
+        0x0D => Some(Modified::Enter),
+        0x20 => Some(Modified::Space),
+        0x09 => Some(Modified::Tab),
+        0x08 => Some(Modified::Backspace),
+        0x7F => Some(Modified::Delete),
+        0xB4 => Some(Modified::LeftCursor),
+        0xB5 => Some(Modified::UpCursor),
+        0xB6 => Some(Modified::DownCursor),
+        0xB7 => Some(Modified::RightCursor),
+        0x21..=0x7E => Some(Modified::Graph(code as char)),
+        _ => None,
+    }
+}
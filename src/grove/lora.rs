@@ -0,0 +1,163 @@
+//! Point-to-point messaging with the Grove LoRa-E5 (Wio-E5) module
+//!
+//! Uses the test mode of the module firmware, which sends and receives
+//! raw LoRa packets without a LoRaWAN network.
+use anyhow::{anyhow, Result};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::at::AtModem;
+
+const TX_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Radio settings of the test mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoraConfig {
+    /// Frequency (MHz)
+    pub frequency: u32,
+    /// Spreading factor, 7 to 12
+    pub spreading_factor: u8,
+    /// Bandwidth (kHz), 125, 250 or 500
+    pub bandwidth: u16,
+    /// TX power (dBm)
+    pub power: i8,
+}
+
+impl Default for LoraConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 868,
+            spreading_factor: 7,
+            bandwidth: 125,
+            power: 14,
+        }
+    }
+}
+
+/// Received packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoraPacket {
+    pub data: Vec<u8>,
+    pub rssi: i16,
+    pub snr: i16,
+}
+
+#[derive(Default)]
+struct Received {
+    signal: (i16, i16),
+    packets: VecDeque<LoraPacket>,
+}
+
+/// LoRa-E5 module on the Grove UART
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::{at::AtModem, lora::{LoraConfig, LoraE5}};
+/// use esp_idf_hal::prelude::*;
+///
+/// let modem = AtModem::new(
+///     peripherals.uart1,
+///     peripherals.pins.gpio2,
+///     peripherals.pins.gpio1,
+///     9600.Hz(),
+/// )
+/// .unwrap();
+/// let mut lora = LoraE5::new(modem, LoraConfig::default()).unwrap();
+/// lora.send(b"hello").unwrap();
+/// loop {
+///     for packet in lora.receive().unwrap() {
+///         log::info!("{:?}", packet);
+///     }
+/// }
+/// ```
+pub struct LoraE5<'a> {
+    modem: AtModem<'a>,
+    received: Arc<Mutex<Received>>,
+}
+
+impl<'a> LoraE5<'a> {
+    /// Switch the module to the test mode and start receiving.
+    pub fn new(mut modem: AtModem<'a>, config: LoraConfig) -> Result<Self> {
+        let received = Arc::new(Mutex::new(Received::default()));
+
+        let shared = received.clone();
+        modem.on_urc("+TEST: LEN:", move |line| {
+            let value = |key| {
+                line.split(',')
+                    .find_map(|field| field.trim().strip_prefix(key))
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_default()
+            };
+            shared.lock().unwrap().signal = (value("RSSI:"), value("SNR:"));
+        });
+        let shared = received.clone();
+        modem.on_urc("+TEST: RX ", move |line| {
+            let hex = line["+TEST: RX ".len()..].trim_matches('"');
+            if let Some(data) = decode_hex(hex) {
+                let mut received = shared.lock().unwrap();
+                let (rssi, snr) = received.signal;
+                received.packets.push_back(LoraPacket { data, rssi, snr });
+            }
+        });
+
+        let mut lora = Self { modem, received };
+        lora.test_command("AT+MODE=TEST", "+MODE:")?;
+        lora.test_command(
+            &format!(
+                "AT+TEST=RFCFG,{},SF{},{},12,15,{},ON,OFF,OFF",
+                config.frequency, config.spreading_factor, config.bandwidth, config.power
+            ),
+            "+TEST: RFCFG",
+        )?;
+        lora.start_receive()?;
+        Ok(lora)
+    }
+
+    /// Send a packet and go back to receiving.
+    pub fn send(&mut self, data: &[u8]) -> Result<()> {
+        let hex: String = data.iter().map(|byte| format!("{:02X}", byte)).collect();
+        self.modem.request(
+            &format!("AT+TEST=TXLRPKT,\"{}\"", hex),
+            TX_TIMEOUT,
+            |line| line == "+TEST: TX DONE",
+        )?;
+        self.start_receive()
+    }
+
+    /// Return the packets received since the last call.
+    pub fn receive(&mut self) -> Result<Vec<LoraPacket>> {
+        self.modem.poll()?;
+        let mut received = self.received.lock().map_err(|e| anyhow!("{:?}", e))?;
+        Ok(received.packets.drain(..).collect())
+    }
+
+    /// Release the modem.
+    pub fn release(self) -> AtModem<'a> {
+        self.modem
+    }
+
+    fn start_receive(&mut self) -> Result<()> {
+        self.test_command("AT+TEST=RXLRPKT", "+TEST: RXLRPKT")
+    }
+
+    fn test_command(&mut self, command: &str, response: &str) -> Result<()> {
+        let timeout = Duration::from_secs(1);
+        self.modem
+            .request(command, timeout, |line| line.starts_with(response))?;
+        Ok(())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|digits| match digits {
+            [high, low] => u8::from_str_radix(std::str::from_utf8(&[*high, *low]).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,205 @@
+//! Hot-plug monitor of the Grove I2C bus
+//!
+//! [`GroveMonitor`] probes the addresses of the known units in a
+//! background thread. When a unit is plugged in, it sends
+//! [`GroveEvent::Attached`] with the matching driver ready to use on the
+//! shared bus; when the unit is removed, [`GroveEvent::Detached`]. A unit
+//! must answer, or stay silent, at two probes in a row before an event is
+//! sent, so the bouncing contacts of a connector being plugged do not
+//! produce spurious events.
+use anyhow::Result;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{cardkb::CardKbReader, sht30::Sht30};
+use crate::i2c_bus::{I2cBus, I2cProxy};
+use crate::rtc::{Ds3231, Pcf8563, Rtc};
+
+/// Stack size of the monitor thread
+const STACK_SIZE: usize = 4096;
+
+/// Probes in a row needed to change the state of a unit
+const CONFIRMATIONS: u8 = 2;
+
+/// Unit identified by its I2C address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// ENV II/III or another SHT30 sensor (0x44)
+    Env,
+    /// CardKB (0x5F)
+    CardKb,
+    /// Unit RTC with a PCF8563 (0x51)
+    Pcf8563,
+    /// DS3231 real-time clock (0x68)
+    Ds3231,
+}
+
+impl Unit {
+    /// Units probed by the monitor
+    pub const ALL: [Unit; 4] = [Unit::Env, Unit::CardKb, Unit::Pcf8563, Unit::Ds3231];
+
+    /// Returns the I2C address of the unit.
+    pub fn address(&self) -> u8 {
+        match self {
+            Unit::Env => Sht30::<I2cProxy>::ADDRESS,
+            Unit::CardKb => 0x5F,
+            Unit::Pcf8563 => Pcf8563::<I2cProxy>::ADDRESS,
+            Unit::Ds3231 => Ds3231::<I2cProxy>::ADDRESS,
+        }
+    }
+
+    /// Create the driver of the unit on the bus.
+    fn driver(&self, bus: &I2cBus<'static>) -> Driver {
+        match self {
+            Unit::Env => Driver::Env(Sht30::new(bus.acquire())),
+            Unit::CardKb => Driver::CardKb(CardKbReader::new(bus.acquire())),
+            Unit::Pcf8563 => Driver::Rtc(Box::new(Pcf8563::new(bus.acquire()))),
+            Unit::Ds3231 => Driver::Rtc(Box::new(Ds3231::new(bus.acquire()))),
+        }
+    }
+}
+
+/// Driver of an attached unit
+pub enum Driver {
+    Env(Sht30<I2cProxy<'static>>),
+    CardKb(CardKbReader<I2cProxy<'static>>),
+    Rtc(Box<dyn Rtc + Send>),
+}
+
+/// Change of the units on the bus
+pub enum GroveEvent {
+    /// The unit was plugged in
+    Attached(Unit, Driver),
+    /// The unit was removed; its driver fails from now on
+    Detached(Unit),
+}
+
+/// Background monitor of the Grove bus
+///
+/// Stopped when dropped.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::monitor::{Driver, GroveEvent, GroveMonitor, Unit};
+/// use cardputer::{grove, i2c_bus::I2cBus};
+///
+/// let (i2c, sda, scl) = board.spare.grove;
+/// let bus = I2cBus::new(grove::build(i2c, sda, scl, 100.kHz().into()).unwrap());
+/// let monitor = GroveMonitor::start(bus, Duration::from_millis(500)).unwrap();
+///
+/// let mut env = None;
+/// loop {
+///     while let Some(event) = monitor.poll() {
+///         match event {
+///             GroveEvent::Attached(_, Driver::Env(x)) => env = Some(x),
+///             GroveEvent::Detached(Unit::Env) => env = None,
+///             _ => {}
+///         }
+///     }
+///     if let Some(x) = env.as_mut().and_then(|x| x.measure().ok()) {
+///         log::info!("{:.1} °C", x.temperature);
+///     }
+///     thread::sleep(Duration::from_secs(1));
+/// }
+/// ```
+pub struct GroveMonitor {
+    events: mpsc::Receiver<GroveEvent>,
+    attached: Arc<Mutex<Vec<Unit>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GroveMonitor {
+    /// Start probing the bus at the interval. The units already plugged in
+    /// are reported as attached after the first two probes.
+    pub fn start(bus: I2cBus<'static>, interval: Duration) -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let attached = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let attached = attached.clone();
+            let stop = stop.clone();
+            thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn(move || run(bus, interval, sender, &attached, &stop))?
+        };
+        Ok(Self {
+            events,
+            attached,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the next event without waiting.
+    pub fn poll(&self) -> Option<GroveEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Wait for an event up to the timeout.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<GroveEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Returns the units attached now.
+    pub fn attached(&self) -> Vec<Unit> {
+        self.attached
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl Drop for GroveMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Probe the units until stopped or the receiver is dropped.
+fn run(
+    bus: I2cBus<'static>,
+    interval: Duration,
+    sender: mpsc::Sender<GroveEvent>,
+    attached: &Mutex<Vec<Unit>>,
+    stop: &AtomicBool,
+) {
+    // probes in a row that disagree with the reported state
+    let mut changes = [0u8; Unit::ALL.len()];
+    while !stop.load(Ordering::Relaxed) {
+        for (unit, change) in Unit::ALL.iter().zip(changes.iter_mut()) {
+            let present = bus.probe(unit.address());
+            let mut attached = attached.lock().unwrap_or_else(|e| e.into_inner());
+            if present == attached.contains(unit) {
+                *change = 0;
+                continue;
+            }
+            *change += 1;
+            if *change < CONFIRMATIONS {
+                continue;
+            }
+            *change = 0;
+            let event = if present {
+                attached.push(*unit);
+                GroveEvent::Attached(*unit, unit.driver(&bus))
+            } else {
+                attached.retain(|x| x != unit);
+                GroveEvent::Detached(*unit)
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+        thread::sleep(interval);
+    }
+}
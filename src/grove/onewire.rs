@@ -0,0 +1,267 @@
+//! Bit-banged 1-Wire bus on a Grove signal pin and DS18B20 driver
+//!
+//! The bus needs a pull-up resistor (4.7 kΩ to 3.3 V) on the data line;
+//! the internal pull-up of the ESP32-S3 is too weak for more than a
+//! short cable.
+use anyhow::{bail, Result};
+use esp_idf_hal::{
+    delay::Ets,
+    gpio::{IOPin, InputOutput, PinDriver, Pull},
+    interrupt,
+    peripheral::Peripheral,
+};
+use std::{thread, time::Duration};
+
+const SEARCH_ROM: u8 = 0xF0;
+const MATCH_ROM: u8 = 0x55;
+const SKIP_ROM: u8 = 0xCC;
+
+/// 1-Wire bus master
+///
+/// The ROM codes are 64-bit values with the family code in the lowest byte.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::onewire::OneWire;
+///
+/// let mut bus = OneWire::new(peripherals.pins.gpio2).unwrap();
+/// for rom in bus.search().unwrap() {
+///     log::info!("{:016x}", rom);
+/// }
+/// ```
+pub struct OneWire<'a, P: IOPin> {
+    pin: PinDriver<'a, P, InputOutput>,
+}
+
+impl<'a, P: IOPin> OneWire<'a, P> {
+    /// Create new bus on the pin (GPIO1 or GPIO2 of the Grove port).
+    pub fn new(pin: impl Peripheral<P = P> + 'a) -> Result<Self> {
+        let mut pin = PinDriver::input_output_od(pin)?;
+        pin.set_pull(Pull::Up)?;
+        pin.set_high()?;
+        Ok(Self { pin })
+    }
+
+    /// Send a reset pulse and return true if any device answered with a presence pulse.
+    pub fn reset(&mut self) -> Result<bool> {
+        self.pin.set_low()?;
+        Ets::delay_us(480);
+        let presence = interrupt::free(|| -> Result<bool> {
+            self.pin.set_high()?;
+            Ets::delay_us(70);
+            Ok(self.pin.is_low())
+        })?;
+        Ets::delay_us(410);
+        Ok(presence)
+    }
+
+    /// Write a bit in a time slot.
+    pub fn write_bit(&mut self, bit: bool) -> Result<()> {
+        let (low, high) = if bit { (6, 64) } else { (60, 10) };
+        interrupt::free(|| -> Result<()> {
+            self.pin.set_low()?;
+            Ets::delay_us(low);
+            self.pin.set_high()?;
+            Ets::delay_us(high);
+            Ok(())
+        })
+    }
+
+    /// Read a bit in a time slot.
+    pub fn read_bit(&mut self) -> Result<bool> {
+        interrupt::free(|| -> Result<bool> {
+            self.pin.set_low()?;
+            Ets::delay_us(6);
+            self.pin.set_high()?;
+            Ets::delay_us(9);
+            let bit = self.pin.is_high();
+            Ets::delay_us(55);
+            Ok(bit)
+        })
+    }
+
+    /// Write a byte, least significant bit first.
+    pub fn write_byte(&mut self, byte: u8) -> Result<()> {
+        for i in 0..8 {
+            self.write_bit(byte & (1 << i) != 0)?;
+        }
+        Ok(())
+    }
+
+    /// Read a byte, least significant bit first.
+    pub fn read_byte(&mut self) -> Result<u8> {
+        let mut byte = 0;
+        for i in 0..8 {
+            if self.read_bit()? {
+                byte |= 1 << i;
+            }
+        }
+        Ok(byte)
+    }
+
+    /// Reset the bus and address the device, or all devices if `rom` is `None`.
+    pub fn select(&mut self, rom: Option<u64>) -> Result<()> {
+        if !self.reset()? {
+            bail!("no device on the 1-Wire bus");
+        }
+        match rom {
+            Some(rom) => {
+                self.write_byte(MATCH_ROM)?;
+                for byte in rom.to_le_bytes() {
+                    self.write_byte(byte)?;
+                }
+            }
+            None => self.write_byte(SKIP_ROM)?,
+        }
+        Ok(())
+    }
+
+    /// Returns the ROM codes of all devices on the bus.
+    pub fn search(&mut self) -> Result<Vec<u64>> {
+        let mut roms = Vec::new();
+        let mut rom = 0u64;
+        // 1-based position of the last branch where 0 was taken, 0 if none
+        let mut last_discrepancy = 0;
+
+        loop {
+            if !self.reset()? {
+                break;
+            }
+            self.write_byte(SEARCH_ROM)?;
+
+            let mut last_zero = 0;
+            for position in 1..=64 {
+                let bit = self.read_bit()?;
+                let complement = self.read_bit()?;
+                let direction = match (bit, complement) {
+                    (true, true) => return Ok(roms),
+                    (bit, complement) if bit != complement => bit,
+                    _ => {
+                        let direction = if position < last_discrepancy {
+                            rom & (1 << (position - 1)) != 0
+                        } else {
+                            position == last_discrepancy
+                        };
+                        if !direction {
+                            last_zero = position;
+                        }
+                        direction
+                    }
+                };
+                if direction {
+                    rom |= 1 << (position - 1);
+                } else {
+                    rom &= !(1 << (position - 1));
+                }
+                self.write_bit(direction)?;
+            }
+
+            if crc8(&rom.to_le_bytes()) == 0 {
+                roms.push(rom);
+            }
+            last_discrepancy = last_zero;
+            if last_discrepancy == 0 {
+                break;
+            }
+        }
+        Ok(roms)
+    }
+}
+
+/// Dallas/Maxim CRC-8. Returns 0 for data that ends with its valid CRC.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        let mut byte = *byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 1;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
+
+const CONVERT_T: u8 = 0x44;
+const READ_SCRATCHPAD: u8 = 0xBE;
+
+/// Conversion time at the default 12-bit resolution
+const CONVERSION_TIME: Duration = Duration::from_millis(750);
+
+/// DS18B20 temperature sensor
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::onewire::{Ds18b20, OneWire};
+///
+/// let mut bus = OneWire::new(peripherals.pins.gpio2).unwrap();
+/// for sensor in Ds18b20::find_all(&mut bus).unwrap() {
+///     log::info!("{:.2}°C", sensor.measure(&mut bus).unwrap());
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ds18b20 {
+    rom: Option<u64>,
+}
+
+impl Ds18b20 {
+    /// Family code of the DS18B20 in the lowest byte of the ROM code
+    pub const FAMILY_CODE: u8 = 0x28;
+
+    /// Create new driver of the sensor with the ROM code.
+    pub fn new(rom: u64) -> Self {
+        Self { rom: Some(rom) }
+    }
+
+    /// Create new driver of the only device on the bus, skipping the ROM code.
+    pub fn single() -> Self {
+        Self { rom: None }
+    }
+
+    /// Returns the drivers of all DS18B20 on the bus.
+    pub fn find_all<P: IOPin>(bus: &mut OneWire<'_, P>) -> Result<Vec<Self>> {
+        Ok(bus
+            .search()?
+            .into_iter()
+            .filter(|x| *x as u8 == Self::FAMILY_CODE)
+            .map(Self::new)
+            .collect())
+    }
+
+    /// Returns the ROM code, or `None` for [`Ds18b20::single`].
+    pub fn rom(&self) -> Option<u64> {
+        self.rom
+    }
+
+    /// Start a temperature conversion, which takes up to 750 ms.
+    pub fn start_conversion<P: IOPin>(&self, bus: &mut OneWire<'_, P>) -> Result<()> {
+        bus.select(self.rom)?;
+        bus.write_byte(CONVERT_T)
+    }
+
+    /// Read the result of the last conversion in degrees Celsius.
+    pub fn read_temperature<P: IOPin>(&self, bus: &mut OneWire<'_, P>) -> Result<f32> {
+        bus.select(self.rom)?;
+        bus.write_byte(READ_SCRATCHPAD)?;
+        let mut scratchpad = [0u8; 9];
+        for byte in scratchpad.iter_mut() {
+            *byte = bus.read_byte()?;
+        }
+        if scratchpad.iter().all(|x| *x == 0xFF) || crc8(&scratchpad) != 0 {
+            bail!("invalid DS18B20 scratchpad: {:02x?}", scratchpad);
+        }
+        Ok(i16::from_le_bytes([scratchpad[0], scratchpad[1]]) as f32 / 16.0)
+    }
+
+    /// Convert and read the temperature in degrees Celsius, blocking for 750 ms.
+    pub fn measure<P: IOPin>(&self, bus: &mut OneWire<'_, P>) -> Result<f32> {
+        self.start_conversion(bus)?;
+        thread::sleep(CONVERSION_TIME);
+        self.read_temperature(bus)
+    }
+}
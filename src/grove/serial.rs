@@ -0,0 +1,240 @@
+//! Serial protocol on the Grove port that streams key events to an
+//! external host and accepts commands for the backlight and display text
+//!
+//! ### Frame
+//!
+//! ```text
+//! 0xAA | type | length | payload (length bytes) | CRC-8
+//! ```
+//!
+//! The CRC-8 (polynomial 0x07, initial value 0) covers the type, the
+//! length and the payload. Frames with a wrong CRC are answered with NAK.
+//!
+//! | Type | Direction    | Payload                                                   |
+//! |------|--------------|-----------------------------------------------------------|
+//! | 0x01 | device→host  | Key event: kind (0 pressed, 1 released), key, modifiers   |
+//! | 0x02 | device→host  | ACK: type of the accepted command                         |
+//! | 0x03 | device→host  | NAK: type of the rejected command, 0 for a CRC error      |
+//! | 0x10 | host→device  | Backlight: 0 off, 1 on                                    |
+//! | 0x11 | host→device  | Display text in UTF-8                                     |
+//! | 0x12 | host→device  | Clear the display text                                    |
+//! | 0x13 | host→device  | Ping, answered with ACK                                   |
+//!
+//! The key is encoded by [`Modified::to_ascii`] and the modifiers by
+//! [`Modifiers::bits`](crate::keyboard::pipeline::Modifiers::bits).
+use anyhow::Result;
+use esp_idf_hal::{
+    delay::NON_BLOCK,
+    gpio::{InputPin, OutputPin},
+    peripheral::Peripheral,
+    uart::{config::Config, Uart, UartDriver},
+    units::Hertz,
+};
+
+use crate::keyboard::{KeyboardState, Modified};
+
+const START: u8 = 0xAA;
+
+const KEY_EVENT: u8 = 0x01;
+const ACK: u8 = 0x02;
+const NAK: u8 = 0x03;
+const BACKLIGHT: u8 = 0x10;
+const TEXT: u8 = 0x11;
+const CLEAR_TEXT: u8 = 0x12;
+const PING: u8 = 0x13;
+
+/// Command received from the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Backlight(bool),
+    Text(String),
+    ClearText,
+}
+
+/// Link to the host over the Grove UART
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::serial::{Command, SerialLink};
+/// use esp_idf_hal::prelude::*;
+///
+/// let mut link = SerialLink::new(
+///     peripherals.uart1,
+///     peripherals.pins.gpio2,
+///     peripherals.pins.gpio1,
+///     115200.Hz(),
+/// )
+/// .unwrap();
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     link.send_keys(&keyboard_state).unwrap();
+///     for command in link.poll().unwrap() {
+///         match command {
+///             Command::Backlight(on) => { /* ... */ }
+///             Command::Text(text) => { /* ... */ }
+///             Command::ClearText => { /* ... */ }
+///         }
+///     }
+/// }
+/// ```
+pub struct SerialLink<'a> {
+    uart: UartDriver<'a>,
+    parser: Parser,
+}
+
+impl<'a> SerialLink<'a> {
+    /// Create new link with the TX and RX pins of the Grove port (GPIO1 and GPIO2).
+    pub fn new<UART: Uart>(
+        uart: impl Peripheral<P = UART> + 'a,
+        tx: impl Peripheral<P = impl OutputPin> + 'a,
+        rx: impl Peripheral<P = impl InputPin> + 'a,
+        baudrate: Hertz,
+    ) -> Result<Self> {
+        let config = Config::new().baudrate(baudrate);
+        let uart = UartDriver::new(
+            uart,
+            tx,
+            rx,
+            Option::<esp_idf_hal::gpio::AnyInputPin>::None,
+            Option::<esp_idf_hal::gpio::AnyOutputPin>::None,
+            &config,
+        )?;
+        Ok(Self {
+            uart,
+            parser: Parser::default(),
+        })
+    }
+
+    /// Send the keys pressed and released in the last update.
+    pub fn send_keys(&mut self, state: &KeyboardState) -> Result<()> {
+        let modifiers = state.modifiers();
+        for (kind, keys) in [(0, state.pressed_keys()), (1, state.released_keys())] {
+            for key in keys.iter().filter_map(Modified::to_ascii) {
+                self.send(KEY_EVENT, &[kind, key, modifiers.bits()])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the received bytes and return the commands, answering each frame.
+    pub fn poll(&mut self) -> Result<Vec<Command>> {
+        let mut commands = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            let len = self.uart.read(&mut buf, NON_BLOCK)?;
+            if len == 0 {
+                return Ok(commands);
+            }
+            for byte in &buf[..len] {
+                match self.parser.push(*byte) {
+                    Some(Ok((PING, payload))) if payload.is_empty() => self.send(ACK, &[PING])?,
+                    Some(Ok((kind, payload))) => match parse_command(kind, &payload) {
+                        Some(command) => {
+                            self.send(ACK, &[kind])?;
+                            commands.push(command);
+                        }
+                        None => self.send(NAK, &[kind])?,
+                    },
+                    Some(Err(())) => self.send(NAK, &[0])?,
+                    None => {}
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, kind: u8, payload: &[u8]) -> Result<()> {
+        let len = payload.len().min(u8::MAX as usize) as u8;
+        let payload = &payload[..len as usize];
+        let mut frame = Vec::with_capacity(payload.len() + 4);
+        frame.extend_from_slice(&[START, kind, len]);
+        frame.extend_from_slice(payload);
+        frame.push(crc8(&frame[1..]));
+        self.uart.write(&frame)?;
+        Ok(())
+    }
+}
+
+/// Returns the command of the frame, or `None` if it is invalid.
+fn parse_command(kind: u8, payload: &[u8]) -> Option<Command> {
+    match (kind, payload) {
+        (BACKLIGHT, [on]) => Some(Command::Backlight(*on != 0)),
+        (TEXT, text) => String::from_utf8(text.to_vec()).ok().map(Command::Text),
+        (CLEAR_TEXT, []) => Some(Command::ClearText),
+        _ => None,
+    }
+}
+
+/// Receiver state machine that resynchronizes on the start byte
+#[derive(Debug, Default)]
+struct Parser {
+    state: ParserState,
+    kind: u8,
+    len: usize,
+    payload: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum ParserState {
+    #[default]
+    Start,
+    Type,
+    Length,
+    Payload,
+    Crc,
+}
+
+impl Parser {
+    /// Feed a byte and return the frame when it is complete, or an error on a CRC mismatch.
+    fn push(&mut self, byte: u8) -> Option<Result<(u8, Vec<u8>), ()>> {
+        match self.state {
+            ParserState::Start => {
+                if byte == START {
+                    self.state = ParserState::Type;
+                }
+            }
+            ParserState::Type => {
+                self.kind = byte;
+                self.state = ParserState::Length;
+            }
+            ParserState::Length => {
+                self.len = byte as usize;
+                self.payload.clear();
+                self.state = if self.len == 0 {
+                    ParserState::Crc
+                } else {
+                    ParserState::Payload
+                };
+            }
+            ParserState::Payload => {
+                self.payload.push(byte);
+                if self.payload.len() == self.len {
+                    self.state = ParserState::Crc;
+                }
+            }
+            ParserState::Crc => {
+                self.state = ParserState::Start;
+                let mut data = vec![self.kind, self.len as u8];
+                data.extend_from_slice(&self.payload);
+                if crc8(&data) != byte {
+                    return Some(Err(()));
+                }
+                return Some(Ok((self.kind, std::mem::take(&mut self.payload))));
+            }
+        }
+        None
+    }
+}
+
+/// CRC-8 with the polynomial 0x07 and the initial value 0
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
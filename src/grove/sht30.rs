@@ -0,0 +1,90 @@
+//! SHT30 temperature and humidity sensor
+//!
+//! The sensor of the M5Stack ENV III unit and of many SHT3x breakouts.
+//! The pressure sensor of the ENV III, a QMP6988, is not read.
+use anyhow::{anyhow, bail, Result};
+use embedded_hal::blocking::i2c::{Read, Write};
+use std::{thread, time::Duration};
+
+/// Single-shot measurement with high repeatability, without clock stretching
+const MEASURE: [u8; 2] = [0x24, 0x00];
+
+/// Longest measurement time with high repeatability
+const MEASUREMENT_TIME: Duration = Duration::from_millis(16);
+
+/// Temperature and relative humidity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    /// °C
+    pub temperature: f32,
+    /// %RH
+    pub humidity: f32,
+}
+
+/// SHT30 sensor
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::sht30::Sht30;
+///
+/// let mut sensor = Sht30::new(bus.acquire());
+/// let x = sensor.measure().unwrap();
+/// log::info!("{:.1} °C {:.0} %", x.temperature, x.humidity);
+/// ```
+pub struct Sht30<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C, E> Sht30<I2C>
+where
+    I2C: Write<Error = E> + Read<Error = E>,
+    E: core::fmt::Debug,
+{
+    pub const ADDRESS: u8 = 0x44;
+
+    pub fn new(i2c: I2C) -> Self {
+        Self { i2c }
+    }
+
+    /// Release the I2C driver.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// Measure the temperature and the humidity, blocking for about 16 ms.
+    pub fn measure(&mut self) -> Result<Measurement> {
+        self.i2c
+            .write(Self::ADDRESS, &MEASURE)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        thread::sleep(MEASUREMENT_TIME);
+        let mut data = [0u8; 6];
+        self.i2c
+            .read(Self::ADDRESS, &mut data)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        for word in data.chunks(3) {
+            if crc8(&word[..2]) != word[2] {
+                bail!("SHT30: CRC error");
+            }
+        }
+        let temperature = u16::from_be_bytes([data[0], data[1]]) as f32;
+        let humidity = u16::from_be_bytes([data[3], data[4]]) as f32;
+        Ok(Measurement {
+            temperature: -45.0 + 175.0 * temperature / 65535.0,
+            humidity: 100.0 * humidity / 65535.0,
+        })
+    }
+}
+
+/// CRC-8 with the polynomial 0x31 and the initial value 0xFF
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0xFF, |crc, x| {
+        (0..8).fold(crc ^ x, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
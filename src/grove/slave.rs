@@ -0,0 +1,207 @@
+//! I2C slave mode that makes the Cardputer a keyboard and text display
+//! peripheral of another MCU
+//!
+//! The master writes the register number, followed by the data for the
+//! write registers, and then reads the read registers.
+//!
+//! The answer of the selected read register is kept in the transmit FIFO,
+//! [`KEY`](register::KEY) until another one is selected, so the master can
+//! read the keys one after another without selecting the register again.
+//! A key is taken from the queue only once the master has read it. After
+//! selecting another register, the master should wait for a
+//! [`poll`](I2cSlave::poll) of the slave before reading it.
+//!
+//! | Register | Access | Description                                          |
+//! |----------|--------|------------------------------------------------------|
+//! | 0x00     | R      | Number of queued keys                                |
+//! | 0x01     | R      | Next queued key (see [`Modified::to_ascii`]), 0 if none |
+//! | 0x02     | R      | Held modifiers: bit 0 Fn, 1 Shift, 2 Ctrl, 3 Alt, 4 Opt |
+//! | 0x10     | W      | Append the bytes to the display text                 |
+//! | 0x11     | W      | Clear the display text                               |
+use anyhow::Result;
+use esp_idf_hal::{
+    delay::NON_BLOCK,
+    gpio::{Gpio1, Gpio2},
+    i2c::{I2cSlaveConfig, I2cSlaveDriver, I2C0},
+    peripheral::Peripheral,
+};
+use std::collections::VecDeque;
+
+use super::{reset_tx_fifo, tx_fifo_len};
+use crate::keyboard::{KeyboardState, Modified};
+
+/// Register numbers
+pub mod register {
+    pub const KEY_COUNT: u8 = 0x00;
+    pub const KEY: u8 = 0x01;
+    pub const MODIFIERS: u8 = 0x02;
+    pub const TEXT: u8 = 0x10;
+    pub const CLEAR_TEXT: u8 = 0x11;
+}
+
+/// Maximum number of keys queued for the master
+const KEY_QUEUE_SIZE: usize = 32;
+
+/// Size of the receive and transmit buffers of the driver
+const BUFFER_SIZE: usize = 128;
+
+/// I2C slave on the Grove port
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::grove::slave::I2cSlave;
+///
+/// let mut slave = I2cSlave::new(
+///     peripherals.i2c0,
+///     peripherals.pins.gpio2,
+///     peripherals.pins.gpio1,
+///     I2cSlave::DEFAULT_ADDRESS,
+/// )
+/// .unwrap();
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     slave.push_keys(&keyboard_state);
+///     slave.poll().unwrap();
+///     if let Some(text) = slave.take_text() {
+///         // draw the text
+///     }
+/// }
+/// ```
+pub struct I2cSlave<'a> {
+    driver: I2cSlaveDriver<'a>,
+    keys: VecDeque<u8>,
+    modifiers: u8,
+    text: String,
+    text_changed: bool,
+    /// Selected read register
+    register: u8,
+    /// Answer waiting in the transmit FIFO
+    loaded: Option<u8>,
+}
+
+impl<'a> I2cSlave<'a> {
+    /// Default slave address
+    pub const DEFAULT_ADDRESS: u8 = 0x42;
+
+    /// Create new slave with the 7-bit address.
+    pub fn new(
+        i2c: impl Peripheral<P = I2C0> + 'a,
+        sda: impl Peripheral<P = Gpio2> + 'a,
+        scl: impl Peripheral<P = Gpio1> + 'a,
+        address: u8,
+    ) -> Result<Self> {
+        let config = I2cSlaveConfig::new()
+            .sda_enable_pullup(true)
+            .scl_enable_pullup(true)
+            .rx_buffer_length(BUFFER_SIZE)
+            .tx_buffer_length(BUFFER_SIZE);
+        let driver = I2cSlaveDriver::new(i2c, sda, scl, address, &config)?;
+        Ok(Self {
+            driver,
+            keys: VecDeque::new(),
+            modifiers: 0,
+            text: String::new(),
+            text_changed: false,
+            register: register::KEY,
+            loaded: None,
+        })
+    }
+
+    /// Queue the keys pressed in the last update for the master.
+    ///
+    /// The oldest keys are dropped when the master does not read them.
+    pub fn push_keys(&mut self, state: &KeyboardState) {
+        for key in state.pressed_keys().iter().filter_map(Modified::to_ascii) {
+            if self.keys.len() == KEY_QUEUE_SIZE {
+                self.keys.pop_front();
+            }
+            self.keys.push_back(key);
+        }
+        self.modifiers = state.modifiers().bits();
+    }
+
+    /// Handle the requests from the master and keep the answer of the
+    /// selected register in the transmit FIFO. Call periodically.
+    pub fn poll(&mut self) -> Result<()> {
+        if let Some(loaded) = self.loaded {
+            if tx_fifo_len() == 0 {
+                // the master has read the answer
+                self.loaded = None;
+                if self.register == register::KEY && self.keys.front() == Some(&loaded) {
+                    self.keys.pop_front();
+                }
+            }
+        }
+        let mut buf = [0u8; BUFFER_SIZE];
+        loop {
+            let len = self.driver.read(&mut buf, NON_BLOCK)?;
+            if len == 0 {
+                break;
+            }
+            self.handle(&buf[..len])?;
+        }
+        self.load()
+    }
+
+    /// Returns the display text if it changed since the last call.
+    pub fn take_text(&mut self) -> Option<String> {
+        if !self.text_changed {
+            return None;
+        }
+        self.text_changed = false;
+        Some(self.text.clone())
+    }
+
+    fn handle(&mut self, request: &[u8]) -> Result<()> {
+        let (register, data) = match request.split_first() {
+            Some((register, data)) => (*register, data),
+            None => return Ok(()),
+        };
+        match register {
+            register::KEY_COUNT | register::KEY | register::MODIFIERS => {
+                // the answer of the previous register is not read anymore
+                if self.loaded.take().is_some() {
+                    reset_tx_fifo()?;
+                }
+                self.register = register;
+                self.load()?;
+            }
+            register::TEXT => {
+                self.text.extend(data.iter().map(|x| *x as char));
+                self.text_changed = true;
+            }
+            register::CLEAR_TEXT => {
+                self.text.clear();
+                self.text_changed = true;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Returns the answer of the selected register.
+    fn response(&self) -> u8 {
+        match self.register {
+            register::KEY_COUNT => self.keys.len() as u8,
+            register::MODIFIERS => self.modifiers,
+            _ => self.keys.front().copied().unwrap_or(0),
+        }
+    }
+
+    /// Put the answer of the selected register in the transmit FIFO,
+    /// replacing an outdated one.
+    fn load(&mut self) -> Result<()> {
+        let response = self.response();
+        match self.loaded {
+            Some(x) if x == response => return Ok(()),
+            Some(_) => reset_tx_fifo()?,
+            None => {}
+        }
+        self.loaded = None;
+        if self.driver.write(&[response], NON_BLOCK)? == 1 {
+            self.loaded = Some(response);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! HID reports shared by the BLE and USB HID modes
+//!
+//! [`REPORT_DESCRIPTOR`] has a boot-compatible keyboard and a consumer
+//! control collection, so the Fn-layer media shortcuts reach the host as
+//! media keys rather than keyboard usages. [`GAMEPAD_REPORT_DESCRIPTOR`]
+//! is a standard gamepad recognized by emulators without drivers.
+//!
+//! The usage table of cardputer-core, re-exported here, maps key imprints
+//! and converted keys to keyboard page usages and modifier bits for a US
+//! layout, so both modes build the same keyboard reports, and [`bridge`]
+//! turns the key events into them.
+use crate::gamepad::{Button, InputMap};
+use crate::media::MediaKey;
+
+/// Report ID of the keyboard input report
+pub const KEYBOARD_REPORT_ID: u8 = 1;
+/// Report ID of the consumer control input report
+pub const CONSUMER_REPORT_ID: u8 = 2;
+
+/// Report descriptor of a keyboard with consumer control
+///
+/// Keyboard report: modifier bits, reserved byte and six key usages.
+/// Consumer report: one 16-bit usage, 0 when released.
+#[rustfmt::skip]
+pub const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x06,             // Usage (Keyboard)
+    0xA1, 0x01,             // Collection (Application)
+    0x85, KEYBOARD_REPORT_ID,
+    0x05, 0x07,             //   Usage Page (Keyboard/Keypad)
+    0x19, 0xE0,             //   Usage Minimum (Left Control)
+    0x29, 0xE7,             //   Usage Maximum (Right GUI)
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x25, 0x01,             //   Logical Maximum (1)
+    0x75, 0x01,             //   Report Size (1)
+    0x95, 0x08,             //   Report Count (8)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0x95, 0x01,             //   Report Count (1)
+    0x75, 0x08,             //   Report Size (8)
+    0x81, 0x01,             //   Input (Constant)
+    0x05, 0x08,             //   Usage Page (LEDs)
+    0x19, 0x01,             //   Usage Minimum (Num Lock)
+    0x29, 0x05,             //   Usage Maximum (Kana)
+    0x95, 0x05,             //   Report Count (5)
+    0x75, 0x01,             //   Report Size (1)
+    0x91, 0x02,             //   Output (Data, Variable, Absolute)
+    0x95, 0x01,             //   Report Count (1)
+    0x75, 0x03,             //   Report Size (3)
+    0x91, 0x01,             //   Output (Constant)
+    0x05, 0x07,             //   Usage Page (Keyboard/Keypad)
+    0x19, 0x00,             //   Usage Minimum (0)
+    0x29, 0x65,             //   Usage Maximum (Application)
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x25, 0x65,             //   Logical Maximum (101)
+    0x95, 0x06,             //   Report Count (6)
+    0x75, 0x08,             //   Report Size (8)
+    0x81, 0x00,             //   Input (Data, Array)
+    0xC0,                   // End Collection
+    0x05, 0x0C,             // Usage Page (Consumer)
+    0x09, 0x01,             // Usage (Consumer Control)
+    0xA1, 0x01,             // Collection (Application)
+    0x85, CONSUMER_REPORT_ID,
+    0x19, 0x00,             //   Usage Minimum (0)
+    0x2A, 0xFF, 0x03,       //   Usage Maximum (0x3FF)
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x26, 0xFF, 0x03,       //   Logical Maximum (0x3FF)
+    0x95, 0x01,             //   Report Count (1)
+    0x75, 0x10,             //   Report Size (16)
+    0x81, 0x00,             //   Input (Data, Array)
+    0xC0,                   // End Collection
+];
+
+pub use cardputer_core::hid::{
+    bridge, char_usage, key_usage, keyboard_report, modifier_bit, modifier_bits, usage, LEFT_ALT,
+    LEFT_CTRL, LEFT_GUI, LEFT_SHIFT,
+};
+
+/// Usage of the consumer page (0x0C)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsumerUsage {
+    BrightnessUp,
+    BrightnessDown,
+    NextTrack,
+    PreviousTrack,
+    Stop,
+    PlayPause,
+    Mute,
+    VolumeUp,
+    VolumeDown,
+}
+
+impl ConsumerUsage {
+    /// Returns the usage ID.
+    pub fn code(&self) -> u16 {
+        match self {
+            ConsumerUsage::BrightnessUp => 0x6F,
+            ConsumerUsage::BrightnessDown => 0x70,
+            ConsumerUsage::NextTrack => 0xB5,
+            ConsumerUsage::PreviousTrack => 0xB6,
+            ConsumerUsage::Stop => 0xB7,
+            ConsumerUsage::PlayPause => 0xCD,
+            ConsumerUsage::Mute => 0xE2,
+            ConsumerUsage::VolumeUp => 0xE9,
+            ConsumerUsage::VolumeDown => 0xEA,
+        }
+    }
+}
+
+impl From<MediaKey> for ConsumerUsage {
+    fn from(key: MediaKey) -> Self {
+        match key {
+            MediaKey::VolumeUp => ConsumerUsage::VolumeUp,
+            MediaKey::VolumeDown => ConsumerUsage::VolumeDown,
+            MediaKey::Mute => ConsumerUsage::Mute,
+            MediaKey::PlayPause => ConsumerUsage::PlayPause,
+            MediaKey::NextTrack => ConsumerUsage::NextTrack,
+            MediaKey::PreviousTrack => ConsumerUsage::PreviousTrack,
+            MediaKey::BrightnessUp => ConsumerUsage::BrightnessUp,
+            MediaKey::BrightnessDown => ConsumerUsage::BrightnessDown,
+        }
+    }
+}
+
+/// Returns the consumer report, without the report ID, of the pressed
+/// usage or of the release.
+pub fn consumer_report(usage: Option<ConsumerUsage>) -> [u8; 2] {
+    usage.map_or(0, |x| x.code()).to_le_bytes()
+}
+
+/// Report descriptor of a gamepad with eight buttons and an X/Y axis pair
+///
+/// Report: button bits, X and Y from -127 to 127.
+#[rustfmt::skip]
+pub const GAMEPAD_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x05,             // Usage (Game Pad)
+    0xA1, 0x01,             // Collection (Application)
+    0x05, 0x09,             //   Usage Page (Button)
+    0x19, 0x01,             //   Usage Minimum (1)
+    0x29, 0x08,             //   Usage Maximum (8)
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x25, 0x01,             //   Logical Maximum (1)
+    0x75, 0x01,             //   Report Size (1)
+    0x95, 0x08,             //   Report Count (8)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0x05, 0x01,             //   Usage Page (Generic Desktop)
+    0x09, 0x30,             //   Usage (X)
+    0x09, 0x31,             //   Usage (Y)
+    0x15, 0x81,             //   Logical Minimum (-127)
+    0x25, 0x7F,             //   Logical Maximum (127)
+    0x75, 0x08,             //   Report Size (8)
+    0x95, 0x02,             //   Report Count (2)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0xC0,                   // End Collection
+];
+
+/// Returns the gamepad report of the buttons held in the input map.
+///
+/// A, B and Start are buttons 1, 2 and 8; the directions drive the axes.
+pub fn gamepad_report(input: &InputMap) -> [u8; 3] {
+    let buttons = [(Button::A, 0x01), (Button::B, 0x02), (Button::Start, 0x80)]
+        .into_iter()
+        .filter(|(button, _)| input.is_pressed(*button))
+        .fold(0, |acc, (_, bit)| acc | bit);
+    let axis = |negative, positive| match (input.is_pressed(negative), input.is_pressed(positive)) {
+        (true, false) => -127i8,
+        (false, true) => 127,
+        _ => 0,
+    };
+    [
+        buttons,
+        axis(Button::Left, Button::Right) as u8,
+        axis(Button::Up, Button::Down) as u8,
+    ]
+}
@@ -0,0 +1,150 @@
+//! Global hotkey registry
+use crate::keyboard::{KeyImprint, KeyboardState, Modified};
+
+/// Key combination of modifier keys and a normal key
+///
+/// The modifier keys must match exactly, so `Ctrl+Q` does not fire
+/// while `Ctrl+Alt+Q` is pressed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chord {
+    key: KeyImprint,
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    opt: bool,
+    fn_: bool,
+}
+
+impl Chord {
+    /// Create new chord of the key without modifier keys.
+    pub fn new(key: KeyImprint) -> Self {
+        Self {
+            key,
+            ctrl: false,
+            alt: false,
+            shift: false,
+            opt: false,
+            fn_: false,
+        }
+    }
+
+    /// Require Ctrl.
+    pub fn ctrl(mut self) -> Self {
+        self.ctrl = true;
+        self
+    }
+
+    /// Require Alt.
+    pub fn alt(mut self) -> Self {
+        self.alt = true;
+        self
+    }
+
+    /// Require Shift.
+    pub fn shift(mut self) -> Self {
+        self.shift = true;
+        self
+    }
+
+    /// Require Opt.
+    pub fn opt(mut self) -> Self {
+        self.opt = true;
+        self
+    }
+
+    /// Require Fn.
+    pub fn fn_key(mut self) -> Self {
+        self.fn_ = true;
+        self
+    }
+
+    /// Returns true if the key is pressed with exactly the modifier keys of the chord.
+    pub fn matches(&self, key: KeyImprint, state: &KeyboardState) -> bool {
+        self.key == key
+            && self.ctrl == state.is_ctrl_pressed()
+            && self.alt == state.is_alt_pressed()
+            && self.shift == state.is_shift_pressed()
+            && self.opt == state.is_opt_pressed()
+            && self.fn_ == state.is_fn_pressed()
+    }
+}
+
+/// Identifier of a registered hotkey
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotkeyId(u32);
+
+struct Hotkey {
+    id: HotkeyId,
+    chord: Chord,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+/// Registry of system-wide hotkeys
+///
+/// Call [`HotkeyRegistry::dispatch`] after each keyboard update and
+/// before delivering the keys to the application; the keys that triggered
+/// a hotkey are removed from the returned keys.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::hotkey::{Chord, HotkeyRegistry};
+/// use cardputer::keyboard::KeyImprint;
+///
+/// let mut hotkeys = HotkeyRegistry::new();
+/// hotkeys.register(Chord::new(KeyImprint::Q).ctrl().alt(), || {
+///     log::info!("Ctrl+Alt+Q");
+/// });
+///
+/// keyboard_state.update(&mut keyboard).unwrap();
+/// let keys = hotkeys.dispatch(&keyboard_state);
+/// ```
+#[derive(Default)]
+pub struct HotkeyRegistry {
+    hotkeys: Vec<Hotkey>,
+    next_id: u32,
+}
+
+impl HotkeyRegistry {
+    /// Create new empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the callback called when the chord is pressed.
+    pub fn register(&mut self, chord: Chord, callback: impl FnMut() + Send + 'static) -> HotkeyId {
+        let id = HotkeyId(self.next_id);
+        self.next_id += 1;
+        self.hotkeys.push(Hotkey {
+            id,
+            chord,
+            callback: Box::new(callback),
+        });
+        id
+    }
+
+    /// Remove the hotkey.
+    pub fn unregister(&mut self, id: HotkeyId) {
+        self.hotkeys.retain(|x| x.id != id);
+    }
+
+    /// Call the callbacks of the pressed chords and return the other pressed keys.
+    pub fn dispatch(&mut self, state: &KeyboardState) -> Vec<Modified> {
+        state
+            .pressed_imprints()
+            .into_iter()
+            .zip(state.pressed_keys())
+            .filter(|(imprint, _)| {
+                let mut fired = false;
+                for hotkey in self.hotkeys.iter_mut() {
+                    if hotkey.chord.matches(*imprint, state) {
+                        (hotkey.callback)();
+                        fired = true;
+                    }
+                }
+                !fired
+            })
+            .map(|(_, key)| key)
+            .collect()
+    }
+}
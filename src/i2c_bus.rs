@@ -0,0 +1,129 @@
+//! I2C bus shared by several device drivers
+//!
+//! Each driver gets its own [`I2cProxy`] instead of the exclusive
+//! ownership of the I2C driver, and the transactions of the drivers are
+//! serialized by a mutex.
+use anyhow::{anyhow, Result};
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+use esp_idf_hal::{
+    delay::TickType,
+    i2c::{I2cDriver, I2cError},
+};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// Time a device is given to acknowledge a probe, so a stuck bus does not
+/// hang the caller
+const PROBE_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Manager of an I2C bus
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{grove, i2c_bus::I2cBus};
+///
+/// let bus = I2cBus::new(grove::build(/* ... */).unwrap());
+///
+/// let mut sensor = Sht3x::new(bus.acquire());
+/// let mut rtc = Bm8563::new(bus.acquire());
+/// ```
+#[derive(Clone)]
+pub struct I2cBus<'a> {
+    driver: Arc<Mutex<I2cDriver<'a>>>,
+}
+
+impl<'a> I2cBus<'a> {
+    /// Create new manager of the driver.
+    pub fn new(driver: I2cDriver<'a>) -> Self {
+        Self {
+            driver: Arc::new(Mutex::new(driver)),
+        }
+    }
+
+    /// Returns a new handle of the bus for a device driver.
+    pub fn acquire(&self) -> I2cProxy<'a> {
+        I2cProxy {
+            driver: self.driver.clone(),
+        }
+    }
+
+    /// Lock the bus to run several transactions without interruption.
+    pub fn lock(&self) -> MutexGuard<'_, I2cDriver<'a>> {
+        lock(&self.driver)
+    }
+
+    /// Returns true if a device acknowledges the 7-bit address.
+    pub fn probe(&self, address: u8) -> bool {
+        self.lock()
+            .write(address, &[], TickType::from(PROBE_TIMEOUT).ticks())
+            .is_ok()
+    }
+
+    /// Returns the 7-bit addresses of the devices that acknowledge on the bus.
+    pub fn scan(&self) -> Vec<u8> {
+        let mut driver = self.lock();
+        let timeout = TickType::from(PROBE_TIMEOUT).ticks();
+        (0x08..0x78)
+            .filter(|x| driver.write(*x, &[], timeout).is_ok())
+            .collect()
+    }
+}
+
+/// Handle of a shared I2C bus implementing the embedded-hal traits
+#[derive(Clone)]
+pub struct I2cProxy<'a> {
+    driver: Arc<Mutex<I2cDriver<'a>>>,
+}
+
+impl I2cProxy<'_> {
+    /// Read a register of the device.
+    pub fn read_register(&mut self, address: u8, register: u8) -> Result<u8> {
+        let mut value = [0u8];
+        self.write_read(address, &[register], &mut value)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        Ok(value[0])
+    }
+
+    /// Write a register of the device.
+    pub fn write_register(&mut self, address: u8, register: u8, value: u8) -> Result<()> {
+        self.write(address, &[register, value])
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+}
+
+impl Read for I2cProxy<'_> {
+    type Error = I2cError;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        Read::read(&mut *lock(&self.driver), address, buffer)
+    }
+}
+
+impl Write for I2cProxy<'_> {
+    type Error = I2cError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        Write::write(&mut *lock(&self.driver), address, bytes)
+    }
+}
+
+impl WriteRead for I2cProxy<'_> {
+    type Error = I2cError;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        WriteRead::write_read(&mut *lock(&self.driver), address, bytes, buffer)
+    }
+}
+
+/// Lock the driver, recovering it if a thread panicked while holding the lock.
+fn lock<'a, 'b>(driver: &'b Mutex<I2cDriver<'a>>) -> MutexGuard<'b, I2cDriver<'a>> {
+    driver.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,276 @@
+//! Improv WiFi provisioning over serial
+//!
+//! Implements the device side of the Improv serial protocol
+//! (<https://www.improv-wifi.com/serial/>), so WiFi credentials can be
+//! entered from a browser-based flasher page such as ESP Web Tools. The
+//! application connects to the network in the callback and keeps the
+//! credentials, e.g. in NVS.
+use anyhow::Result;
+use std::time::Duration;
+
+use crate::xmodem::Port;
+
+const HEADER: &[u8; 6] = b"IMPROV";
+const VERSION: u8 = 1;
+/// Header, version, type and length
+const PREFIX_LEN: usize = 9;
+const MAX_BUFFER: usize = 512;
+
+const TYPE_CURRENT_STATE: u8 = 0x01;
+const TYPE_ERROR_STATE: u8 = 0x02;
+const TYPE_RPC: u8 = 0x03;
+const TYPE_RPC_RESULT: u8 = 0x04;
+
+const COMMAND_WIFI_SETTINGS: u8 = 0x01;
+const COMMAND_CURRENT_STATE: u8 = 0x02;
+const COMMAND_DEVICE_INFO: u8 = 0x03;
+const COMMAND_SCAN: u8 = 0x04;
+
+/// Provisioning state reported to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Ready = 0x02,
+    Provisioning = 0x03,
+    Provisioned = 0x04,
+}
+
+/// Error reported to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorState {
+    None = 0x00,
+    InvalidRpc = 0x01,
+    UnknownCommand = 0x02,
+    UnableToConnect = 0x03,
+}
+
+/// Network found by a scan
+#[derive(Debug, Clone, PartialEq)]
+pub struct Network {
+    pub ssid: String,
+    pub rssi: i8,
+    pub is_secured: bool,
+}
+
+/// Connects to the network with the SSID and password, returning the URL
+/// of the device page to offer to the user, if any
+pub type ConnectCallback = Box<dyn FnMut(&str, &str) -> Result<Option<String>> + Send>;
+/// Returns the networks in range
+pub type ScanCallback = Box<dyn FnMut() -> Vec<Network> + Send>;
+
+/// Improv serial device
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{improv::Improv, usb_serial::UsbSerial};
+///
+/// let mut improv = Improv::new(UsbSerial::new().unwrap(), "cardputer-app", "0.1.0", "Cardputer")
+///     .on_connect(move |ssid, password| {
+///         connect_wifi(&mut wifi, ssid, password)?;
+///         save_credentials(ssid, password)?;
+///         Ok(None)
+///     });
+/// while !improv.is_provisioned() {
+///     improv.poll(Duration::from_millis(100)).unwrap();
+/// }
+/// ```
+pub struct Improv<P: Port> {
+    port: P,
+    device_info: [String; 4],
+    state: State,
+    connect: Option<ConnectCallback>,
+    scan: Option<ScanCallback>,
+    buffer: Vec<u8>,
+}
+
+impl<P: Port> Improv<P> {
+    /// Create new device on the port with the firmware name and version
+    /// and the device name shown by the client.
+    pub fn new(port: P, firmware: &str, version: &str, device_name: &str) -> Self {
+        Self {
+            port,
+            device_info: [
+                firmware.to_string(),
+                version.to_string(),
+                "ESP32-S3".to_string(),
+                device_name.to_string(),
+            ],
+            state: State::Ready,
+            connect: None,
+            scan: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Set the callback connecting to the network. Without it the
+    /// credentials are refused.
+    pub fn on_connect(
+        mut self,
+        callback: impl FnMut(&str, &str) -> Result<Option<String>> + Send + 'static,
+    ) -> Self {
+        self.connect = Some(Box::new(callback));
+        self
+    }
+
+    /// Set the callback scanning the networks offered to the user.
+    pub fn on_scan(mut self, callback: impl FnMut() -> Vec<Network> + Send + 'static) -> Self {
+        self.scan = Some(Box::new(callback));
+        self
+    }
+
+    /// Start in the provisioned state, e.g. when the stored credentials
+    /// worked. The client can still send new credentials.
+    pub fn with_provisioned(mut self) -> Self {
+        self.state = State::Provisioned;
+        self
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Returns true once a connection succeeded.
+    pub fn is_provisioned(&self) -> bool {
+        self.state == State::Provisioned
+    }
+
+    /// Return the port.
+    pub fn release(self) -> P {
+        self.port
+    }
+
+    /// Read the input, waiting up to the timeout, and answer the complete
+    /// packets. Bytes outside of packets, e.g. typed text, are dropped.
+    pub fn poll(&mut self, timeout: Duration) -> Result<()> {
+        let mut buf = [0u8; 64];
+        let len = self.port.read(&mut buf, timeout)?;
+        self.buffer.extend_from_slice(&buf[..len]);
+
+        loop {
+            let Some(start) = self.buffer.windows(HEADER.len()).position(|x| x == HEADER) else {
+                // keep a possible partial header
+                let keep = self.buffer.len().min(HEADER.len() - 1);
+                self.buffer.drain(..self.buffer.len() - keep);
+                return Ok(());
+            };
+            self.buffer.drain(..start);
+            if self.buffer.len() < PREFIX_LEN {
+                return Ok(());
+            }
+            let len = PREFIX_LEN + self.buffer[8] as usize + 1;
+            if self.buffer.len() < len {
+                if self.buffer.len() > MAX_BUFFER {
+                    self.buffer.clear();
+                }
+                return Ok(());
+            }
+            let packet: Vec<u8> = self.buffer.drain(..len).collect();
+            let (body, checksum) = packet.split_at(len - 1);
+            if packet[6] != VERSION || checksum[0] != sum(body) {
+                self.send_error(ErrorState::InvalidRpc)?;
+                continue;
+            }
+            if packet[7] == TYPE_RPC {
+                self.handle_rpc(&body[PREFIX_LEN..])?;
+            }
+        }
+    }
+
+    fn handle_rpc(&mut self, data: &[u8]) -> Result<()> {
+        let (command, args) = match data {
+            [command, len, args @ ..] if args.len() == *len as usize => (*command, args),
+            _ => return self.send_error(ErrorState::InvalidRpc),
+        };
+        match command {
+            COMMAND_WIFI_SETTINGS => {
+                let Some([ssid, password]) =
+                    parse_strings(args).and_then(|x| <[String; 2]>::try_from(x).ok())
+                else {
+                    return self.send_error(ErrorState::InvalidRpc);
+                };
+                self.send_error(ErrorState::None)?;
+                self.set_state(State::Provisioning)?;
+                let result = match self.connect.as_mut() {
+                    Some(connect) => connect(&ssid, &password),
+                    None => Err(anyhow::anyhow!("no connect callback")),
+                };
+                match result {
+                    Ok(url) => {
+                        self.set_state(State::Provisioned)?;
+                        let strings: Vec<&str> = url.iter().map(|x| x.as_str()).collect();
+                        self.send_result(command, &strings)
+                    }
+                    Err(_) => {
+                        self.set_state(State::Ready)?;
+                        self.send_error(ErrorState::UnableToConnect)
+                    }
+                }
+            }
+            COMMAND_CURRENT_STATE => self.set_state(self.state),
+            COMMAND_DEVICE_INFO => {
+                let info = self.device_info.clone();
+                let strings: Vec<&str> = info.iter().map(|x| x.as_str()).collect();
+                self.send_result(command, &strings)
+            }
+            COMMAND_SCAN => {
+                let networks = self.scan.as_mut().map(|scan| scan()).unwrap_or_default();
+                for network in networks {
+                    let rssi = network.rssi.to_string();
+                    let secured = if network.is_secured { "YES" } else { "NO" };
+                    self.send_result(command, &[&network.ssid, &rssi, secured])?;
+                }
+                // an empty result ends the list
+                self.send_result(command, &[])
+            }
+            _ => self.send_error(ErrorState::UnknownCommand),
+        }
+    }
+
+    fn set_state(&mut self, state: State) -> Result<()> {
+        self.state = state;
+        self.send_packet(TYPE_CURRENT_STATE, &[state as u8])
+    }
+
+    fn send_error(&mut self, error: ErrorState) -> Result<()> {
+        self.send_packet(TYPE_ERROR_STATE, &[error as u8])
+    }
+
+    fn send_result(&mut self, command: u8, strings: &[&str]) -> Result<()> {
+        let mut data = vec![command, 0];
+        for string in strings {
+            data.push(string.len() as u8);
+            data.extend_from_slice(string.as_bytes());
+        }
+        data[1] = (data.len() - 2) as u8;
+        self.send_packet(TYPE_RPC_RESULT, &data)
+    }
+
+    fn send_packet(&mut self, kind: u8, data: &[u8]) -> Result<()> {
+        let mut packet = HEADER.to_vec();
+        packet.extend_from_slice(&[VERSION, kind, data.len() as u8]);
+        packet.extend_from_slice(data);
+        packet.push(sum(&packet));
+        // terminate the line for clients reading the port line by line
+        packet.push(b'\n');
+        self.port.write_all(&packet)
+    }
+}
+
+/// Parse length-prefixed strings.
+fn parse_strings(mut data: &[u8]) -> Option<Vec<String>> {
+    let mut strings = Vec::new();
+    while let [len, rest @ ..] = data {
+        let len = *len as usize;
+        if rest.len() < len {
+            return None;
+        }
+        strings.push(String::from_utf8_lossy(&rest[..len]).to_string());
+        data = &rest[len..];
+    }
+    Some(strings)
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |acc, x| acc.wrapping_add(*x))
+}
@@ -0,0 +1,237 @@
+//! Infrared remote receiver on an expansion pin
+//!
+//! Captures the output of an external demodulating IR receiver module
+//! (TSOP38238, VS1838B and the like) with the RMT peripheral and decodes
+//! NEC and RC5 frames. The raw timing is kept in every capture, so
+//! unknown protocols can be learned and replayed as-is.
+use anyhow::Result;
+use esp_idf_hal::{
+    delay::TickType,
+    gpio::InputPin,
+    peripheral::Peripheral,
+    rmt::{self, config::ReceiveConfig, PinState, Receive, RmtChannel, RxRmtDriver},
+};
+use std::time::Duration;
+
+/// Silence that ends a frame (µs)
+const IDLE_THRESHOLD_US: u16 = 12000;
+const RING_BUFFER_SIZE: usize = 1000;
+const MAX_ITEMS: usize = 128;
+
+const NEC_HEADER_MARK_US: u32 = 9000;
+const NEC_HEADER_SPACE_US: u32 = 4500;
+const NEC_REPEAT_SPACE_US: u32 = 2250;
+const NEC_BIT_MARK_US: u32 = 560;
+const NEC_ZERO_SPACE_US: u32 = 560;
+const NEC_ONE_SPACE_US: u32 = 1690;
+const RC5_HALF_BIT_US: u32 = 889;
+const RC5_BITS: usize = 14;
+
+/// A mark (carrier on) or a space (carrier off) with its duration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pulse {
+    pub is_mark: bool,
+    pub micros: u32,
+}
+
+/// Decoded remote control code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrCode {
+    /// NEC frame; the address is 16 bits wide for the extended variant
+    Nec { address: u16, command: u8 },
+    /// NEC repeat code sent while the button is held
+    NecRepeat,
+    /// Philips RC5 frame; commands above 63 are RC5X
+    Rc5 {
+        toggle: bool,
+        address: u8,
+        command: u8,
+    },
+    /// Signal in an unsupported protocol
+    Unknown,
+}
+
+/// A captured IR frame
+///
+/// Pulses alternate between marks and spaces starting with a mark.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrSignal {
+    pulses: Vec<Pulse>,
+}
+
+impl IrSignal {
+    /// Create signal from the raw timing.
+    pub fn new(pulses: Vec<Pulse>) -> Self {
+        Self { pulses }
+    }
+
+    /// Return the raw timing.
+    pub fn pulses(&self) -> &[Pulse] {
+        &self.pulses
+    }
+
+    /// Decode the signal.
+    pub fn decode(&self) -> IrCode {
+        decode_nec(&self.pulses)
+            .or_else(|| decode_rc5(&self.pulses))
+            .unwrap_or(IrCode::Unknown)
+    }
+
+    fn from_items(items: &[(rmt::Pulse, rmt::Pulse)]) -> Self {
+        let mut pulses: Vec<Pulse> = Vec::new();
+        for pulse in items.iter().flat_map(|(first, second)| [first, second]) {
+            let micros = pulse.ticks.ticks() as u32;
+            if micros == 0 {
+                break;
+            }
+            // the receiver module pulls the output low while the carrier is on
+            let is_mark = pulse.pin_state == PinState::Low;
+            match pulses.last_mut() {
+                Some(last) if last.is_mark == is_mark => last.micros += micros,
+                None if !is_mark => {}
+                _ => pulses.push(Pulse { is_mark, micros }),
+            }
+        }
+        Self { pulses }
+    }
+}
+
+/// IR receiver module on an expansion or Grove pin
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::ir::{IrCode, IrReceiver};
+///
+/// let mut ir = IrReceiver::new(peripherals.rmt.channel4, peripherals.pins.gpio1).unwrap();
+/// loop {
+///     if let Some(signal) = ir.receive(Duration::from_millis(100)).unwrap() {
+///         match signal.decode() {
+///             IrCode::Unknown => log::info!("{:?}", signal.pulses()),
+///             code => log::info!("{:?}", code),
+///         }
+///     }
+/// }
+/// ```
+pub struct IrReceiver<'a> {
+    driver: RxRmtDriver<'a>,
+    items: Vec<(rmt::Pulse, rmt::Pulse)>,
+}
+
+impl<'a> IrReceiver<'a> {
+    /// Create new receiver and start capturing.
+    ///
+    /// Only the RMT channels 4 to 7 can receive on the ESP32-S3.
+    pub fn new<C: RmtChannel>(
+        channel: impl Peripheral<P = C> + 'a,
+        pin: impl Peripheral<P = impl InputPin> + 'a,
+    ) -> Result<Self> {
+        let config = ReceiveConfig::new()
+            .clock_divider(80)
+            .idle_threshold(IDLE_THRESHOLD_US)
+            .filter_ticks_thresh(100)
+            .filter_en(true);
+        let driver = RxRmtDriver::new(channel, pin, &config, RING_BUFFER_SIZE)?;
+        driver.start()?;
+        Ok(Self {
+            driver,
+            items: vec![(rmt::Pulse::zero(), rmt::Pulse::zero()); MAX_ITEMS],
+        })
+    }
+
+    /// Wait for a frame.
+    ///
+    /// Returns None on timeout or if the frame was too long to capture.
+    pub fn receive(&mut self, timeout: Duration) -> Result<Option<IrSignal>> {
+        match self
+            .driver
+            .receive(&mut self.items, TickType::from(timeout).ticks())?
+        {
+            Receive::Read(len) => {
+                let signal = IrSignal::from_items(&self.items[..len]);
+                Ok((!signal.pulses.is_empty()).then_some(signal))
+            }
+            Receive::Overflow(_) | Receive::Timeout => Ok(None),
+        }
+    }
+}
+
+fn is_near(actual: u32, expected: u32) -> bool {
+    actual * 4 >= expected * 3 && actual * 4 <= expected * 5
+}
+
+fn decode_nec(pulses: &[Pulse]) -> Option<IrCode> {
+    if pulses.len() < 3 || !is_near(pulses[0].micros, NEC_HEADER_MARK_US) {
+        return None;
+    }
+    if is_near(pulses[1].micros, NEC_REPEAT_SPACE_US) && is_near(pulses[2].micros, NEC_BIT_MARK_US)
+    {
+        return Some(IrCode::NecRepeat);
+    }
+    if pulses.len() < 67 || !is_near(pulses[1].micros, NEC_HEADER_SPACE_US) {
+        return None;
+    }
+
+    let mut data = 0u32;
+    for (i, bit) in pulses[2..66].chunks(2).enumerate() {
+        if !is_near(bit[0].micros, NEC_BIT_MARK_US) {
+            return None;
+        }
+        if is_near(bit[1].micros, NEC_ONE_SPACE_US) {
+            data |= 1 << i;
+        } else if !is_near(bit[1].micros, NEC_ZERO_SPACE_US) {
+            return None;
+        }
+    }
+
+    let [address, address_inv, command, command_inv] = data.to_le_bytes();
+    if command != !command_inv {
+        return None;
+    }
+    let address = if address == !address_inv {
+        address as u16
+    } else {
+        u16::from_le_bytes([address, address_inv])
+    };
+    Some(IrCode::Nec { address, command })
+}
+
+fn decode_rc5(pulses: &[Pulse]) -> Option<IrCode> {
+    // the first half of the start bit is a space that cannot be captured
+    let mut halves = vec![false];
+    for pulse in pulses {
+        let count = if is_near(pulse.micros, RC5_HALF_BIT_US) {
+            1
+        } else if is_near(pulse.micros, RC5_HALF_BIT_US * 2) {
+            2
+        } else {
+            return None;
+        };
+        halves.resize(halves.len() + count, pulse.is_mark);
+    }
+    // a trailing space merges into the idle period
+    if halves.len() == RC5_BITS * 2 - 1 {
+        halves.push(false);
+    }
+    if halves.len() != RC5_BITS * 2 {
+        return None;
+    }
+
+    let mut bits = 0u16;
+    for half in halves.chunks(2) {
+        let bit = match half {
+            [false, true] => 1,
+            [true, false] => 0,
+            _ => return None,
+        };
+        bits = bits << 1 | bit;
+    }
+
+    // S1 S2 T A4..A0 C5..C0, where S2 is the inverted C6 in RC5X
+    let field = if bits & 0x1000 == 0 { 0x40 } else { 0 };
+    Some(IrCode::Rc5 {
+        toggle: bits & 0x0800 != 0,
+        address: (bits >> 6 & 0x1F) as u8,
+        command: (bits & 0x3F) as u8 | field,
+    })
+}
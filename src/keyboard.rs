@@ -13,208 +13,28 @@
 //!  -  L  H  | FN  SHT  a   s   d   f   g   h   j   k   l   ;   '  ENT
 //!  -  L  L  | CTL OPT ALT  z   x   c   v   b   n   m   ,   .   /  SPC
 //! ```
+//!
+//! The key types, the key map and the keyboard state are defined in the
+//! hardware-independent `cardputer-core` crate and re-exported here.
 use anyhow::Result;
 use esp_idf_hal::{
     gpio::{Gpio11, Gpio13, Gpio15, Gpio3, Gpio4, Gpio5, Gpio6, Gpio7, Gpio8, Gpio9},
-    gpio::{Input, Level, Output, PinDriver},
+    gpio::{Input, Level, Output, PinDriver, Pull},
     peripheral::Peripheral,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum KeyImprint {
-    Backquote,
-    One,
-    Two,
-    Three,
-    Four,
-    Five,
-    Six,
-    Seven,
-    Eight,
-    Nine,
-    Zero,
-    Minus,
-    Equal,
-    Backspace,
-    Tab,
-    Q,
-    W,
-    E,
-    R,
-    T,
-    Y,
-    U,
-    I,
-    O,
-    P,
-    OpenSquareBracket,
-    CloseSquareBracket,
-    Backslash,
-    LeftFn,
-    LeftShift,
-    A,
-    S,
-    D,
-    F,
-    G,
-    H,
-    J,
-    K,
-    L,
-    SemiColon,
-    Quote,
-    Enter,
-    LeftCtrl,
-    LeftOpt,
-    LeftAlt,
-    Z,
-    X,
-    C,
-    V,
-    B,
-    N,
-    M,
-    Comma,
-    Period,
-    Slash,
-    Space,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum Modified {
-    Graph(char),
-    Escape,
-    Enter,
-    Space,
-    Tab,
-    LeftCursor,
-    DownCursor,
-    UpCursor,
-    RightCursor,
-    Backspace,
-    Delete,
-}
-macro_rules! graph {
-    ($x:expr) => {
-        Modified::Graph($x)
-    };
-}
-
-#[derive(Debug, PartialEq, Clone, Copy)]
-/// Conversion rule
-pub struct ConversionRule(KeyImprint, Modified, Modified);
-impl ConversionRule {
-    /// Convert according to the state of Fn and Shift key
-    pub fn modified(&self, is_fn_pressed: bool, is_shift_pressed: bool) -> Modified {
-        match (self.0, is_fn_pressed, is_shift_pressed) {
-            (KeyImprint::SemiColon, true, _) => Modified::UpCursor,
-            (KeyImprint::Period, true, _) => Modified::DownCursor,
-            (KeyImprint::Slash, true, _) => Modified::RightCursor,
-            (KeyImprint::Comma, true, _) => Modified::LeftCursor,
-            (KeyImprint::Backquote, true, _) => Modified::Escape,
-            (KeyImprint::Backspace, true, _) => Modified::Delete,
-            (_, _, true) => self.2,
-            (_, _, _) => self.1,
-        }
-    }
-
-    /// Returns the imprint of the key assigned to the rule
-    pub fn imprint(&self) -> KeyImprint {
-        self.0
-    }
-}
-
-#[derive(Debug, Copy, Clone)]
-/// Define the type of key as modifier key and normal key
-pub enum KeyType {
-    Modifier(KeyImprint),
-    Normal(ConversionRule),
-}
-impl KeyType {
-    pub fn imprint(&self) -> KeyImprint {
-        match self {
-            KeyType::Modifier(x) => *x,
-            KeyType::Normal(x) => x.imprint(),
-        }
-    }
-}
-macro_rules! normal {
-    ($x:expr,$y:expr,$z:expr) => {
-        KeyType::Normal(ConversionRule($x, $y, $z))
-    };
-}
-
-const COLUMN_MAP: [[usize; 7]; 2] = [[1, 3, 5, 7, 9, 11, 13], [0, 2, 4, 6, 8, 10, 12]];
-const KEY_MAP: [[KeyType; 14]; 4] = [
-    [
-        KeyType::Modifier(KeyImprint::LeftCtrl),
-        KeyType::Modifier(KeyImprint::LeftOpt),
-        KeyType::Modifier(KeyImprint::LeftAlt),
-        normal!(KeyImprint::Z, graph!('z'), graph!('Z')),
-        normal!(KeyImprint::X, graph!('x'), graph!('X')),
-        normal!(KeyImprint::C, graph!('c'), graph!('C')),
-        normal!(KeyImprint::V, graph!('v'), graph!('V')),
-        normal!(KeyImprint::B, graph!('b'), graph!('B')),
-        normal!(KeyImprint::N, graph!('n'), graph!('N')),
-        normal!(KeyImprint::M, graph!('m'), graph!('M')),
-        normal!(KeyImprint::Comma, graph!(','), graph!('<')),
-        normal!(KeyImprint::Period, graph!('.'), graph!('>')),
-        normal!(KeyImprint::Slash, graph!('/'), graph!('?')),
-        normal!(KeyImprint::Space, Modified::Space, Modified::Space),
-    ],
-    [
-        KeyType::Modifier(KeyImprint::LeftFn),
-        KeyType::Modifier(KeyImprint::LeftShift),
-        normal!(KeyImprint::A, graph!('a'), graph!('A')),
-        normal!(KeyImprint::S, graph!('s'), graph!('S')),
-        normal!(KeyImprint::D, graph!('d'), graph!('D')),
-        normal!(KeyImprint::F, graph!('f'), graph!('F')),
-        normal!(KeyImprint::G, graph!('g'), graph!('G')),
-        normal!(KeyImprint::H, graph!('h'), graph!('H')),
-        normal!(KeyImprint::J, graph!('j'), graph!('J')),
-        normal!(KeyImprint::K, graph!('k'), graph!('K')),
-        normal!(KeyImprint::L, graph!('l'), graph!('L')),
-        normal!(KeyImprint::SemiColon, graph!(';'), graph!(':')),
-        normal!(KeyImprint::Quote, graph!('\''), graph!('"')),
-        normal!(KeyImprint::Enter, Modified::Enter, Modified::Enter),
-    ],
-    [
-        normal!(KeyImprint::Tab, Modified::Tab, Modified::Tab),
-        normal!(KeyImprint::Q, graph!('q'), graph!('Q')),
-        normal!(KeyImprint::W, graph!('w'), graph!('W')),
-        normal!(KeyImprint::E, graph!('e'), graph!('E')),
-        normal!(KeyImprint::R, graph!('r'), graph!('R')),
-        normal!(KeyImprint::T, graph!('t'), graph!('T')),
-        normal!(KeyImprint::Y, graph!('y'), graph!('Y')),
-        normal!(KeyImprint::U, graph!('u'), graph!('U')),
-        normal!(KeyImprint::I, graph!('i'), graph!('I')),
-        normal!(KeyImprint::O, graph!('o'), graph!('O')),
-        normal!(KeyImprint::P, graph!('p'), graph!('P')),
-        normal!(KeyImprint::OpenSquareBracket, graph!('['), graph!('{')),
-        normal!(KeyImprint::CloseSquareBracket, graph!(']'), graph!('}')),
-        normal!(KeyImprint::Backslash, graph!('\\'), graph!('|')),
-    ],
-    [
-        normal!(KeyImprint::Backquote, graph!('`'), graph!('~')),
-        normal!(KeyImprint::One, graph!('1'), graph!('!')),
-        normal!(KeyImprint::Two, graph!('2'), graph!('@')),
-        normal!(KeyImprint::Three, graph!('3'), graph!('#')),
-        normal!(KeyImprint::Four, graph!('4'), graph!('$')),
-        normal!(KeyImprint::Five, graph!('5'), graph!('%')),
-        normal!(KeyImprint::Six, graph!('6'), graph!('^')),
-        normal!(KeyImprint::Seven, graph!('7'), graph!('&')),
-        normal!(KeyImprint::Eight, graph!('8'), graph!('*')),
-        normal!(KeyImprint::Nine, graph!('9'), graph!('(')),
-        normal!(KeyImprint::Zero, graph!('0'), graph!(')')),
-        normal!(KeyImprint::Minus, graph!('-'), graph!('_')),
-        normal!(KeyImprint::Equal, graph!('='), graph!('+')),
-        normal!(
-            KeyImprint::Backspace,
-            Modified::Backspace,
-            Modified::Backspace
-        ),
-    ],
-];
+pub mod accessibility;
+pub mod keymap_file;
+pub mod layer_cue;
+mod self_test;
+pub mod tca8418;
+pub mod typing_stats;
+pub use cardputer_core::keyboard::{
+    broadcast, event_ring, fn_key, idle, numpad, pipeline, Activator, ConversionRule, KeyImprint,
+    KeyType, KeyboardScanner, KeyboardState, Layer, Layers, Modified,
+};
+pub(crate) use cardputer_core::keyboard::{decode_matrix, key_at, KEY_MAP};
+pub use self_test::{self_test, SelfTestPrompt, SelfTestReport};
 
 macro_rules! pin_level {
     ($x:expr) => {
@@ -225,12 +45,6 @@ macro_rules! pin_level {
     };
 }
 
-/// Keyboard scanner trait
-pub trait KeyboardScanner {
-    /// Scan the keyboard and return the Vector of KeyType.
-    fn scan_pressed_keytypes(&mut self) -> Result<Vec<KeyType>>;
-}
-
 /// Keyboard scanner for Cardputer
 ///
 /// # Examples
@@ -282,6 +96,7 @@ impl<'a> Keyboard<'a> {
         y5: impl Peripheral<P = Gpio6> + 'a,
         y6: impl Peripheral<P = Gpio7> + 'a,
     ) -> Result<Self> {
+        crate::sleep::release_keyboard();
         Ok(Self {
             addr0: PinDriver::output(a0)?,
             addr1: PinDriver::output(a1)?,
@@ -311,8 +126,15 @@ impl<'a> Keyboard<'a> {
 
 impl KeyboardScanner for Keyboard<'_> {
     fn scan_pressed_keytypes(&mut self) -> Result<Vec<KeyType>> {
-        let mut keys: Vec<KeyType> = vec![];
-        for i in 0..8 {
+        Ok(decode_matrix(&self.scan_matrix()?))
+    }
+}
+
+impl Keyboard<'_> {
+    /// Scan the keyboard and return the pressed state of each input for each address.
+    fn scan_matrix(&mut self) -> Result<[[bool; 7]; 8]> {
+        let mut matrix = [[false; 7]; 8];
+        for (i, row) in matrix.iter_mut().enumerate() {
             self.addr0.set_level(pin_level!(i & 0b00000001))?;
             self.addr1.set_level(pin_level!(i & 0b00000010))?;
             self.addr2.set_level(pin_level!(i & 0b00000100))?;
@@ -326,137 +148,23 @@ impl KeyboardScanner for Keyboard<'_> {
                 self.y5.get_level(),
                 self.y6.get_level(),
             ];
-            for (j, decoded) in inputs.iter().enumerate() {
-                if *decoded == Level::High {
-                    continue;
-                }
-                let (col, row) = if i < 4 {
-                    (COLUMN_MAP[0][j], i)
-                } else {
-                    (COLUMN_MAP[1][j], i - 4)
-                };
-                keys.push(KEY_MAP[row][col]);
+            for (pressed, decoded) in row.iter_mut().zip(inputs.iter()) {
+                *pressed = *decoded == Level::Low;
             }
         }
 
-        Ok(keys)
+        Ok(matrix)
     }
-}
-
-/// Structure that scans the keyboard and keeps track of state changes
-///
-/// # Examples
-///
-/// ```
-/// use cardputer::keyboard::{Keyboard, KeyboardState};
-///
-/// let peripherals = Peripherals::take().unwrap();
-///
-/// let mut keyboard = Keyboard::new(
-///     peripherals.pins.gpio8,
-///     peripherals.pins.gpio9,
-///     peripherals.pins.gpio11,
-///     peripherals.pins.gpio13,
-///     peripherals.pins.gpio15,
-///     peripherals.pins.gpio3,
-///     peripherals.pins.gpio4,
-///     peripherals.pins.gpio5,
-///     peripherals.pins.gpio6,
-///     peripherals.pins.gpio7,
-/// )
-/// .unwrap();
-///
-/// let mut keyboard_state = KeyboardState::default();
-/// keyboard_state.update(&mut keyboard).unwrap();
-/// log::info!("{:?}", keyboard_state.pressed_keys());
-/// log::info!("{:?}", keyboard_state.released_keys());
-/// ```
-#[derive(Debug, Default)]
-pub struct KeyboardState {
-    is_fn_pressed: bool,
-    is_ctrl_pressed: bool,
-    is_shift_pressed: bool,
-    is_alt_pressed: bool,
-
-    hold_keys: Vec<ConversionRule>,
-    pressed_keys: Vec<ConversionRule>,
-    released_keys: Vec<ConversionRule>,
-}
-
-impl KeyboardState {
-    /// Get the latest key state and update the Pressed/Released state
-    pub fn update(&mut self, keyboard: &mut impl KeyboardScanner) -> Result<()> {
-        let mut new_hold_keys: Vec<ConversionRule> = Vec::new();
-
-        self.pressed_keys.clear();
-        self.released_keys.clear();
-
-        self.is_fn_pressed = false;
-        self.is_ctrl_pressed = false;
-        self.is_shift_pressed = false;
-        self.is_alt_pressed = false;
-
-        for pressed in keyboard.scan_pressed_keytypes()?.iter() {
-            match pressed {
-                KeyType::Modifier(KeyImprint::LeftFn) => self.is_fn_pressed = true,
-                KeyType::Modifier(KeyImprint::LeftCtrl) => self.is_ctrl_pressed = true,
-                KeyType::Modifier(KeyImprint::LeftShift) => self.is_shift_pressed = true,
-                KeyType::Modifier(KeyImprint::LeftAlt) => self.is_alt_pressed = true,
-                KeyType::Normal(h) => {
-                    new_hold_keys.push(*h);
-                    if !self.hold_keys.contains(h) {
-                        self.pressed_keys.push(*h);
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        for key in self.hold_keys.iter() {
-            if !new_hold_keys.contains(key) {
-                self.released_keys.push(*key);
-            }
-        }
-
-        self.hold_keys = new_hold_keys;
 
+    /// Enable the internal pull-ups of the input lines.
+    fn enable_pull_ups(&mut self) -> Result<()> {
+        self.y0.set_pull(Pull::Up)?;
+        self.y1.set_pull(Pull::Up)?;
+        self.y2.set_pull(Pull::Up)?;
+        self.y3.set_pull(Pull::Up)?;
+        self.y4.set_pull(Pull::Up)?;
+        self.y5.set_pull(Pull::Up)?;
+        self.y6.set_pull(Pull::Up)?;
         Ok(())
     }
-
-    pub fn pressed_keys(&self) -> Vec<Modified> {
-        self.pressed_keys
-            .iter()
-            .map(|x| x.modified(self.is_fn_pressed, self.is_shift_pressed))
-            .collect()
-    }
-
-    pub fn released_keys(&self) -> Vec<Modified> {
-        self.released_keys
-            .iter()
-            .map(|x| x.modified(self.is_fn_pressed, self.is_shift_pressed))
-            .collect()
-    }
-
-    pub fn hold_keys(&self) -> Vec<Modified> {
-        self.hold_keys
-            .iter()
-            .map(|x| x.modified(self.is_fn_pressed, self.is_shift_pressed))
-            .collect()
-    }
-
-    pub fn is_fn_pressed(&self) -> bool {
-        self.is_fn_pressed
-    }
-
-    pub fn is_ctrl_pressed(&self) -> bool {
-        self.is_ctrl_pressed
-    }
-
-    pub fn is_shift_pressed(&self) -> bool {
-        self.is_shift_pressed
-    }
-
-    pub fn is_alt_pressed(&self) -> bool {
-        self.is_alt_pressed
-    }
 }
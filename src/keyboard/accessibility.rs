@@ -0,0 +1,97 @@
+//! Accessibility filters for the input pipeline
+//!
+//! Slow keys accept a key only after it is held for a while, bounce keys
+//! ignore a key pressed again right after it was released, and sticky
+//! modifiers keep a modifier pressed alone active for the next key. The
+//! filters go after the [`Debouncer`] and before the [`LayerMapper`].
+//!
+//! The filters are defined in the hardware-independent `cardputer-core`
+//! crate and re-exported here with the settings kept in a [`Store`].
+use anyhow::Result;
+use std::time::Duration;
+
+use super::pipeline::{Debouncer, LayerMapper, Pipeline, Repeater};
+use crate::storage::Store;
+pub use cardputer_core::keyboard::accessibility::{BounceKeys, SlowKeys, StickyModifiers};
+
+// the keys fit in the 15 bytes of an NVS key
+
+/// Store key of the slow keys hold time in milliseconds, 0 when off
+pub const SLOW_KEYS_KEY: &str = "a11y_slow";
+/// Store key of the bounce keys time in milliseconds, 0 when off
+pub const BOUNCE_KEYS_KEY: &str = "a11y_bounce";
+/// Store key of the sticky modifiers, "1" when on
+pub const STICKY_MODIFIERS_KEY: &str = "a11y_sticky";
+
+/// Accessibility settings kept in a [`Store`]
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::keyboard::accessibility::AccessibilitySettings;
+///
+/// let settings = AccessibilitySettings::load(&nvs).unwrap();
+/// let mut pipeline = settings.pipeline();
+/// loop {
+///     for event in pipeline.update(&mut keyboard).unwrap() {
+///         log::info!("{:?}", event);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccessibilitySettings {
+    /// Hold time of the slow keys, `None` when off
+    pub slow_keys: Option<Duration>,
+    /// Time of the bounce keys, `None` when off
+    pub bounce_keys: Option<Duration>,
+    /// Whether a modifier pressed alone stays active for the next key
+    pub sticky_modifiers: bool,
+}
+
+impl AccessibilitySettings {
+    /// Read the settings from the store. Missing keys are off.
+    pub fn load(store: &impl Store) -> Result<Self> {
+        let millis = |key| -> Result<Option<Duration>> {
+            let value: u64 = match store.read_string(key)? {
+                Some(x) => x.trim().parse()?,
+                None => 0,
+            };
+            Ok((value > 0).then(|| Duration::from_millis(value)))
+        };
+        Ok(Self {
+            slow_keys: millis(SLOW_KEYS_KEY)?,
+            bounce_keys: millis(BOUNCE_KEYS_KEY)?,
+            sticky_modifiers: store.read_string(STICKY_MODIFIERS_KEY)?.as_deref() == Some("1"),
+        })
+    }
+
+    /// Write the settings to the store.
+    pub fn save(&self, store: &mut impl Store) -> Result<()> {
+        let millis = |x: Option<Duration>| x.map_or(0, |x| x.as_millis()).to_string();
+        store.write(SLOW_KEYS_KEY, millis(self.slow_keys).as_bytes())?;
+        store.write(BOUNCE_KEYS_KEY, millis(self.bounce_keys).as_bytes())?;
+        let sticky = if self.sticky_modifiers { "1" } else { "0" };
+        store.write(STICKY_MODIFIERS_KEY, sticky.as_bytes())
+    }
+
+    /// Append the enabled filters to the pipeline.
+    pub fn with_filters(&self, mut pipeline: Pipeline) -> Pipeline {
+        if let Some(time) = self.bounce_keys {
+            pipeline = pipeline.with_stage(BounceKeys::new(time));
+        }
+        if let Some(hold) = self.slow_keys {
+            pipeline = pipeline.with_stage(SlowKeys::new(hold));
+        }
+        if self.sticky_modifiers {
+            pipeline = pipeline.with_stage(StickyModifiers::new());
+        }
+        pipeline
+    }
+
+    /// The standard pipeline with the enabled filters after the debouncer.
+    pub fn pipeline(&self) -> Pipeline {
+        self.with_filters(Pipeline::new().with_stage(Debouncer::default()))
+            .with_stage(Repeater::default())
+            .with_stage(LayerMapper::default())
+    }
+}
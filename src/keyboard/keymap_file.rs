@@ -0,0 +1,405 @@
+//! Keymap files loaded from storage
+//!
+//! Layouts are described in a TOML subset, so they can be customized on the
+//! SD card or flash without rebuilding the firmware:
+//!
+//! ```toml
+//! # added to the default Fn layer, replacing the keys it already assigns
+//! [fn]
+//! H = "LeftCursor"
+//! J = "DownCursor"
+//!
+//! # layer active while Opt is held
+//! [[layer]]
+//! hold = "LeftOpt"
+//! Q = "!"
+//! W = "@"
+//!
+//! # layer toggled each time a key converted to the value is pressed
+//! [[layer]]
+//! toggle = "NumLock"
+//! U = "4"
+//!
+//! # repeat delay and interval in milliseconds, or "off", for all keys,
+//! # the classes letters, digits and cursors, or single keys
+//! [repeat]
+//! default = "500,50"
+//! letters = "off"
+//! cursors = "300,30"
+//! Backspace = "400,80"
+//!
+//! # text typed when the key is pressed with exactly these modifiers
+//! [macros]
+//! "Opt+G" = "git status\n"
+//! "Ctrl+Alt+M" = "mail@example.com"
+//! ```
+//!
+//! Keys on the left are [`KeyImprint`] names and values are [`Modified`]
+//! names or single characters, both ignoring case. Values are basic strings
+//! with the `\n`, `\t`, `\"` and `\\` escapes.
+use anyhow::{anyhow, bail, Result};
+
+use std::time::Duration;
+
+use super::pipeline::{
+    Debouncer, KeyClass, KeyEvent, KeyEventKind, LayerMapper, Modifiers, Pipeline, Repeater, Scan,
+    Stage, Typematic,
+};
+use super::{Activator, KeyImprint, Layer, Layers, Modified};
+use crate::storage::Store;
+
+/// Text typed when a key is pressed with the modifiers
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub imprint: KeyImprint,
+    pub modifiers: Modifiers,
+    pub text: String,
+}
+
+/// Stage that replaces the trigger keys of macros with their text
+///
+/// Place it after the [`LayerMapper`], which fills the modifiers of the events.
+/// The repeats and the release of a trigger key are swallowed with its press.
+#[derive(Debug, Clone, Default)]
+pub struct MacroExpander {
+    macros: Vec<Macro>,
+    /// Trigger keys held since their macro was typed
+    triggered: Vec<KeyImprint>,
+}
+
+impl MacroExpander {
+    /// Create new expander without macros.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the macro, replacing the one with the same trigger.
+    pub fn with_macro(mut self, imprint: KeyImprint, modifiers: Modifiers, text: &str) -> Self {
+        self.macros
+            .retain(|x| !(x.imprint == imprint && x.modifiers == modifiers));
+        self.macros.push(Macro {
+            imprint,
+            modifiers,
+            text: text.to_string(),
+        });
+        self
+    }
+
+    /// Returns the macros.
+    pub fn macros(&self) -> &[Macro] {
+        &self.macros
+    }
+
+    fn find(&self, event: &KeyEvent) -> Option<&Macro> {
+        self.macros
+            .iter()
+            .find(|x| x.imprint == event.imprint && x.modifiers == event.modifiers)
+    }
+}
+
+impl Stage for MacroExpander {
+    fn process(&mut self, scan: &mut Scan) {
+        if self.macros.is_empty() {
+            return;
+        }
+        let mut events = Vec::with_capacity(scan.events.len());
+        for event in scan.events.drain(..) {
+            let is_triggered = self.triggered.contains(&event.imprint);
+            match (event.kind, self.find(&event)) {
+                (KeyEventKind::Pressed, Some(x)) => {
+                    for c in x.text.chars() {
+                        let key = match c {
+                            '\n' => Modified::Enter,
+                            '\t' => Modified::Tab,
+                            ' ' => Modified::Space,
+                            c => Modified::Graph(c),
+                        };
+                        for kind in [KeyEventKind::Pressed, KeyEventKind::Released] {
+                            events.push(KeyEvent {
+                                key: Some(key),
+                                ..KeyEvent::new(kind, event.imprint)
+                            });
+                        }
+                    }
+                    if !is_triggered {
+                        self.triggered.push(event.imprint);
+                    }
+                }
+                (KeyEventKind::Repeated, _) if is_triggered => {}
+                (KeyEventKind::Released, _) if is_triggered => {
+                    self.triggered.retain(|x| *x != event.imprint);
+                }
+                _ => events.push(event),
+            }
+        }
+        scan.events = events;
+    }
+}
+
+/// Layout read from a keymap file
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::keyboard::keymap_file::KeymapFile;
+/// use cardputer::storage::sd::SdCard;
+///
+/// let card = SdCard::mount(/* ... */).unwrap();
+/// let keymap = KeymapFile::load(&card.store(), "keymap.toml")
+///     .unwrap()
+///     .unwrap_or_default();
+/// let mut pipeline = keymap.pipeline();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct KeymapFile {
+    fn_rules: Vec<(KeyImprint, Modified)>,
+    layers: Vec<Layer>,
+    repeater: Repeater,
+    macros: MacroExpander,
+}
+
+impl KeymapFile {
+    /// Parse the keymap file.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser::default();
+        for (number, line) in text.lines().enumerate() {
+            parser
+                .line(line)
+                .map_err(|e| anyhow!("line {}: {}", number + 1, e))?;
+        }
+        parser.finish()
+    }
+
+    /// Read and parse the keymap file, or return `None` if it does not exist.
+    pub fn load(store: &impl Store, key: &str) -> Result<Option<Self>> {
+        match store.read_string(key)? {
+            Some(text) => Ok(Some(Self::parse(&text)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the default layers with the Fn bindings of the file, followed
+    /// by the layers of the file.
+    pub fn layers(&self) -> Layers {
+        let layers = Layers::new()
+            .with_layer(Layer::fn_layer().with_rules(&self.fn_rules))
+            .with_layer(Layer::numpad_layer());
+        self.layers
+            .iter()
+            .cloned()
+            .fold(layers, |layers, layer| layers.with_layer(layer))
+    }
+
+    /// Returns the repeat stage with the timing of the file.
+    pub fn repeater(&self) -> Repeater {
+        self.repeater.clone()
+    }
+
+    /// Returns the stage typing the macros of the file.
+    pub fn macros(&self) -> MacroExpander {
+        self.macros.clone()
+    }
+
+    /// The standard pipeline with the layers and macros of the file.
+    pub fn pipeline(&self) -> Pipeline {
+        Pipeline::new()
+            .with_stage(Debouncer::default())
+            .with_stage(self.repeater())
+            .with_stage(LayerMapper::new(self.layers()))
+            .with_stage(self.macros())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Section {
+    #[default]
+    None,
+    Fn,
+    Layer,
+    Repeat,
+    Macros,
+}
+
+#[derive(Default)]
+struct Parser {
+    section: Section,
+    keymap: KeymapFile,
+    /// Activator of the `[[layer]]` being read
+    activator: Option<Activator>,
+    /// Rules of the `[[layer]]` being read
+    rules: Vec<(KeyImprint, Modified)>,
+}
+
+impl Parser {
+    fn line(&mut self, line: &str) -> Result<()> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+        if line.starts_with('[') {
+            let header = line.split('#').next().unwrap_or_default().trim();
+            self.finish_layer()?;
+            self.section = match header {
+                "[fn]" => Section::Fn,
+                "[[layer]]" => Section::Layer,
+                "[repeat]" => Section::Repeat,
+                "[macros]" => Section::Macros,
+                _ => bail!("unknown section {}", header),
+            };
+            return Ok(());
+        }
+
+        let mut chars = line.chars().peekable();
+        let name = if chars.peek() == Some(&'"') {
+            string(&mut chars)?
+        } else {
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(|x| !x.is_whitespace() && *x != '=') {
+                name.push(c);
+            }
+            name
+        };
+        while chars.next_if(|x| x.is_whitespace()).is_some() {}
+        if chars.next() != Some('=') {
+            bail!("expected '=' after {:?}", name);
+        }
+        while chars.next_if(|x| x.is_whitespace()).is_some() {}
+        let value = string(&mut chars)?;
+        let rest: String = chars.collect();
+        if !rest.trim().is_empty() && !rest.trim().starts_with('#') {
+            bail!("unexpected {:?}", rest.trim());
+        }
+        self.entry(&name, &value)
+    }
+
+    fn entry(&mut self, name: &str, value: &str) -> Result<()> {
+        match self.section {
+            Section::Fn => {
+                let rule = rule(name, value)?;
+                self.keymap.fn_rules.retain(|(x, _)| *x != rule.0);
+                self.keymap.fn_rules.push(rule);
+            }
+            Section::Layer if name == "hold" => {
+                let imprint = KeyImprint::from_name(value)
+                    .ok_or_else(|| anyhow!("unknown key {:?}", value))?;
+                self.activator = Some(Activator::Momentary(imprint));
+            }
+            Section::Layer if name == "toggle" => {
+                let key =
+                    Modified::from_name(value).ok_or_else(|| anyhow!("unknown key {:?}", value))?;
+                self.activator = Some(Activator::Toggle(key));
+            }
+            Section::Layer => {
+                let rule = rule(name, value)?;
+                self.rules.retain(|(x, _)| *x != rule.0);
+                self.rules.push(rule);
+            }
+            Section::Repeat => {
+                let typematic = typematic(value)?;
+                let repeater = std::mem::take(&mut self.keymap.repeater);
+                self.keymap.repeater = match name.to_ascii_lowercase().as_str() {
+                    "default" => repeater.with_default(typematic),
+                    "letters" => repeater.with_class(KeyClass::Letter, typematic),
+                    "digits" => repeater.with_class(KeyClass::Digit, typematic),
+                    "cursors" => repeater.with_class(KeyClass::Cursor, typematic),
+                    _ => {
+                        let imprint = KeyImprint::from_name(name)
+                            .ok_or_else(|| anyhow!("unknown key {:?}", name))?;
+                        repeater.with_key(imprint, typematic)
+                    }
+                };
+            }
+            Section::Macros => {
+                let (imprint, modifiers) = trigger(name)?;
+                self.keymap.macros =
+                    std::mem::take(&mut self.keymap.macros).with_macro(imprint, modifiers, value);
+            }
+            Section::None => bail!("{:?} outside of a section", name),
+        }
+        Ok(())
+    }
+
+    fn finish_layer(&mut self) -> Result<()> {
+        if self.section == Section::Layer {
+            let activator = self
+                .activator
+                .take()
+                .ok_or_else(|| anyhow!("layer without \"hold\" or \"toggle\""))?;
+            let rules = std::mem::take(&mut self.rules);
+            self.keymap
+                .layers
+                .push(Layer::new(activator).with_rules(&rules));
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<KeymapFile> {
+        self.finish_layer()?;
+        Ok(self.keymap)
+    }
+}
+
+fn rule(name: &str, value: &str) -> Result<(KeyImprint, Modified)> {
+    let imprint = KeyImprint::from_name(name).ok_or_else(|| anyhow!("unknown key {:?}", name))?;
+    let key = Modified::from_name(value).ok_or_else(|| anyhow!("unknown key {:?}", value))?;
+    Ok((imprint, key))
+}
+
+/// Parse a timing such as "500,50" or "off".
+fn typematic(value: &str) -> Result<Typematic> {
+    if value.eq_ignore_ascii_case("off") {
+        return Ok(Typematic::Off);
+    }
+    let (delay, interval) = value
+        .split_once(',')
+        .ok_or_else(|| anyhow!("expected \"delay,interval\" or \"off\""))?;
+    let interval = Duration::from_millis(interval.trim().parse()?);
+    if interval.is_zero() {
+        bail!("zero repeat interval");
+    }
+    Ok(Typematic::new(
+        Duration::from_millis(delay.trim().parse()?),
+        interval,
+    ))
+}
+
+/// Parse a trigger such as "Opt+G" into the key and the modifiers.
+fn trigger(name: &str) -> Result<(KeyImprint, Modifiers)> {
+    let mut parts: Vec<&str> = name.split('+').map(str::trim).collect();
+    let key = parts.pop().unwrap_or_default();
+    let imprint = KeyImprint::from_name(key).ok_or_else(|| anyhow!("unknown key {:?}", key))?;
+    let mut modifiers = Modifiers::default();
+    for part in parts {
+        let flag = match part.to_ascii_lowercase().as_str() {
+            "fn" => &mut modifiers.is_fn_pressed,
+            "shift" => &mut modifiers.is_shift_pressed,
+            "ctrl" => &mut modifiers.is_ctrl_pressed,
+            "alt" => &mut modifiers.is_alt_pressed,
+            "opt" => &mut modifiers.is_opt_pressed,
+            _ => bail!("unknown modifier {:?}", part),
+        };
+        *flag = true;
+    }
+    Ok((imprint, modifiers))
+}
+
+/// Parse a basic string starting at the opening quote.
+fn string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String> {
+    if chars.next() != Some('"') {
+        bail!("expected a quoted string");
+    }
+    let mut text = String::new();
+    loop {
+        match chars.next().ok_or_else(|| anyhow!("unterminated string"))? {
+            '"' => return Ok(text),
+            '\\' => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some('r') => text.push('\r'),
+                Some(c) => text.push(c),
+                None => bail!("unterminated string"),
+            },
+            c => text.push(c),
+        }
+    }
+}
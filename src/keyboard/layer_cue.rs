@@ -0,0 +1,244 @@
+//! Audio ticks on layer changes
+//!
+//! The small keyboard gives no tactile hint of the active layer, so
+//! [`LayerCues`] plays a short tick when a momentary layer such as Fn is
+//! entered or left, and a longer one when a toggled layer such as the
+//! numeric keypad is switched on or off. Each cue has its own pitch and
+//! can be turned off.
+//!
+//! The ticks are mixed by a [`CueMixer`], either as an
+//! [`Effect`](crate::audio_pipeline::Effect) on the block of a running
+//! [`AudioPipeline`](crate::audio_pipeline::AudioPipeline), or as an
+//! [`AudioSource`] of silence and ticks for a pipeline of its own.
+use anyhow::Result;
+use std::{
+    f32::consts::PI,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::{Activator, KeyboardState};
+use crate::audio_pipeline::{AudioSource, Effect};
+
+/// Layer change played as a tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerCue {
+    /// A momentary layer became active
+    Enter,
+    /// A momentary layer became inactive
+    Leave,
+    /// A toggled layer was switched on
+    ToggleOn,
+    /// A toggled layer was switched off
+    ToggleOff,
+}
+
+impl LayerCue {
+    const ALL: [LayerCue; 4] = [
+        LayerCue::Enter,
+        LayerCue::Leave,
+        LayerCue::ToggleOn,
+        LayerCue::ToggleOff,
+    ];
+
+    /// Returns the frequency in Hz and the length of the tick.
+    fn tone(&self) -> (f32, Duration) {
+        match self {
+            LayerCue::Enter => (2400.0, Duration::from_millis(6)),
+            LayerCue::Leave => (1600.0, Duration::from_millis(6)),
+            LayerCue::ToggleOn => (2800.0, Duration::from_millis(20)),
+            LayerCue::ToggleOff => (1000.0, Duration::from_millis(20)),
+        }
+    }
+
+    fn index(&self) -> usize {
+        LayerCue::ALL.iter().position(|x| x == self).unwrap_or(0)
+    }
+}
+
+/// Tick being played
+struct Voice {
+    frequency: f32,
+    /// Samples played
+    position: usize,
+    len: usize,
+}
+
+/// Ticks shared by [`LayerCues`] and its mixers
+struct Voices {
+    sample_rate: u32,
+    amplitude: f32,
+    playing: Vec<Voice>,
+}
+
+impl Voices {
+    fn mix(&mut self, samples: &mut [i16]) {
+        let sample_rate = self.sample_rate as f32;
+        let amplitude = self.amplitude * i16::MAX as f32;
+        for voice in self.playing.iter_mut() {
+            let step = 2.0 * PI * voice.frequency / sample_rate;
+            for sample in samples.iter_mut() {
+                if voice.position >= voice.len {
+                    break;
+                }
+                // linear decay, so the tick ends without a click
+                let envelope = 1.0 - voice.position as f32 / voice.len as f32;
+                let value = (voice.position as f32 * step).sin() * envelope * amplitude;
+                *sample = sample.saturating_add(value as i16);
+                voice.position += 1;
+            }
+        }
+        self.playing.retain(|x| x.position < x.len);
+    }
+}
+
+/// Mixer of the layer ticks into the audio output
+pub struct CueMixer {
+    voices: Arc<Mutex<Voices>>,
+}
+
+impl Effect for CueMixer {
+    fn process(&mut self, samples: &mut [i16]) {
+        if let Ok(mut voices) = self.voices.lock() {
+            voices.mix(samples);
+        }
+    }
+}
+
+impl AudioSource for CueMixer {
+    fn sample_rate(&self) -> u32 {
+        self.voices.lock().map_or(0, |x| x.sample_rate)
+    }
+
+    fn read(&mut self, samples: &mut [i16]) -> Result<()> {
+        samples.fill(0);
+        self.process(samples);
+        Ok(())
+    }
+}
+
+/// Player of the layer cues
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::audio_pipeline::AudioPipeline;
+/// use cardputer::keyboard::layer_cue::{LayerCue, LayerCues};
+///
+/// let mut cues = LayerCues::new(speaker.sample_rate()).with_cue(LayerCue::Leave, false);
+/// let mut mixer = cues.mixer();
+/// speaker::set_source_gain("cues", -12.0);
+/// thread::spawn(move || {
+///     let mut speaker = speaker.with_source("cues");
+///     let stop = AtomicBool::new(false);
+///     AudioPipeline::new().run(&mut mixer, &mut speaker, &stop).unwrap();
+/// });
+///
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     cues.update(&keyboard_state);
+///     thread::sleep(Duration::from_millis(10));
+/// }
+/// ```
+pub struct LayerCues {
+    voices: Arc<Mutex<Voices>>,
+    enabled: [bool; LayerCue::ALL.len()],
+    /// Activity of the layers at the last update, by layer number from 1
+    active: Vec<bool>,
+}
+
+impl LayerCues {
+    /// Create new player of every cue at the sample rate of the output.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            voices: Arc::new(Mutex::new(Voices {
+                sample_rate,
+                amplitude: 0.3,
+                playing: Vec::new(),
+            })),
+            enabled: [true; LayerCue::ALL.len()],
+            active: Vec::new(),
+        }
+    }
+
+    /// Enable or disable the cue (all enabled).
+    pub fn with_cue(mut self, cue: LayerCue, enabled: bool) -> Self {
+        self.set_enabled(cue, enabled);
+        self
+    }
+
+    /// Set the amplitude of the ticks, from 0.0 to 1.0 (0.3).
+    pub fn with_amplitude(self, amplitude: f32) -> Self {
+        if let Ok(mut voices) = self.voices.lock() {
+            voices.amplitude = amplitude.clamp(0.0, 1.0);
+        }
+        self
+    }
+
+    /// Returns true if the cue is played.
+    pub fn is_enabled(&self, cue: LayerCue) -> bool {
+        self.enabled[cue.index()]
+    }
+
+    /// Enable or disable the cue.
+    pub fn set_enabled(&mut self, cue: LayerCue, enabled: bool) {
+        self.enabled[cue.index()] = enabled;
+    }
+
+    /// Returns a new mixer of the ticks.
+    pub fn mixer(&self) -> CueMixer {
+        CueMixer {
+            voices: self.voices.clone(),
+        }
+    }
+
+    /// Play the tick of the cue if it is enabled.
+    pub fn play(&self, cue: LayerCue) {
+        if !self.is_enabled(cue) {
+            return;
+        }
+        let Ok(mut voices) = self.voices.lock() else {
+            return;
+        };
+        let (frequency, length) = cue.tone();
+        let len = (length.as_micros() * voices.sample_rate as u128 / 1_000_000) as usize;
+        voices.playing.push(Voice {
+            frequency,
+            position: 0,
+            len,
+        });
+    }
+
+    /// Compare the layers with the last update and play the cues of the
+    /// changes. Returns the cues of the changes, enabled or not. The first
+    /// update only records the layers.
+    pub fn update(&mut self, state: &KeyboardState) -> Vec<LayerCue> {
+        let layers = state.layers();
+        let active: Vec<bool> = (1..)
+            .map_while(|number| layers.layer(number))
+            .map(|x| x.is_active())
+            .collect();
+        let mut cues = Vec::new();
+        if self.active.len() == active.len() {
+            for (number, (was, is)) in (1..).zip(self.active.iter().zip(&active)) {
+                if was == is {
+                    continue;
+                }
+                let momentary = layers
+                    .layer(number)
+                    .is_some_and(|x| matches!(x.activator(), Activator::Momentary(_)));
+                cues.push(match (momentary, *is) {
+                    (true, true) => LayerCue::Enter,
+                    (true, false) => LayerCue::Leave,
+                    (false, true) => LayerCue::ToggleOn,
+                    (false, false) => LayerCue::ToggleOff,
+                });
+            }
+        }
+        self.active = active;
+        for cue in &cues {
+            self.play(*cue);
+        }
+        cues
+    }
+}
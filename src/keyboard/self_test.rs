@@ -0,0 +1,142 @@
+//! Keyboard hardware self test
+use anyhow::Result;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{key_at, KeyImprint, Keyboard};
+
+/// Interval between the scans while waiting for a key
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Time given to the user to release all keys
+const RELEASE_WAIT: Duration = Duration::from_secs(1);
+/// Number of scans used to detect the stuck keys
+const IDLE_SCANS: usize = 10;
+
+/// Instruction to be shown to the user during the self test
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelfTestPrompt {
+    /// Release all keys
+    ReleaseAll,
+    /// Press the key
+    Press(KeyImprint),
+}
+
+/// Result of the keyboard self test
+///
+/// Rows are the outputs of the 74HC138 (address 0 to 7) and columns are
+/// the input lines (Y0 to Y6).
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SelfTestReport {
+    /// Keys read as pressed while all keys were released
+    pub stuck_keys: Vec<KeyImprint>,
+    /// Keys not detected within the timeout while the user was asked to press them
+    pub dead_keys: Vec<KeyImprint>,
+    /// Addresses where no key was detected
+    pub dead_rows: Vec<usize>,
+    /// Input lines where no key was detected
+    pub dead_columns: Vec<usize>,
+}
+
+impl SelfTestReport {
+    /// Returns true if no problem was found.
+    pub fn is_ok(&self) -> bool {
+        self.stuck_keys.is_empty() && self.dead_keys.is_empty()
+    }
+}
+
+/// Walk through the keyboard matrix and report the broken keys and lines.
+///
+/// The internal pull-ups of the input lines are enabled first, then every
+/// address is checked for keys read as pressed while nothing is pressed.
+/// After that the user is asked to press each key in turn through `prompt`;
+/// keys not detected within `timeout` are reported as dead.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::keyboard::{self, SelfTestPrompt};
+///
+/// let report = keyboard::self_test(
+///     &mut keyboard,
+///     |prompt| match prompt {
+///         SelfTestPrompt::ReleaseAll => log::info!("Release all keys"),
+///         SelfTestPrompt::Press(key) => log::info!("Press {:?}", key),
+///     },
+///     Duration::from_secs(5),
+/// )
+/// .unwrap();
+/// log::info!("{:?}", report);
+/// ```
+pub fn self_test(
+    keyboard: &mut Keyboard,
+    mut prompt: impl FnMut(SelfTestPrompt),
+    timeout: Duration,
+) -> Result<SelfTestReport> {
+    let mut report = SelfTestReport::default();
+
+    keyboard.enable_pull_ups()?;
+    prompt(SelfTestPrompt::ReleaseAll);
+    thread::sleep(RELEASE_WAIT);
+
+    let mut stuck = [[false; 7]; 8];
+    for _ in 0..IDLE_SCANS {
+        for (stuck_row, row) in stuck.iter_mut().zip(keyboard.scan_matrix()?.iter()) {
+            for (stuck_key, pressed) in stuck_row.iter_mut().zip(row.iter()) {
+                *stuck_key |= *pressed;
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let mut dead = [[false; 7]; 8];
+    // ask in the order of the layout, from the top row
+    for row in (0..4).rev() {
+        for col in 0..14 {
+            let (address, input) = if col % 2 == 1 {
+                (row, col / 2)
+            } else {
+                (row + 4, col / 2)
+            };
+            let Some(imprint) = key_at(address, input).map(|x| x.imprint()) else {
+                continue;
+            };
+            if stuck[address][input] {
+                report.stuck_keys.push(imprint);
+                continue;
+            }
+
+            prompt(SelfTestPrompt::Press(imprint));
+            if !wait_for(keyboard, address, input, true, timeout)? {
+                report.dead_keys.push(imprint);
+                dead[address][input] = true;
+                continue;
+            }
+            wait_for(keyboard, address, input, false, timeout)?;
+        }
+    }
+
+    report.dead_rows = (0..8).filter(|&i| dead[i].iter().all(|x| *x)).collect();
+    report.dead_columns = (0..7).filter(|&j| dead.iter().all(|row| row[j])).collect();
+
+    Ok(report)
+}
+
+/// Wait until the key becomes the state, returning false on timeout.
+fn wait_for(
+    keyboard: &mut Keyboard,
+    address: usize,
+    input: usize,
+    pressed: bool,
+    timeout: Duration,
+) -> Result<bool> {
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        if keyboard.scan_matrix()?[address][input] == pressed {
+            return Ok(true);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(false)
+}
@@ -0,0 +1,308 @@
+//! TCA8418 keypad controller of the Cardputer ADV
+//!
+//! The ADV scans its keyboard with a TCA8418 on the internal I2C bus
+//! (SDA GPIO8, SCL GPIO9, interrupt GPIO11) instead of the 74HC138
+//! decoder. The controller queues press and release events in a FIFO;
+//! [`Tca8418Keyboard`] replays them to keep the set of held keys and
+//! implements [`KeyboardScanner`] with the same key map as [`Keyboard`].
+//! The events are decoded by the hardware-independent [`HeldKeys`].
+//!
+//! [`Tca8418Keyboard::with_config`] sets the bus frequency and the timeout
+//! of each transaction with a [`Tca8418Config`]; some units with a long
+//! flex cable only work reliably at 100 kHz. The timeouts and errors are
+//! counted in [`Tca8418Stats`].
+//!
+//! [`Keyboard`]: super::Keyboard
+//! [`HeldKeys`]: cardputer_core::keyboard::tca8418::HeldKeys
+use anyhow::{anyhow, Result};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use esp_idf_hal::{
+    delay::TickType,
+    gpio::{InputPin, OutputPin},
+    i2c::{I2c, I2cConfig, I2cDriver},
+    peripheral::Peripheral,
+    prelude::*,
+    sys::{EspError, ESP_ERR_TIMEOUT},
+    units::Hertz,
+};
+use std::time::Duration;
+
+use super::{KeyType, KeyboardScanner};
+use cardputer_core::keyboard::tca8418::{Event, HeldKeys, COLUMNS, ROWS};
+
+const CFG: u8 = 0x01;
+const INT_STAT: u8 = 0x02;
+const KEY_LCK_EC: u8 = 0x03;
+const KEY_EVENT_A: u8 = 0x04;
+const KP_GPIO1: u8 = 0x1D;
+const KP_GPIO2: u8 = 0x1E;
+const KP_GPIO3: u8 = 0x1F;
+
+/// Key event interrupt enable
+const CFG_KE_IEN: u8 = 0x01;
+/// Key events are not lost when the FIFO is full; the oldest is overwritten
+const CFG_OVR_FLOW_M: u8 = 0x20;
+/// Key event interrupt status
+const INT_K_INT: u8 = 0x01;
+
+/// Bus settings of the controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tca8418Config {
+    pub frequency: Hertz,
+    /// Longest wait for each I2C transaction
+    pub timeout: Duration,
+}
+
+impl Tca8418Config {
+    /// Create new settings with the defaults.
+    pub fn new() -> Self {
+        Self {
+            frequency: 400.kHz().into(),
+            timeout: Duration::from_millis(500),
+        }
+    }
+
+    /// Set the bus frequency (400 kHz).
+    pub fn with_frequency(mut self, frequency: Hertz) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    /// Set the timeout of each transaction (500 ms).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl Default for Tca8418Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counters of the I2C transactions of [`TimeoutI2c`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tca8418Stats {
+    pub transactions: u32,
+    /// Transactions not completed within the timeout
+    pub timeouts: u32,
+    /// Transactions failed otherwise, e.g. not acknowledged
+    pub errors: u32,
+}
+
+/// I2C driver with a timeout for each transaction, counting the failures
+pub struct TimeoutI2c<'d> {
+    driver: I2cDriver<'d>,
+    timeout: Duration,
+    stats: Tca8418Stats,
+}
+
+impl<'d> TimeoutI2c<'d> {
+    /// Wrap the driver.
+    pub fn new(driver: I2cDriver<'d>, timeout: Duration) -> Self {
+        Self {
+            driver,
+            timeout,
+            stats: Tca8418Stats::default(),
+        }
+    }
+
+    /// Returns the counters since the creation or the last reset.
+    pub fn stats(&self) -> Tca8418Stats {
+        self.stats
+    }
+
+    /// Clear the counters.
+    pub fn reset_stats(&mut self) {
+        self.stats = Tca8418Stats::default();
+    }
+
+    /// Change the timeout of each transaction.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Release the I2C driver.
+    pub fn release(self) -> I2cDriver<'d> {
+        self.driver
+    }
+
+    fn ticks(&self) -> u32 {
+        TickType::from(self.timeout).ticks()
+    }
+
+    fn count(&mut self, result: Result<(), EspError>) -> Result<(), EspError> {
+        self.stats.transactions = self.stats.transactions.wrapping_add(1);
+        match &result {
+            Ok(()) => {}
+            Err(e) if e.code() == ESP_ERR_TIMEOUT => self.stats.timeouts += 1,
+            Err(_) => self.stats.errors += 1,
+        }
+        result
+    }
+}
+
+impl Write for TimeoutI2c<'_> {
+    type Error = EspError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        let result = self.driver.write(address, bytes, self.ticks());
+        self.count(result)
+    }
+}
+
+impl WriteRead for TimeoutI2c<'_> {
+    type Error = EspError;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let result = self.driver.write_read(address, bytes, buffer, self.ticks());
+        self.count(result)
+    }
+}
+
+/// Keyboard scanner of the Cardputer ADV
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::keyboard::{tca8418::Tca8418Keyboard, KeyboardState};
+///
+/// let i2c = I2cDriver::new(
+///     peripherals.i2c1,
+///     peripherals.pins.gpio8,
+///     peripherals.pins.gpio9,
+///     &I2cConfig::new().baudrate(400.kHz().into()),
+/// )
+/// .unwrap();
+/// let mut keyboard = Tca8418Keyboard::new(i2c).unwrap();
+/// let mut keyboard_state = KeyboardState::default();
+/// keyboard_state.update(&mut keyboard).unwrap();
+/// ```
+///
+/// With a slower bus and the counters of the timeouts:
+///
+/// ```
+/// use cardputer::keyboard::tca8418::{Tca8418Config, Tca8418Keyboard};
+///
+/// let config = Tca8418Config::new().with_frequency(100.kHz().into());
+/// let mut keyboard = Tca8418Keyboard::with_config(
+///     peripherals.i2c1,
+///     peripherals.pins.gpio8,
+///     peripherals.pins.gpio9,
+///     &config,
+/// )
+/// .unwrap();
+/// keyboard_state.update(&mut keyboard).unwrap();
+/// log::info!("{:?}", keyboard.stats());
+/// ```
+pub struct Tca8418Keyboard<I2C> {
+    i2c: I2C,
+    held: HeldKeys,
+}
+
+impl<I2C, E> Tca8418Keyboard<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    pub const ADDRESS: u8 = 0x34;
+
+    /// Configure the controller for the keyboard matrix.
+    pub fn new(i2c: I2C) -> Result<Self> {
+        let mut keyboard = Self {
+            i2c,
+            held: HeldKeys::new(),
+        };
+        keyboard.write_register(KP_GPIO1, (1 << ROWS) - 1)?;
+        keyboard.write_register(KP_GPIO2, ((1u16 << COLUMNS) - 1) as u8)?;
+        keyboard.write_register(KP_GPIO3, 0)?;
+        keyboard.write_register(CFG, CFG_KE_IEN | CFG_OVR_FLOW_M)?;
+        keyboard.flush()?;
+        Ok(keyboard)
+    }
+
+    /// Release the I2C driver.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// Discard the queued events and forget the held keys.
+    pub fn flush(&mut self) -> Result<()> {
+        while self.read_event()?.is_some() {}
+        self.held.clear();
+        Ok(())
+    }
+
+    /// Returns the next queued event.
+    fn read_event(&mut self) -> Result<Option<Event>> {
+        if self.read_register(KEY_LCK_EC)? & 0x0F == 0 {
+            self.write_register(INT_STAT, INT_K_INT)?;
+            return Ok(None);
+        }
+        let event = self.read_register(KEY_EVENT_A)?;
+        Ok(Some(Event::decode(event)))
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8> {
+        let mut value = [0u8];
+        self.i2c
+            .write_read(Self::ADDRESS, &[register], &mut value)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        Ok(value[0])
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<()> {
+        self.i2c
+            .write(Self::ADDRESS, &[register, value])
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+}
+
+impl<'d> Tca8418Keyboard<TimeoutI2c<'d>> {
+    /// Create the I2C driver with the settings and configure the
+    /// controller.
+    pub fn with_config<I2C: I2c>(
+        i2c: impl Peripheral<P = I2C> + 'd,
+        sda: impl Peripheral<P = impl InputPin + OutputPin> + 'd,
+        scl: impl Peripheral<P = impl InputPin + OutputPin> + 'd,
+        config: &Tca8418Config,
+    ) -> Result<Self> {
+        let i2c_config = I2cConfig::new().baudrate(config.frequency);
+        let driver = I2cDriver::new(i2c, sda, scl, &i2c_config)?;
+        Self::new(TimeoutI2c::new(driver, config.timeout))
+    }
+
+    /// Returns the counters of the I2C transactions.
+    pub fn stats(&self) -> Tca8418Stats {
+        self.i2c.stats()
+    }
+
+    /// Clear the counters of the I2C transactions.
+    pub fn reset_stats(&mut self) {
+        self.i2c.reset_stats();
+    }
+
+    /// Change the timeout of each I2C transaction.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.i2c.set_timeout(timeout);
+    }
+}
+
+impl<I2C, E> KeyboardScanner for Tca8418Keyboard<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    fn scan_pressed_keytypes(&mut self) -> Result<Vec<KeyType>> {
+        while let Some(event) = self.read_event()? {
+            self.held.apply(event);
+        }
+        Ok(self.held.keys())
+    }
+}
@@ -0,0 +1,44 @@
+//! Typing statistics for practicing on the keyboard
+//!
+//! [`TypingStats`] is defined in the hardware-independent `cardputer-core`
+//! crate and re-exported here. [`load`] and [`save`] keep it in a
+//! [`Store`] in its text format, so a session can be continued after a
+//! reboot.
+//!
+//! # Examples
+//!
+//! ```
+//! use cardputer::keyboard::typing_stats;
+//! use cardputer::widget::typing_stats::TypingStatsView;
+//!
+//! let mut stats = typing_stats::load(&store).unwrap().unwrap_or_default();
+//! loop {
+//!     keyboard_state.update(&mut keyboard).unwrap();
+//!     stats.record(&keyboard_state);
+//!     if keyboard_state.pressed_keys().contains(&Modified::Escape) {
+//!         typing_stats::save(&stats, &mut store).unwrap();
+//!         break;
+//!     }
+//! }
+//! TypingStatsView::new(&stats, Point::new(1, 1)).draw(&mut fb).unwrap();
+//! ```
+use anyhow::Result;
+
+use crate::storage::Store;
+pub use cardputer_core::keyboard::typing_stats::{TypingStats, IDLE_GAP};
+
+/// Store key of the statistics
+const TYPING_STATS_KEY: &str = "typing_stats";
+
+/// Read the statistics from the store. Returns `None` if none are saved.
+pub fn load(store: &impl Store) -> Result<Option<TypingStats>> {
+    store
+        .read_string(TYPING_STATS_KEY)?
+        .map(|x| TypingStats::from_text(&x))
+        .transpose()
+}
+
+/// Write the statistics to the store.
+pub fn save(stats: &TypingStats, store: &mut impl Store) -> Result<()> {
+    store.write(TYPING_STATS_KEY, stats.to_text().as_bytes())
+}
@@ -0,0 +1,333 @@
+//! WS2812 status LED and a pattern engine driven by system states
+//!
+//! The Cardputer has one WS2812 RGB LED on GPIO21. [`LedPatterns`] binds
+//! declarative [`Pattern`]s (solid colors, blinking, breathing, blink
+//! codes) to [`SystemState`]s such as WiFi connecting or low battery, and
+//! shows the pattern of the most important active state on any
+//! [`LedOutput`], including the backlight.
+use anyhow::Result;
+use core::f32::consts::PI;
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+use esp_idf_hal::{
+    gpio::Gpio21,
+    peripheral::Peripheral,
+    rmt::{config::TransmitConfig, FixedLengthSignal, PinState, Pulse, RmtChannel, TxRmtDriver},
+};
+use std::time::{Duration, Instant};
+
+use crate::backlight::Backlight;
+
+/// Output showing the color of a pattern
+pub trait LedOutput {
+    fn set_color(&mut self, color: Rgb888) -> Result<()>;
+}
+
+/// WS2812 RGB LED driver
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::led::StatusLed;
+///
+/// let peripherals = Peripherals::take().unwrap();
+///
+/// let mut led = StatusLed::new(peripherals.rmt.channel0, peripherals.pins.gpio21).unwrap();
+/// led.set(Rgb888::new(0, 0, 32)).unwrap();
+/// ```
+pub struct StatusLed<'a> {
+    driver: TxRmtDriver<'a>,
+    /// Pulses of a 0 bit and a 1 bit
+    bits: [(Pulse, Pulse); 2],
+}
+
+impl<'a> StatusLed<'a> {
+    /// Create new driver on the RMT channel.
+    pub fn new<C: RmtChannel>(
+        channel: impl Peripheral<P = C> + 'a,
+        gpio: impl Peripheral<P = Gpio21> + 'a,
+    ) -> Result<Self> {
+        let config = TransmitConfig::new().clock_divider(1);
+        let driver = TxRmtDriver::new(channel, gpio, &config)?;
+        let ticks = driver.counter_clock()?;
+        let pulse = |state, ns| Pulse::new_with_duration(ticks, state, &Duration::from_nanos(ns));
+        let bits = [
+            (pulse(PinState::High, 350)?, pulse(PinState::Low, 800)?),
+            (pulse(PinState::High, 700)?, pulse(PinState::Low, 600)?),
+        ];
+        Ok(Self { driver, bits })
+    }
+
+    /// Show the color.
+    pub fn set(&mut self, color: Rgb888) -> Result<()> {
+        // the LED takes green, red, blue, most significant bit first
+        let value = (color.g() as u32) << 16 | (color.r() as u32) << 8 | color.b() as u32;
+        let mut signal = FixedLengthSignal::<24>::new();
+        for i in 0..24 {
+            let bit = (value >> (23 - i)) & 1;
+            signal.set(i, &self.bits[bit as usize])?;
+        }
+        self.driver.start_blocking(&signal)?;
+        Ok(())
+    }
+
+    /// Turn off the LED.
+    pub fn off(&mut self) -> Result<()> {
+        self.set(Rgb888::BLACK)
+    }
+}
+
+impl LedOutput for StatusLed<'_> {
+    fn set_color(&mut self, color: Rgb888) -> Result<()> {
+        self.set(color)
+    }
+}
+
+/// The brightness of the backlight follows the brightest channel of the
+/// pattern, so breathing fades it; black turns it off. The brightness set
+/// by the pattern stays after it ends.
+impl LedOutput for Backlight<'_> {
+    fn set_color(&mut self, color: Rgb888) -> Result<()> {
+        let level = color.r().max(color.g()).max(color.b()) as u32;
+        if level == 0 {
+            return self.off();
+        }
+        self.set_brightness(level.saturating_mul(100).div_ceil(255) as u8)?;
+        self.on()
+    }
+}
+
+/// Declarative LED pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Off,
+    Solid(Rgb888),
+    /// On and off repeatedly
+    Blink {
+        color: Rgb888,
+        on: Duration,
+        off: Duration,
+    },
+    /// Fade in and out smoothly over the period
+    Breathe {
+        color: Rgb888,
+        period: Duration,
+    },
+    /// `count` short flashes followed by a pause, repeated
+    BlinkCode {
+        color: Rgb888,
+        count: u32,
+    },
+}
+
+/// Length of a flash and of the gap between flashes of a blink code
+const CODE_FLASH: Duration = Duration::from_millis(200);
+/// Pause after the flashes of a blink code
+const CODE_PAUSE: Duration = Duration::from_millis(1200);
+
+impl Pattern {
+    /// Returns the color of the pattern at the time since it started.
+    pub fn color_at(&self, elapsed: Duration) -> Rgb888 {
+        match *self {
+            Pattern::Off => Rgb888::BLACK,
+            Pattern::Solid(color) => color,
+            Pattern::Blink { color, on, off } => {
+                let period = (on + off).as_millis().max(1);
+                if elapsed.as_millis() % period < on.as_millis() {
+                    color
+                } else {
+                    Rgb888::BLACK
+                }
+            }
+            Pattern::Breathe { color, period } => {
+                let phase = elapsed.as_secs_f32() / period.as_secs_f32().max(0.001);
+                let level = (1.0 - (phase * 2.0 * PI).cos()) / 2.0;
+                scale(color, level)
+            }
+            Pattern::BlinkCode { color, count } => {
+                let flash = CODE_FLASH.as_millis();
+                let period = flash * 2 * count as u128 + CODE_PAUSE.as_millis();
+                let t = elapsed.as_millis() % period;
+                if t < flash * 2 * count as u128 && (t / flash) & 1 == 0 {
+                    color
+                } else {
+                    Rgb888::BLACK
+                }
+            }
+        }
+    }
+}
+
+fn scale(color: Rgb888, level: f32) -> Rgb888 {
+    let channel = |x: u8| (x as f32 * level.clamp(0.0, 1.0) + 0.5) as u8;
+    Rgb888::new(channel(color.r()), channel(color.g()), channel(color.b()))
+}
+
+/// State of the system shown by the LED
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SystemState {
+    Error,
+    LowBattery,
+    WifiConnecting,
+    WifiConnected,
+    Charging,
+    Busy,
+    Idle,
+    /// Application-defined state
+    Custom(u8),
+}
+
+/// Engine showing the pattern of the most important active state
+///
+/// States bound earlier take precedence. The default bindings, from the
+/// most important, are:
+///
+/// | State | Pattern |
+/// |---|---|
+/// | Error | fast red blinking |
+/// | LowBattery | blink code of 2 in orange |
+/// | WifiConnecting | blue blinking |
+/// | Charging | green breathing |
+/// | Busy | white breathing |
+/// | WifiConnected | dim blue |
+/// | Idle | off |
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::led::{LedPatterns, StatusLed, SystemState};
+///
+/// let mut led = StatusLed::new(peripherals.rmt.channel0, peripherals.pins.gpio21).unwrap();
+/// let mut patterns = LedPatterns::default();
+/// patterns.activate(SystemState::WifiConnecting);
+/// // ...
+/// patterns.deactivate(SystemState::WifiConnecting);
+/// patterns.activate(SystemState::WifiConnected);
+/// loop {
+///     patterns.update(&mut led).unwrap();
+///     // ...
+/// }
+/// ```
+pub struct LedPatterns {
+    bindings: Vec<(SystemState, Pattern)>,
+    /// Active states with the time of the activation
+    active: Vec<(SystemState, Instant)>,
+    shown: Option<Rgb888>,
+}
+
+impl LedPatterns {
+    /// Create new engine without bindings.
+    pub fn new() -> Self {
+        Self {
+            bindings: Vec::new(),
+            active: Vec::new(),
+            shown: None,
+        }
+    }
+
+    /// Show the pattern while the state is active, replacing the pattern
+    /// already bound to the state. A new state is the least important.
+    pub fn with_pattern(mut self, state: SystemState, pattern: Pattern) -> Self {
+        match self.bindings.iter_mut().find(|(x, _)| *x == state) {
+            Some(binding) => binding.1 = pattern,
+            None => self.bindings.push((state, pattern)),
+        }
+        self
+    }
+
+    /// Mark the state active.
+    pub fn activate(&mut self, state: SystemState) {
+        if !self.is_active(state) {
+            self.active.push((state, Instant::now()));
+        }
+    }
+
+    /// Mark the state inactive.
+    pub fn deactivate(&mut self, state: SystemState) {
+        self.active.retain(|(x, _)| *x != state);
+    }
+
+    /// Activate or deactivate the state.
+    pub fn set(&mut self, state: SystemState, active: bool) {
+        if active {
+            self.activate(state);
+        } else {
+            self.deactivate(state);
+        }
+    }
+
+    /// Returns true if the state is active.
+    pub fn is_active(&self, state: SystemState) -> bool {
+        self.active.iter().any(|(x, _)| *x == state)
+    }
+
+    /// Returns the color to show now.
+    pub fn color(&self) -> Rgb888 {
+        self.bindings
+            .iter()
+            .find_map(|(state, pattern)| {
+                let (_, since) = self.active.iter().find(|(x, _)| x == state)?;
+                Some(pattern.color_at(since.elapsed()))
+            })
+            .unwrap_or(Rgb888::BLACK)
+    }
+
+    /// Show the current color on the output if it changed. Call it at
+    /// least every 20 ms for smooth breathing.
+    pub fn update(&mut self, output: &mut impl LedOutput) -> Result<()> {
+        let color = self.color();
+        if self.shown != Some(color) {
+            output.set_color(color)?;
+            self.shown = Some(color);
+        }
+        Ok(())
+    }
+}
+
+impl Default for LedPatterns {
+    fn default() -> Self {
+        let blue = Rgb888::new(0, 0, 64);
+        Self::new()
+            .with_pattern(
+                SystemState::Error,
+                Pattern::Blink {
+                    color: Rgb888::new(96, 0, 0),
+                    on: Duration::from_millis(100),
+                    off: Duration::from_millis(100),
+                },
+            )
+            .with_pattern(
+                SystemState::LowBattery,
+                Pattern::BlinkCode {
+                    color: Rgb888::new(96, 32, 0),
+                    count: 2,
+                },
+            )
+            .with_pattern(
+                SystemState::WifiConnecting,
+                Pattern::Blink {
+                    color: blue,
+                    on: Duration::from_millis(500),
+                    off: Duration::from_millis(500),
+                },
+            )
+            .with_pattern(
+                SystemState::Charging,
+                Pattern::Breathe {
+                    color: Rgb888::new(0, 64, 0),
+                    period: Duration::from_secs(3),
+                },
+            )
+            .with_pattern(
+                SystemState::Busy,
+                Pattern::Breathe {
+                    color: Rgb888::new(48, 48, 48),
+                    period: Duration::from_secs(1),
+                },
+            )
+            .with_pattern(
+                SystemState::WifiConnected,
+                Pattern::Solid(Rgb888::new(0, 0, 8)),
+            )
+            .with_pattern(SystemState::Idle, Pattern::Off)
+    }
+}
@@ -1,5 +1,62 @@
 //! Utilities for M5Stack Cardputer
+pub mod audio_pipeline;
 pub mod backlight;
+#[cfg(feature = "ble")]
+pub mod ble;
+pub mod board;
+pub mod button;
+pub mod chat;
+pub mod clipboard;
+pub mod compositor;
+pub mod crash;
+pub mod diagnostics;
 pub mod display;
+pub mod dither;
+pub mod focus;
+pub mod frame_stream;
+pub mod framebuffer;
+pub mod gamepad;
+pub mod generator;
+pub mod gif;
 pub mod grove;
+pub mod hid;
+pub mod hotkey;
+pub mod i2c_bus;
+pub mod improv;
+pub mod ir;
 pub mod keyboard;
+pub mod led;
+#[cfg(feature = "logger")]
+pub mod logger;
+pub mod media;
+pub mod memory;
+pub mod microphone;
+pub mod morse;
+pub mod pacer;
+pub mod panic_screen;
+pub mod power;
+pub mod radio;
+pub mod rtc;
+pub mod screen;
+pub mod sensors;
+pub mod sleep;
+pub mod smooth_text;
+pub mod sound_trigger;
+pub mod speaker;
+pub mod spectrum;
+pub mod status_refresh;
+pub mod storage;
+#[cfg(feature = "async")]
+pub mod tasks;
+pub mod timers;
+#[cfg(feature = "usb")]
+pub mod usb;
+pub mod usb_serial;
+pub mod video;
+pub mod wall_clock;
+pub mod watchdog;
+pub mod wav;
+pub mod web;
+pub mod widget;
+pub mod xmodem;
+pub use cardputer_core::color;
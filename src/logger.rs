@@ -0,0 +1,308 @@
+//! Logger writing the `log` records to several sinks
+//!
+//! Only one logger can be installed, so [`Logger`] forwards each record to
+//! its sinks: the serial console of ESP-IDF and, e.g., a [`ConsoleSink`]
+//! showing the records on the display when no monitor is attached, or a
+//! [`FileSink`] keeping them on the SD card to diagnose intermittent bugs
+//! after the fact.
+use anyhow::{anyhow, Result};
+use embedded_graphics::pixelcolor::{Rgb565, RgbColor};
+use esp_idf_svc::log::EspLogger;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use crate::wall_clock::Timestamp;
+use crate::widget::console::Console;
+
+/// Logger forwarding the records to the sinks
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::logger::{ConsoleSink, Logger};
+/// use cardputer::widget::console::Console;
+///
+/// let console = Arc::new(Mutex::new(Console::new(fb.bounding_box())));
+/// Logger::new()
+///     .with_serial()
+///     .with_sink(ConsoleSink::new(console.clone()))
+///     .install()
+///     .unwrap();
+///
+/// log::info!("started");
+/// loop {
+///     console.lock().unwrap().draw(&mut fb).unwrap();
+///     fb.flush(&mut display).unwrap();
+/// }
+/// ```
+pub struct Logger {
+    sinks: Vec<Box<dyn Log>>,
+    level: LevelFilter,
+}
+
+impl Logger {
+    /// Create new logger without sinks.
+    pub fn new() -> Self {
+        Self {
+            sinks: Vec::new(),
+            level: LevelFilter::Info,
+        }
+    }
+
+    /// Set the most verbose level logged (info).
+    pub fn with_level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Write the records to the serial console as the ESP-IDF logger does.
+    pub fn with_serial(self) -> Self {
+        self.with_sink(EspLogger)
+    }
+
+    /// Write the records to the sink as well.
+    pub fn with_sink(mut self, sink: impl Log + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Install the logger for the `log` macros. Fails if a logger is
+    /// already installed.
+    pub fn install(self) -> Result<()> {
+        let level = self.level;
+        log::set_logger(Box::leak(Box::new(self))).map_err(|e| anyhow!("{:?}", e))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        for sink in self.sinks.iter() {
+            if sink.enabled(record.metadata()) {
+                sink.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        for sink in self.sinks.iter() {
+            sink.flush();
+        }
+    }
+}
+
+/// Returns the letter of the level as ESP-IDF prints it.
+fn level_letter(level: Level) -> char {
+    match level {
+        Level::Error => 'E',
+        Level::Warn => 'W',
+        Level::Info => 'I',
+        Level::Debug => 'D',
+        Level::Trace => 'V',
+    }
+}
+
+/// Sink adding the records to a console widget, colored by level
+///
+/// The application draws the console; a record logged while the console
+/// is locked by the same thread would deadlock, so do not log while
+/// holding the lock.
+pub struct ConsoleSink {
+    console: Arc<Mutex<Console>>,
+    level: LevelFilter,
+}
+
+impl ConsoleSink {
+    /// Create new sink adding the records to the console.
+    pub fn new(console: Arc<Mutex<Console>>) -> Self {
+        Self {
+            console,
+            level: LevelFilter::Trace,
+        }
+    }
+
+    /// Set the most verbose level shown (all).
+    pub fn with_level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+impl Log for ConsoleSink {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        let color = match record.level() {
+            Level::Error => Rgb565::RED,
+            Level::Warn => Rgb565::YELLOW,
+            Level::Info => Rgb565::WHITE,
+            Level::Debug | Level::Trace => Rgb565::new(16, 32, 16),
+        };
+        let line = format!("{} {}", level_letter(record.level()), record.args());
+        if let Ok(mut console) = self.console.lock() {
+            console.push_colored_line(&line, color);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sink appending the records to size-limited files, rotated like
+/// `app.log`, `app.log.1`, `app.log.2`, ...
+///
+/// Each line starts with a [`Timestamp`]: the UTC time once the clock is
+/// set (e.g. by SNTP), or the time since the boot before that. A failed
+/// write, e.g. while the card is removed, drops the record and the file
+/// is reopened for the next one.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::logger::{FileSink, Logger};
+///
+/// Logger::new()
+///     .with_serial()
+///     .with_sink(FileSink::new("/sdcard/app.log").with_max_size(32 * 1024))
+///     .install()
+///     .unwrap();
+/// ```
+pub struct FileSink {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    level: LevelFilter,
+    state: Mutex<FileState>,
+}
+
+#[derive(Default)]
+struct FileState {
+    file: Option<File>,
+    size: u64,
+}
+
+impl FileSink {
+    /// Create new sink writing to the file.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size: 64 * 1024,
+            max_files: 4,
+            level: LevelFilter::Trace,
+            state: Mutex::new(FileState::default()),
+        }
+    }
+
+    /// Set the size in bytes at which the file is rotated (64 KiB).
+    pub fn with_max_size(mut self, size: u64) -> Self {
+        self.max_size = size.max(1);
+        self
+    }
+
+    /// Set the number of files kept, including the current one (4).
+    pub fn with_max_files(mut self, count: usize) -> Self {
+        self.max_files = count.max(1);
+        self
+    }
+
+    /// Set the most verbose level written (all).
+    pub fn with_level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    /// Shift the rotated files and move the current file to `.1`. If the
+    /// current file cannot be moved, it is truncated so that it does not
+    /// grow past the maximum size.
+    fn rotate(&self) {
+        let _ = fs::remove_file(self.rotated_path(self.max_files - 1));
+        for index in (1..self.max_files - 1).rev() {
+            let _ = fs::rename(self.rotated_path(index), self.rotated_path(index + 1));
+        }
+        let moved = if self.max_files > 1 {
+            fs::rename(&self.path, self.rotated_path(1))
+        } else {
+            fs::remove_file(&self.path)
+        };
+        if moved.is_err() {
+            let _ = File::create(&self.path);
+        }
+    }
+
+    fn write(&self, state: &mut FileState, line: &[u8]) -> std::io::Result<()> {
+        if state.file.is_some() && state.size + line.len() as u64 > self.max_size {
+            state.file = None;
+            self.rotate();
+        }
+        let file = match &mut state.file {
+            Some(x) => x,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                state.size = file.metadata()?.len();
+                state.file.insert(file)
+            }
+        };
+        file.write_all(line)?;
+        state.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+impl Log for FileSink {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        let line = format!(
+            "{} {} {}: {}\n",
+            Timestamp::now(),
+            level_letter(record.level()),
+            record.target(),
+            record.args()
+        );
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if self.write(&mut state, line.as_bytes()).is_err() {
+            state.file = None;
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(file) = &mut state.file {
+                let _ = file.flush();
+            }
+        }
+    }
+}
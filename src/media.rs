@@ -0,0 +1,121 @@
+//! System media shortcuts
+//!
+//! Fn+Minus/Equal change the volume, Fn+Space mutes, Fn+P plays or
+//! pauses, Fn+[/] skip tracks and Fn+9/0 change the brightness.
+use crate::keyboard::Modified;
+use crate::speaker;
+
+/// Media key produced by the Fn shortcuts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKey {
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    PlayPause,
+    NextTrack,
+    PreviousTrack,
+    BrightnessUp,
+    BrightnessDown,
+}
+
+impl MediaKey {
+    /// Returns the media key of the modified key, if any.
+    pub fn from_modified(key: Modified) -> Option<Self> {
+        match key {
+            Modified::VolumeUp => Some(MediaKey::VolumeUp),
+            Modified::VolumeDown => Some(MediaKey::VolumeDown),
+            Modified::Mute => Some(MediaKey::Mute),
+            Modified::PlayPause => Some(MediaKey::PlayPause),
+            Modified::NextTrack => Some(MediaKey::NextTrack),
+            Modified::PreviousTrack => Some(MediaKey::PreviousTrack),
+            Modified::BrightnessUp => Some(MediaKey::BrightnessUp),
+            Modified::BrightnessDown => Some(MediaKey::BrightnessDown),
+            _ => None,
+        }
+    }
+}
+
+/// Handler of the media shortcuts
+///
+/// By default the volume shortcuts adjust the global volume of the
+/// speaker and the others are dropped. HID modes can install a hook with
+/// [`MediaShortcuts::forward_to`] to send them to the host as
+/// consumer-control reports instead.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::media::MediaShortcuts;
+///
+/// let mut media = MediaShortcuts::new();
+/// keyboard_state.update(&mut keyboard).unwrap();
+/// let keys = media.process(keyboard_state.pressed_keys());
+/// ```
+pub struct MediaShortcuts {
+    step: u8,
+    hook: Option<Box<dyn FnMut(MediaKey) + Send>>,
+}
+
+impl MediaShortcuts {
+    /// Create new handler that changes the volume by 10 per key press.
+    pub fn new() -> Self {
+        Self {
+            step: 10,
+            hook: None,
+        }
+    }
+
+    /// Set the amount of the volume change per key press.
+    pub fn with_step(mut self, step: u8) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Forward the media keys to the hook instead of the speaker.
+    pub fn forward_to(mut self, hook: impl FnMut(MediaKey) + Send + 'static) -> Self {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Stop forwarding and control the speaker again.
+    pub fn clear_hook(&mut self) {
+        self.hook = None;
+    }
+
+    /// Handle the media keys in the pressed keys and return the other keys.
+    pub fn process(&mut self, keys: Vec<Modified>) -> Vec<Modified> {
+        keys.into_iter()
+            .filter(|key| match MediaKey::from_modified(*key) {
+                Some(media) => {
+                    self.handle(media);
+                    false
+                }
+                None => true,
+            })
+            .collect()
+    }
+
+    /// Handle a media key.
+    ///
+    /// Volume and mute changes stay in RAM until [`speaker::save`].
+    pub fn handle(&mut self, key: MediaKey) {
+        if let Some(hook) = self.hook.as_mut() {
+            hook(key);
+            return;
+        }
+        match key {
+            MediaKey::VolumeUp => speaker::set_volume(speaker::volume().saturating_add(self.step)),
+            MediaKey::VolumeDown => {
+                speaker::set_volume(speaker::volume().saturating_sub(self.step))
+            }
+            MediaKey::Mute => speaker::set_muted(!speaker::is_muted()),
+            _ => {}
+        }
+    }
+}
+
+impl Default for MediaShortcuts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
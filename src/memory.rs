@@ -0,0 +1,380 @@
+//! Placement of large buffers in PSRAM or internal RAM
+//!
+//! The stock Cardputer has no PSRAM, but modules and boards built around
+//! the same firmware may have it. [`Buffer`] uses it when it is available
+//! and falls back to internal RAM otherwise, so the same code works on both.
+use anyhow::{bail, Result};
+use core::{
+    alloc::Layout,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+use esp_idf_hal::sys::{
+    heap_caps_aligned_alloc, heap_caps_free, heap_caps_get_free_size,
+    heap_caps_get_largest_free_block, heap_caps_get_minimum_free_size, heap_caps_get_total_size,
+    MALLOC_CAP_8BIT, MALLOC_CAP_DMA, MALLOC_CAP_INTERNAL, MALLOC_CAP_SPIRAM,
+};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+
+/// Minimum alignment of the buffers, as required by DMA
+const MIN_ALIGN: usize = 4;
+
+/// Number of buffers in the shared DMA pool
+const SHARED_POOL_BUFFERS: usize = 4;
+
+/// Size in bytes of a buffer in the shared DMA pool
+const SHARED_POOL_BUFFER_SIZE: usize = 4096;
+
+/// Where a buffer is placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// PSRAM if available, internal RAM otherwise. For frame buffers,
+    /// decoded images, scrollback and other large data.
+    Large,
+    /// DMA-capable internal RAM. For SPI and I2S transfers.
+    Dma,
+    /// Internal RAM
+    Internal,
+}
+
+/// Returns true if PSRAM is available.
+pub fn has_psram() -> bool {
+    // SAFETY: the heap functions only read the statistics of the heap
+    unsafe { heap_caps_get_total_size(MALLOC_CAP_SPIRAM) > 0 }
+}
+
+/// Returns the free bytes of PSRAM.
+pub fn free_psram() -> usize {
+    // SAFETY: see has_psram
+    unsafe { heap_caps_get_free_size(MALLOC_CAP_SPIRAM) }
+}
+
+/// Returns the free bytes of internal RAM.
+pub fn free_internal() -> usize {
+    // SAFETY: see has_psram
+    unsafe { heap_caps_get_free_size(MALLOC_CAP_INTERNAL | MALLOC_CAP_8BIT) }
+}
+
+/// Snapshot of the heap usage in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub free_internal: usize,
+    /// Largest block that can be allocated in internal RAM; much smaller
+    /// than the free size when the heap is fragmented
+    pub largest_internal_block: usize,
+    /// Lowest free internal RAM since boot
+    pub min_free_internal: usize,
+    pub total_psram: usize,
+    pub free_psram: usize,
+}
+
+impl MemoryStats {
+    /// Read the current usage.
+    pub fn current() -> Self {
+        let internal = MALLOC_CAP_INTERNAL | MALLOC_CAP_8BIT;
+        // SAFETY: the heap functions only read the statistics of the heap
+        unsafe {
+            Self {
+                free_internal: heap_caps_get_free_size(internal),
+                largest_internal_block: heap_caps_get_largest_free_block(internal),
+                min_free_internal: heap_caps_get_minimum_free_size(internal),
+                total_psram: heap_caps_get_total_size(MALLOC_CAP_SPIRAM),
+                free_psram: heap_caps_get_free_size(MALLOC_CAP_SPIRAM),
+            }
+        }
+    }
+}
+
+type LowMemoryCallback = Box<dyn FnMut(&MemoryStats) + Send>;
+
+/// Watches the free internal RAM and calls back when it runs low
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::memory::MemoryMonitor;
+///
+/// let mut monitor = MemoryMonitor::new(32 * 1024);
+/// monitor.on_low_memory(|stats| log::warn!("low memory: {:?}", stats));
+/// loop {
+///     let stats = monitor.check();
+///     // ...
+/// }
+/// ```
+pub struct MemoryMonitor {
+    threshold: usize,
+    callback: Option<LowMemoryCallback>,
+    is_low: bool,
+}
+
+impl MemoryMonitor {
+    /// Create new monitor that treats less free internal RAM than `threshold` bytes as low.
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            callback: None,
+            is_low: false,
+        }
+    }
+
+    /// Set the callback for low memory.
+    ///
+    /// It is called once when the free memory falls below the threshold,
+    /// and again only after it has recovered.
+    pub fn on_low_memory(&mut self, callback: impl FnMut(&MemoryStats) + Send + 'static) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Returns true if the free memory was below the threshold at the last check.
+    pub fn is_low(&self) -> bool {
+        self.is_low
+    }
+
+    /// Read the usage and call the callback if the memory became low.
+    pub fn check(&mut self) -> MemoryStats {
+        let stats = MemoryStats::current();
+        let is_low = stats.free_internal < self.threshold;
+        if is_low && !self.is_low {
+            if let Some(callback) = self.callback.as_mut() {
+                callback(&stats);
+            }
+        }
+        self.is_low = is_low;
+        stats
+    }
+}
+
+/// Fixed-size buffer allocated according to a [`Placement`]
+///
+/// Dereferences to a slice.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::memory::{Buffer, Placement};
+///
+/// let mut samples = Buffer::new(16000, 0i16, Placement::Large);
+/// samples[0] = 100;
+/// log::info!("in PSRAM: {}", samples.is_psram());
+/// ```
+pub struct Buffer<T: Copy> {
+    ptr: NonNull<T>,
+    len: usize,
+    caps: u32,
+}
+
+// SAFETY: the buffer owns its memory like Vec
+unsafe impl<T: Copy + Send> Send for Buffer<T> {}
+// SAFETY: shared references only read the elements, like &[T]
+unsafe impl<T: Copy + Sync> Sync for Buffer<T> {}
+
+impl<T: Copy> Buffer<T> {
+    /// Allocate the buffer filled with the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the memory is exhausted, like `vec!`.
+    pub fn new(len: usize, value: T, placement: Placement) -> Self {
+        match Self::try_new(len, value, placement) {
+            Ok(x) => x,
+            Err(_) => std::alloc::handle_alloc_error(Layout::array::<T>(len).unwrap()),
+        }
+    }
+
+    /// Allocate the buffer filled with the value, or return an error if
+    /// the memory is exhausted.
+    pub fn try_new(len: usize, value: T, placement: Placement) -> Result<Self> {
+        let Ok(layout) = Layout::array::<T>(len) else {
+            bail!("buffer too large: {} elements", len);
+        };
+        if layout.size() == 0 {
+            return Ok(Self {
+                ptr: NonNull::dangling(),
+                len,
+                caps: 0,
+            });
+        }
+
+        let candidates: &[u32] = match placement {
+            Placement::Large => &[
+                MALLOC_CAP_SPIRAM | MALLOC_CAP_8BIT,
+                MALLOC_CAP_INTERNAL | MALLOC_CAP_8BIT,
+            ],
+            Placement::Dma => &[MALLOC_CAP_DMA | MALLOC_CAP_INTERNAL | MALLOC_CAP_8BIT],
+            Placement::Internal => &[MALLOC_CAP_INTERNAL | MALLOC_CAP_8BIT],
+        };
+        for caps in candidates {
+            let align = layout.align().max(MIN_ALIGN);
+            // SAFETY: the alignment is a power of two and the size is not
+            // zero; a null pointer is handled below
+            let ptr = unsafe { heap_caps_aligned_alloc(align, layout.size(), *caps) };
+            if let Some(ptr) = NonNull::new(ptr as *mut T) {
+                for i in 0..len {
+                    // SAFETY: the memory is allocated for len elements
+                    unsafe { ptr.as_ptr().add(i).write(value) };
+                }
+                return Ok(Self {
+                    ptr,
+                    len,
+                    caps: *caps,
+                });
+            }
+        }
+        bail!("out of memory: {} bytes for {:?}", layout.size(), placement)
+    }
+
+    /// Returns true if the buffer is in PSRAM.
+    pub fn is_psram(&self) -> bool {
+        self.caps & MALLOC_CAP_SPIRAM != 0
+    }
+
+    /// Returns true if the buffer can be used for DMA transfers.
+    pub fn is_dma_capable(&self) -> bool {
+        self.caps & MALLOC_CAP_DMA != 0
+    }
+}
+
+impl<T: Copy> Deref for Buffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: the memory is initialized for len elements
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Copy> DerefMut for Buffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: the memory is initialized for len elements
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Copy> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        if self.caps != 0 {
+            // SAFETY: the pointer was returned by heap_caps_aligned_alloc
+            // and is freed once; zero-sized buffers (caps 0) own no memory
+            unsafe { heap_caps_free(self.ptr.as_ptr() as *mut _) };
+        }
+    }
+}
+
+/// Pool of DMA-capable byte buffers recycled after each transfer
+///
+/// Allocating the buffers once avoids per-transfer allocation and the
+/// fragmentation of the scarce DMA-capable memory. The pool can be cloned
+/// to share it between tasks.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::memory::DmaPool;
+///
+/// let pool = DmaPool::new(2, 4096).unwrap();
+/// let mut buffer = pool.acquire();
+/// buffer[..4].copy_from_slice(&[1, 2, 3, 4]);
+/// // the buffer returns to the pool when dropped
+/// ```
+#[derive(Clone)]
+pub struct DmaPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    free: Mutex<Vec<Buffer<u8>>>,
+    returned: Condvar,
+    buffer_size: usize,
+}
+
+impl DmaPool {
+    /// Allocate the buffers of the size rounded up to a multiple of 4 bytes.
+    pub fn new(count: usize, size: usize) -> Result<Self> {
+        let buffer_size = size.next_multiple_of(MIN_ALIGN);
+        let free = (0..count)
+            .map(|_| Buffer::try_new(buffer_size, 0u8, Placement::Dma))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            inner: Arc::new(PoolInner {
+                free: Mutex::new(free),
+                returned: Condvar::new(),
+                buffer_size,
+            }),
+        })
+    }
+
+    /// Returns the size of a buffer in bytes.
+    pub fn buffer_size(&self) -> usize {
+        self.inner.buffer_size
+    }
+
+    /// Take a buffer, waiting until one is returned if all are in use.
+    pub fn acquire(&self) -> DmaBuffer {
+        let mut free = self.inner.free.lock().unwrap();
+        loop {
+            if let Some(buffer) = free.pop() {
+                return self.wrap(buffer);
+            }
+            free = self.inner.returned.wait(free).unwrap();
+        }
+    }
+
+    /// Take a buffer if one is free.
+    pub fn try_acquire(&self) -> Option<DmaBuffer> {
+        let buffer = self.inner.free.lock().unwrap().pop()?;
+        Some(self.wrap(buffer))
+    }
+
+    fn wrap(&self, buffer: Buffer<u8>) -> DmaBuffer {
+        DmaBuffer {
+            buffer: Some(buffer),
+            pool: self.inner.clone(),
+        }
+    }
+}
+
+/// Buffer taken from a [`DmaPool`], returned to the pool when dropped
+pub struct DmaBuffer {
+    buffer: Option<Buffer<u8>>,
+    pool: Arc<PoolInner>,
+}
+
+impl Deref for DmaBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buffer.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for DmaBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buffer.as_mut().unwrap()
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.free.lock().unwrap().push(buffer);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+/// Returns the pool shared by the display flushes, the audio transfers of
+/// the speaker and the microphone, and other drivers copying into DMA
+/// buffers.
+///
+/// The pool is allocated on the first call.
+///
+/// # Panics
+///
+/// Panics if the DMA-capable memory is exhausted.
+pub fn shared_dma_pool() -> &'static DmaPool {
+    static POOL: OnceLock<DmaPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        DmaPool::new(SHARED_POOL_BUFFERS, SHARED_POOL_BUFFER_SIZE)
+            .expect("failed to allocate the shared DMA pool")
+    })
+}
@@ -0,0 +1,94 @@
+//! PDM microphone (SPM1423) driver
+//!
+//! The clock of the microphone is on GPIO43, which is also the word select
+//! of the speaker, so the microphone and the speaker cannot run at the
+//! same time.
+use anyhow::Result;
+use esp_idf_hal::{
+    delay::BLOCK,
+    gpio::{Gpio43, Gpio46},
+    i2s::{
+        config::{
+            Config, DataBitWidth, PdmRxClkConfig, PdmRxConfig, PdmRxGpioConfig, PdmRxSlotConfig,
+            SlotMode,
+        },
+        I2s, I2sDriver, I2sRx,
+    },
+    peripheral::Peripheral,
+};
+
+use crate::memory;
+
+/// Microphone driver that records 16-bit mono PCM samples
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::microphone::Microphone;
+///
+/// let peripherals = Peripherals::take().unwrap();
+///
+/// let mut microphone = Microphone::new(
+///     peripherals.i2s0,
+///     peripherals.pins.gpio43,
+///     peripherals.pins.gpio46,
+///     16000,
+/// )
+/// .unwrap();
+/// let mut samples = [0i16; 512];
+/// microphone.read(&mut samples).unwrap();
+/// ```
+pub struct Microphone<'a> {
+    driver: I2sDriver<'a, I2sRx>,
+    sample_rate: u32,
+}
+
+impl<'a> Microphone<'a> {
+    /// Create new driver with the sample rate in Hz.
+    ///
+    /// Only I2S0 supports PDM input.
+    pub fn new<I2S: I2s>(
+        i2s: impl Peripheral<P = I2S> + 'a,
+        clk: impl Peripheral<P = Gpio43> + 'a,
+        din: impl Peripheral<P = Gpio46> + 'a,
+        sample_rate: u32,
+    ) -> Result<Self> {
+        let config = PdmRxConfig::new(
+            Config::default(),
+            PdmRxClkConfig::from_sample_rate_hz(sample_rate),
+            PdmRxSlotConfig::from_bits_per_sample_and_slot_mode(
+                DataBitWidth::Bits16,
+                SlotMode::Mono,
+            ),
+            PdmRxGpioConfig::default(),
+        );
+        let mut driver = I2sDriver::new_pdm_rx(i2s, &config, clk, din)?;
+        driver.rx_enable()?;
+
+        Ok(Self {
+            driver,
+            sample_rate,
+        })
+    }
+
+    /// Returns the sample rate in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Fill the buffer with samples, blocking until all of them are recorded.
+    pub fn read(&mut self, samples: &mut [i16]) -> Result<()> {
+        let mut bytes = memory::shared_dma_pool().acquire();
+        for chunk in samples.chunks_mut(bytes.len() / 2) {
+            let len = chunk.len() * 2;
+            let mut filled = 0;
+            while filled < len {
+                filled += self.driver.read(&mut bytes[filled..len], BLOCK)?;
+            }
+            for (sample, src) in chunk.iter_mut().zip(bytes.chunks_exact(2)) {
+                *sample = i16::from_le_bytes([src[0], src[1]]);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Morse code output on the speaker
+//!
+//! The timing follows the PARIS standard: a dot lasts 1200 / WPM ms, a
+//! dash three dots, the gap between letters three dots and the gap
+//! between words seven dots.
+use anyhow::Result;
+use std::{f32::consts::PI, time::Duration};
+
+use crate::speaker::Speaker;
+
+const DEFAULT_WPM: u32 = 20;
+const DEFAULT_FREQUENCY: u32 = 700;
+const AMPLITUDE: f32 = 12000.0;
+/// Fade in and out of a tone to avoid clicks
+const RAMP: Duration = Duration::from_millis(4);
+
+const CODES: [(char, &str); 54] = [
+    ('A', ".-"),
+    ('B', "-..."),
+    ('C', "-.-."),
+    ('D', "-.."),
+    ('E', "."),
+    ('F', "..-."),
+    ('G', "--."),
+    ('H', "...."),
+    ('I', ".."),
+    ('J', ".---"),
+    ('K', "-.-"),
+    ('L', ".-.."),
+    ('M', "--"),
+    ('N', "-."),
+    ('O', "---"),
+    ('P', ".--."),
+    ('Q', "--.-"),
+    ('R', ".-."),
+    ('S', "..."),
+    ('T', "-"),
+    ('U', "..-"),
+    ('V', "...-"),
+    ('W', ".--"),
+    ('X', "-..-"),
+    ('Y', "-.--"),
+    ('Z', "--.."),
+    ('0', "-----"),
+    ('1', ".----"),
+    ('2', "..---"),
+    ('3', "...--"),
+    ('4', "....-"),
+    ('5', "....."),
+    ('6', "-...."),
+    ('7', "--..."),
+    ('8', "---.."),
+    ('9', "----."),
+    ('.', ".-.-.-"),
+    (',', "--..--"),
+    ('?', "..--.."),
+    ('\'', ".----."),
+    ('!', "-.-.--"),
+    ('/', "-..-."),
+    ('(', "-.--."),
+    (')', "-.--.-"),
+    ('&', ".-..."),
+    (':', "---..."),
+    (';', "-.-.-."),
+    ('=', "-...-"),
+    ('+', ".-.-."),
+    ('-', "-....-"),
+    ('_', "..--.-"),
+    ('"', ".-..-."),
+    ('$', "...-..-"),
+    ('@', ".--.-."),
+];
+
+/// Returns the code of the character as dots and dashes, or `None` if it has none.
+pub fn encode(c: char) -> Option<&'static str> {
+    let c = c.to_ascii_uppercase();
+    CODES.iter().find(|(x, _)| *x == c).map(|(_, code)| *code)
+}
+
+/// Tone or silence of a Morse message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Element {
+    On(Duration),
+    Off(Duration),
+}
+
+/// Morse code sender
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::morse::Morse;
+///
+/// let morse = Morse::new().with_wpm(15);
+/// morse
+///     .play_with(&mut speaker, "CQ CQ", |on| {
+///         if on { backlight.on().unwrap() } else { backlight.off().unwrap() }
+///     })
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Morse {
+    wpm: u32,
+    frequency: u32,
+}
+
+impl Default for Morse {
+    fn default() -> Self {
+        Self {
+            wpm: DEFAULT_WPM,
+            frequency: DEFAULT_FREQUENCY,
+        }
+    }
+}
+
+impl Morse {
+    /// Create new sender at 20 WPM and 700 Hz.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the speed in words per minute.
+    pub fn with_wpm(mut self, wpm: u32) -> Self {
+        self.wpm = wpm.max(1);
+        self
+    }
+
+    /// Set the tone frequency in Hz.
+    pub fn with_frequency(mut self, frequency: u32) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    /// Returns the length of a dot.
+    pub fn dot(&self) -> Duration {
+        Duration::from_millis(1200) / self.wpm
+    }
+
+    /// Convert the text to tones and silences. Characters without a code are skipped.
+    pub fn elements(&self, text: &str) -> Vec<Element> {
+        let dot = self.dot();
+        let mut elements = Vec::new();
+        for word in text.split_whitespace() {
+            push_off(&mut elements, dot * 7);
+            for code in word.chars().filter_map(encode) {
+                push_off(&mut elements, dot * 3);
+                for symbol in code.chars() {
+                    push_off(&mut elements, dot);
+                    let units = if symbol == '-' { 3 } else { 1 };
+                    elements.push(Element::On(dot * units));
+                }
+            }
+        }
+        elements
+    }
+
+    /// Play the text on the speaker.
+    pub fn play(&self, speaker: &mut Speaker, text: &str) -> Result<()> {
+        self.play_with(speaker, text, |_| {})
+    }
+
+    /// Play the text, calling `on_key` with true when a tone starts and
+    /// false when it ends, e.g. to blink the backlight in sync.
+    ///
+    /// The calls run ahead of the sound by the queue length of the I2S driver.
+    pub fn play_with(
+        &self,
+        speaker: &mut Speaker,
+        text: &str,
+        mut on_key: impl FnMut(bool),
+    ) -> Result<()> {
+        let sample_rate = speaker.sample_rate();
+        for element in self.elements(text) {
+            match element {
+                Element::On(duration) => {
+                    on_key(true);
+                    speaker.play(&tone(self.frequency, duration, sample_rate))?;
+                    on_key(false);
+                }
+                Element::Off(duration) => speaker.play(&vec![0; samples(duration, sample_rate)])?,
+            }
+        }
+        // flush the last tone out of the DMA buffers
+        speaker.play(&vec![0; samples(self.dot(), sample_rate)])
+    }
+}
+
+/// Append a gap, or lengthen the previous gap. Leading gaps are dropped.
+fn push_off(elements: &mut Vec<Element>, duration: Duration) {
+    match elements.last_mut() {
+        Some(Element::Off(last)) => *last = (*last).max(duration),
+        Some(_) => elements.push(Element::Off(duration)),
+        None => {}
+    }
+}
+
+fn samples(duration: Duration, sample_rate: u32) -> usize {
+    (duration.as_micros() * sample_rate as u128 / 1_000_000) as usize
+}
+
+/// Generate a sine tone with short fades at both ends.
+fn tone(frequency: u32, duration: Duration, sample_rate: u32) -> Vec<i16> {
+    let len = samples(duration, sample_rate);
+    let ramp = samples(RAMP, sample_rate).min(len / 2).max(1) as f32;
+    (0..len)
+        .map(|i| {
+            let envelope = (i.min(len - 1 - i) as f32 / ramp).min(1.0);
+            let phase = 2.0 * PI * frequency as f32 * i as f32 / sample_rate as f32;
+            (phase.sin() * AMPLITUDE * envelope) as i16
+        })
+        .collect()
+}
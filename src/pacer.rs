@@ -0,0 +1,116 @@
+//! Frame pacing for animation loops
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Statistics collected by [`FramePacer`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    /// Number of frames paced
+    pub frames: u32,
+    /// Number of frames whose render and flush took longer than the frame interval
+    pub overruns: u32,
+    /// Average time spent rendering and flushing a frame
+    pub average_work: Duration,
+    /// Longest time spent rendering and flushing a frame
+    pub max_work: Duration,
+    /// Average difference between the actual and the target frame interval
+    pub average_jitter: Duration,
+    /// Largest difference between the actual and the target frame interval
+    pub max_jitter: Duration,
+}
+
+/// Frame rate limiter
+///
+/// Measures the time spent since the previous frame and sleeps the
+/// remainder of the frame interval.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::pacer::FramePacer;
+///
+/// let mut pacer = FramePacer::new(30);
+/// for _ in 0..300 {
+///     // render and flush the frame
+///     pacer.wait();
+/// }
+/// log::info!("{:?}", pacer.stats());
+/// ```
+pub struct FramePacer {
+    interval: Duration,
+    frame_start: Instant,
+    deadline: Instant,
+    stats: FrameStats,
+    total_work: Duration,
+    total_jitter: Duration,
+}
+
+impl FramePacer {
+    /// Create new pacer targeting the frame rate.
+    pub fn new(fps: u32) -> Self {
+        let interval = Duration::from_secs(1) / fps.max(1);
+        let now = Instant::now();
+        Self {
+            interval,
+            frame_start: now,
+            deadline: now + interval,
+            stats: FrameStats::default(),
+            total_work: Duration::ZERO,
+            total_jitter: Duration::ZERO,
+        }
+    }
+
+    /// Returns the target frame interval.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Change the target frame rate.
+    pub fn set_fps(&mut self, fps: u32) {
+        self.interval = Duration::from_secs(1) / fps.max(1);
+        self.deadline = self.frame_start + self.interval;
+    }
+
+    /// Sleep until the next frame and return the time spent in this frame.
+    pub fn wait(&mut self) -> Duration {
+        let work = self.frame_start.elapsed();
+        let now = Instant::now();
+        if now < self.deadline {
+            thread::sleep(self.deadline - now);
+            self.deadline += self.interval;
+        } else {
+            // do not try to catch up with the missed frames
+            self.stats.overruns += 1;
+            self.deadline = now + self.interval;
+        }
+
+        let now = Instant::now();
+        let period = now - self.frame_start;
+        let jitter = period.max(self.interval) - period.min(self.interval);
+        self.frame_start = now;
+
+        self.stats.frames += 1;
+        self.total_work += work;
+        self.total_jitter += jitter;
+        self.stats.max_work = self.stats.max_work.max(work);
+        self.stats.max_jitter = self.stats.max_jitter.max(jitter);
+        self.stats.average_work = self.total_work / self.stats.frames;
+        self.stats.average_jitter = self.total_jitter / self.stats.frames;
+
+        work
+    }
+
+    /// Returns the statistics since the creation or the last reset.
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// Clear the statistics.
+    pub fn reset_stats(&mut self) {
+        self.stats = FrameStats::default();
+        self.total_work = Duration::ZERO;
+        self.total_jitter = Duration::ZERO;
+    }
+}
@@ -0,0 +1,209 @@
+//! Panic hook that shows the panic on the display
+//!
+//! Without it a panic in the field leaves a frozen screen. The hook draws
+//! the message, the location and the return addresses of the stack on a
+//! red screen, optionally appends them to a file on the SD card, holds the
+//! screen for a while or until a key is pressed, and then runs the
+//! previous hook, which prints the panic to the console before the reset.
+//!
+//! The addresses can be resolved with `addr2line -pfiaC -e <elf>`.
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
+use esp_idf_hal::sys::{
+    esp_backtrace_frame_t, esp_backtrace_get_next_frame, esp_backtrace_get_start,
+};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex, TryLockError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::keyboard::KeyboardScanner;
+use crate::widget::console::Console;
+
+/// Maximum number of stack frames shown
+const MAX_FRAMES: usize = 8;
+/// Maximum number of characters of the message shown
+const MAX_MESSAGE_LEN: usize = 200;
+/// Interval between the keyboard scans while holding the screen
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Keyboard shared with the application
+pub type SharedKeyboard = Arc<Mutex<dyn KeyboardScanner + Send>>;
+
+/// Panic hook configuration
+///
+/// The display is shared with the application through a mutex. If the
+/// panicking thread holds the lock, e.g. a panic while drawing, the screen
+/// cannot be taken over and only the file and the console get the report.
+/// The keyboard is shared the same way.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::panic_screen::PanicScreen;
+///
+/// let display = Arc::new(Mutex::new(display::build(/* ... */).unwrap()));
+/// let keyboard = Arc::new(Mutex::new(Keyboard::new(/* ... */)));
+/// PanicScreen::new(display.clone())
+///     .with_log_file("/sdcard/panic.log")
+///     .with_hold(Duration::from_secs(60))
+///     .with_keyboard(keyboard.clone())
+///     .install();
+/// ```
+pub struct PanicScreen<D> {
+    display: Arc<Mutex<D>>,
+    log_file: Option<PathBuf>,
+    hold: Duration,
+    keyboard: Option<SharedKeyboard>,
+}
+
+impl<D> PanicScreen<D>
+where
+    D: DrawTarget<Color = Rgb565> + Send + 'static,
+{
+    /// Create new configuration drawing on the display.
+    pub fn new(display: Arc<Mutex<D>>) -> Self {
+        Self {
+            display,
+            log_file: None,
+            hold: Duration::from_secs(10),
+            keyboard: None,
+        }
+    }
+
+    /// Append the report to the file as well.
+    pub fn with_log_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_file = Some(path.into());
+        self
+    }
+
+    /// Set the time the report stays on the screen before the previous
+    /// hook runs (10 seconds). `Duration::MAX` holds it until a key is
+    /// pressed.
+    pub fn with_hold(mut self, hold: Duration) -> Self {
+        self.hold = hold;
+        self
+    }
+
+    /// End the hold when a key is pressed on the keyboard.
+    pub fn with_keyboard(mut self, keyboard: SharedKeyboard) -> Self {
+        self.keyboard = Some(keyboard);
+        self
+    }
+
+    /// Replace the panic hook.
+    pub fn install(self) {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let message = match info.payload().downcast_ref::<&str>() {
+                Some(x) => x.to_string(),
+                None => match info.payload().downcast_ref::<String>() {
+                    Some(x) => x.clone(),
+                    None => "Box<dyn Any>".to_string(),
+                },
+            };
+            let location = info
+                .location()
+                .map(|x| format!("{}:{}", x.file(), x.line()))
+                .unwrap_or_default();
+            let frames = backtrace()
+                .iter()
+                .map(|x| format!("0x{:08x}", x))
+                .collect::<Vec<_>>();
+
+            if let Some(path) = &self.log_file {
+                let _ = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| {
+                        writeln!(
+                            file,
+                            "panicked at {}: {}\nbacktrace: {}",
+                            location,
+                            message,
+                            frames.join(" ")
+                        )
+                    });
+            }
+            let display = match self.display.try_lock() {
+                Ok(x) => Some(x),
+                Err(TryLockError::Poisoned(x)) => Some(x.into_inner()),
+                Err(TryLockError::WouldBlock) => None,
+            };
+            if let Some(mut display) = display {
+                draw(&mut *display, &message, &location, &frames);
+                drop(display);
+                self.hold_screen();
+            }
+            previous(info);
+        }));
+    }
+}
+
+impl<D> PanicScreen<D> {
+    /// Wait for the hold time, or for a key press if the keyboard can be
+    /// scanned. Keys already held at the panic do not count.
+    fn hold_screen(&self) {
+        let keyboard = self.keyboard.as_ref().and_then(|x| match x.try_lock() {
+            Ok(x) => Some(x),
+            Err(TryLockError::Poisoned(x)) => Some(x.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        });
+        let Some(mut keyboard) = keyboard else {
+            thread::sleep(self.hold);
+            return;
+        };
+        let started = Instant::now();
+        let mut released = false;
+        while started.elapsed() < self.hold {
+            thread::sleep(KEY_POLL_INTERVAL);
+            match keyboard.scan_pressed_keytypes() {
+                Ok(keys) if keys.is_empty() => released = true,
+                Ok(_) if released => return,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Draw the report over the whole display.
+fn draw<D>(display: &mut D, message: &str, location: &str, frames: &[String])
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let background = Rgb565::new(16, 0, 0);
+    let mut console = Console::new(display.bounding_box()).with_background(background);
+    console.push_colored_line("PANIC", Rgb565::YELLOW);
+    console.push_line(&message.chars().take(MAX_MESSAGE_LEN).collect::<String>());
+    console.push_colored_line(location, Rgb565::CYAN);
+    console.push_colored_line("backtrace:", Rgb565::YELLOW);
+    for chunk in frames.chunks(3) {
+        console.push_line(&chunk.join(" "));
+    }
+    let _ = console.draw(display);
+}
+
+/// Returns the return addresses of the stack of the current task.
+fn backtrace() -> Vec<u32> {
+    let mut frame = esp_backtrace_frame_t {
+        pc: 0,
+        sp: 0,
+        next_pc: 0,
+        exc_frame: core::ptr::null(),
+    };
+    unsafe { esp_backtrace_get_start(&mut frame.pc, &mut frame.sp, &mut frame.next_pc) };
+    let mut frames = Vec::new();
+    while frames.len() < MAX_FRAMES && unsafe { esp_backtrace_get_next_frame(&mut frame) } {
+        if frame.pc == 0 {
+            break;
+        }
+        // the window size is in the top bits of a return address; the call
+        // instruction is 3 bytes before it
+        frames.push(((frame.pc & 0x3FFF_FFFF) | 0x4000_0000) - 3);
+    }
+    frames
+}
@@ -0,0 +1,461 @@
+//! Battery voltage, power source detection, CPU frequency scaling and
+//! power profiles
+//!
+//! [`PowerMonitor`] reads the battery through the ADC divider on GPIO10,
+//! which both the Cardputer and the Cardputer ADV have. No charger or
+//! power-management chip is read, so whether USB power is connected is
+//! inferred from the battery voltage: the charger holds the battery near
+//! 4.2 V and makes the voltage rise, while running on the battery makes it
+//! fall.
+use anyhow::Result;
+use esp_idf_hal::{
+    adc::{attenuation::DB_11, config::Config, AdcChannelDriver, AdcDriver, ADC1},
+    gpio::Gpio10,
+    peripheral::Peripheral,
+    sys::{
+        esp, esp_pm_config_t, esp_pm_configure, esp_pm_lock_acquire, esp_pm_lock_create,
+        esp_pm_lock_handle_t, esp_pm_lock_release, esp_pm_lock_type_t_ESP_PM_CPU_FREQ_MAX,
+    },
+};
+use std::{
+    collections::VecDeque,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use crate::backlight::Backlight;
+use crate::storage::Store;
+
+/// Store key of the name of the power profile
+pub const POWER_PROFILE_KEY: &str = "power_profile";
+
+/// Readings averaged per sample
+const READINGS: u32 = 16;
+
+/// Voltage above which the charger must be connected (mV)
+const CHARGING_VOLTAGE: u32 = 4150;
+
+/// Change over [`TREND_WINDOW`] that counts as charging or discharging (mV)
+const TREND_THRESHOLD: i32 = 30;
+const TREND_WINDOW: Duration = Duration::from_secs(60);
+
+/// Step between two samples caused by plugging or unplugging USB (mV)
+const STEP_THRESHOLD: i32 = 60;
+
+/// Lowest frequency while idle with automatic light sleep (MHz), the crystal frequency
+const IDLE_FREQUENCY: i32 = 40;
+
+/// Where the device is powered from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerSource {
+    Usb,
+    Battery,
+}
+
+/// Battery monitor on GPIO10 (battery voltage through a 1/2 divider)
+///
+/// The power source is estimated from the voltage alone, on both variants;
+/// a change is reported after a jump of the voltage or a trend over a
+/// minute.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::power::{PowerMonitor, PowerSource};
+///
+/// let mut power = PowerMonitor::new(peripherals.adc1, peripherals.pins.gpio10).unwrap();
+/// loop {
+///     if let Some(source) = power.update().unwrap() {
+///         match source {
+///             PowerSource::Usb => { /* full brightness, no auto sleep */ }
+///             PowerSource::Battery => { /* dim and sleep sooner */ }
+///         }
+///     }
+///     thread::sleep(Duration::from_secs(1));
+/// }
+/// ```
+pub struct PowerMonitor<'a> {
+    adc: AdcDriver<'a, ADC1>,
+    channel: AdcChannelDriver<'a, DB_11, Gpio10>,
+    samples: VecDeque<(Instant, u32)>,
+    source: PowerSource,
+}
+
+impl<'a> PowerMonitor<'a> {
+    /// Create new monitor. The initial source is guessed from the voltage.
+    pub fn new(
+        adc: impl Peripheral<P = ADC1> + 'a,
+        pin: impl Peripheral<P = Gpio10> + 'a,
+    ) -> Result<Self> {
+        let adc = AdcDriver::new(adc, &Config::new().calibration(true))?;
+        let channel = AdcChannelDriver::new(pin)?;
+        let mut monitor = Self {
+            adc,
+            channel,
+            samples: VecDeque::new(),
+            source: PowerSource::Battery,
+        };
+        if monitor.battery_voltage()? >= CHARGING_VOLTAGE {
+            monitor.source = PowerSource::Usb;
+        }
+        Ok(monitor)
+    }
+
+    /// Read the battery voltage (mV).
+    pub fn battery_voltage(&mut self) -> Result<u32> {
+        let mut sum = 0;
+        for _ in 0..READINGS {
+            sum += self.adc.read(&mut self.channel)? as u32;
+        }
+        Ok(sum / READINGS * 2)
+    }
+
+    /// Estimate the battery level (%) from the voltage.
+    ///
+    /// Only meaningful on battery power; the charger raises the voltage.
+    pub fn battery_level(&mut self) -> Result<u8> {
+        let voltage = self.battery_voltage()?.clamp(3300, 4100);
+        Ok(((voltage - 3300) / 8) as u8)
+    }
+
+    /// Return the current power source.
+    pub fn source(&self) -> PowerSource {
+        self.source
+    }
+
+    /// Take a sample and return the new power source if it changed.
+    ///
+    /// Call it periodically, e.g. once per second.
+    pub fn update(&mut self) -> Result<Option<PowerSource>> {
+        let now = Instant::now();
+        let voltage = self.battery_voltage()?;
+
+        let step = self
+            .samples
+            .back()
+            .map_or(0, |(_, last)| voltage as i32 - *last as i32);
+        while self
+            .samples
+            .front()
+            .is_some_and(|(time, _)| now.duration_since(*time) > TREND_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, voltage));
+        let trend = self.samples.front().map_or(0, |(time, first)| {
+            if now.duration_since(*time) * 2 >= TREND_WINDOW {
+                voltage as i32 - *first as i32
+            } else {
+                0
+            }
+        });
+
+        let source = if step >= STEP_THRESHOLD || trend >= TREND_THRESHOLD {
+            PowerSource::Usb
+        } else if step <= -STEP_THRESHOLD || trend <= -TREND_THRESHOLD {
+            PowerSource::Battery
+        } else {
+            self.source
+        };
+
+        if source == self.source {
+            return Ok(None);
+        }
+        // start over so the old trend does not flip the source back
+        self.samples.clear();
+        self.samples.push_back((now, voltage));
+        self.source = source;
+        Ok(Some(source))
+    }
+}
+
+/// Maximum CPU frequency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuFrequency {
+    Mhz80,
+    Mhz160,
+    Mhz240,
+}
+
+impl CpuFrequency {
+    fn mhz(&self) -> i32 {
+        match self {
+            CpuFrequency::Mhz80 => 80,
+            CpuFrequency::Mhz160 => 160,
+            CpuFrequency::Mhz240 => 240,
+        }
+    }
+}
+
+/// Set the CPU frequency, and let the chip enter light sleep when all
+/// tasks are idle if `light_sleep` is true.
+///
+/// With light sleep the CPU runs at 40 MHz while idle and at the given
+/// frequency while a [`CpuBoost`] is held or a driver needs it. Requires
+/// `CONFIG_PM_ENABLE=y` (and `CONFIG_FREERTOS_USE_TICKLESS_IDLE=y` for
+/// light sleep) in sdkconfig.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::power::{self, CpuFrequency};
+///
+/// power::set_cpu_profile(CpuFrequency::Mhz160, true).unwrap();
+/// ```
+pub fn set_cpu_profile(frequency: CpuFrequency, light_sleep: bool) -> Result<()> {
+    let config = esp_pm_config_t {
+        max_freq_mhz: frequency.mhz(),
+        min_freq_mhz: if light_sleep {
+            IDLE_FREQUENCY
+        } else {
+            frequency.mhz()
+        },
+        light_sleep_enable: light_sleep,
+    };
+    // SAFETY: the configuration is only read during the call
+    esp!(unsafe { esp_pm_configure(&config as *const esp_pm_config_t as *const _) })?;
+    Ok(())
+}
+
+/// Preset of the power settings
+///
+/// A profile sets the CPU frequency, light sleep and the backlight
+/// brightness when applied, and passes the keyboard scan interval and the
+/// timeouts to the [`ProfileHooks`] of the application.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::power::{PowerProfile, ProfileHooks};
+///
+/// let scan_interval = Arc::new(Mutex::new(Duration::from_millis(10)));
+/// let interval = scan_interval.clone();
+/// let mut hooks = ProfileHooks::new()
+///     .with_scan_interval(move |x| *interval.lock().unwrap() = x)
+///     .with_sleep(move |dim_after, sleep_after| idle_timeouts.set(dim_after, sleep_after));
+///
+/// let mut profile = PowerProfile::load(&store).unwrap().unwrap_or_default();
+/// profile.apply(&mut backlight, &mut hooks).unwrap();
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     if keyboard_state.pressed_keys().contains(&Modified::Graph('p')) {
+///         profile = profile.next();
+///         profile.apply(&mut backlight, &mut hooks).unwrap();
+///         profile.save(&mut store).unwrap();
+///     }
+///     thread::sleep(*scan_interval.lock().unwrap());
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PowerProfile {
+    /// Full speed and brightness, never dims or sleeps
+    Performance,
+    #[default]
+    Balanced,
+    /// Low speed and brightness, dims and sleeps early
+    BatterySaver,
+}
+
+/// Settings of a [`PowerProfile`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileSettings {
+    pub cpu_frequency: CpuFrequency,
+    /// Light sleep while all tasks are idle
+    pub light_sleep: bool,
+    /// Interval between keyboard scans
+    pub scan_interval: Duration,
+    /// Backlight brightness in percent
+    pub brightness: u8,
+    /// Idle time before dimming the backlight, `None` for never
+    pub dim_after: Option<Duration>,
+    /// Idle time before sleeping, `None` for never
+    pub sleep_after: Option<Duration>,
+}
+
+impl PowerProfile {
+    /// Returns the settings of the profile.
+    pub fn settings(&self) -> ProfileSettings {
+        match self {
+            PowerProfile::Performance => ProfileSettings {
+                cpu_frequency: CpuFrequency::Mhz240,
+                light_sleep: false,
+                scan_interval: Duration::from_millis(5),
+                brightness: 100,
+                dim_after: None,
+                sleep_after: None,
+            },
+            PowerProfile::Balanced => ProfileSettings {
+                cpu_frequency: CpuFrequency::Mhz160,
+                light_sleep: true,
+                scan_interval: Duration::from_millis(10),
+                brightness: 70,
+                dim_after: Some(Duration::from_secs(60)),
+                sleep_after: Some(Duration::from_secs(300)),
+            },
+            PowerProfile::BatterySaver => ProfileSettings {
+                cpu_frequency: CpuFrequency::Mhz80,
+                light_sleep: true,
+                scan_interval: Duration::from_millis(20),
+                brightness: 40,
+                dim_after: Some(Duration::from_secs(15)),
+                sleep_after: Some(Duration::from_secs(60)),
+            },
+        }
+    }
+
+    /// Set the CPU frequency, light sleep and backlight brightness of the
+    /// profile, call the hooks with the scan interval and the timeouts, and
+    /// return its settings. See [`set_cpu_profile`] for the sdkconfig
+    /// requirements.
+    pub fn apply(
+        &self,
+        backlight: &mut Backlight,
+        hooks: &mut ProfileHooks,
+    ) -> Result<ProfileSettings> {
+        let settings = self.settings();
+        set_cpu_profile(settings.cpu_frequency, settings.light_sleep)?;
+        backlight.set_brightness(settings.brightness)?;
+        if let Some(f) = hooks.scan_interval.as_mut() {
+            f(settings.scan_interval);
+        }
+        if let Some(f) = hooks.sleep.as_mut() {
+            f(settings.dim_after, settings.sleep_after);
+        }
+        Ok(settings)
+    }
+
+    /// Returns the following profile, to cycle through them.
+    pub fn next(&self) -> Self {
+        match self {
+            PowerProfile::Performance => PowerProfile::Balanced,
+            PowerProfile::Balanced => PowerProfile::BatterySaver,
+            PowerProfile::BatterySaver => PowerProfile::Performance,
+        }
+    }
+
+    /// Returns the name stored in the store.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PowerProfile::Performance => "performance",
+            PowerProfile::Balanced => "balanced",
+            PowerProfile::BatterySaver => "battery_saver",
+        }
+    }
+
+    /// Returns the profile of the name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            PowerProfile::Performance,
+            PowerProfile::Balanced,
+            PowerProfile::BatterySaver,
+        ]
+        .into_iter()
+        .find(|x| x.name() == name.trim())
+    }
+
+    /// Read the profile from the store. Returns `None` if none is saved or
+    /// the name is unknown.
+    pub fn load(store: &impl Store) -> Result<Option<Self>> {
+        Ok(store
+            .read_string(POWER_PROFILE_KEY)?
+            .and_then(|x| Self::from_name(&x)))
+    }
+
+    /// Write the profile to the store.
+    pub fn save(&self, store: &mut impl Store) -> Result<()> {
+        store.write(POWER_PROFILE_KEY, self.name().as_bytes())
+    }
+}
+
+/// Hook receiving the idle times before dimming and before sleeping
+pub type SleepCallback = Box<dyn FnMut(Option<Duration>, Option<Duration>) + Send>;
+
+/// Callbacks applying the settings of a [`PowerProfile`] that belong to
+/// the application
+#[derive(Default)]
+pub struct ProfileHooks {
+    scan_interval: Option<Box<dyn FnMut(Duration) + Send>>,
+    sleep: Option<SleepCallback>,
+}
+
+impl ProfileHooks {
+    /// Create new hooks doing nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call `f` with the interval between keyboard scans (none).
+    pub fn with_scan_interval(mut self, f: impl FnMut(Duration) + Send + 'static) -> Self {
+        self.scan_interval = Some(Box::new(f));
+        self
+    }
+
+    /// Call `f` with the idle times before dimming and before sleeping,
+    /// `None` for never (none).
+    pub fn with_sleep(
+        mut self,
+        f: impl FnMut(Option<Duration>, Option<Duration>) + Send + 'static,
+    ) -> Self {
+        self.sleep = Some(Box::new(f));
+        self
+    }
+}
+
+struct PmLock(esp_pm_lock_handle_t);
+
+// SAFETY: the handle is never freed, and the power management lock
+// functions may be called from any task
+unsafe impl Send for PmLock {}
+// SAFETY: see Send; the lock counts the acquisitions itself
+unsafe impl Sync for PmLock {}
+
+/// Lock that holds the maximum CPU frequency, `None` without power management
+static BOOST_LOCK: OnceLock<Option<PmLock>> = OnceLock::new();
+
+/// Guard that runs the CPU at the maximum frequency of the profile until dropped
+///
+/// The frame buffer flush and the speaker hold it during transfers. Does
+/// nothing if power management is disabled.
+pub struct CpuBoost {
+    lock: Option<&'static PmLock>,
+}
+
+impl CpuBoost {
+    pub fn new() -> Self {
+        let lock = BOOST_LOCK
+            .get_or_init(|| {
+                let mut handle: esp_pm_lock_handle_t = core::ptr::null_mut();
+                // SAFETY: the name is a static C string, and the handle
+                // outlives the call that writes it
+                esp!(unsafe {
+                    esp_pm_lock_create(
+                        esp_pm_lock_type_t_ESP_PM_CPU_FREQ_MAX,
+                        0,
+                        c"boost".as_ptr(),
+                        &mut handle,
+                    )
+                })
+                .ok()
+                .map(|_| PmLock(handle))
+            })
+            .as_ref()
+            // SAFETY: the handle was created above and is never deleted
+            .filter(|lock| esp!(unsafe { esp_pm_lock_acquire(lock.0) }).is_ok());
+        Self { lock }
+    }
+}
+
+impl Default for CpuBoost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for CpuBoost {
+    fn drop(&mut self) {
+        if let Some(lock) = self.lock {
+            // SAFETY: the lock was acquired by new and is never deleted
+            unsafe { esp_pm_lock_release(lock.0) };
+        }
+    }
+}
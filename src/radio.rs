@@ -0,0 +1,380 @@
+//! Internet radio: audio streamed over HTTP to the speaker
+//!
+//! A background thread downloads the stream into a ring buffer, and
+//! [`RadioStream::play`] decodes it to the speaker. Playback starts when
+//! the buffer is half full and pauses to refill it after an underrun.
+//!
+//! Raw 16-bit PCM and WAV streams are handled by [`PcmDecoder`], and MP3
+//! streams by `Mp3Decoder` with the `mp3` feature. AAC is not supported;
+//! other formats need their own [`AudioDecoder`] implementation.
+use anyhow::{anyhow, bail, Result};
+use esp_idf_svc::{
+    http::{
+        client::{Configuration, EspHttpConnection},
+        Method,
+    },
+    sys::esp_crt_bundle_attach,
+};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::speaker::Speaker;
+
+/// Default size of the ring buffer in bytes
+pub const DEFAULT_BUFFER_SIZE: usize = 32 * 1024;
+
+const FETCH_STACK_SIZE: usize = 8 * 1024;
+const READ_SIZE: usize = 1024;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Decoder from the stream data to mono 16-bit samples
+pub trait AudioDecoder {
+    /// Decode the data, append the samples to `output` and return the
+    /// number of bytes consumed. Unconsumed bytes are passed again with
+    /// more data appended.
+    fn decode(&mut self, input: &[u8], output: &mut Vec<i16>) -> Result<usize>;
+}
+
+/// Decoder for raw 16-bit little-endian mono PCM, with or without a WAV header
+///
+/// The sample rate is not read from the header; the speaker must be
+/// created with the rate of the stream.
+#[derive(Debug, Default)]
+pub struct PcmDecoder {
+    header_checked: bool,
+}
+
+impl PcmDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AudioDecoder for PcmDecoder {
+    fn decode(&mut self, input: &[u8], output: &mut Vec<i16>) -> Result<usize> {
+        let mut consumed = 0;
+        if !self.header_checked {
+            if input.len() < 12 {
+                return Ok(0);
+            }
+            if input.starts_with(b"RIFF") {
+                // skip to the samples after the "data" chunk header
+                match input.windows(4).position(|x| x == b"data") {
+                    Some(position) if position + 8 <= input.len() => consumed = position + 8,
+                    _ => return Ok(0),
+                }
+            }
+            self.header_checked = true;
+        }
+        let samples = input[consumed..].chunks_exact(2);
+        consumed += samples.len() * 2;
+        output.extend(samples.map(|x| i16::from_le_bytes([x[0], x[1]])));
+        Ok(consumed)
+    }
+}
+
+/// Decoder for MPEG-1/2 Layer III streams, down-mixed to mono
+///
+/// The speaker must be created with the rate of the stream, which is
+/// known once the first frame is decoded; most stations use 44100 Hz.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::radio::{Mp3Decoder, RadioStream};
+///
+/// let mut radio = RadioStream::connect("http://192.168.1.10:8000/stream.mp3").unwrap();
+/// let mut speaker = Speaker::new(i2s, bclk, ws, dout, 44100).unwrap();
+/// radio.play(&mut speaker, &mut Mp3Decoder::new()).unwrap();
+/// ```
+#[cfg(feature = "mp3")]
+pub struct Mp3Decoder {
+    decoder: rmp3::RawDecoder,
+    /// Samples of one frame, too large for the stack of the caller
+    pcm: Box<[rmp3::Sample; rmp3::MAX_SAMPLES_PER_FRAME]>,
+    sample_rate: Option<u32>,
+}
+
+#[cfg(feature = "mp3")]
+impl Mp3Decoder {
+    pub fn new() -> Self {
+        Self {
+            decoder: rmp3::RawDecoder::new(),
+            pcm: Box::new([0; rmp3::MAX_SAMPLES_PER_FRAME]),
+            sample_rate: None,
+        }
+    }
+
+    /// Returns the sample rate of the last decoded frame.
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+}
+
+#[cfg(feature = "mp3")]
+impl Default for Mp3Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "mp3")]
+impl AudioDecoder for Mp3Decoder {
+    fn decode(&mut self, input: &[u8], output: &mut Vec<i16>) -> Result<usize> {
+        let mut consumed = 0;
+        // stops at an incomplete frame, which is passed again with more data
+        while let Some((frame, len)) = self.decoder.next(&input[consumed..], &mut self.pcm) {
+            consumed += len;
+            // ID3 tags and garbage are skipped as other frames
+            if let rmp3::Frame::Audio(audio) = frame {
+                self.sample_rate = Some(audio.sample_rate());
+                let channels = audio.channels().max(1) as usize;
+                output.extend(
+                    audio.samples().chunks_exact(channels).map(|x| {
+                        (x.iter().map(|&x| x as i32).sum::<i32>() / channels as i32) as i16
+                    }),
+                );
+            }
+        }
+        Ok(consumed)
+    }
+}
+
+struct Shared {
+    buffer: Mutex<RingState>,
+    changed: Condvar,
+    capacity: usize,
+    stop: AtomicBool,
+}
+
+impl Shared {
+    fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // taken so that a thread about to wait sees the flag or the wake-up
+        drop(self.buffer.lock());
+        self.changed.notify_all();
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+}
+
+/// Handle stopping the download and the playback of a [`RadioStream`]
+/// from another thread
+#[derive(Clone)]
+pub struct StopHandle {
+    shared: Arc<Shared>,
+}
+
+impl StopHandle {
+    /// Stop the download and the playback, waking up the waiting threads.
+    pub fn stop(&self) {
+        self.shared.stop();
+    }
+
+    /// Returns true once stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.shared.is_stopped()
+    }
+}
+
+struct RingState {
+    data: VecDeque<u8>,
+    /// Set when the download ended or failed
+    is_finished: bool,
+}
+
+/// Audio stream downloaded from an HTTP URL
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::radio::{PcmDecoder, RadioStream};
+///
+/// // connect to WiFi first
+/// let mut radio = RadioStream::connect("http://192.168.1.10:8000/stream.wav").unwrap();
+/// radio.play(&mut speaker, &mut PcmDecoder::new()).unwrap();
+/// log::info!("underruns: {}", radio.underruns());
+/// ```
+pub struct RadioStream {
+    shared: Arc<Shared>,
+    content_type: Option<String>,
+    underruns: u32,
+    fetcher: Option<JoinHandle<Result<()>>>,
+}
+
+impl RadioStream {
+    /// Connect with the default buffer size.
+    pub fn connect(url: &str) -> Result<Self> {
+        Self::connect_with_buffer(url, DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Connect and start downloading into a buffer of `buffer_size` bytes.
+    pub fn connect_with_buffer(url: &str, buffer_size: usize) -> Result<Self> {
+        let shared = Arc::new(Shared {
+            buffer: Mutex::new(RingState {
+                data: VecDeque::with_capacity(buffer_size),
+                is_finished: false,
+            }),
+            changed: Condvar::new(),
+            capacity: buffer_size,
+            stop: AtomicBool::new(false),
+        });
+
+        // the connection is not Send, so it is opened in the thread
+        let (tx, rx) = mpsc::channel();
+        let fetcher = {
+            let url = url.to_string();
+            let shared = shared.clone();
+            thread::Builder::new()
+                .stack_size(FETCH_STACK_SIZE)
+                .spawn(move || {
+                    let result = fetch(&url, &shared, tx);
+                    if let Ok(mut buffer) = shared.buffer.lock() {
+                        buffer.is_finished = true;
+                    }
+                    shared.changed.notify_all();
+                    result
+                })?
+        };
+        let content_type = match rx.recv() {
+            Ok(content_type) => content_type,
+            // the sender is dropped without sending when the connection failed
+            Err(_) => {
+                let result = fetcher
+                    .join()
+                    .map_err(|_| anyhow!("download thread panicked"))?;
+                return Err(result.err().unwrap_or_else(|| anyhow!("connection failed")));
+            }
+        };
+
+        Ok(Self {
+            shared,
+            content_type,
+            underruns: 0,
+            fetcher: Some(fetcher),
+        })
+    }
+
+    /// Return the Content-Type of the stream.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Return a handle that stops the download and the playback.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Return the number of bytes in the buffer.
+    pub fn buffered(&self) -> usize {
+        self.shared
+            .buffer
+            .lock()
+            .map(|buffer| buffer.data.len())
+            .unwrap_or(0)
+    }
+
+    /// Return how many times the buffer ran empty during playback.
+    pub fn underruns(&self) -> u32 {
+        self.underruns
+    }
+
+    /// Play until the stream ends or it is stopped with a [`StopHandle`].
+    pub fn play<D: AudioDecoder>(&mut self, speaker: &mut Speaker, decoder: &mut D) -> Result<()> {
+        let mut input: Vec<u8> = Vec::new();
+        let mut samples = Vec::new();
+        let mut is_buffering = true;
+
+        while !self.shared.is_stopped() {
+            {
+                let mut buffer = self.shared.buffer.lock().map_err(|e| anyhow!("{:?}", e))?;
+                if is_buffering {
+                    buffer = self
+                        .shared
+                        .changed
+                        .wait_while(buffer, |x| {
+                            x.data.len() < self.shared.capacity / 2
+                                && !x.is_finished
+                                && !self.shared.is_stopped()
+                        })
+                        .map_err(|e| anyhow!("{:?}", e))?;
+                    is_buffering = false;
+                }
+                if buffer.data.is_empty() {
+                    if buffer.is_finished {
+                        break;
+                    }
+                    self.underruns += 1;
+                    is_buffering = true;
+                    continue;
+                }
+                let len = buffer.data.len().min(READ_SIZE);
+                input.extend(buffer.data.drain(..len));
+            }
+            self.shared.changed.notify_all();
+
+            let consumed = decoder.decode(&input, &mut samples)?;
+            input.drain(..consumed);
+            if !samples.is_empty() {
+                speaker.play(&samples)?;
+                samples.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RadioStream {
+    fn drop(&mut self) {
+        self.shared.stop();
+        if let Some(fetcher) = self.fetcher.take() {
+            let _ = fetcher.join();
+        }
+    }
+}
+
+/// Download the stream into the buffer, reporting the Content-Type once connected.
+fn fetch(url: &str, shared: &Shared, connected: mpsc::Sender<Option<String>>) -> Result<()> {
+    let config = Configuration {
+        timeout: Some(TIMEOUT),
+        crt_bundle_attach: Some(esp_crt_bundle_attach),
+        ..Default::default()
+    };
+    let mut connection = EspHttpConnection::new(&config)?;
+    connection.initiate_request(Method::Get, url, &[])?;
+    connection.initiate_response()?;
+    if connection.status() != 200 {
+        bail!("HTTP status {}", connection.status());
+    }
+    let _ = connected.send(connection.header("Content-Type").map(str::to_string));
+
+    let mut chunk = [0u8; READ_SIZE];
+    while !shared.is_stopped() {
+        let len = connection.read(&mut chunk)?;
+        if len == 0 {
+            break;
+        }
+        let buffer = shared.buffer.lock().map_err(|e| anyhow!("{:?}", e))?;
+        let mut buffer = shared
+            .changed
+            .wait_while(buffer, |x| {
+                x.data.len() + len > shared.capacity && !shared.is_stopped()
+            })
+            .map_err(|e| anyhow!("{:?}", e))?;
+        buffer.data.extend(&chunk[..len]);
+        drop(buffer);
+        shared.changed.notify_all();
+    }
+    Ok(())
+}
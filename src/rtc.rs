@@ -0,0 +1,335 @@
+//! Real-time clock modules on the Grove I2C port
+//!
+//! Drivers for the PCF8563 (M5Stack Unit RTC) and the DS3231 behind a
+//! common [`Rtc`] trait, so clock apps do not depend on the chip.
+//! [`SystemClock`] implements the trait over the system time set by SNTP.
+use anyhow::{anyhow, bail, Result};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+/// Date and time without a time zone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Create date and time, or return `None` if a field is out of range.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<Self> {
+        let datetime = Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        };
+        datetime.is_valid().then_some(datetime)
+    }
+
+    /// Create from seconds since 1970-01-01 00:00:00.
+    pub fn from_timestamp(timestamp: u64) -> Self {
+        let days = (timestamp / 86400) as i64;
+        let seconds = timestamp % 86400;
+        // civil_from_days by Howard Hinnant
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + (month <= 2) as i64) as u16;
+        Self {
+            year,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    /// Return seconds since 1970-01-01 00:00:00.
+    pub fn timestamp(&self) -> u64 {
+        let seconds = self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        self.days() as u64 * 86400 + seconds
+    }
+
+    /// Return the day of the week, 0 for Sunday.
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        ((self.days() + 4) % 7) as u8
+    }
+
+    fn days(&self) -> i64 {
+        // days_from_civil by Howard Hinnant
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let month = self.month as i64;
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+    }
+
+    fn is_valid(&self) -> bool {
+        (2000..2100).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+}
+
+/// Real-time clock
+pub trait Rtc {
+    /// Read the current date and time.
+    fn now(&mut self) -> Result<DateTime>;
+    /// Set the date and time.
+    fn set(&mut self, datetime: &DateTime) -> Result<()>;
+}
+
+/// System time, as set by SNTP or [`Rtc::set`], in a fixed time zone
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::rtc::{Rtc, SystemClock};
+///
+/// let _sntp = EspSntp::new_default().unwrap();
+/// let mut clock = SystemClock::new().with_utc_offset(9 * 3600);
+/// log::info!("{:?}", clock.now().unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock {
+    utc_offset: i64,
+}
+
+impl SystemClock {
+    /// Create clock in UTC.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the offset from UTC in seconds.
+    pub fn with_utc_offset(mut self, seconds: i64) -> Self {
+        self.utc_offset = seconds;
+        self
+    }
+}
+
+impl Rtc for SystemClock {
+    /// Read the local time, or fail until the time has been set.
+    fn now(&mut self) -> Result<DateTime> {
+        let utc = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| anyhow!("{:?}", e))?
+            .as_secs() as i64;
+        let datetime = DateTime::from_timestamp((utc + self.utc_offset).max(0) as u64);
+        if !datetime.is_valid() {
+            bail!("system time is not set");
+        }
+        Ok(datetime)
+    }
+
+    /// Set the system time from the local time.
+    fn set(&mut self, datetime: &DateTime) -> Result<()> {
+        if !datetime.is_valid() {
+            bail!("invalid date and time: {:?}", datetime);
+        }
+        let time = esp_idf_hal::sys::timeval {
+            tv_sec: (datetime.timestamp() as i64 - self.utc_offset) as _,
+            tv_usec: 0,
+        };
+        if unsafe { esp_idf_hal::sys::settimeofday(&time, core::ptr::null()) } != 0 {
+            bail!("settimeofday failed");
+        }
+        crate::wall_clock::sync_system();
+        Ok(())
+    }
+}
+
+/// PCF8563 real-time clock
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::rtc::{Pcf8563, Rtc};
+///
+/// let mut rtc = Pcf8563::new(i2c);
+/// log::info!("{:?}", rtc.now().unwrap());
+/// ```
+pub struct Pcf8563<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C, E> Pcf8563<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    pub const ADDRESS: u8 = 0x51;
+    const SECONDS: u8 = 0x02;
+
+    pub fn new(i2c: I2C) -> Self {
+        Self { i2c }
+    }
+
+    /// Release the I2C driver.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+impl<I2C, E> Rtc for Pcf8563<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    fn now(&mut self) -> Result<DateTime> {
+        let mut regs = [0u8; 7];
+        self.i2c
+            .write_read(Self::ADDRESS, &[Self::SECONDS], &mut regs)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        // the VL flag is set when the clock was stopped by a low supply voltage
+        if regs[0] & 0x80 != 0 {
+            bail!("PCF8563: clock integrity is not guaranteed");
+        }
+        let century = if regs[5] & 0x80 != 0 { 2100 } else { 2000 };
+        Ok(DateTime {
+            year: century + from_bcd(regs[6]) as u16,
+            month: from_bcd(regs[5] & 0x1F),
+            day: from_bcd(regs[3] & 0x3F),
+            hour: from_bcd(regs[2] & 0x3F),
+            minute: from_bcd(regs[1] & 0x7F),
+            second: from_bcd(regs[0] & 0x7F),
+        })
+    }
+
+    fn set(&mut self, datetime: &DateTime) -> Result<()> {
+        if !datetime.is_valid() {
+            bail!("invalid date and time: {:?}", datetime);
+        }
+        let regs = [
+            Self::SECONDS,
+            to_bcd(datetime.second),
+            to_bcd(datetime.minute),
+            to_bcd(datetime.hour),
+            to_bcd(datetime.day),
+            datetime.weekday(),
+            to_bcd(datetime.month),
+            to_bcd((datetime.year % 100) as u8),
+        ];
+        self.i2c
+            .write(Self::ADDRESS, &regs)
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+}
+
+/// DS3231 real-time clock
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::rtc::{DateTime, Ds3231, Rtc};
+///
+/// let mut rtc = Ds3231::new(i2c);
+/// rtc.set(&DateTime::new(2024, 1, 1, 0, 0, 0).unwrap()).unwrap();
+/// log::info!("{} °C", rtc.temperature().unwrap());
+/// ```
+pub struct Ds3231<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C, E> Ds3231<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    pub const ADDRESS: u8 = 0x68;
+    const SECONDS: u8 = 0x00;
+    const TEMPERATURE: u8 = 0x11;
+
+    pub fn new(i2c: I2C) -> Self {
+        Self { i2c }
+    }
+
+    /// Release the I2C driver.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// Read the die temperature (°C) used for the oscillator compensation.
+    pub fn temperature(&mut self) -> Result<f32> {
+        let mut regs = [0u8; 2];
+        self.i2c
+            .write_read(Self::ADDRESS, &[Self::TEMPERATURE], &mut regs)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        Ok(i16::from_be_bytes(regs) as f32 / 256.0)
+    }
+}
+
+impl<I2C, E> Rtc for Ds3231<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    fn now(&mut self) -> Result<DateTime> {
+        let mut regs = [0u8; 7];
+        self.i2c
+            .write_read(Self::ADDRESS, &[Self::SECONDS], &mut regs)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        let hour = if regs[2] & 0x40 != 0 {
+            // 12-hour mode
+            from_bcd(regs[2] & 0x1F) % 12 + if regs[2] & 0x20 != 0 { 12 } else { 0 }
+        } else {
+            from_bcd(regs[2] & 0x3F)
+        };
+        let century = if regs[5] & 0x80 != 0 { 2100 } else { 2000 };
+        Ok(DateTime {
+            year: century + from_bcd(regs[6]) as u16,
+            month: from_bcd(regs[5] & 0x1F),
+            day: from_bcd(regs[4] & 0x3F),
+            hour,
+            minute: from_bcd(regs[1] & 0x7F),
+            second: from_bcd(regs[0] & 0x7F),
+        })
+    }
+
+    fn set(&mut self, datetime: &DateTime) -> Result<()> {
+        if !datetime.is_valid() {
+            bail!("invalid date and time: {:?}", datetime);
+        }
+        let regs = [
+            Self::SECONDS,
+            to_bcd(datetime.second),
+            to_bcd(datetime.minute),
+            to_bcd(datetime.hour),
+            datetime.weekday() + 1,
+            to_bcd(datetime.day),
+            to_bcd(datetime.month),
+            to_bcd((datetime.year % 100) as u8),
+        ];
+        self.i2c
+            .write(Self::ADDRESS, &regs)
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
@@ -0,0 +1,317 @@
+//! Stack of screens with animated transitions
+//!
+//! Each app screen implements [`Screen`]. The [`ScreenManager`] feeds the
+//! keyboard to the top screen, draws it into the frame buffer and, when a
+//! screen is pushed or popped, animates the change by composing the old
+//! and new frames at the pace of a [`FramePacer`].
+//!
+//! The manager also keeps the [`Focus`] of the widgets of its screens;
+//! a screen with nested widgets implements [`Screen::update_focused`] and
+//! is updated with [`ScreenManager::update_routed`].
+use anyhow::Result;
+use core::fmt::Debug;
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
+use std::time::{Duration, Instant};
+
+use crate::color::blend;
+use crate::display::{DISPLAY_SIZE_HEIGHT, DISPLAY_SIZE_WIDTH};
+use crate::focus::{Focus, KeyRouter, RoutedKeys};
+use crate::framebuffer::FrameBuffer;
+use crate::keyboard::KeyboardState;
+use crate::memory::{Buffer, Placement};
+use crate::pacer::FramePacer;
+
+/// Screen of an app
+pub trait Screen {
+    /// Handle the keys pressed in the last update.
+    fn update(&mut self, keyboard: &KeyboardState) -> Action;
+
+    /// Handle the keys routed by the focus in the last update. The widgets
+    /// of the screen take and release the focus on `focus`.
+    ///
+    /// Defaults to [`Screen::update`], ignoring the focus.
+    fn update_focused(
+        &mut self,
+        keyboard: &KeyboardState,
+        _keys: &RoutedKeys,
+        _focus: &mut Focus,
+    ) -> Action {
+        self.update(keyboard)
+    }
+
+    /// Draw the whole screen.
+    fn draw(&mut self, fb: &mut FrameBuffer);
+}
+
+/// Change of the screen stack requested by a screen
+pub enum Action {
+    /// Stay on the screen.
+    None,
+    /// Show the new screen on top of the current one.
+    Push(Box<dyn Screen + Send>, Transition),
+    /// Go back to the previous screen. Ignored on the root screen.
+    Pop(Transition),
+    /// Replace the current screen.
+    Replace(Box<dyn Screen + Send>, Transition),
+}
+
+/// Direction in which the screens move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    /// Returns the unit vector of the motion.
+    fn vector(&self) -> (i32, i32) {
+        match self {
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+        }
+    }
+}
+
+/// Animation between two screens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    /// Switch at once.
+    None,
+    /// Cross-fade from the old screen to the new one.
+    Fade,
+    /// The new screen slides in over the old one.
+    Slide(Direction),
+    /// The new screen pushes the old one out.
+    Push(Direction),
+}
+
+/// Stack of screens
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::screen::{Action, Direction, Screen, ScreenManager, Transition};
+///
+/// struct Menu;
+/// impl Screen for Menu {
+///     fn update(&mut self, keyboard: &KeyboardState) -> Action {
+///         if keyboard.pressed_keys().contains(&Modified::Enter) {
+///             return Action::Push(Box::new(Settings), Transition::Push(Direction::Left));
+///         }
+///         Action::None
+///     }
+///     fn draw(&mut self, fb: &mut FrameBuffer) { /* ... */ }
+/// }
+///
+/// let mut screens = ScreenManager::new(Menu);
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     screens.update(&keyboard_state, &mut fb, &mut display).unwrap();
+/// }
+/// ```
+pub struct ScreenManager {
+    stack: Vec<Box<dyn Screen + Send>>,
+    duration: Duration,
+    fps: u32,
+    focus: Focus,
+}
+
+impl ScreenManager {
+    /// Create new manager with the root screen.
+    pub fn new(root: impl Screen + Send + 'static) -> Self {
+        let mut focus = Focus::new();
+        focus.set_depth(1);
+        Self {
+            stack: vec![Box::new(root)],
+            duration: Duration::from_millis(250),
+            fps: 30,
+            focus,
+        }
+    }
+
+    /// Set the length of the transitions (250 ms).
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Set the frame rate of the transitions (30).
+    pub fn with_fps(mut self, fps: u32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    /// Returns the number of screens on the stack.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Returns the focus of the widgets of the screens.
+    pub fn focus(&mut self) -> &mut Focus {
+        &mut self.focus
+    }
+
+    /// Pass the keys to the top screen, apply its action and show the result.
+    pub fn update<D>(
+        &mut self,
+        keyboard: &KeyboardState,
+        fb: &mut FrameBuffer,
+        display: &mut D,
+    ) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        let action = self.top().update(keyboard);
+        self.apply(action, fb, display)?;
+        self.top().draw(fb);
+        fb.flush(display)
+    }
+
+    /// Route the keys with the focus, pass them to the top screen, apply
+    /// its action and show the result.
+    pub fn update_routed<D>(
+        &mut self,
+        router: &mut KeyRouter,
+        keyboard: &KeyboardState,
+        fb: &mut FrameBuffer,
+        display: &mut D,
+    ) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        let keys = router.route(keyboard, &self.focus);
+        let screen = self
+            .stack
+            .last_mut()
+            .expect("the root screen is never popped");
+        let action = screen.update_focused(keyboard, &keys, &mut self.focus);
+        self.apply(action, fb, display)?;
+        self.top().draw(fb);
+        fb.flush(display)
+    }
+
+    /// Apply the action, animating the transition to the new top screen.
+    pub fn apply<D>(&mut self, action: Action, fb: &mut FrameBuffer, display: &mut D) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        let transition = match action {
+            Action::None => return Ok(()),
+            Action::Pop(_) if self.stack.len() == 1 => return Ok(()),
+            Action::Push(_, x) | Action::Pop(x) | Action::Replace(_, x) => x,
+        };
+        let from = self.capture(fb, transition)?;
+        match action {
+            Action::Push(screen, _) => self.stack.push(screen),
+            Action::Pop(_) => {
+                self.stack.pop();
+            }
+            Action::Replace(screen, _) => {
+                self.stack.pop();
+                self.focus.set_depth(self.stack.len());
+                self.stack.push(screen);
+            }
+            Action::None => {}
+        }
+        self.focus.set_depth(self.stack.len());
+        let Some(from) = from else {
+            return Ok(());
+        };
+        self.top().draw(fb);
+        let mut to = Buffer::try_new(fb.pixels().len(), Rgb565::BLACK, Placement::Large)?;
+        to.copy_from_slice(fb.pixels());
+
+        let mut pacer = FramePacer::new(self.fps);
+        let started = Instant::now();
+        loop {
+            let progress = started.elapsed().as_secs_f32() / self.duration.as_secs_f32();
+            if progress >= 1.0 {
+                break;
+            }
+            compose(fb.pixels_mut(), &from, &to, transition, ease(progress));
+            fb.flush(display)?;
+            pacer.wait();
+        }
+        fb.pixels_mut().copy_from_slice(&to);
+        Ok(())
+    }
+
+    fn top(&mut self) -> &mut Box<dyn Screen + Send> {
+        self.stack
+            .last_mut()
+            .expect("the root screen is never popped")
+    }
+
+    /// Returns a copy of the current screen, or `None` without a transition.
+    fn capture(
+        &mut self,
+        fb: &mut FrameBuffer,
+        transition: Transition,
+    ) -> Result<Option<Buffer<Rgb565>>> {
+        if transition == Transition::None || self.duration.is_zero() {
+            return Ok(None);
+        }
+        self.top().draw(fb);
+        let mut from = Buffer::try_new(fb.pixels().len(), Rgb565::BLACK, Placement::Large)?;
+        from.copy_from_slice(fb.pixels());
+        Ok(Some(from))
+    }
+}
+
+/// Ease in and out.
+fn ease(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Compose the frame of the transition at the progress from 0.0 to 1.0.
+fn compose(
+    out: &mut [Rgb565],
+    from: &[Rgb565],
+    to: &[Rgb565],
+    transition: Transition,
+    progress: f32,
+) {
+    let (width, height) = (DISPLAY_SIZE_WIDTH as i32, DISPLAY_SIZE_HEIGHT as i32);
+    let at = |pixels: &[Rgb565], x: i32, y: i32| {
+        ((0..width).contains(&x) && (0..height).contains(&y))
+            .then(|| pixels[(y * width + x) as usize])
+    };
+    match transition {
+        Transition::None => out.copy_from_slice(to),
+        Transition::Fade => {
+            let alpha = (progress * 255.0) as u8;
+            for ((out, from), to) in out.iter_mut().zip(from).zip(to) {
+                *out = blend(*from, *to, alpha);
+            }
+        }
+        Transition::Slide(direction) | Transition::Push(direction) => {
+            let (dx, dy) = direction.vector();
+            // offset of the new screen, from one screen away to zero
+            let remaining = 1.0 - progress;
+            let (nx, ny) = (
+                -(dx as f32 * remaining * width as f32) as i32,
+                -(dy as f32 * remaining * height as f32) as i32,
+            );
+            // offset of the old screen when it is pushed
+            let (ox, oy) = match transition {
+                Transition::Push(_) => (nx + dx * width, ny + dy * height),
+                _ => (0, 0),
+            };
+            for y in 0..height {
+                for x in 0..width {
+                    let pixel = at(to, x - nx, y - ny)
+                        .or_else(|| at(from, x - ox, y - oy))
+                        .unwrap_or(Rgb565::BLACK);
+                    out[(y * width + x) as usize] = pixel;
+                }
+            }
+        }
+    }
+}
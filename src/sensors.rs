@@ -0,0 +1,76 @@
+//! On-chip sensors of the ESP32-S3
+use anyhow::{anyhow, Result};
+use esp_idf_hal::sys::{
+    esp, soc_periph_temperature_sensor_clk_src_t_TEMPERATURE_SENSOR_CLK_SRC_DEFAULT,
+    temperature_sensor_config_t, temperature_sensor_enable, temperature_sensor_get_celsius,
+    temperature_sensor_handle_t, temperature_sensor_install,
+};
+use std::sync::Mutex;
+
+/// Measurement range (°C) with the smallest error (< 1 °C)
+const RANGE: (i32, i32) = (-10, 80);
+
+struct TemperatureSensor {
+    handle: temperature_sensor_handle_t,
+    offset: f32,
+}
+
+// The handle is only used while the mutex is locked.
+unsafe impl Send for TemperatureSensor {}
+
+static TEMPERATURE_SENSOR: Mutex<Option<TemperatureSensor>> = Mutex::new(None);
+
+/// Read the die temperature (°C).
+///
+/// The sensor is installed on the first call. The factory calibration in
+/// eFuse is applied by ESP-IDF; the offset set by
+/// [`set_chip_temperature_offset`] is added on top of it. The die is
+/// warmer than the air around the device, by more when the CPU is busy.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::sensors;
+///
+/// log::info!("{:.1} °C", sensors::chip_temperature().unwrap());
+/// ```
+pub fn chip_temperature() -> Result<f32> {
+    with_sensor(|sensor| {
+        let mut celsius = 0.0;
+        esp!(unsafe { temperature_sensor_get_celsius(sensor.handle, &mut celsius) })?;
+        Ok(celsius + sensor.offset)
+    })
+}
+
+/// Set the correction (°C) added to [`chip_temperature`], e.g. the
+/// difference to a reference thermometer measured at idle.
+pub fn set_chip_temperature_offset(offset: f32) -> Result<()> {
+    with_sensor(|sensor| {
+        sensor.offset = offset;
+        Ok(())
+    })
+}
+
+/// Run `f` with the sensor, installing it on the first call.
+fn with_sensor<R>(f: impl FnOnce(&mut TemperatureSensor) -> Result<R>) -> Result<R> {
+    let mut sensor = TEMPERATURE_SENSOR.lock().map_err(|e| anyhow!("{:?}", e))?;
+    match sensor.as_mut() {
+        Some(sensor) => f(sensor),
+        None => f(sensor.insert(install()?)),
+    }
+}
+
+fn install() -> Result<TemperatureSensor> {
+    let config = temperature_sensor_config_t {
+        range_min: RANGE.0,
+        range_max: RANGE.1,
+        clk_src: soc_periph_temperature_sensor_clk_src_t_TEMPERATURE_SENSOR_CLK_SRC_DEFAULT,
+    };
+    let mut handle: temperature_sensor_handle_t = core::ptr::null_mut();
+    esp!(unsafe { temperature_sensor_install(&config, &mut handle) })?;
+    esp!(unsafe { temperature_sensor_enable(handle) })?;
+    Ok(TemperatureSensor {
+        handle,
+        offset: 0.0,
+    })
+}
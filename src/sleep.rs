@@ -0,0 +1,217 @@
+//! Deep sleep with wake sources and an application state kept in RTC memory
+//!
+//! The RTC memory survives deep sleep but not a power cycle or a reset, so
+//! [`restore_state`] returns `None` after a cold boot.
+use anyhow::{anyhow, bail, Result};
+use esp_idf_hal::sys::{
+    esp, esp_deep_sleep_start, esp_light_sleep_start, esp_sleep_disable_wakeup_source,
+    esp_sleep_enable_ext0_wakeup, esp_sleep_enable_ext1_wakeup, esp_sleep_enable_timer_wakeup,
+    esp_sleep_ext1_wakeup_mode_t_ESP_EXT1_WAKEUP_ANY_LOW, esp_sleep_get_wakeup_cause,
+    esp_sleep_source_t_ESP_SLEEP_WAKEUP_ALL, esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT0,
+    esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1, esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER,
+    esp_sleep_source_t_ESP_SLEEP_WAKEUP_UNDEFINED, gpio_deep_sleep_hold_en, gpio_hold_dis,
+    gpio_hold_en, gpio_set_level, rtc_gpio_pulldown_dis, rtc_gpio_pullup_en,
+};
+use std::{sync::Mutex, time::Duration};
+
+/// Maximum size in bytes of the saved state
+pub const MAX_STATE_SIZE: usize = 1024;
+
+const MAGIC: u32 = 0x4344_5354;
+
+/// GPIO of the G0 button
+const BUTTON_PIN: i32 = 0;
+/// Address lines of the keyboard decoder
+const ADDRESS_PINS: [i32; 3] = [8, 9, 11];
+/// Input lines of the keyboard
+const INPUT_PINS: [i32; 7] = [13, 15, 3, 4, 5, 6, 7];
+
+#[repr(C)]
+struct RtcState {
+    magic: u32,
+    len: u32,
+    checksum: u32,
+    data: [u8; MAX_STATE_SIZE],
+}
+
+#[link_section = ".rtc.data"]
+static mut RTC_STATE: RtcState = RtcState {
+    magic: 0,
+    len: 0,
+    checksum: 0,
+    data: [0; MAX_STATE_SIZE],
+};
+
+/// Serializes the accesses to [`RTC_STATE`]
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Why the chip woke up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeReason {
+    /// Not woken from deep sleep: power on or reset
+    PowerOn,
+    Timer,
+    Button,
+    Keyboard,
+    Other(u32),
+}
+
+/// Return why the chip woke up.
+pub fn wake_reason() -> WakeReason {
+    #[allow(non_upper_case_globals)]
+    match unsafe { esp_sleep_get_wakeup_cause() } {
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_UNDEFINED => WakeReason::PowerOn,
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER => WakeReason::Timer,
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT0 => WakeReason::Button,
+        esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1 => WakeReason::Keyboard,
+        other => WakeReason::Other(other),
+    }
+}
+
+/// Save the application state to RTC memory.
+pub fn save_state(data: &[u8]) -> Result<()> {
+    if data.len() > MAX_STATE_SIZE {
+        bail!("state too large: {} > {}", data.len(), MAX_STATE_SIZE);
+    }
+    let _lock = LOCK.lock().map_err(|e| anyhow!("{:?}", e))?;
+    let state = unsafe { &mut *core::ptr::addr_of_mut!(RTC_STATE) };
+    state.data[..data.len()].copy_from_slice(data);
+    state.len = data.len() as u32;
+    state.checksum = checksum(data);
+    state.magic = MAGIC;
+    Ok(())
+}
+
+/// Return the state saved before the deep sleep, or `None` if there is no valid state.
+pub fn restore_state() -> Option<Vec<u8>> {
+    let _lock = LOCK.lock().ok()?;
+    let state = unsafe { &*core::ptr::addr_of!(RTC_STATE) };
+    let data = state.data.get(..state.len as usize)?;
+    (state.magic == MAGIC && state.checksum == checksum(data)).then(|| data.to_vec())
+}
+
+/// Discard the saved state.
+pub fn clear_state() {
+    if let Ok(_lock) = LOCK.lock() {
+        unsafe { (*core::ptr::addr_of_mut!(RTC_STATE)).magic = 0 };
+    }
+}
+
+/// Release the keyboard address lines held during deep sleep.
+///
+/// Called by [`Keyboard::new`](crate::keyboard::Keyboard::new).
+pub(crate) fn release_keyboard() {
+    for pin in ADDRESS_PINS {
+        unsafe { gpio_hold_dis(pin) };
+    }
+}
+
+/// Deep sleep configuration
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::sleep::{self, DeepSleep, WakeReason};
+///
+/// if sleep::wake_reason() != WakeReason::PowerOn {
+///     if let Some(state) = sleep::restore_state() {
+///         // resume from the state
+///     }
+/// }
+///
+/// // ...
+///
+/// sleep::save_state(&state).unwrap();
+/// DeepSleep::new()
+///     .with_keyboard()
+///     .with_button()
+///     .with_timer(Duration::from_secs(3600))
+///     .start()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DeepSleep {
+    timer: Option<Duration>,
+    button: bool,
+    keyboard: bool,
+}
+
+impl DeepSleep {
+    /// Create new configuration without wake sources.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wake up after the duration.
+    pub fn with_timer(mut self, duration: Duration) -> Self {
+        self.timer = Some(duration);
+        self
+    }
+
+    /// Wake up when the G0 button is pressed.
+    pub fn with_button(mut self) -> Self {
+        self.button = true;
+        self
+    }
+
+    /// Wake up when a key is pressed.
+    ///
+    /// Only one address of the decoder can be selected during sleep, so
+    /// only Space, Opt, Z, C, B, M and `.` wake the chip.
+    pub fn with_keyboard(mut self) -> Self {
+        self.keyboard = true;
+        self
+    }
+
+    /// Enter deep sleep. Returns only on error; waking up restarts the firmware.
+    pub fn start(self) -> Result<()> {
+        if let Some(duration) = self.timer {
+            esp!(unsafe { esp_sleep_enable_timer_wakeup(duration.as_micros() as u64) })?;
+        }
+        if self.button {
+            esp!(unsafe { esp_sleep_enable_ext0_wakeup(BUTTON_PIN, 0) })?;
+        }
+        if self.keyboard {
+            for pin in ADDRESS_PINS {
+                esp!(unsafe { gpio_set_level(pin, 0) })?;
+                esp!(unsafe { gpio_hold_en(pin) })?;
+            }
+            unsafe { gpio_deep_sleep_hold_en() };
+            let mut mask = 0u64;
+            for pin in INPUT_PINS {
+                esp!(unsafe { rtc_gpio_pullup_en(pin) })?;
+                esp!(unsafe { rtc_gpio_pulldown_dis(pin) })?;
+                mask |= 1 << pin;
+            }
+            esp!(unsafe {
+                esp_sleep_enable_ext1_wakeup(
+                    mask,
+                    esp_sleep_ext1_wakeup_mode_t_ESP_EXT1_WAKEUP_ANY_LOW,
+                )
+            })?;
+        }
+        unsafe { esp_deep_sleep_start() }
+    }
+}
+
+/// Enter light sleep for the duration, or until the G0 button is pressed
+/// if `button` is true, and return why the chip woke up.
+///
+/// Unlike deep sleep, RAM and the display contents are kept and the
+/// function returns when the chip wakes up.
+pub fn light_sleep(duration: Duration, button: bool) -> Result<WakeReason> {
+    esp!(unsafe { esp_sleep_disable_wakeup_source(esp_sleep_source_t_ESP_SLEEP_WAKEUP_ALL) })?;
+    esp!(unsafe { esp_sleep_enable_timer_wakeup(duration.as_micros() as u64) })?;
+    if button {
+        esp!(unsafe { esp_sleep_enable_ext0_wakeup(BUTTON_PIN, 0) })?;
+    }
+    esp!(unsafe { esp_light_sleep_start() })?;
+    Ok(wake_reason())
+}
+
+/// FNV-1a hash
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811C_9DC5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
@@ -0,0 +1,199 @@
+//! Anti-aliased text for large headlines
+//!
+//! Glyphs of a mono font are scaled up with bilinear filtering and a soft
+//! edge, then alpha-blended, so headline text does not show the jagged
+//! steps of a plainly scaled 1-bit font.
+use core::convert::Infallible;
+use embedded_graphics::{
+    mono_font::{MonoFont, MonoTextStyle},
+    pixelcolor::{BinaryColor, Rgb565},
+    prelude::*,
+    text::{Baseline, Text},
+};
+
+use crate::color::blend;
+use crate::display::{DISPLAY_SIZE_HEIGHT, DISPLAY_SIZE_WIDTH};
+use crate::framebuffer::FrameBuffer;
+
+/// Scaled text with smooth edges
+///
+/// Drawn into a [`FrameBuffer`] with [`SmoothText::draw_blended`] it blends
+/// with what is already there; drawn into any other target it blends with
+/// the background color.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_graphics::mono_font::ascii::FONT_10X20;
+/// use cardputer::smooth_text::SmoothText;
+///
+/// SmoothText::new("12:34", Point::new(20, 30), &FONT_10X20, 3.0)
+///     .with_color(Rgb565::CYAN)
+///     .draw_blended(&mut fb);
+/// ```
+pub struct SmoothText<'a> {
+    text: &'a str,
+    top_left: Point,
+    font: &'a MonoFont<'a>,
+    scale: f32,
+    color: Rgb565,
+    background: Rgb565,
+}
+
+impl<'a> SmoothText<'a> {
+    /// Create new text with the top-left position, the font and the scale.
+    pub fn new(text: &'a str, top_left: Point, font: &'a MonoFont<'a>, scale: f32) -> Self {
+        Self {
+            text,
+            top_left,
+            font,
+            scale: scale.max(1.0),
+            color: Rgb565::WHITE,
+            background: Rgb565::BLACK,
+        }
+    }
+
+    /// Set the text color (white).
+    pub fn with_color(mut self, color: Rgb565) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Set the color blended with outside a frame buffer (black).
+    pub fn with_background(mut self, color: Rgb565) -> Self {
+        self.background = color;
+        self
+    }
+
+    /// Returns the size of the scaled text.
+    pub fn size(&self) -> Size {
+        let mask = self.mask_size();
+        Size::new(
+            (mask.width as f32 * self.scale).ceil() as u32,
+            (mask.height as f32 * self.scale).ceil() as u32,
+        )
+    }
+
+    /// Blend the text into the frame buffer.
+    pub fn draw_blended(&self, fb: &mut FrameBuffer) {
+        let width = DISPLAY_SIZE_WIDTH as i32;
+        let height = DISPLAY_SIZE_HEIGHT as i32;
+        let pixels = fb.pixels_mut();
+        self.rasterize(|point, alpha| {
+            if (0..width).contains(&point.x) && (0..height).contains(&point.y) {
+                let pixel = &mut pixels[(point.y * width + point.x) as usize];
+                *pixel = blend(*pixel, self.color, alpha);
+            }
+        });
+    }
+
+    fn mask_size(&self) -> Size {
+        let columns = self.text.chars().count() as u32;
+        let character = self.font.character_size;
+        Size::new(
+            columns * (character.width + self.font.character_spacing),
+            character.height,
+        )
+    }
+
+    /// Call the function with each covered pixel and its coverage from 0 to 255.
+    fn rasterize(&self, mut f: impl FnMut(Point, u8)) {
+        let size = self.mask_size();
+        let mut mask = Mask {
+            size,
+            bits: vec![false; (size.width * size.height) as usize],
+        };
+        let _ = Text::with_baseline(
+            self.text,
+            Point::zero(),
+            MonoTextStyle::new(self.font, BinaryColor::On),
+            Baseline::Top,
+        )
+        .draw(&mut mask);
+
+        // edge width in mask pixels giving about one pixel of gradient
+        let edge = 0.5 / self.scale + 0.1;
+        let output = self.size();
+        for y in 0..output.height {
+            let my = (y as f32 + 0.5) / self.scale - 0.5;
+            for x in 0..output.width {
+                let mx = (x as f32 + 0.5) / self.scale - 0.5;
+                let coverage = mask.sample(mx, my);
+                let alpha = smoothstep(0.5 - edge, 0.5 + edge, coverage);
+                if alpha > 0.0 {
+                    let point = self.top_left + Point::new(x as i32, y as i32);
+                    f(point, (alpha * 255.0 + 0.5) as u8);
+                }
+            }
+        }
+    }
+}
+
+impl Drawable for SmoothText<'_> {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let mut pixels = Vec::new();
+        self.rasterize(|point, alpha| {
+            pixels.push(Pixel(point, blend(self.background, self.color, alpha)));
+        });
+        target.draw_iter(pixels)
+    }
+}
+
+fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+    let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// 1-bit glyph image
+struct Mask {
+    size: Size,
+    bits: Vec<bool>,
+}
+
+impl Mask {
+    fn get(&self, x: i32, y: i32) -> f32 {
+        if x < 0 || y < 0 || x >= self.size.width as i32 || y >= self.size.height as i32 {
+            return 0.0;
+        }
+        self.bits[(y * self.size.width as i32 + x) as usize] as u8 as f32
+    }
+
+    /// Bilinear sample at the mask coordinates.
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let top = self.get(x0, y0) * (1.0 - fx) + self.get(x0 + 1, y0) * fx;
+        let bottom = self.get(x0, y0 + 1) * (1.0 - fx) + self.get(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+impl OriginDimensions for Mask {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for Mask {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if self.bounding_box().contains(point) {
+                self.bits[(point.y * self.size.width as i32 + point.x) as usize] = color.is_on();
+            }
+        }
+        Ok(())
+    }
+}
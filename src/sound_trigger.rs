@@ -0,0 +1,129 @@
+//! Clap and loud sound detection on microphone samples
+//!
+//! The level of each frame is compared with a slowly adapting noise floor.
+//! A trigger fires when the level jumps above both the floor times a
+//! ratio and an absolute minimum, then further triggers are held off for
+//! the debounce window.
+use std::time::Duration;
+
+const DEFAULT_RATIO: f32 = 4.0;
+const DEFAULT_MIN_LEVEL: u16 = 2000;
+const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(300);
+/// Weight of a new frame in the noise floor average
+const FLOOR_WEIGHT: f32 = 0.05;
+
+/// Called with the level of the frame that fired the trigger
+pub type TriggerCallback = Box<dyn FnMut(u16) + Send>;
+
+/// Sound trigger detector
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::sound_trigger::SoundTrigger;
+///
+/// let mut trigger = SoundTrigger::new(microphone.sample_rate());
+/// trigger.on_trigger(|level| log::info!("clap: {}", level));
+/// let mut samples = [0i16; 256];
+/// loop {
+///     microphone.read(&mut samples).unwrap();
+///     if trigger.process(&samples) {
+///         backlight.on().unwrap();
+///     }
+/// }
+/// ```
+pub struct SoundTrigger {
+    sample_rate: u32,
+    ratio: f32,
+    min_level: u16,
+    debounce: Duration,
+    callback: Option<TriggerCallback>,
+    floor: f32,
+    /// Samples left in the debounce window
+    hold: u64,
+    /// Level of the previous frame
+    last_level: u16,
+}
+
+impl SoundTrigger {
+    /// Create new detector for samples at the rate in Hz.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            ratio: DEFAULT_RATIO,
+            min_level: DEFAULT_MIN_LEVEL,
+            debounce: DEFAULT_DEBOUNCE,
+            callback: None,
+            floor: 0.0,
+            hold: 0,
+            last_level: 0,
+        }
+    }
+
+    /// Set how many times louder than the noise floor a sound must be (4.0).
+    pub fn with_ratio(mut self, ratio: f32) -> Self {
+        self.ratio = ratio.max(1.0);
+        self
+    }
+
+    /// Set the minimum level of a trigger as mean absolute sample value (2000).
+    pub fn with_min_level(mut self, level: u16) -> Self {
+        self.min_level = level;
+        self
+    }
+
+    /// Set the time after a trigger during which no other fires (300 ms).
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Set the callback called on each trigger.
+    pub fn on_trigger(&mut self, callback: impl FnMut(u16) + Send + 'static) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Returns the current noise floor.
+    pub fn noise_floor(&self) -> u16 {
+        self.floor as u16
+    }
+
+    /// Process a frame of samples and return true if it fired the trigger.
+    ///
+    /// Frames of 5 to 20 ms suit claps.
+    pub fn process(&mut self, samples: &[i16]) -> bool {
+        if samples.is_empty() {
+            return false;
+        }
+        let level = (samples
+            .iter()
+            .map(|sample| sample.unsigned_abs() as u64)
+            .sum::<u64>()
+            / samples.len() as u64) as u16;
+
+        let threshold = (self.floor * self.ratio).max(self.min_level as f32);
+        // only a rise above the threshold counts, not a sound that stays loud
+        let fired =
+            self.hold == 0 && level as f32 >= threshold && (self.last_level as f32) < threshold;
+
+        self.hold = self.hold.saturating_sub(samples.len() as u64);
+        if fired {
+            self.hold = self.debounce.as_micros() as u64 * self.sample_rate as u64 / 1_000_000;
+            if let Some(callback) = self.callback.as_mut() {
+                callback(level);
+            }
+        } else if (level as f32) < threshold {
+            // loud frames are kept out of the floor
+            self.floor += (level as f32 - self.floor) * FLOOR_WEIGHT;
+        }
+        self.last_level = level;
+        fired
+    }
+
+    /// Forget the noise floor and the debounce state.
+    pub fn reset(&mut self) {
+        self.floor = 0.0;
+        self.hold = 0;
+        self.last_level = 0;
+    }
+}
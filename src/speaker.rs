@@ -0,0 +1,275 @@
+//! I2S speaker (NS4168) driver
+//!
+//! All output goes through a software gain stage: the global volume in
+//! dB, the mute switch and the gain of the source set on the speaker.
+//! Call [`load`] once at startup to restore the settings from a
+//! [`Store`], and [`save`] after changing them.
+use anyhow::Result;
+use esp_idf_hal::{
+    delay::BLOCK,
+    gpio::{AnyIOPin, Gpio41, Gpio42, Gpio43},
+    i2s::{
+        config::{
+            Config, DataBitWidth, SlotMode, StdClkConfig, StdConfig, StdGpioConfig, StdSlotConfig,
+        },
+        I2s, I2sDriver, I2sTx,
+    },
+    peripheral::Peripheral,
+};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    Mutex, MutexGuard,
+};
+
+use crate::{memory, power::CpuBoost, storage::Store};
+
+/// Maximum value of the global volume
+pub const MAX_VOLUME: u8 = 100;
+/// Volume in dB at 1; each step up to [`MAX_VOLUME`] (0 dB) adds about 0.6 dB
+pub const MIN_VOLUME_DB: f32 = -60.0;
+
+/// Store key of the global volume
+pub const VOLUME_KEY: &str = "speaker_volume";
+/// Store key of the mute switch
+pub const MUTED_KEY: &str = "speaker_muted";
+/// Store key of the source gains
+pub const GAINS_KEY: &str = "speaker_gains";
+
+static VOLUME: AtomicU8 = AtomicU8::new(MAX_VOLUME);
+static MUTED: AtomicBool = AtomicBool::new(false);
+/// Gain in dB of each named source
+static SOURCE_GAINS: Mutex<Vec<(String, f32)>> = Mutex::new(Vec::new());
+/// The settings changed since the last save
+static CHANGED: AtomicBool = AtomicBool::new(false);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns the global volume (0 to [`MAX_VOLUME`]) applied to all speaker output.
+pub fn volume() -> u8 {
+    VOLUME.load(Ordering::Relaxed)
+}
+
+/// Set the global volume, clamped to [`MAX_VOLUME`].
+pub fn set_volume(volume: u8) {
+    VOLUME.store(volume.min(MAX_VOLUME), Ordering::Relaxed);
+    CHANGED.store(true, Ordering::Relaxed);
+}
+
+/// Returns the global volume in dB, or negative infinity at volume 0.
+pub fn volume_db() -> f32 {
+    match volume() {
+        0 => f32::NEG_INFINITY,
+        volume => MIN_VOLUME_DB * (MAX_VOLUME - volume) as f32 / (MAX_VOLUME - 1) as f32,
+    }
+}
+
+/// Set the global volume in dB, rounded to the nearest step. Below
+/// [`MIN_VOLUME_DB`] the volume is 0.
+pub fn set_volume_db(db: f32) {
+    let volume = if db < MIN_VOLUME_DB {
+        0
+    } else {
+        let steps = (MAX_VOLUME - 1) as f32 * db.min(0.0) / MIN_VOLUME_DB;
+        MAX_VOLUME - steps.round() as u8
+    };
+    set_volume(volume);
+}
+
+/// Returns true if the speaker output is muted.
+pub fn is_muted() -> bool {
+    MUTED.load(Ordering::Relaxed)
+}
+
+/// Mute or unmute the speaker output.
+pub fn set_muted(muted: bool) {
+    MUTED.store(muted, Ordering::Relaxed);
+    CHANGED.store(true, Ordering::Relaxed);
+}
+
+/// Returns the gain in dB of the source, 0 dB if not set.
+pub fn source_gain(source: &str) -> f32 {
+    lock(&SOURCE_GAINS)
+        .iter()
+        .find(|(name, _)| name == source)
+        .map(|(_, gain)| *gain)
+        .unwrap_or(0.0)
+}
+
+/// Set the gain in dB of the source, e.g. to make key clicks quieter than music.
+///
+/// Positive gains may clip loud samples.
+pub fn set_source_gain(source: &str, db: f32) {
+    {
+        let mut gains = lock(&SOURCE_GAINS);
+        match gains.iter_mut().find(|(name, _)| name == source) {
+            Some((_, gain)) => *gain = db,
+            None => gains.push((source.to_string(), db)),
+        }
+    }
+    CHANGED.store(true, Ordering::Relaxed);
+}
+
+/// Restore the volume, mute and source gains saved in the store.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::speaker;
+///
+/// speaker::load(&store).unwrap();
+/// speaker::set_volume(speaker::volume().saturating_sub(10));
+/// speaker::save(&mut store).unwrap();
+/// ```
+pub fn load(store: &impl Store) -> Result<()> {
+    if let Some(volume) = store.read_string(VOLUME_KEY)? {
+        VOLUME.store(
+            volume.trim().parse::<u8>()?.min(MAX_VOLUME),
+            Ordering::Relaxed,
+        );
+    }
+    if let Some(muted) = store.read_string(MUTED_KEY)? {
+        MUTED.store(muted.trim().parse()?, Ordering::Relaxed);
+    }
+    if let Some(text) = store.read_string(GAINS_KEY)? {
+        // one "name=dB" per line
+        *lock(&SOURCE_GAINS) = text
+            .lines()
+            .filter_map(|line| {
+                let (name, gain) = line.rsplit_once('=')?;
+                Some((name.to_string(), gain.parse().ok()?))
+            })
+            .collect();
+    }
+    CHANGED.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Write the settings changed since the last load or save to the store.
+///
+/// The setters only change the settings in RAM, so that stepping the
+/// volume does not wear the flash; call it when the user is done, e.g.
+/// when a volume overlay closes or before sleeping. Does nothing without
+/// changes. On failure the settings stay effective in RAM and are written
+/// at the next call.
+pub fn save(store: &mut impl Store) -> Result<()> {
+    if !CHANGED.swap(false, Ordering::Relaxed) {
+        return Ok(());
+    }
+    let gains: String = lock(&SOURCE_GAINS)
+        .iter()
+        .map(|(name, gain)| format!("{}={}\n", name, gain))
+        .collect();
+    let result = store
+        .write(VOLUME_KEY, volume().to_string().as_bytes())
+        .and_then(|_| store.write(MUTED_KEY, is_muted().to_string().as_bytes()))
+        .and_then(|_| store.write(GAINS_KEY, gains.as_bytes()));
+    if result.is_err() {
+        CHANGED.store(true, Ordering::Relaxed);
+    }
+    result
+}
+
+/// Returns the linear gain of the source after the volume and mute.
+fn gain(source: Option<&str>) -> f32 {
+    if is_muted() || volume() == 0 {
+        return 0.0;
+    }
+    let db = volume_db() + source.map(source_gain).unwrap_or(0.0);
+    10f32.powf(db / 20.0)
+}
+
+/// Speaker driver that plays 16-bit mono PCM samples
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::speaker::Speaker;
+///
+/// let peripherals = Peripherals::take().unwrap();
+///
+/// let mut speaker = Speaker::new(
+///     peripherals.i2s0,
+///     peripherals.pins.gpio41,
+///     peripherals.pins.gpio43,
+///     peripherals.pins.gpio42,
+///     16000,
+/// )
+/// .unwrap();
+/// speaker.play(&samples).unwrap();
+///
+/// speaker::set_source_gain("effects", -12.0);
+/// speaker.set_source(Some("effects"));
+/// speaker.play(&click).unwrap();
+/// ```
+pub struct Speaker<'a> {
+    driver: I2sDriver<'a, I2sTx>,
+    sample_rate: u32,
+    source: Option<String>,
+}
+
+impl<'a> Speaker<'a> {
+    /// Create new driver with the sample rate in Hz.
+    pub fn new<I2S: I2s>(
+        i2s: impl Peripheral<P = I2S> + 'a,
+        bclk: impl Peripheral<P = Gpio41> + 'a,
+        ws: impl Peripheral<P = Gpio43> + 'a,
+        dout: impl Peripheral<P = Gpio42> + 'a,
+        sample_rate: u32,
+    ) -> Result<Self> {
+        let config = StdConfig::new(
+            Config::default(),
+            StdClkConfig::from_sample_rate_hz(sample_rate),
+            StdSlotConfig::philips_slot_default(DataBitWidth::Bits16, SlotMode::Mono),
+            StdGpioConfig::default(),
+        );
+        let mut driver =
+            I2sDriver::new_std_tx(i2s, &config, bclk, dout, Option::<AnyIOPin>::None, ws)?;
+        driver.tx_enable()?;
+
+        Ok(Self {
+            driver,
+            sample_rate,
+            source: None,
+        })
+    }
+
+    /// Set the source whose gain is applied to the output.
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.set_source(Some(source));
+        self
+    }
+
+    /// Returns the current source.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    /// Change the source, or apply no source gain with `None`.
+    pub fn set_source(&mut self, source: Option<&str>) {
+        self.source = source.map(str::to_string);
+    }
+
+    /// Returns the sample rate in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Play the samples at the global volume and the source gain,
+    /// blocking until all of them are queued.
+    pub fn play(&mut self, samples: &[i16]) -> Result<()> {
+        let _boost = CpuBoost::new();
+        let gain = gain(self.source.as_deref());
+        let mut bytes = memory::shared_dma_pool().acquire();
+        for chunk in samples.chunks(bytes.len() / 2) {
+            for (dst, sample) in bytes.chunks_exact_mut(2).zip(chunk) {
+                // the cast saturates samples clipped by a positive gain
+                let sample = (*sample as f32 * gain) as i16;
+                dst.copy_from_slice(&sample.to_le_bytes());
+            }
+            self.driver.write_all(&bytes[..chunk.len() * 2], BLOCK)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+//! Spectrum analysis of audio frames
+//!
+//! A fixed-point radix-2 FFT over a Hann-windowed frame, with the bins
+//! grouped into logarithmically spaced bands for visualizers. The
+//! magnitudes are scaled by 1/N, so a full-scale sine gives about 8000.
+use anyhow::{bail, Result};
+use std::f32::consts::PI;
+
+/// Fixed-point FFT with banded magnitudes
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{spectrum::Spectrum, widget::spectrum::SpectrumBars};
+///
+/// let mut spectrum = Spectrum::new(512, 16).unwrap();
+/// let mut samples = [0i16; 512];
+/// loop {
+///     microphone.read(&mut samples).unwrap();
+///     let bands = spectrum.compute(&samples);
+///     SpectrumBars::new(bands, fb.bounding_box()).draw(&mut fb).unwrap();
+///     fb.flush(&mut display).unwrap();
+///     log::info!("peak {} Hz", spectrum.peak_frequency(microphone.sample_rate()));
+/// }
+/// ```
+pub struct Spectrum {
+    size: usize,
+    /// Hann window in Q15
+    window: Vec<i32>,
+    /// exp(-2πik/N) in Q15 for k < N/2
+    twiddles: Vec<(i32, i32)>,
+    re: Vec<i32>,
+    im: Vec<i32>,
+    /// Magnitude of the bins below the Nyquist frequency
+    bins: Vec<u16>,
+    /// First and end bins of each band
+    bands: Vec<(usize, usize)>,
+    magnitudes: Vec<u16>,
+}
+
+impl Spectrum {
+    /// Create new analyzer of `size` samples, a power of two from 8 to
+    /// 4096, with `bands` bands.
+    pub fn new(size: usize, bands: usize) -> Result<Self> {
+        if !size.is_power_of_two() || !(8..=4096).contains(&size) {
+            bail!("FFT size must be a power of two from 8 to 4096: {}", size);
+        }
+        if bands == 0 || bands > size / 2 - 1 {
+            bail!("invalid number of bands: {}", bands);
+        }
+
+        let q15 = |x: f32| (x * 32767.0).round() as i32;
+        let window = (0..size)
+            .map(|i| q15(0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos()))
+            .collect();
+        let twiddles = (0..size / 2)
+            .map(|k| {
+                let angle = 2.0 * PI * k as f32 / size as f32;
+                (q15(angle.cos()), q15(-angle.sin()))
+            })
+            .collect();
+
+        Ok(Self {
+            size,
+            window,
+            twiddles,
+            re: vec![0; size],
+            im: vec![0; size],
+            bins: vec![0; size / 2],
+            bands: band_edges(size / 2, bands),
+            magnitudes: vec![0; bands],
+        })
+    }
+
+    /// Returns the number of samples per frame.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Analyze a frame and return the magnitude of each band, from low to
+    /// high frequencies. Missing samples are taken as silence.
+    pub fn compute(&mut self, samples: &[i16]) -> &[u16] {
+        for i in 0..self.size {
+            let sample = samples.get(i).copied().unwrap_or(0) as i32;
+            let j = reverse_bits(i, self.size);
+            self.re[j] = (sample * self.window[i]) >> 15;
+            self.im[j] = 0;
+        }
+        self.transform();
+
+        for (bin, magnitude) in self.bins.iter_mut().enumerate() {
+            let (re, im) = (self.re[bin] as f32, self.im[bin] as f32);
+            *magnitude = (re * re + im * im).sqrt().min(u16::MAX as f32) as u16;
+        }
+        for (magnitude, (start, end)) in self.magnitudes.iter_mut().zip(&self.bands) {
+            *magnitude = self.bins[*start..*end].iter().copied().max().unwrap_or(0);
+        }
+        &self.magnitudes
+    }
+
+    /// Returns the magnitude of each bin of the last frame. Bin `k` is
+    /// centered on `k * sample_rate / size` Hz.
+    pub fn bins(&self) -> &[u16] {
+        &self.bins
+    }
+
+    /// Returns the magnitude of each band of the last frame.
+    pub fn bands(&self) -> &[u16] {
+        &self.magnitudes
+    }
+
+    /// Returns the frequency in Hz of the strongest bin of the last frame, ignoring DC.
+    pub fn peak_frequency(&self, sample_rate: u32) -> f32 {
+        let peak = (1..self.bins.len())
+            .max_by_key(|bin| self.bins[*bin])
+            .unwrap_or(0);
+        peak as f32 * sample_rate as f32 / self.size as f32
+    }
+
+    /// In-place FFT of the bit-reversed data, halving at every stage to
+    /// keep the values in 16 bits.
+    fn transform(&mut self) {
+        let mut len = 2;
+        while len <= self.size {
+            let half = len / 2;
+            let step = self.size / len;
+            for start in (0..self.size).step_by(len) {
+                for k in 0..half {
+                    let (wr, wi) = self.twiddles[k * step];
+                    let (a, b) = (start + k, start + k + half);
+                    let tr = (self.re[b] * wr - self.im[b] * wi) >> 15;
+                    let ti = (self.re[b] * wi + self.im[b] * wr) >> 15;
+                    self.re[b] = (self.re[a] - tr) >> 1;
+                    self.im[b] = (self.im[a] - ti) >> 1;
+                    self.re[a] = (self.re[a] + tr) >> 1;
+                    self.im[a] = (self.im[a] + ti) >> 1;
+                }
+            }
+            len *= 2;
+        }
+    }
+}
+
+fn reverse_bits(index: usize, size: usize) -> usize {
+    index.reverse_bits() >> (usize::BITS - size.trailing_zeros())
+}
+
+/// Split bins 1 to `bins` into logarithmically spaced bands of at least one bin.
+fn band_edges(bins: usize, bands: usize) -> Vec<(usize, usize)> {
+    let mut edges = Vec::with_capacity(bands);
+    let mut start = 1;
+    for band in 1..=bands {
+        let remaining = bands - band;
+        let end = ((bins as f32).powf(band as f32 / bands as f32).round() as usize)
+            .max(start + 1)
+            .min(bins - remaining);
+        edges.push((start, end));
+        start = end;
+    }
+    edges
+}
@@ -0,0 +1,138 @@
+//! Low-power refresh of status regions for always-on display apps
+//!
+//! While the mode is active the rest of the screen is frozen: only the
+//! registered regions of the frame buffer (a clock, the battery level) are
+//! transferred to the display, at a slow cadence, and the chip stays in
+//! light sleep between the refreshes. The panel keeps showing the last
+//! full frame meanwhile.
+use anyhow::{anyhow, Result};
+use core::fmt::Debug;
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+use std::time::{Duration, Instant};
+
+use crate::display::{DISPLAY_SIZE_HEIGHT, DISPLAY_SIZE_WIDTH};
+use crate::framebuffer::FrameBuffer;
+use crate::power::CpuBoost;
+use crate::sleep::{self, WakeReason};
+
+/// Refresh of the status regions only
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::sleep::WakeReason;
+/// use cardputer::status_refresh::StatusRefresh;
+///
+/// let clock = Rectangle::new(Point::new(60, 50), Size::new(120, 40));
+/// let battery = Rectangle::new(Point::new(200, 0), Size::new(40, 12));
+/// let mut status = StatusRefresh::new(Duration::from_secs(60))
+///     .with_region(clock)
+///     .with_region(battery)
+///     .with_button_wake();
+///
+/// loop {
+///     draw_clock(&mut fb);
+///     draw_battery(&mut fb);
+///     status.flush(&fb, &mut display).unwrap();
+///     if status.sleep().unwrap() == WakeReason::Button {
+///         break;
+///     }
+/// }
+/// fb.flush(&mut display).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct StatusRefresh {
+    regions: Vec<Rectangle>,
+    interval: Duration,
+    button: bool,
+    last_refresh: Option<Instant>,
+}
+
+impl StatusRefresh {
+    /// Create new mode refreshing at the interval, without regions.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            regions: Vec::new(),
+            interval,
+            button: false,
+            last_refresh: None,
+        }
+    }
+
+    /// Add a region to refresh. Parts outside the display are ignored.
+    pub fn with_region(mut self, area: Rectangle) -> Self {
+        let display = Rectangle::new(
+            Point::zero(),
+            Size::new(DISPLAY_SIZE_WIDTH as u32, DISPLAY_SIZE_HEIGHT as u32),
+        );
+        let area = area.intersection(&display);
+        if !area.is_zero_sized() {
+            self.regions.push(area);
+        }
+        self
+    }
+
+    /// Wake up from the sleep when the G0 button is pressed.
+    pub fn with_button_wake(mut self) -> Self {
+        self.button = true;
+        self
+    }
+
+    /// Returns the regions refreshed.
+    pub fn regions(&self) -> &[Rectangle] {
+        &self.regions
+    }
+
+    /// Returns true if the area lies within one of the regions, so that
+    /// drawing it shows up on the next refresh.
+    pub fn accepts(&self, area: &Rectangle) -> bool {
+        let Some(bottom_right) = area.bottom_right() else {
+            return true;
+        };
+        self.regions
+            .iter()
+            .any(|region| region.contains(area.top_left) && region.contains(bottom_right))
+    }
+
+    /// Returns true if the interval has passed since the last refresh.
+    pub fn is_due(&self) -> bool {
+        match self.last_refresh {
+            Some(x) => x.elapsed() >= self.interval,
+            None => true,
+        }
+    }
+
+    /// Transfer the regions of the frame buffer to the display. The rest of
+    /// the frame buffer is not sent.
+    pub fn flush<D>(&mut self, fb: &FrameBuffer, display: &mut D) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        let _boost = CpuBoost::new();
+        let width = DISPLAY_SIZE_WIDTH as usize;
+        let pixels = fb.pixels();
+        for region in self.regions.iter() {
+            let (x, y) = (region.top_left.x as usize, region.top_left.y as usize);
+            let (w, h) = (region.size.width as usize, region.size.height as usize);
+            let colors = (y..y + h).flat_map(|row| pixels[row * width + x..][..w].iter().copied());
+            display
+                .fill_contiguous(region, colors)
+                .map_err(|e| anyhow!("{:?}", e))?;
+        }
+        self.last_refresh = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Stay in light sleep until the next refresh is due, and return why
+    /// the chip woke up.
+    pub fn sleep(&self) -> Result<WakeReason> {
+        let remaining = self.last_refresh.map_or(Duration::ZERO, |x| {
+            self.interval.saturating_sub(x.elapsed())
+        });
+        if remaining.is_zero() {
+            return Ok(WakeReason::Timer);
+        }
+        sleep::light_sleep(remaining, self.button)
+    }
+}
@@ -0,0 +1,52 @@
+//! Persistent storage for keymaps, macros, recordings and settings
+//!
+//! [`Store`] is a key-value interface with implementations over files on
+//! a mounted filesystem ([`file::FileStore`], on the SD card or the
+//! internal flash) and over NVS blobs ([`nvs::NvsStore`]), so features can
+//! save their data on whatever storage is available.
+use anyhow::Result;
+
+pub mod file;
+pub mod flash;
+pub mod nvs;
+pub mod sd;
+
+/// Key-value storage of byte blobs
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::storage::{nvs::NvsStore, sd::SdCard, Store};
+///
+/// // keep the card mounted while the store is used
+/// let card = SdCard::mount(/* ... */);
+/// let mut store: Box<dyn Store> = match &card {
+///     Ok(card) => Box::new(card.store()),
+///     Err(_) => Box::new(NvsStore::new(nvs)),
+/// };
+/// store.write("layout", b"us").unwrap();
+/// let layout = store.read_string("layout").unwrap();
+/// ```
+pub trait Store {
+    /// Returns the data of the key, or `None` if it does not exist.
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Replace the data of the key.
+    fn write(&mut self, key: &str, data: &[u8]) -> Result<()>;
+
+    /// Delete the key and return true if it existed.
+    fn remove(&mut self, key: &str) -> Result<bool>;
+
+    /// Returns true if the key exists.
+    fn contains(&self, key: &str) -> Result<bool> {
+        Ok(self.read(key)?.is_some())
+    }
+
+    /// Returns the data of the key as UTF-8 text.
+    fn read_string(&self, key: &str) -> Result<Option<String>> {
+        match self.read(key)? {
+            Some(data) => Ok(Some(String::from_utf8(data)?)),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Store over files in a directory of a mounted filesystem (SD card or flash)
+use anyhow::{bail, Result};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+
+use super::Store;
+
+/// Store keeping each key in a file under a root directory
+///
+/// Keys may contain `/` to use subdirectories, e.g. `keymaps/us`. FAT
+/// needs long file name support in sdkconfig for names longer than 8.3.
+pub struct FileStore {
+    root: PathBuf,
+    flat: bool,
+}
+
+impl FileStore {
+    /// Create new store under the directory, e.g. the mount point of the SD card.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            flat: false,
+        }
+    }
+
+    /// Keep the `/` of the keys in the file names instead of creating
+    /// subdirectories, for filesystems without directories like SPIFFS.
+    pub fn with_flat_names(mut self) -> Self {
+        self.flat = true;
+        self
+    }
+
+    /// Returns the root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path of the key, rejecting keys that leave the root.
+    pub fn path(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        if key.is_empty()
+            || !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!("invalid key: {:?}", key);
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl Store for FileStore {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)?) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&mut self, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent().filter(|_| !self.flat) {
+            fs::create_dir_all(parent)?;
+        }
+        // write a temporary file first so that a power loss keeps the old
+        // data; the suffix keeps it apart from the files of other keys
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".~tmp");
+        let temporary = PathBuf::from(temporary);
+        fs::write(&temporary, data)?;
+        if let Err(e) = fs::rename(&temporary, &path) {
+            if !path.exists() {
+                return Err(e.into());
+            }
+            // FAT does not rename over an existing file
+            fs::remove_file(&path)?;
+            fs::rename(&temporary, &path)?;
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<bool> {
+        match fs::remove_file(self.path(key)?) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn contains(&self, key: &str) -> Result<bool> {
+        Ok(self.path(key)?.is_file())
+    }
+}
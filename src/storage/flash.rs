@@ -0,0 +1,108 @@
+//! SPIFFS filesystem on a data partition of the internal flash
+//!
+//! Lets devices without an SD card save notes, settings and macros. The
+//! partition table of the application needs a SPIFFS data partition, e.g.
+//!
+//! ```text
+//! # Name,   Type, SubType, Offset,  Size
+//! nvs,      data, nvs,     0x9000,  0x6000
+//! phy_init, data, phy,     0xf000,  0x1000
+//! factory,  app,  factory, 0x10000, 3M
+//! storage,  data, spiffs,  ,        1M
+//! ```
+//!
+//! SPIFFS is used because it ships with ESP-IDF; LittleFS needs an extra
+//! component. SPIFFS has no directories, so names containing `/` are
+//! stored as flat file names.
+use anyhow::Result;
+use esp_idf_hal::sys::{
+    esp, esp_spiffs_check, esp_spiffs_format, esp_spiffs_info, esp_vfs_spiffs_conf_t,
+    esp_vfs_spiffs_register, esp_vfs_spiffs_unregister,
+};
+use std::ffi::CString;
+
+use super::file::FileStore;
+
+/// Default mount point of the filesystem
+pub const MOUNT_POINT: &str = "/flash";
+/// Default label of the partition
+pub const PARTITION: &str = "storage";
+
+const MAX_FILES: usize = 5;
+
+/// Mounted flash filesystem
+///
+/// Unmounted on drop.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::storage::{flash::FlashFs, Store};
+///
+/// let flash = FlashFs::mount().unwrap();
+/// let (total, used) = flash.usage().unwrap();
+/// let mut store = flash.store();
+/// store.write("notes", b"hello").unwrap();
+/// ```
+pub struct FlashFs {
+    mount_point: CString,
+    label: CString,
+}
+
+impl FlashFs {
+    /// Mount the [`PARTITION`] partition at [`MOUNT_POINT`], formatting it
+    /// if it has no filesystem yet.
+    pub fn mount() -> Result<Self> {
+        Self::mount_partition(PARTITION, MOUNT_POINT, true)
+    }
+
+    /// Mount the partition with the label at the path.
+    pub fn mount_partition(label: &str, mount_point: &str, format_if_failed: bool) -> Result<Self> {
+        let mount_point = CString::new(mount_point)?;
+        let label = CString::new(label)?;
+        let config = esp_vfs_spiffs_conf_t {
+            base_path: mount_point.as_ptr(),
+            partition_label: label.as_ptr(),
+            max_files: MAX_FILES,
+            format_if_mount_failed: format_if_failed,
+        };
+        esp!(unsafe { esp_vfs_spiffs_register(&config) })?;
+        Ok(Self { mount_point, label })
+    }
+
+    /// Returns the mount point.
+    pub fn mount_point(&self) -> &str {
+        self.mount_point.to_str().unwrap_or_default()
+    }
+
+    /// Returns the total and used bytes.
+    pub fn usage(&self) -> Result<(usize, usize)> {
+        let (mut total, mut used) = (0, 0);
+        esp!(unsafe { esp_spiffs_info(self.label.as_ptr(), &mut total, &mut used) })?;
+        Ok((total, used))
+    }
+
+    /// Erase all files.
+    pub fn format(&mut self) -> Result<()> {
+        esp!(unsafe { esp_spiffs_format(self.label.as_ptr()) })?;
+        Ok(())
+    }
+
+    /// Check the filesystem and repair it, e.g. after a power loss during a write.
+    pub fn check(&mut self) -> Result<()> {
+        esp!(unsafe { esp_spiffs_check(self.label.as_ptr()) })?;
+        Ok(())
+    }
+
+    /// Returns a store over the files of the filesystem.
+    pub fn store(&self) -> FileStore {
+        // SPIFFS has no directories but accepts `/` in names
+        FileStore::new(self.mount_point()).with_flat_names()
+    }
+}
+
+impl Drop for FlashFs {
+    fn drop(&mut self) {
+        unsafe { esp_vfs_spiffs_unregister(self.label.as_ptr()) };
+    }
+}
@@ -0,0 +1,62 @@
+//! Store over NVS blobs
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::EspDefaultNvs;
+
+use super::Store;
+
+/// Maximum length of an NVS key
+pub const MAX_KEY_LEN: usize = 15;
+
+/// Store keeping each key as a blob in an NVS namespace
+///
+/// NVS suits small data such as settings; the default partition holds a
+/// few kilobytes in total.
+pub struct NvsStore {
+    nvs: EspDefaultNvs,
+}
+
+impl NvsStore {
+    /// Create new store over the namespace.
+    pub fn new(nvs: EspDefaultNvs) -> Self {
+        Self { nvs }
+    }
+
+    /// Return the NVS namespace.
+    pub fn release(self) -> EspDefaultNvs {
+        self.nvs
+    }
+}
+
+fn check_key(key: &str) -> Result<()> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        bail!("NVS key must be 1 to {} bytes: {:?}", MAX_KEY_LEN, key);
+    }
+    Ok(())
+}
+
+impl Store for NvsStore {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        check_key(key)?;
+        let Some(len) = self.nvs.blob_len(key)? else {
+            return Ok(None);
+        };
+        let mut buf = vec![0u8; len];
+        Ok(self.nvs.get_raw(key, &mut buf)?.map(<[u8]>::to_vec))
+    }
+
+    fn write(&mut self, key: &str, data: &[u8]) -> Result<()> {
+        check_key(key)?;
+        self.nvs.set_raw(key, data)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<bool> {
+        check_key(key)?;
+        Ok(self.nvs.remove(key)?)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool> {
+        check_key(key)?;
+        Ok(self.nvs.blob_len(key)?.is_some())
+    }
+}
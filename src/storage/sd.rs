@@ -0,0 +1,305 @@
+//! microSD card slot mounted as a FAT filesystem
+//!
+//! The slot of the Cardputer has no card detect switch, so
+//! [`SdCard::poll`] checks the card with a status command and tries to
+//! mount it again after it was removed.
+use anyhow::{bail, Result};
+use esp_idf_hal::{
+    gpio::{AnyInputPin, Gpio12, Gpio14, Gpio39, Gpio40, Input, Pin, PinDriver},
+    peripheral::{Peripheral, PeripheralRef},
+    spi::{config::DriverConfig, Dma, SpiAnyPins, SpiDriver},
+    sys::{
+        esp, esp_vfs_fat_mount_config_t, esp_vfs_fat_sdcard_unmount, esp_vfs_fat_sdspi_mount,
+        sdmmc_card_t, sdmmc_get_status, sdmmc_host_t, sdmmc_host_t__bindgen_ty_1,
+        sdspi_device_config_t, sdspi_host_do_transaction, sdspi_host_get_real_freq,
+        sdspi_host_init, sdspi_host_io_int_enable, sdspi_host_io_int_wait,
+        sdspi_host_remove_device, sdspi_host_set_card_clk, SDMMC_FREQ_DEFAULT,
+        SDMMC_HOST_FLAG_DEINIT_ARG, SDMMC_HOST_FLAG_SPI,
+    },
+};
+use std::ffi::CString;
+
+use super::file::FileStore;
+
+/// Default mount point of the card
+pub const MOUNT_POINT: &str = "/sdcard";
+
+const MAX_FILES: i32 = 5;
+const DMA_BUFFER_SIZE: usize = 4096;
+
+/// Change of the card presence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SdEvent {
+    /// Card inserted and mounted
+    Inserted,
+    /// Card removed and unmounted
+    Removed,
+}
+
+/// Called when the card is inserted or removed
+pub type SdCallback<'a> = Box<dyn FnMut(SdEvent) + Send + 'a>;
+
+/// microSD card slot
+///
+/// The card is unmounted on drop.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::storage::{sd::SdCard, Store};
+///
+/// let peripherals = Peripherals::take().unwrap();
+///
+/// let card = SdCard::mount(
+///     peripherals.spi3,
+///     peripherals.pins.gpio40,
+///     peripherals.pins.gpio14,
+///     peripherals.pins.gpio39,
+///     peripherals.pins.gpio12,
+/// )
+/// .unwrap();
+/// std::fs::write("/sdcard/hello.txt", "hello").unwrap();
+/// let mut store = card.store();
+/// store.write("notes/todo", b"buy milk").unwrap();
+/// ```
+///
+/// Hot-plug:
+///
+/// ```
+/// use cardputer::storage::sd::{SdCard, SdEvent};
+///
+/// let mut card = SdCard::new(/* ... */).unwrap();
+/// card.on_change(|event| log::info!("{:?}", event));
+/// loop {
+///     card.poll().unwrap();
+///     if card.is_mounted() {
+///         // browse the files
+///     }
+///     thread::sleep(Duration::from_secs(1));
+/// }
+/// ```
+pub struct SdCard<'a> {
+    spi: SpiDriver<'a>,
+    cs: PeripheralRef<'a, Gpio12>,
+    card: *mut sdmmc_card_t,
+    mount_point: CString,
+    detect: Option<PinDriver<'a, AnyInputPin, Input>>,
+    callback: Option<SdCallback<'a>>,
+}
+
+impl<'a> SdCard<'a> {
+    /// Mount the card at [`MOUNT_POINT`]. Fails if there is no card.
+    pub fn mount<SPI: SpiAnyPins>(
+        spi: impl Peripheral<P = SPI> + 'a,
+        sck: impl Peripheral<P = Gpio40> + 'a,
+        mosi: impl Peripheral<P = Gpio14> + 'a,
+        miso: impl Peripheral<P = Gpio39> + 'a,
+        cs: impl Peripheral<P = Gpio12> + 'a,
+    ) -> Result<Self> {
+        Self::mount_at(spi, sck, mosi, miso, cs, MOUNT_POINT)
+    }
+
+    /// Mount the card at the path. Fails if there is no card.
+    pub fn mount_at<SPI: SpiAnyPins>(
+        spi: impl Peripheral<P = SPI> + 'a,
+        sck: impl Peripheral<P = Gpio40> + 'a,
+        mosi: impl Peripheral<P = Gpio14> + 'a,
+        miso: impl Peripheral<P = Gpio39> + 'a,
+        cs: impl Peripheral<P = Gpio12> + 'a,
+        mount_point: &str,
+    ) -> Result<Self> {
+        let mut card = Self::new(spi, sck, mosi, miso, cs, mount_point)?;
+        card.mount_card()?;
+        Ok(card)
+    }
+
+    /// Create new driver for the slot at the mount point without mounting
+    /// a card; [`poll`](Self::poll) mounts it when present.
+    pub fn new<SPI: SpiAnyPins>(
+        spi: impl Peripheral<P = SPI> + 'a,
+        sck: impl Peripheral<P = Gpio40> + 'a,
+        mosi: impl Peripheral<P = Gpio14> + 'a,
+        miso: impl Peripheral<P = Gpio39> + 'a,
+        cs: impl Peripheral<P = Gpio12> + 'a,
+        mount_point: &str,
+    ) -> Result<Self> {
+        let config = DriverConfig::new().dma(Dma::Auto(DMA_BUFFER_SIZE));
+        let spi = SpiDriver::new(spi, sck, mosi, Some(miso), &config)?;
+        Ok(Self {
+            spi,
+            cs: cs.into_ref(),
+            card: core::ptr::null_mut(),
+            mount_point: CString::new(mount_point)?,
+            detect: None,
+            callback: None,
+        })
+    }
+
+    /// Use a card detect switch, low while a card is present, instead of
+    /// probing the card. For slots wired on custom boards.
+    pub fn with_detect_pin(mut self, pin: PinDriver<'a, AnyInputPin, Input>) -> Self {
+        self.detect = Some(pin);
+        self
+    }
+
+    /// Set the callback called when [`poll`](Self::poll) detects an insertion or a removal.
+    pub fn on_change(&mut self, callback: impl FnMut(SdEvent) + Send + 'a) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Returns true if a card is mounted.
+    pub fn is_mounted(&self) -> bool {
+        !self.card.is_null()
+    }
+
+    /// Check the card, unmounting it when removed and mounting it when
+    /// inserted.
+    ///
+    /// Without a detect pin a mount is attempted at every call while no
+    /// card is present, so call it about once per second.
+    pub fn poll(&mut self) -> Result<Option<SdEvent>> {
+        let is_present = match self.detect.as_ref() {
+            Some(pin) => pin.is_low(),
+            None if self.is_mounted() => esp!(unsafe { sdmmc_get_status(self.card) }).is_ok(),
+            // probed by the mount below
+            None => true,
+        };
+        let event = if self.is_mounted() && !is_present {
+            self.unmount();
+            Some(SdEvent::Removed)
+        } else if !self.is_mounted() && is_present && self.mount_card().is_ok() {
+            Some(SdEvent::Inserted)
+        } else {
+            None
+        };
+        if let (Some(event), Some(callback)) = (event, self.callback.as_mut()) {
+            callback(event);
+        }
+        Ok(event)
+    }
+
+    /// Mount the card again, e.g. after [`unmount`](Self::unmount).
+    pub fn remount(&mut self) -> Result<()> {
+        if self.is_mounted() {
+            bail!("already mounted");
+        }
+        self.mount_card()
+    }
+
+    /// Unmount the card so that it can be removed safely. Files still
+    /// open fail on the next access.
+    pub fn unmount(&mut self) {
+        if self.is_mounted() {
+            unsafe { esp_vfs_fat_sdcard_unmount(self.mount_point.as_ptr(), self.card) };
+            self.card = core::ptr::null_mut();
+        }
+    }
+
+    /// Returns the mount point.
+    pub fn mount_point(&self) -> &str {
+        self.mount_point.to_str().unwrap_or_default()
+    }
+
+    /// Returns the capacity in bytes, or 0 if no card is mounted.
+    pub fn capacity(&self) -> u64 {
+        if !self.is_mounted() {
+            return 0;
+        }
+        let csd = unsafe { (*self.card).csd };
+        csd.capacity as u64 * csd.sector_size as u64
+    }
+
+    /// Returns a store over the files on the card.
+    pub fn store(&self) -> FileStore {
+        FileStore::new(self.mount_point())
+    }
+
+    /// Initialize the card without mounting a filesystem, for drivers
+    /// that access the sectors directly. Release it with [`deinit_card`].
+    #[cfg(feature = "usb")]
+    pub(crate) fn init_card(&mut self) -> Result<Box<sdmmc_card_t>> {
+        use esp_idf_hal::sys::{
+            sdmmc_card_init, sdspi_dev_handle_t, sdspi_host_init_device, ESP_ERR_INVALID_STATE,
+        };
+
+        if self.is_mounted() {
+            bail!("card is mounted");
+        }
+        // the host may be initialized by a previous mount
+        match esp!(unsafe { sdspi_host_init() }) {
+            Err(e) if e.code() != ESP_ERR_INVALID_STATE => return Err(e.into()),
+            _ => {}
+        }
+        let mut handle: sdspi_dev_handle_t = 0;
+        esp!(unsafe { sdspi_host_init_device(&self.slot_config(), &mut handle) })?;
+        let mut host = self.host_config();
+        host.slot = handle;
+        let mut card = Box::<sdmmc_card_t>::default();
+        if let Err(e) = esp!(unsafe { sdmmc_card_init(&host, card.as_mut()) }) {
+            unsafe { sdspi_host_remove_device(handle) };
+            return Err(e.into());
+        }
+        Ok(card)
+    }
+
+    fn mount_card(&mut self) -> Result<()> {
+        let mount = esp_vfs_fat_mount_config_t {
+            format_if_mount_failed: false,
+            max_files: MAX_FILES,
+            allocation_unit_size: 16 * 1024,
+            ..Default::default()
+        };
+        esp!(unsafe {
+            esp_vfs_fat_sdspi_mount(
+                self.mount_point.as_ptr(),
+                &self.host_config(),
+                &self.slot_config(),
+                &mount,
+                &mut self.card,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// SDSPI_HOST_DEFAULT() of ESP-IDF
+    fn host_config(&self) -> sdmmc_host_t {
+        sdmmc_host_t {
+            flags: SDMMC_HOST_FLAG_SPI | SDMMC_HOST_FLAG_DEINIT_ARG,
+            slot: self.spi.host() as i32,
+            max_freq_khz: SDMMC_FREQ_DEFAULT as i32,
+            io_voltage: 3.3,
+            init: Some(sdspi_host_init),
+            set_card_clk: Some(sdspi_host_set_card_clk),
+            do_transaction: Some(sdspi_host_do_transaction),
+            __bindgen_anon_1: sdmmc_host_t__bindgen_ty_1 {
+                deinit_p: Some(sdspi_host_remove_device),
+            },
+            io_int_enable: Some(sdspi_host_io_int_enable),
+            io_int_wait: Some(sdspi_host_io_int_wait),
+            get_real_freq: Some(sdspi_host_get_real_freq),
+            ..Default::default()
+        }
+    }
+
+    fn slot_config(&self) -> sdspi_device_config_t {
+        sdspi_device_config_t {
+            host_id: self.spi.host(),
+            gpio_cs: self.cs.pin(),
+            gpio_cd: -1,
+            gpio_wp: -1,
+            gpio_int: -1,
+        }
+    }
+}
+
+/// Release a card initialized with [`SdCard::init_card`].
+#[cfg(feature = "usb")]
+pub(crate) fn deinit_card(card: &sdmmc_card_t) {
+    unsafe { sdspi_host_remove_device(card.host.slot) };
+}
+
+impl Drop for SdCard<'_> {
+    fn drop(&mut self) {
+        self.unmount();
+    }
+}
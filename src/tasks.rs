@@ -0,0 +1,144 @@
+//! Ready-made embassy tasks for the keyboard, the display and the backlight
+//!
+//! The bodies of the tasks are async functions that never return. Embassy
+//! tasks cannot be generic, so the application wraps each of them in its
+//! own `#[embassy_executor::task]` with the concrete types. The tasks share
+//! state through the `embassy-sync` primitives passed to them:
+//!
+//! - [`keyboard_task`] scans the keyboard and sends the key events to a
+//!   channel and a [`KeyEventBroadcast`], and updates an [`IdleDetector`]
+//! - [`display_task`] flushes the frame buffer to the display when a
+//!   redraw is signalled
+//! - [`auto_dim_task`] turns the backlight off at the idle events of the
+//!   detector and on again at the next key
+//!
+//! The time driver of embassy-time must be enabled with the
+//! `embassy-time-driver` feature of esp-idf-svc.
+//!
+//! # Examples
+//!
+//! ```
+//! use cardputer::keyboard::{
+//!     broadcast::{KeyEventBroadcast, Subscription},
+//!     idle::{ActivityEvent, IdleDetector},
+//!     pipeline::KeyEvent,
+//! };
+//! use cardputer::tasks;
+//! use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+//!
+//! static EVENTS: Channel<CriticalSectionRawMutex, KeyEvent, 8> = Channel::new();
+//!
+//! #[embassy_executor::task]
+//! async fn keyboard(keyboard: Keyboard<'static>, broadcast: KeyEventBroadcast, mut idle: IdleDetector) {
+//!     let state = KeyboardState::default();
+//!     let period = Duration::from_millis(10);
+//!     tasks::keyboard_task(keyboard, state, EVENTS.sender(), Some(&broadcast), Some(&mut idle), period).await
+//! }
+//!
+//! #[embassy_executor::task]
+//! async fn auto_dim(backlight: Backlight<'static>, activity: Subscription<ActivityEvent>) {
+//!     tasks::auto_dim_task(backlight, activity).await
+//! }
+//!
+//! let idle = IdleDetector::new().with_threshold(Duration::from_secs(30));
+//! spawner.spawn(auto_dim(backlight, idle.subscribe())).unwrap();
+//! spawner.spawn(keyboard(keyboard, KeyEventBroadcast::new(), idle)).unwrap();
+//! ```
+use core::fmt::Debug;
+use embassy_sync::{blocking_mutex::raw::RawMutex, channel::Sender, mutex::Mutex, signal::Signal};
+use embassy_time::{Instant, Ticker, Timer};
+use embedded_graphics::{pixelcolor::Rgb565, prelude::DrawTarget};
+use std::time::Duration;
+
+use crate::backlight::Backlight;
+use crate::framebuffer::FrameBuffer;
+use crate::keyboard::{
+    broadcast::{KeyEventBroadcast, Subscription},
+    idle::{ActivityEvent, IdleDetector},
+    pipeline::KeyEvent,
+    KeyboardScanner, KeyboardState,
+};
+
+/// Interval at which [`auto_dim_task`] checks its subscription
+const ACTIVITY_POLL_INTERVAL: embassy_time::Duration = embassy_time::Duration::from_millis(50);
+
+fn embassy_duration(duration: Duration) -> embassy_time::Duration {
+    embassy_time::Duration::from_micros(duration.as_micros() as u64)
+}
+
+/// Scan the keyboard every period and send the key events to the channel.
+///
+/// A failed scan is skipped. When given, each event is also published to
+/// `broadcast`, whose subscribers drop their oldest events instead of
+/// blocking the scan, and `idle` is updated after each scan, e.g. for
+/// [`auto_dim_task`]. Sending waits while the channel is full, so the
+/// receiver must keep up.
+pub async fn keyboard_task<M: RawMutex, const N: usize>(
+    mut scanner: impl KeyboardScanner,
+    mut state: KeyboardState,
+    events: Sender<'_, M, KeyEvent, N>,
+    broadcast: Option<&KeyEventBroadcast>,
+    mut idle: Option<&mut IdleDetector>,
+    period: Duration,
+) -> ! {
+    let mut ticker = Ticker::every(embassy_duration(period));
+    loop {
+        ticker.next().await;
+        if state.update(&mut scanner).is_err() {
+            continue;
+        }
+        if let Some(idle) = idle.as_deref_mut() {
+            idle.update(&state);
+        }
+        for event in state.drain_events() {
+            if let Some(broadcast) = broadcast {
+                broadcast.publish(event);
+            }
+            events.send(event).await;
+        }
+    }
+}
+
+/// Flush the frame buffer to the display each time `redraw` is signalled,
+/// at most `max_fps` times per second.
+///
+/// Draw on the frame buffer while holding its lock, then signal `redraw`.
+/// Redraws signalled during a flush are combined into the next one. A
+/// failed flush is retried at the next redraw.
+pub async fn display_task<M: RawMutex, D>(
+    frame_buffer: &Mutex<M, FrameBuffer>,
+    mut display: D,
+    redraw: &Signal<M, ()>,
+    max_fps: u32,
+) -> !
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: Debug,
+{
+    let interval = embassy_time::Duration::from_micros(1_000_000 / max_fps.max(1) as u64);
+    loop {
+        redraw.wait().await;
+        let started = Instant::now();
+        let _ = frame_buffer.lock().await.flush(&mut display);
+        Timer::at(started + interval).await;
+    }
+}
+
+/// Turn the backlight off at each idle event of the subscription, e.g. of
+/// the [`IdleDetector`] updated by [`keyboard_task`], and on again at the
+/// active event.
+pub async fn auto_dim_task(
+    mut backlight: Backlight<'_>,
+    activity: Subscription<ActivityEvent>,
+) -> ! {
+    let _ = backlight.on();
+    loop {
+        while let Some(event) = activity.try_recv() {
+            let _ = match event {
+                ActivityEvent::Idle(_) => backlight.off(),
+                ActivityEvent::Active => backlight.on(),
+            };
+        }
+        Timer::after(ACTIVITY_POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,273 @@
+//! Countdown timers and stopwatches
+//!
+//! Both are polled from the main loop: [`Countdown::poll`] calls the
+//! expiry callback once when the time is up. Either can be bound to a
+//! [`DigitalClock`] showing the remaining or elapsed time as HH:MM:SS;
+//! `redraw` then updates only the digits that changed since the last call.
+use embedded_graphics::{pixelcolor::Rgb565, prelude::DrawTarget};
+use std::time::{Duration, Instant};
+
+use crate::rtc::DateTime;
+use crate::widget::clock::DigitalClock;
+
+type ExpiryCallback = Box<dyn FnMut() + Send>;
+
+/// Returns the duration as the time of day shown by a clock, up to
+/// 99:59:59.
+fn clock_time(duration: Duration) -> DateTime {
+    let seconds = duration.as_secs().min(99 * 3600 + 59 * 60 + 59);
+    DateTime {
+        hour: (seconds / 3600) as u8,
+        minute: (seconds / 60 % 60) as u8,
+        second: (seconds % 60) as u8,
+        ..DateTime::default()
+    }
+}
+
+/// Timer counting down from a duration
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::timers::Countdown;
+/// use cardputer::widget::clock::DigitalClock;
+///
+/// let mut timer = Countdown::new(Duration::from_secs(3 * 60))
+///     .with_display(DigitalClock::new(Point::new(10, 30)).with_seconds(true));
+/// timer.on_expiry(|| log::info!("tea is ready"));
+/// timer.start();
+/// loop {
+///     timer.poll();
+///     timer.redraw(&mut display).unwrap();
+///     thread::sleep(Duration::from_millis(100));
+/// }
+/// ```
+pub struct Countdown {
+    duration: Duration,
+    /// Time left when paused
+    remaining: Duration,
+    /// End of the countdown while running
+    deadline: Option<Instant>,
+    expired: bool,
+    callback: Option<ExpiryCallback>,
+    display: Option<DigitalClock>,
+}
+
+impl Countdown {
+    /// Create new stopped timer of the duration.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            remaining: duration,
+            deadline: None,
+            expired: false,
+            callback: None,
+            display: None,
+        }
+    }
+
+    /// Show the remaining time on the clock (none).
+    pub fn with_display(mut self, display: DigitalClock) -> Self {
+        self.display = Some(display);
+        self
+    }
+
+    /// Set the callback called by [`poll`](Self::poll) when the time is up.
+    pub fn on_expiry(&mut self, callback: impl FnMut() + Send + 'static) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Start or resume counting down. Does nothing once expired.
+    pub fn start(&mut self) {
+        if self.deadline.is_none() && !self.expired {
+            self.deadline = Some(Instant::now() + self.remaining);
+        }
+    }
+
+    /// Stop counting down, keeping the remaining time.
+    pub fn pause(&mut self) {
+        self.remaining = self.remaining();
+        self.deadline = None;
+    }
+
+    /// Stop and go back to the full duration.
+    pub fn reset(&mut self) {
+        self.remaining = self.duration;
+        self.deadline = None;
+        self.expired = false;
+    }
+
+    /// Change the duration and reset.
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration = duration;
+        self.reset();
+    }
+
+    /// Returns the duration counted down from.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Returns the time left.
+    pub fn remaining(&self) -> Duration {
+        match self.deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => self.remaining,
+        }
+    }
+
+    /// Returns true while counting down.
+    pub fn is_running(&self) -> bool {
+        self.deadline.is_some()
+    }
+
+    /// Returns true once the time is up, until reset.
+    pub fn is_expired(&self) -> bool {
+        self.expired
+    }
+
+    /// Check the time and return true, calling the callback, when it has
+    /// just run out.
+    pub fn poll(&mut self) -> bool {
+        if self.deadline.is_none() || !self.remaining().is_zero() {
+            return false;
+        }
+        self.deadline = None;
+        self.remaining = Duration::ZERO;
+        self.expired = true;
+        if let Some(callback) = self.callback.as_mut() {
+            callback();
+        }
+        true
+    }
+
+    /// Returns the bound clock, e.g. to invalidate it after the screen
+    /// was cleared.
+    pub fn display_mut(&mut self) -> Option<&mut DigitalClock> {
+        self.display.as_mut()
+    }
+
+    /// Update the changed digits of the bound clock, rounding the
+    /// remaining time up so that it shows 00:00:00 only when expired.
+    pub fn redraw<D>(&mut self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let remaining = self.remaining();
+        let shown = if remaining.subsec_nanos() == 0 {
+            remaining
+        } else {
+            Duration::from_secs(remaining.as_secs() + 1)
+        };
+        match self.display.as_mut() {
+            Some(display) => display.redraw(&clock_time(shown), target),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Stopwatch with laps
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::timers::Stopwatch;
+/// use cardputer::widget::clock::DigitalClock;
+///
+/// let mut stopwatch =
+///     Stopwatch::new().with_display(DigitalClock::new(Point::new(10, 30)).with_seconds(true));
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     for key in keyboard_state.pressed_keys() {
+///         match key {
+///             Modified::Space if stopwatch.is_running() => stopwatch.stop(),
+///             Modified::Space => stopwatch.start(),
+///             Modified::Enter => log::info!("lap {:?}", stopwatch.lap()),
+///             _ => {}
+///         }
+///     }
+///     stopwatch.redraw(&mut display).unwrap();
+///     thread::sleep(Duration::from_millis(50));
+/// }
+/// ```
+#[derive(Default)]
+pub struct Stopwatch {
+    /// Time measured before the last start
+    accumulated: Duration,
+    started: Option<Instant>,
+    /// Elapsed time at each lap
+    laps: Vec<Duration>,
+    display: Option<DigitalClock>,
+}
+
+impl Stopwatch {
+    /// Create new stopped stopwatch at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Show the elapsed time on the clock (none).
+    pub fn with_display(mut self, display: DigitalClock) -> Self {
+        self.display = Some(display);
+        self
+    }
+
+    /// Start or resume measuring.
+    pub fn start(&mut self) {
+        self.started.get_or_insert_with(Instant::now);
+    }
+
+    /// Stop measuring, keeping the elapsed time.
+    pub fn stop(&mut self) {
+        self.accumulated = self.elapsed();
+        self.started = None;
+    }
+
+    /// Stop and clear the elapsed time and the laps.
+    pub fn reset(&mut self) {
+        self.accumulated = Duration::ZERO;
+        self.started = None;
+        self.laps.clear();
+    }
+
+    /// Returns the time measured.
+    pub fn elapsed(&self) -> Duration {
+        self.accumulated + self.started.map_or(Duration::ZERO, |x| x.elapsed())
+    }
+
+    /// Returns true while measuring.
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// Record a lap and return its time since the previous lap.
+    pub fn lap(&mut self) -> Duration {
+        let elapsed = self.elapsed();
+        let previous = self.laps.last().copied().unwrap_or_default();
+        self.laps.push(elapsed);
+        elapsed - previous
+    }
+
+    /// Returns the elapsed time at each lap.
+    pub fn laps(&self) -> &[Duration] {
+        &self.laps
+    }
+
+    /// Returns the bound clock, e.g. to invalidate it after the screen
+    /// was cleared.
+    pub fn display_mut(&mut self) -> Option<&mut DigitalClock> {
+        self.display.as_mut()
+    }
+
+    /// Update the changed digits of the bound clock.
+    pub fn redraw<D>(&mut self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let elapsed = self.elapsed();
+        match self.display.as_mut() {
+            Some(display) => display.redraw(&clock_time(elapsed), target),
+            None => Ok(()),
+        }
+    }
+}
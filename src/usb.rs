@@ -0,0 +1,178 @@
+//! USB device modes (`usb` feature)
+//!
+//! Built on the TinyUSB stack of the esp_tinyusb component, which has to
+//! be added to the Cargo.toml of the application with the classes enabled
+//! in its sdkconfig:
+//!
+//! ```toml
+//! [[package.metadata.esp-idf-sys.extra_components]]
+//! remote_component = { name = "espressif/esp_tinyusb", version = "1.4" }
+//! ```
+//!
+//! ```text
+//! CONFIG_TINYUSB_MSC_ENABLED=y
+//! CONFIG_TINYUSB_HID_COUNT=1
+//! ```
+//!
+//! The USB OTG peripheral takes over the pins of the USB Serial/JTAG
+//! port, so the console and [`crate::usb_serial`] stop working once a mode
+//! is started. Only one mode can be started per boot.
+use anyhow::{bail, Result};
+use esp_idf_hal::sys::esp;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+pub mod gamepad;
+pub mod keyboard;
+pub mod msc;
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+/// Report descriptor returned to the host by the HID mode
+static HID_REPORT_DESCRIPTOR: Mutex<&'static [u8]> = Mutex::new(&[]);
+
+/// Total length of [`hid_configuration_descriptor`]
+const HID_CONFIGURATION_LEN: usize = 9 + 9 + 9 + 7;
+/// IN endpoint of the HID interface
+const HID_ENDPOINT: u8 = 0x81;
+const HID_ENDPOINT_SIZE: u8 = 16;
+const HID_POLL_INTERVAL_MS: u8 = 10;
+
+/// Bindings of esp_tinyusb
+#[allow(non_camel_case_types)]
+mod ffi {
+    use core::ffi::{c_char, c_int, c_void};
+    use esp_idf_hal::sys::{esp_err_t, esp_vfs_fat_mount_config_t, sdmmc_card_t};
+
+    #[repr(C)]
+    pub struct tinyusb_config_t {
+        pub device_descriptor: *const c_void,
+        pub string_descriptor: *const *const c_char,
+        pub string_descriptor_count: c_int,
+        pub external_phy: bool,
+        pub configuration_descriptor: *const u8,
+        pub self_powered: bool,
+        pub vbus_monitor_io: c_int,
+    }
+
+    pub type tusb_msc_callback_t = Option<unsafe extern "C" fn(event: *mut c_void)>;
+
+    #[repr(C)]
+    pub struct tinyusb_msc_sdmmc_config_t {
+        pub card: *mut sdmmc_card_t,
+        pub callback_mount_changed: tusb_msc_callback_t,
+        pub callback_premount_changed: tusb_msc_callback_t,
+        pub mount_config: esp_vfs_fat_mount_config_t,
+    }
+
+    extern "C" {
+        pub fn tinyusb_driver_install(config: *const tinyusb_config_t) -> esp_err_t;
+        pub fn tinyusb_msc_storage_init_sdmmc(
+            config: *const tinyusb_msc_sdmmc_config_t,
+        ) -> esp_err_t;
+        pub fn tinyusb_msc_storage_deinit();
+        pub fn tinyusb_msc_storage_mount(base_path: *const c_char) -> esp_err_t;
+        pub fn tinyusb_msc_storage_unmount() -> esp_err_t;
+        pub fn tinyusb_msc_storage_in_use_by_usb_host() -> bool;
+        pub fn tud_mounted() -> bool;
+        pub fn tud_hid_n_ready(instance: u8) -> bool;
+        pub fn tud_hid_n_report(
+            instance: u8,
+            report_id: u8,
+            report: *const c_void,
+            len: u16,
+        ) -> bool;
+    }
+}
+
+/// Install the TinyUSB driver with the configuration descriptor, or with
+/// the default descriptors of esp_tinyusb for the enabled classes.
+fn install(configuration_descriptor: Option<&'static [u8]>) -> Result<()> {
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        bail!("a USB mode is already started");
+    }
+    let config = ffi::tinyusb_config_t {
+        device_descriptor: core::ptr::null(),
+        string_descriptor: core::ptr::null(),
+        string_descriptor_count: 0,
+        external_phy: false,
+        configuration_descriptor: configuration_descriptor
+            .map_or(core::ptr::null(), |x| x.as_ptr()),
+        self_powered: false,
+        vbus_monitor_io: -1,
+    };
+    if let Err(e) = esp!(unsafe { ffi::tinyusb_driver_install(&config) }) {
+        INSTALLED.store(false, Ordering::SeqCst);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Install the driver with a single HID interface using the report
+/// descriptor.
+fn install_hid(report_descriptor: &'static [u8]) -> Result<()> {
+    *HID_REPORT_DESCRIPTOR.lock().unwrap() = report_descriptor;
+    // kept by the driver, installed once per boot
+    let configuration = Box::leak(Box::new(hid_configuration_descriptor(
+        report_descriptor.len() as u16,
+    )));
+    install(Some(configuration))
+}
+
+/// TUD_CONFIG_DESCRIPTOR and TUD_HID_DESCRIPTOR of TinyUSB
+fn hid_configuration_descriptor(report_len: u16) -> [u8; HID_CONFIGURATION_LEN] {
+    let total = (HID_CONFIGURATION_LEN as u16).to_le_bytes();
+    let report_len = report_len.to_le_bytes();
+    #[rustfmt::skip]
+    let descriptor = [
+        // configuration: 1 interface, bus powered, remote wakeup, 100 mA
+        9, 0x02, total[0], total[1], 1, 1, 0, 0xA0, 50,
+        // interface 0: HID without boot protocol, 1 endpoint
+        9, 0x04, 0, 0, 1, 0x03, 0x00, 0x00, 0,
+        // HID 1.11 with one report descriptor
+        9, 0x21, 0x11, 0x01, 0, 1, 0x22, report_len[0], report_len[1],
+        // interrupt IN endpoint
+        7, 0x05, HID_ENDPOINT, 0x03, HID_ENDPOINT_SIZE, 0, HID_POLL_INTERVAL_MS,
+    ];
+    descriptor
+}
+
+/// Send an input report on the HID interface. Returns false if the host
+/// is not connected or the previous report is still pending.
+fn send_hid_report(report_id: u8, report: &[u8]) -> bool {
+    unsafe {
+        ffi::tud_mounted()
+            && ffi::tud_hid_n_ready(0)
+            && ffi::tud_hid_n_report(0, report_id, report.as_ptr().cast(), report.len() as u16)
+    }
+}
+
+/// Called by TinyUSB to get the report descriptor.
+#[no_mangle]
+extern "C" fn tud_hid_descriptor_report_cb(_instance: u8) -> *const u8 {
+    HID_REPORT_DESCRIPTOR.lock().unwrap().as_ptr()
+}
+
+/// Called by TinyUSB on GET_REPORT; not supported.
+#[no_mangle]
+extern "C" fn tud_hid_get_report_cb(
+    _instance: u8,
+    _report_id: u8,
+    _report_type: u8,
+    _buffer: *mut u8,
+    _len: u16,
+) -> u16 {
+    0
+}
+
+/// Called by TinyUSB on SET_REPORT and OUT data; ignored.
+#[no_mangle]
+extern "C" fn tud_hid_set_report_cb(
+    _instance: u8,
+    _report_id: u8,
+    _report_type: u8,
+    _buffer: *const u8,
+    _len: u16,
+) {
+}
@@ -0,0 +1,59 @@
+//! USB HID gamepad driven by the keyboard
+use anyhow::Result;
+
+use super::{ffi, install_hid, send_hid_report};
+use crate::gamepad::InputMap;
+use crate::hid::{gamepad_report, GAMEPAD_REPORT_DESCRIPTOR};
+
+/// USB gamepad
+///
+/// The buttons of the [`InputMap`] are reported as a standard gamepad, so
+/// the Cardputer works as a controller for emulators on the PC.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{gamepad::InputMap, usb::gamepad::UsbGamepad};
+///
+/// let mut gamepad = UsbGamepad::start().unwrap();
+/// let mut input = InputMap::arrows();
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     input.update(&keyboard_state);
+///     gamepad.send(&input);
+///     thread::sleep(Duration::from_millis(5));
+/// }
+/// ```
+pub struct UsbGamepad {
+    /// Last report accepted by the host
+    last: Option<[u8; 3]>,
+}
+
+impl UsbGamepad {
+    /// Start the USB device.
+    pub fn start() -> Result<Self> {
+        install_hid(GAMEPAD_REPORT_DESCRIPTOR)?;
+        Ok(Self { last: None })
+    }
+
+    /// Returns true if the host has configured the device.
+    pub fn is_connected(&self) -> bool {
+        unsafe { ffi::tud_mounted() }
+    }
+
+    /// Send the state of the input map if it changed since the last
+    /// report. Returns true if a report was sent.
+    pub fn send(&mut self, input: &InputMap) -> bool {
+        if !self.is_connected() {
+            // report the state again after reconnection
+            self.last = None;
+            return false;
+        }
+        let report = gamepad_report(input);
+        if self.last == Some(report) || !send_hid_report(0, &report) {
+            return false;
+        }
+        self.last = Some(report);
+        true
+    }
+}
@@ -0,0 +1,61 @@
+//! USB HID keyboard with consumer control
+use anyhow::Result;
+
+use super::{ffi, install_hid, send_hid_report};
+use crate::hid::{
+    bridge::KeyboardOutput, consumer_report, ConsumerUsage, CONSUMER_REPORT_ID, KEYBOARD_REPORT_ID,
+    REPORT_DESCRIPTOR,
+};
+
+/// USB keyboard
+///
+/// Uses the same reports as the [BLE keyboard](crate::ble::hid), so the
+/// key events reach the host through a [`HidBridge`](crate::hid::bridge::HidBridge)
+/// whichever the connection.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{hid::bridge::HidBridge, usb::keyboard::UsbKeyboard};
+///
+/// let mut keyboard = UsbKeyboard::start().unwrap();
+/// let mut bridge = HidBridge::new();
+/// let events = broadcast.subscribe();
+/// loop {
+///     bridge.handle_all(events.drain());
+///     bridge.flush(&mut keyboard).unwrap();
+///     thread::sleep(Duration::from_millis(1));
+/// }
+/// ```
+pub struct UsbKeyboard {
+    _private: (),
+}
+
+impl UsbKeyboard {
+    /// Start the USB device.
+    pub fn start() -> Result<Self> {
+        install_hid(REPORT_DESCRIPTOR)?;
+        Ok(Self { _private: () })
+    }
+
+    /// Returns true if the host has configured the device.
+    pub fn is_connected(&self) -> bool {
+        unsafe { ffi::tud_mounted() }
+    }
+
+    /// Press the consumer usage, or release it with `None`. Returns false
+    /// if the report could not be sent.
+    pub fn send_consumer(&mut self, usage: Option<ConsumerUsage>) -> bool {
+        send_hid_report(CONSUMER_REPORT_ID, &consumer_report(usage))
+    }
+}
+
+impl KeyboardOutput for UsbKeyboard {
+    fn send_report(&mut self, report: &[u8; 8]) -> Result<bool> {
+        // dropped while unplugged, retried while the last report is pending
+        if !self.is_connected() {
+            return Ok(true);
+        }
+        Ok(send_hid_report(KEYBOARD_REPORT_ID, report))
+    }
+}
@@ -0,0 +1,113 @@
+//! USB mass-storage device exposing the SD card
+//!
+//! The FAT filesystem can't be shared: while the card is exposed to the
+//! PC, local file access under the mount point fails. Let the user switch
+//! between the two, e.g. with [`crate::widget::usb_storage::UsbStorageToggle`],
+//! and ask them to eject the drive on the PC before switching back.
+use anyhow::{bail, Result};
+use esp_idf_hal::sys::{esp, esp_vfs_fat_mount_config_t, sdmmc_card_t};
+use std::ffi::CString;
+
+use super::{ffi, install};
+use crate::storage::sd::{deinit_card, SdCard};
+
+const MAX_FILES: i32 = 5;
+
+/// USB MSC device on the SD card
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{storage::sd::SdCard, usb::msc::UsbMassStorage};
+///
+/// let card = SdCard::new(/* ... */, "/sdcard").unwrap();
+/// let mut msc = UsbMassStorage::start(card).unwrap();
+/// // the files are available locally until exposed
+/// std::fs::write("/sdcard/hello.txt", "hello").unwrap();
+/// msc.expose().unwrap();
+/// ```
+pub struct UsbMassStorage<'a> {
+    /// Keeps the SPI bus and the CS pin
+    _sd: SdCard<'a>,
+    card: Box<sdmmc_card_t>,
+    mount_point: CString,
+}
+
+impl<'a> UsbMassStorage<'a> {
+    /// Start the USB device on the card of the slot and mount it locally
+    /// at the mount point of the slot. The card is unmounted first if
+    /// mounted.
+    pub fn start(mut sd: SdCard<'a>) -> Result<Self> {
+        sd.unmount();
+        let mount_point = CString::new(sd.mount_point())?;
+        let mut card = sd.init_card()?;
+        let config = ffi::tinyusb_msc_sdmmc_config_t {
+            card: card.as_mut(),
+            callback_mount_changed: None,
+            callback_premount_changed: None,
+            mount_config: esp_vfs_fat_mount_config_t {
+                format_if_mount_failed: false,
+                max_files: MAX_FILES,
+                allocation_unit_size: 16 * 1024,
+                ..Default::default()
+            },
+        };
+        if let Err(e) = esp!(unsafe { ffi::tinyusb_msc_storage_init_sdmmc(&config) }) {
+            deinit_card(&card);
+            return Err(e.into());
+        }
+        let msc = Self {
+            _sd: sd,
+            card,
+            mount_point,
+        };
+        install(None)?;
+        esp!(unsafe { ffi::tinyusb_msc_storage_mount(msc.mount_point.as_ptr()) })?;
+        Ok(msc)
+    }
+
+    /// Returns true if the card is exposed to the PC.
+    pub fn is_exposed(&self) -> bool {
+        unsafe { ffi::tinyusb_msc_storage_in_use_by_usb_host() }
+    }
+
+    /// Unmount the card locally and expose it to the PC.
+    pub fn expose(&mut self) -> Result<()> {
+        if self.is_exposed() {
+            bail!("already exposed");
+        }
+        esp!(unsafe { ffi::tinyusb_msc_storage_unmount() })?;
+        Ok(())
+    }
+
+    /// Take the card back from the PC and mount it locally again.
+    pub fn reclaim(&mut self) -> Result<()> {
+        if !self.is_exposed() {
+            bail!("not exposed");
+        }
+        esp!(unsafe { ffi::tinyusb_msc_storage_mount(self.mount_point.as_ptr()) })?;
+        Ok(())
+    }
+
+    /// Switch between local access and the PC. Returns true if exposed.
+    pub fn toggle(&mut self) -> Result<bool> {
+        if self.is_exposed() {
+            self.reclaim()?;
+        } else {
+            self.expose()?;
+        }
+        Ok(self.is_exposed())
+    }
+
+    /// Returns the local mount point.
+    pub fn mount_point(&self) -> &str {
+        self.mount_point.to_str().unwrap_or_default()
+    }
+}
+
+impl Drop for UsbMassStorage<'_> {
+    fn drop(&mut self) {
+        unsafe { ffi::tinyusb_msc_storage_deinit() };
+        deinit_card(&self.card);
+    }
+}
@@ -0,0 +1,92 @@
+//! Byte stream over the USB Serial/JTAG port
+//!
+//! The port is shared with the console, so log output written while it is
+//! used for a binary protocol corrupts the stream; lower the log level
+//! during transfers.
+use anyhow::{anyhow, Result};
+use esp_idf_hal::{
+    delay::TickType,
+    sys::{
+        esp, usb_serial_jtag_driver_config_t, usb_serial_jtag_driver_install,
+        usb_serial_jtag_driver_uninstall, usb_serial_jtag_read_bytes, usb_serial_jtag_write_bytes,
+        ESP_ERR_INVALID_STATE,
+    },
+};
+use std::time::Duration;
+
+const BUFFER_SIZE: u32 = 2048;
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// USB Serial/JTAG driver
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::usb_serial::UsbSerial;
+///
+/// let mut usb = UsbSerial::new().unwrap();
+/// usb.write_all(b"hello\r\n").unwrap();
+/// let mut buf = [0u8; 64];
+/// let len = usb.read(&mut buf, Duration::from_millis(100)).unwrap();
+/// ```
+pub struct UsbSerial {
+    /// False if the driver was already installed, e.g. by the console
+    installed: bool,
+}
+
+impl UsbSerial {
+    /// Install the driver.
+    pub fn new() -> Result<Self> {
+        let mut config = usb_serial_jtag_driver_config_t {
+            tx_buffer_size: BUFFER_SIZE,
+            rx_buffer_size: BUFFER_SIZE,
+        };
+        let installed = match esp!(unsafe { usb_serial_jtag_driver_install(&mut config) }) {
+            Ok(()) => true,
+            Err(e) if e.code() == ESP_ERR_INVALID_STATE => false,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { installed })
+    }
+
+    /// Read the available bytes, waiting up to the timeout for the first
+    /// one. Returns 0 on timeout.
+    pub fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let len = unsafe {
+            usb_serial_jtag_read_bytes(
+                buf.as_mut_ptr().cast(),
+                buf.len() as u32,
+                TickType::from(timeout).ticks(),
+            )
+        };
+        usize::try_from(len).map_err(|_| anyhow!("USB serial read failed: {}", len))
+    }
+
+    /// Write all bytes.
+    pub fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let mut written = 0;
+        while written < data.len() {
+            let rest = &data[written..];
+            let len = unsafe {
+                usb_serial_jtag_write_bytes(
+                    rest.as_ptr().cast(),
+                    rest.len(),
+                    TickType::from(WRITE_TIMEOUT).ticks(),
+                )
+            };
+            match usize::try_from(len) {
+                Ok(0) | Err(_) => return Err(anyhow!("USB serial write timed out")),
+                Ok(len) => written += len,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UsbSerial {
+    fn drop(&mut self) {
+        if self.installed {
+            unsafe { usb_serial_jtag_driver_uninstall() };
+        }
+    }
+}
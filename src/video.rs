@@ -0,0 +1,174 @@
+//! Raw RGB565 frame sequence playback
+//!
+//! Plays a stream of pre-converted frames, e.g. produced by
+//!
+//! ```sh
+//! % ffmpeg -i input.mp4 -vf scale=240:135 -r 15 -f rawvideo -pix_fmt rgb565be video.raw
+//! % ffmpeg -i input.mp4 -ac 1 -ar 16000 -acodec pcm_s16le audio.wav
+//! ```
+//!
+//! Frames are shown on schedule and skipped when the display falls behind,
+//! so the video stays in sync with the audio track played by the speaker.
+use anyhow::{anyhow, bail, Result};
+use core::fmt::Debug;
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+use std::{
+    io::Read,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::color;
+use crate::display::{DISPLAY_SIZE_HEIGHT, DISPLAY_SIZE_WIDTH};
+use crate::memory::{Buffer, Placement};
+use crate::speaker::Speaker;
+use crate::wav::WavReader;
+
+/// Number of audio samples sent to the speaker at once
+const AUDIO_CHUNK_SAMPLES: usize = 512;
+
+/// Player of raw big-endian RGB565 frame sequences
+///
+/// # Examples
+///
+/// ```
+/// use std::fs::File;
+/// use cardputer::{video::RawVideo, wav::WavReader};
+///
+/// let frames = File::open("/sdcard/video.raw").unwrap();
+/// let audio = WavReader::new(File::open("/sdcard/audio.wav").unwrap()).unwrap();
+///
+/// let mut video = RawVideo::new(frames, 15);
+/// video.play_with_audio(&mut display, &mut speaker, audio).unwrap();
+/// ```
+pub struct RawVideo<R> {
+    reader: R,
+    size: Size,
+    frame_interval: Duration,
+    dropped_frames: u32,
+}
+
+impl<R: Read> RawVideo<R> {
+    /// Create new player of display-sized frames at the frame rate.
+    pub fn new(reader: R, frame_rate: u32) -> Self {
+        Self {
+            reader,
+            size: Size::new(DISPLAY_SIZE_WIDTH as u32, DISPLAY_SIZE_HEIGHT as u32),
+            frame_interval: Duration::from_secs(1) / frame_rate.max(1),
+            dropped_frames: 0,
+        }
+    }
+
+    /// Set the size of the frames, which are drawn centered on the display.
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.size = Size::new(width, height);
+        self
+    }
+
+    /// Returns the number of frames skipped to keep up with the schedule.
+    pub fn dropped_frames(&self) -> u32 {
+        self.dropped_frames
+    }
+
+    /// Play the video without sound.
+    pub fn play<D>(&mut self, display: &mut D) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        self.play_frames(display, &AtomicBool::new(false))
+    }
+
+    /// Play the video together with the audio track.
+    pub fn play_with_audio<D, A>(
+        &mut self,
+        display: &mut D,
+        speaker: &mut Speaker<'_>,
+        mut audio: WavReader<A>,
+    ) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+        A: Read + Send,
+    {
+        if audio.sample_rate() != speaker.sample_rate() {
+            bail!(
+                "sample rate mismatch: audio {} Hz, speaker {} Hz",
+                audio.sample_rate(),
+                speaker.sample_rate()
+            );
+        }
+
+        let stop = AtomicBool::new(false);
+        thread::scope(|s| {
+            let sound = s.spawn(|| -> Result<()> {
+                let mut samples = vec![0i16; AUDIO_CHUNK_SAMPLES];
+                while !stop.load(Ordering::Relaxed) {
+                    match audio.read_samples(&mut samples)? {
+                        0 => break,
+                        n => speaker.play(&samples[..n])?,
+                    }
+                }
+                Ok(())
+            });
+
+            let result = self.play_frames(display, &stop);
+            stop.store(true, Ordering::Relaxed);
+            let sound = sound.join().map_err(|_| anyhow!("audio thread panicked"))?;
+            result.and(sound)
+        })
+    }
+
+    fn play_frames<D>(&mut self, display: &mut D, stop: &AtomicBool) -> Result<()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        D::Error: Debug,
+    {
+        let bounds = display.bounding_box();
+        let area = Rectangle::new(
+            bounds.top_left
+                + Point::new(
+                    (bounds.size.width as i32 - self.size.width as i32) / 2,
+                    (bounds.size.height as i32 - self.size.height as i32) / 2,
+                ),
+            self.size,
+        );
+        let mut frame = Buffer::new(
+            self.size.width as usize * self.size.height as usize * 2,
+            0u8,
+            Placement::Large,
+        );
+        let started = Instant::now();
+
+        for n in 0u32.. {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            match self.reader.read_exact(&mut frame) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+
+            let due = self.frame_interval * n;
+            let now = started.elapsed();
+            if now > due + self.frame_interval {
+                self.dropped_frames += 1;
+                continue;
+            }
+            if let Some(rest) = due.checked_sub(now) {
+                thread::sleep(rest);
+            }
+
+            let colors = frame
+                .chunks_exact(2)
+                .map(|x| color::from_raw(u16::from_be_bytes([x[0], x[1]])));
+            display
+                .fill_contiguous(&area, colors)
+                .map_err(|e| anyhow!("{:?}", e))?;
+        }
+
+        Ok(())
+    }
+}
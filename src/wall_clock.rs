@@ -0,0 +1,194 @@
+//! Wall-clock time of monotonic timestamps
+//!
+//! Key events and sensor readings are stamped with [`Instant`]s, which
+//! count from the boot and mean nothing in a log read later. This module
+//! keeps an anchor pairing an instant with the wall-clock time, so any
+//! instant taken since the boot can be converted to an absolute
+//! [`Timestamp`].
+//!
+//! The anchor is taken from the system time once it is valid, e.g. after
+//! SNTP has set it, or after [`sync_rtc`] at boot has set it from an RTC
+//! module before the network is up. Each conversion compares the anchor
+//! with the system time and takes it again when the time was corrected,
+//! so the timestamps follow SNTP and [`SystemClock`] adjustments. The
+//! system time keeps running through deep sleep; an RTC module keeps it
+//! across power cycles.
+use anyhow::{anyhow, Result};
+use core::fmt;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::rtc::{DateTime, Rtc, SystemClock};
+
+/// System time before which the clock is considered not set (2020-01-01)
+pub(crate) const MIN_VALID_TIME: u64 = 1_577_836_800;
+
+/// Instant paired with the time since the Unix epoch
+#[derive(Debug, Clone, Copy)]
+struct Anchor {
+    instant: Instant,
+    unix: Duration,
+}
+
+static ANCHOR: Mutex<Option<Anchor>> = Mutex::new(None);
+
+/// Difference from the system time above which the anchor is taken again
+const MAX_DRIFT: Duration = Duration::from_millis(50);
+
+/// Returns the system time since the Unix epoch if it has been set.
+fn system_time() -> Option<Duration> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .filter(|x| x.as_secs() >= MIN_VALID_TIME)
+}
+
+/// Take the anchor from the system time now. Returns false if the system
+/// time has not been set yet.
+///
+/// The conversions already follow the corrections of the system time;
+/// call it to check that the time is set.
+pub fn sync_system() -> bool {
+    let instant = Instant::now();
+    let Some(unix) = system_time() else {
+        return false;
+    };
+    if let Ok(mut anchor) = ANCHOR.lock() {
+        *anchor = Some(Anchor { instant, unix });
+    }
+    true
+}
+
+/// Set the system time from the RTC, in the time zone of the offset from
+/// UTC in seconds, and take the anchor from it.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{rtc::Pcf8563, wall_clock};
+///
+/// wall_clock::sync_rtc(&mut Pcf8563::new(i2c), 9 * 3600).unwrap();
+/// ```
+pub fn sync_rtc(rtc: &mut impl Rtc, utc_offset: i64) -> Result<()> {
+    let datetime = rtc.now()?;
+    SystemClock::new()
+        .with_utc_offset(utc_offset)
+        .set(&datetime)?;
+    if !sync_system() {
+        return Err(anyhow!("RTC time is before 2020: {:?}", datetime));
+    }
+    Ok(())
+}
+
+/// Returns true once the anchor has been taken.
+pub fn is_synced() -> bool {
+    anchor().is_some()
+}
+
+/// Returns the anchor, taking it from the system time if there is none or
+/// the system time was changed since.
+fn anchor() -> Option<Anchor> {
+    let instant = Instant::now();
+    let current = system_time().map(|unix| Anchor { instant, unix });
+    let mut anchor = ANCHOR.lock().ok()?;
+    match (*anchor, current) {
+        (Some(x), Some(current)) => {
+            let expected = x.unix + current.instant.saturating_duration_since(x.instant);
+            if expected.abs_diff(current.unix) > MAX_DRIFT {
+                *anchor = Some(current);
+            }
+        }
+        (None, Some(current)) => *anchor = Some(current),
+        // the system time was set back before 2020, keep the last anchor
+        (_, None) => {}
+    }
+    *anchor
+}
+
+/// Returns the time since the Unix epoch at the instant, or `None` before
+/// the clock has been set.
+pub fn unix_time(instant: Instant) -> Option<Duration> {
+    let anchor = anchor()?;
+    match instant.checked_duration_since(anchor.instant) {
+        Some(after) => Some(anchor.unix + after),
+        None => anchor.unix.checked_sub(anchor.instant - instant),
+    }
+}
+
+/// Returns the time since the boot at the instant.
+fn uptime(instant: Instant) -> Duration {
+    // SAFETY: esp_timer_get_time only reads the timer, which runs from the
+    // boot
+    let now = Duration::from_micros(unsafe { esp_idf_hal::sys::esp_timer_get_time() } as u64);
+    now.saturating_sub(instant.elapsed())
+}
+
+/// Absolute time of an event, with the uptime as a fallback
+///
+/// Displayed as `2024-05-01 12:34:56.789` in UTC, or `+12.345` seconds
+/// since the boot before the clock has been set.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{sensors, wall_clock::Timestamp};
+///
+/// let reading = (Instant::now(), sensors::chip_temperature().unwrap());
+/// // ... later, e.g. when the readings are written to the SD card
+/// writeln!(log, "{} {:.1}", Timestamp::of(reading.0), reading.1).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    /// Time since the boot
+    pub uptime: Duration,
+    /// Time since the Unix epoch, if the clock was set
+    pub unix: Option<Duration>,
+}
+
+impl Timestamp {
+    /// Returns the timestamp of the instant.
+    pub fn of(instant: Instant) -> Self {
+        Self {
+            uptime: uptime(instant),
+            unix: unix_time(instant),
+        }
+    }
+
+    /// Returns the timestamp of the current time.
+    pub fn now() -> Self {
+        Self::of(Instant::now())
+    }
+
+    /// Returns the date and time in the time zone of the offset from UTC in
+    /// seconds, or `None` if the clock was not set.
+    pub fn datetime(&self, utc_offset: i64) -> Option<DateTime> {
+        let seconds = self.unix?.as_secs() as i64 + utc_offset;
+        Some(DateTime::from_timestamp(seconds.max(0) as u64))
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.unix, self.datetime(0)) {
+            (Some(unix), Some(x)) => write!(
+                f,
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+                x.year,
+                x.month,
+                x.day,
+                x.hour,
+                x.minute,
+                x.second,
+                unix.subsec_millis()
+            ),
+            _ => write!(
+                f,
+                "+{}.{:03}",
+                self.uptime.as_secs(),
+                self.uptime.subsec_millis()
+            ),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Task watchdog integration
+//!
+//! Each service loop registers a [`Watch`] and feeds it once per iteration.
+//! If a watch is not fed within the timeout, the task watchdog of ESP-IDF
+//! panics and the device reboots; [`check_starvation`] does the same from
+//! the application with the name of the starved loop in the panic message.
+use anyhow::{anyhow, Result};
+use esp_idf_hal::sys::{
+    esp, esp_task_wdt_add_user, esp_task_wdt_config_t, esp_task_wdt_delete_user, esp_task_wdt_init,
+    esp_task_wdt_reconfigure, esp_task_wdt_reset_user, esp_task_wdt_user_handle_t,
+};
+use std::{
+    ffi::CString,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+/// Name of the watch for the keyboard scanning loop
+pub const KEYBOARD: &str = "keyboard";
+/// Name of the watch for the display flushing loop
+pub const DISPLAY: &str = "display";
+
+struct Entry {
+    name: String,
+    last_feed: Mutex<Instant>,
+}
+
+static WATCHES: Mutex<Vec<Weak<Entry>>> = Mutex::new(Vec::new());
+static TIMEOUT: Mutex<Duration> = Mutex::new(Duration::from_secs(5));
+
+/// Set the timeout of the task watchdog and make it panic on expiry.
+///
+/// The idle tasks are not watched, so busy loops that never yield are
+/// only detected if they own a [`Watch`].
+pub fn configure(timeout: Duration) -> Result<()> {
+    let config = esp_task_wdt_config_t {
+        timeout_ms: timeout.as_millis() as u32,
+        idle_core_mask: 0,
+        trigger_panic: true,
+    };
+    // the watchdog is not running if it was disabled in sdkconfig
+    if esp!(unsafe { esp_task_wdt_reconfigure(&config) }).is_err() {
+        esp!(unsafe { esp_task_wdt_init(&config) })?;
+    }
+    *TIMEOUT.lock().map_err(|e| anyhow!("{:?}", e))? = timeout;
+    Ok(())
+}
+
+/// Panic if a watch has not been fed within the timeout.
+///
+/// Call it from a loop other than the watched ones, e.g. the main loop.
+pub fn check_starvation() {
+    if let Some((name, elapsed)) = starving().into_iter().next() {
+        panic!("watchdog: {} not fed for {:?}", name, elapsed);
+    }
+}
+
+/// Return the watches not fed within the timeout and the time since their last feed.
+pub fn starving() -> Vec<(String, Duration)> {
+    let timeout = TIMEOUT.lock().map(|timeout| *timeout).unwrap_or_default();
+    let Ok(mut watches) = WATCHES.lock() else {
+        return Vec::new();
+    };
+    watches.retain(|watch| watch.strong_count() > 0);
+    watches
+        .iter()
+        .filter_map(Weak::upgrade)
+        .filter_map(|entry| {
+            let elapsed = entry.last_feed.lock().ok()?.elapsed();
+            (elapsed > timeout).then(|| (entry.name.clone(), elapsed))
+        })
+        .collect()
+}
+
+/// Feed point registered with the task watchdog
+///
+/// Unregistered on drop.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::watchdog::{self, Watch};
+///
+/// watchdog::configure(Duration::from_secs(3)).unwrap();
+///
+/// let watch = Watch::new(watchdog::KEYBOARD).unwrap();
+/// let keyboard_task = timer_service
+///     .timer(move || {
+///         keyboard_state.update(&mut keyboard).unwrap();
+///         watch.feed().unwrap();
+///     })
+///     .unwrap();
+/// ```
+pub struct Watch {
+    handle: esp_task_wdt_user_handle_t,
+    entry: Arc<Entry>,
+    // the watchdog keeps the pointer to the name
+    _name: CString,
+}
+
+// The handle is an opaque token of the watchdog, which is thread-safe.
+unsafe impl Send for Watch {}
+unsafe impl Sync for Watch {}
+
+impl Watch {
+    /// Register new watch. The first feed is due within the timeout.
+    pub fn new(name: &str) -> Result<Self> {
+        let c_name = CString::new(name)?;
+        let mut handle: esp_task_wdt_user_handle_t = core::ptr::null_mut();
+        esp!(unsafe { esp_task_wdt_add_user(c_name.as_ptr(), &mut handle) })?;
+        let entry = Arc::new(Entry {
+            name: name.to_string(),
+            last_feed: Mutex::new(Instant::now()),
+        });
+        WATCHES
+            .lock()
+            .map_err(|e| anyhow!("{:?}", e))?
+            .push(Arc::downgrade(&entry));
+        Ok(Self {
+            handle,
+            entry,
+            _name: c_name,
+        })
+    }
+
+    /// Return the name.
+    pub fn name(&self) -> &str {
+        &self.entry.name
+    }
+
+    /// Tell the watchdog that the loop is alive.
+    pub fn feed(&self) -> Result<()> {
+        esp!(unsafe { esp_task_wdt_reset_user(self.handle) })?;
+        *self
+            .entry
+            .last_feed
+            .lock()
+            .map_err(|e| anyhow!("{:?}", e))? = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        unsafe { esp_task_wdt_delete_user(self.handle) };
+    }
+}
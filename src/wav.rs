@@ -0,0 +1,125 @@
+//! Minimal WAV (RIFF, 16-bit PCM) reader
+use anyhow::{bail, Result};
+use std::io::Read;
+
+/// Reader that streams the samples of a WAV file
+///
+/// Stereo data is mixed down to mono for the speaker.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::wav::WavReader;
+///
+/// let file = std::fs::File::open("/sdcard/sound.wav").unwrap();
+/// let mut wav = WavReader::new(file).unwrap();
+///
+/// let mut samples = [0i16; 256];
+/// while let Ok(n @ 1..) = wav.read_samples(&mut samples) {
+///     speaker.play(&samples[..n]).unwrap();
+/// }
+/// ```
+pub struct WavReader<R> {
+    reader: R,
+    sample_rate: u32,
+    channels: u16,
+    remaining: u32,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> WavReader<R> {
+    /// Parse the header and move to the beginning of the sample data.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut riff = [0u8; 12];
+        reader.read_exact(&mut riff)?;
+        if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+            bail!("not a WAV file");
+        }
+
+        let mut format: Option<(u16, u32)> = None;
+        loop {
+            let mut header = [0u8; 8];
+            reader.read_exact(&mut header)?;
+            let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            match &header[0..4] {
+                b"fmt " => {
+                    let mut fmt = vec![0u8; len as usize];
+                    reader.read_exact(&mut fmt)?;
+                    if fmt.len() < 16 {
+                        bail!("invalid fmt chunk");
+                    }
+                    let tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                    let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                    let rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+                    let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                    if tag != 1 || bits != 16 || !(1..=2).contains(&channels) {
+                        bail!("unsupported format: only 16-bit PCM mono/stereo is supported");
+                    }
+                    format = Some((channels, rate));
+                }
+                b"data" => {
+                    let Some((channels, sample_rate)) = format else {
+                        bail!("data chunk before fmt chunk");
+                    };
+                    return Ok(Self {
+                        reader,
+                        sample_rate,
+                        channels,
+                        remaining: len,
+                        buffer: Vec::new(),
+                    });
+                }
+                _ => {
+                    // chunks are padded to even length
+                    let skip = len as u64 + (len & 1) as u64;
+                    std::io::copy(&mut (&mut reader).take(skip), &mut std::io::sink())?;
+                }
+            }
+        }
+    }
+
+    /// Returns the sample rate in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the number of channels in the file.
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Read mono samples into the buffer and return the number of samples read.
+    ///
+    /// Returns 0 at the end of the data.
+    pub fn read_samples(&mut self, samples: &mut [i16]) -> Result<usize> {
+        let frame_len = self.channels as usize * 2;
+        let frames = samples.len().min(self.remaining as usize / frame_len);
+        self.buffer.resize(frames * frame_len, 0);
+
+        let mut filled = 0;
+        while filled < self.buffer.len() {
+            match self.reader.read(&mut self.buffer[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        let frames = filled / frame_len;
+        self.remaining = if filled < self.buffer.len() {
+            0
+        } else {
+            self.remaining - filled as u32
+        };
+
+        for (sample, frame) in samples
+            .iter_mut()
+            .zip(self.buffer[..frames * frame_len].chunks_exact(frame_len))
+        {
+            let sum: i32 = frame
+                .chunks_exact(2)
+                .map(|x| i16::from_le_bytes([x[0], x[1]]) as i32)
+                .sum();
+            *sample = (sum / self.channels as i32) as i16;
+        }
+        Ok(frames)
+    }
+}
@@ -0,0 +1,32 @@
+//! Web services over WiFi
+//!
+//! The services register their handlers on an HTTP server owned by the
+//! application, so they can share the port:
+//!
+//! ```
+//! use cardputer::web::{self, mirror::DisplayMirror};
+//!
+//! // connect to WiFi with esp-idf-svc first
+//! let mut server = web::start_server().unwrap();
+//! let mut mirror = DisplayMirror::register(&mut server).unwrap();
+//! ```
+//!
+//! The WebSocket endpoints need `CONFIG_HTTPD_WS_SUPPORT=y` in sdkconfig.
+use anyhow::Result;
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+
+pub mod api;
+pub mod mirror;
+pub mod portal;
+
+const MAX_URI_HANDLERS: usize = 16;
+
+/// Start an HTTP server on port 80 with room for the handlers of all services.
+pub fn start_server() -> Result<EspHttpServer<'static>> {
+    let config = Configuration {
+        max_uri_handlers: MAX_URI_HANDLERS,
+        uri_match_wildcard: true,
+        ..Default::default()
+    };
+    Ok(EspHttpServer::new(&config)?)
+}
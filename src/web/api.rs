@@ -0,0 +1,374 @@
+//! Authenticated JSON API for home-automation systems
+//!
+//! All endpoints require an `Authorization: Bearer <token>` header and
+//! answer with a JSON object, `{"error": "..."}` on failure: status 401
+//! for a wrong token, 400 for an invalid request and 500 when the store
+//! or the application loop fails.
+//!
+//! | Endpoint | Body | |
+//! |---|---|---|
+//! | `GET /api/status` | | battery and backlight state |
+//! | `PUT /api/backlight` | `{"brightness": 0-100}` | [`Command::SetBrightness`] |
+//! | `POST /api/display` | `{"text": "..."}` | [`Command::ShowText`] |
+//! | `POST /api/keys` | `{"keys": "H I Enter"}` | key presses by [`KeyImprint`] name |
+//! | `GET /api/config/<key>` | | `{"key": "...", "value": "..." or null}` |
+//! | `PUT /api/config/<key>` | `{"value": "..."}` | |
+//! | `DELETE /api/config/<key>` | | |
+//!
+//! Requests that need the hardware are queued as [`Command`]s for the
+//! application loop, which owns the display and the backlight.
+use anyhow::{anyhow, bail, Result};
+use cardputer_core::json::{self, Value};
+use embedded_svc::{
+    http::server::Request,
+    io::{Read, Write},
+};
+use esp_idf_svc::http::{
+    server::{EspHttpConnection, EspHttpServer},
+    Method,
+};
+use std::{
+    fmt,
+    sync::{mpsc, Arc, Mutex},
+};
+
+use crate::keyboard::pipeline::{KeyEvent, KeyEventKind, Scan, Stage};
+use crate::keyboard::KeyImprint;
+use crate::storage::Store;
+
+const MAX_BODY_SIZE: usize = 1024;
+const CONFIG_PREFIX: &str = "/api/config/";
+
+type HttpRequest<'a, 'b> = Request<&'a mut EspHttpConnection<'b>>;
+
+/// Request for the application loop
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Set the backlight brightness in percent
+    SetBrightness(u8),
+    /// Show the text on the display
+    ShowText(String),
+}
+
+/// Device state reported by `GET /api/status`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Status {
+    /// Battery voltage in mV
+    pub battery_voltage: u32,
+    /// Battery level in percent
+    pub battery_level: u8,
+    pub usb_powered: bool,
+    /// Backlight brightness in percent
+    pub brightness: u8,
+}
+
+/// JSON API registered on the HTTP server
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::web::{self, api::{Command, RestApi, Status}};
+/// use cardputer::storage::nvs::NvsStore;
+///
+/// let mut server = web::start_server().unwrap();
+/// let api = RestApi::register(&mut server, "secret", NvsStore::new(nvs)).unwrap();
+/// let mut pipeline = Pipeline::new()
+///     .with_stage(Debouncer::default())
+///     .with_stage(api.key_injector())
+///     .with_stage(LayerMapper::default());
+/// loop {
+///     for command in api.commands() {
+///         match command {
+///             Command::SetBrightness(x) => set_brightness(x),
+///             Command::ShowText(text) => show(&text),
+///         }
+///     }
+///     api.set_status(Status {
+///         battery_voltage: power.battery_voltage().unwrap(),
+///         battery_level: power.battery_level().unwrap(),
+///         usb_powered: power.source() == PowerSource::Usb,
+///         brightness,
+///     });
+///     let events = pipeline.update(&mut keyboard).unwrap();
+/// }
+/// ```
+pub struct RestApi {
+    commands: mpsc::Receiver<Command>,
+    status: Arc<Mutex<Status>>,
+    injected: Arc<Mutex<Vec<KeyImprint>>>,
+}
+
+impl RestApi {
+    /// Register the endpoints on the server. Configuration values are kept
+    /// in the store. An empty token is refused.
+    pub fn register(
+        server: &mut EspHttpServer,
+        token: &str,
+        store: impl Store + Send + 'static,
+    ) -> Result<Self> {
+        if token.is_empty() {
+            bail!("empty API token");
+        }
+        let token = Arc::new(token.to_string());
+        let (sender, commands) = mpsc::channel();
+        let status = Arc::new(Mutex::new(Status::default()));
+        let injected = Arc::new(Mutex::new(Vec::new()));
+        let store = Arc::new(Mutex::new(store));
+
+        {
+            let status = status.clone();
+            route(server, "/api/status", Method::Get, &token, move |_| {
+                let status = *status.lock().unwrap();
+                Ok(format!(
+                    r#"{{"battery_voltage":{},"battery_level":{},"usb_powered":{},"brightness":{}}}"#,
+                    status.battery_voltage,
+                    status.battery_level,
+                    status.usb_powered,
+                    status.brightness
+                ))
+            })?;
+        }
+        {
+            let sender = Mutex::new(sender.clone());
+            let status = status.clone();
+            route(
+                server,
+                "/api/backlight",
+                Method::Put,
+                &token,
+                move |request| {
+                    let fields = read_object(request)?;
+                    let brightness = match json::field(&fields, "brightness") {
+                        Some(Value::Number(x)) if (0.0..=100.0).contains(x) => *x as u8,
+                        _ => bail!("brightness must be a number from 0 to 100"),
+                    };
+                    status.lock().unwrap().brightness = brightness;
+                    sender
+                        .lock()
+                        .unwrap()
+                        .send(Command::SetBrightness(brightness))
+                        .map_err(|e| internal(e.into()))?;
+                    Ok(format!(r#"{{"brightness":{}}}"#, brightness))
+                },
+            )?;
+        }
+        {
+            let sender = Mutex::new(sender);
+            route(
+                server,
+                "/api/display",
+                Method::Post,
+                &token,
+                move |request| {
+                    let fields = read_object(request)?;
+                    let Some(Value::String(text)) = json::field(&fields, "text") else {
+                        bail!("text must be a string");
+                    };
+                    sender
+                        .lock()
+                        .unwrap()
+                        .send(Command::ShowText(text.clone()))
+                        .map_err(|e| internal(e.into()))?;
+                    Ok(r#"{"ok":true}"#.to_string())
+                },
+            )?;
+        }
+        {
+            let injected = injected.clone();
+            route(server, "/api/keys", Method::Post, &token, move |request| {
+                let fields = read_object(request)?;
+                let Some(Value::String(keys)) = json::field(&fields, "keys") else {
+                    bail!("keys must be a string of key names");
+                };
+                let imprints = keys
+                    .split_whitespace()
+                    .map(|x| KeyImprint::from_name(x).ok_or_else(|| anyhow!("unknown key: {}", x)))
+                    .collect::<Result<Vec<_>>>()?;
+                injected.lock().unwrap().extend(imprints);
+                Ok(r#"{"ok":true}"#.to_string())
+            })?;
+        }
+        {
+            let store = store.clone();
+            route(
+                server,
+                "/api/config/*",
+                Method::Get,
+                &token,
+                move |request| {
+                    let key = config_key(request)?;
+                    let value = store.lock().unwrap().read_string(&key).map_err(internal)?;
+                    Ok(format!(
+                        r#"{{"key":{},"value":{}}}"#,
+                        json::string(&key),
+                        value.map_or("null".to_string(), |x| json::string(&x))
+                    ))
+                },
+            )?;
+        }
+        {
+            let store = store.clone();
+            route(
+                server,
+                "/api/config/*",
+                Method::Put,
+                &token,
+                move |request| {
+                    let key = config_key(request)?;
+                    let fields = read_object(request)?;
+                    let Some(Value::String(value)) = json::field(&fields, "value") else {
+                        bail!("value must be a string");
+                    };
+                    store
+                        .lock()
+                        .unwrap()
+                        .write(&key, value.as_bytes())
+                        .map_err(internal)?;
+                    Ok(r#"{"ok":true}"#.to_string())
+                },
+            )?;
+        }
+        route(
+            server,
+            "/api/config/*",
+            Method::Delete,
+            &token,
+            move |request| {
+                let key = config_key(request)?;
+                let removed = store.lock().unwrap().remove(&key).map_err(internal)?;
+                Ok(format!(r#"{{"removed":{}}}"#, removed))
+            },
+        )?;
+
+        Ok(Self {
+            commands,
+            status,
+            injected,
+        })
+    }
+
+    /// Returns the commands received since the last call.
+    pub fn commands(&self) -> Vec<Command> {
+        self.commands.try_iter().collect()
+    }
+
+    /// Set the state reported by `GET /api/status`.
+    pub fn set_status(&self, status: Status) {
+        *self.status.lock().unwrap() = status;
+    }
+
+    /// Returns a pipeline stage that adds the keys posted to `/api/keys`.
+    /// Insert it before the [`LayerMapper`](crate::keyboard::pipeline::LayerMapper).
+    pub fn key_injector(&self) -> KeyInjector {
+        KeyInjector {
+            keys: self.injected.clone(),
+        }
+    }
+}
+
+/// Pipeline stage that adds a press and a release for each injected key
+pub struct KeyInjector {
+    keys: Arc<Mutex<Vec<KeyImprint>>>,
+}
+
+impl Stage for KeyInjector {
+    fn process(&mut self, scan: &mut Scan) {
+        for imprint in self.keys.lock().unwrap().drain(..) {
+            scan.events
+                .push(KeyEvent::new(KeyEventKind::Pressed, imprint));
+            scan.events
+                .push(KeyEvent::new(KeyEventKind::Released, imprint));
+        }
+    }
+}
+
+/// Register an authenticated handler returning a JSON body.
+fn route<F>(
+    server: &mut EspHttpServer,
+    uri: &str,
+    method: Method,
+    token: &Arc<String>,
+    handler: F,
+) -> Result<()>
+where
+    F: for<'a, 'b> Fn(&mut HttpRequest<'a, 'b>) -> Result<String> + Send + 'static,
+{
+    let token = token.clone();
+    server.fn_handler(uri, method, move |mut request| {
+        let authorization = request.header("Authorization").unwrap_or_default();
+        let authorized = authorization
+            .strip_prefix("Bearer ")
+            .is_some_and(|x| constant_time_eq(x.as_bytes(), token.as_bytes()));
+        let (status, body) = if !authorized {
+            (401, error("unauthorized"))
+        } else {
+            match handler(&mut request) {
+                Ok(body) => (200, body),
+                Err(e) if e.is::<Internal>() => (500, error(&e.to_string())),
+                Err(e) => (400, error(&e.to_string())),
+            }
+        };
+        request
+            .into_response(status, None, &[("Content-Type", "application/json")])?
+            .write_all(body.as_bytes())?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Returns true if the bytes are equal, taking the same time wherever they
+/// differ, so the token cannot be guessed from the response time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Failure of the device rather than of the request
+struct Internal(anyhow::Error);
+
+impl fmt::Debug for Internal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for Internal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for Internal {}
+
+/// Mark the error to be answered with status 500.
+fn internal(error: anyhow::Error) -> anyhow::Error {
+    anyhow::Error::new(Internal(error))
+}
+
+fn error(message: &str) -> String {
+    format!(r#"{{"error":{}}}"#, json::string(message))
+}
+
+fn read_object(request: &mut HttpRequest) -> Result<Vec<(String, Value)>> {
+    let mut body = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let len = request.read(&mut buf).map_err(|e| anyhow!("{:?}", e))?;
+        if len == 0 {
+            break;
+        }
+        body.extend_from_slice(&buf[..len]);
+        if body.len() > MAX_BODY_SIZE {
+            bail!("body too large");
+        }
+    }
+    json::parse_object(std::str::from_utf8(&body)?)
+}
+
+fn config_key(request: &HttpRequest) -> Result<String> {
+    let uri = request.uri();
+    let path = uri.split('?').next().unwrap_or_default();
+    match path.strip_prefix(CONFIG_PREFIX) {
+        Some(key) if !key.is_empty() => Ok(key.to_string()),
+        _ => bail!("missing key"),
+    }
+}
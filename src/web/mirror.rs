@@ -0,0 +1,224 @@
+//! Live view of the screen in a browser
+//!
+//! A page served at `/mirror` shows the screen on a canvas that can be
+//! saved as a PNG. The device sends the rectangles that changed since the
+//! previous update over the WebSocket at `/mirror/ws`; each rectangle is
+//! a header of x, y, width and height followed by its RGB565 pixels, all
+//! 16-bit little-endian.
+use anyhow::Result;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_svc::{io::Write, ws::FrameType};
+use esp_idf_svc::http::{
+    server::{ws::EspHttpWsDetachedSender, EspHttpServer},
+    Method,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::color;
+use crate::display::{DISPLAY_SIZE_HEIGHT, DISPLAY_SIZE_WIDTH};
+use crate::framebuffer::FrameBuffer;
+
+const WIDTH: usize = DISPLAY_SIZE_WIDTH as usize;
+const HEIGHT: usize = DISPLAY_SIZE_HEIGHT as usize;
+/// Rows compared as a unit; each band with changes is sent as one rectangle
+const BAND_HEIGHT: usize = 8;
+const DEFAULT_MAX_FPS: u32 = 15;
+
+const PAGE: &str = r##"<!DOCTYPE html>
+<html><head><meta name="viewport" content="width=device-width">
+<title>Cardputer</title></head>
+<body style="background:#222;color:#ccc;font-family:sans-serif;text-align:center">
+<canvas id="screen" width="WIDTH" height="HEIGHT"
+ style="width:min(96vw,960px);image-rendering:pixelated;background:#000"></canvas>
+<p><a id="save" download="cardputer.png" href="#" style="color:#8cf">Save screenshot</a></p>
+<script>
+const canvas = document.getElementById('screen');
+const context = canvas.getContext('2d');
+const image = context.createImageData(canvas.width, canvas.height);
+function connect() {
+  const ws = new WebSocket('ws://' + location.host + '/mirror/ws');
+  ws.binaryType = 'arraybuffer';
+  ws.onmessage = (event) => {
+    const data = new DataView(event.data);
+    let offset = 0;
+    while (offset < data.byteLength) {
+      const [x, y, w, h] = [0, 2, 4, 6].map((i) => data.getUint16(offset + i, true));
+      offset += 8;
+      for (let j = 0; j < h; j++) {
+        for (let i = 0; i < w; i++, offset += 2) {
+          const p = data.getUint16(offset, true);
+          const k = ((y + j) * canvas.width + x + i) * 4;
+          image.data.set([(p >> 11) << 3, ((p >> 5) & 63) << 2, (p & 31) << 3, 255], k);
+        }
+      }
+    }
+    context.putImageData(image, 0, 0);
+  };
+  ws.onclose = () => setTimeout(connect, 1000);
+}
+document.getElementById('save').onclick = (event) => {
+  event.target.href = canvas.toDataURL('image/png');
+};
+connect();
+</script></body></html>
+"##;
+
+/// Streams the frame buffer to the browsers connected to the page
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::web::{self, mirror::DisplayMirror};
+///
+/// let mut server = web::start_server().unwrap();
+/// let mut mirror = DisplayMirror::register(&mut server).unwrap();
+/// loop {
+///     draw(&mut fb);
+///     fb.flush(&mut display).unwrap();
+///     mirror.update(&fb).unwrap();
+/// }
+/// ```
+pub struct DisplayMirror {
+    clients: Arc<Mutex<Vec<EspHttpWsDetachedSender>>>,
+    /// Set when a client connects and needs the whole screen
+    needs_full: Arc<AtomicBool>,
+    previous: Vec<Rgb565>,
+    min_interval: Duration,
+    last_sent: Option<Instant>,
+}
+
+impl DisplayMirror {
+    /// Register the page and the WebSocket endpoint on the server.
+    pub fn register(server: &mut EspHttpServer) -> Result<Self> {
+        let page = PAGE
+            .replace("WIDTH", &WIDTH.to_string())
+            .replace("HEIGHT", &HEIGHT.to_string());
+        server.fn_handler("/mirror", Method::Get, move |request| {
+            request
+                .into_response(200, None, &[("Content-Type", "text/html")])?
+                .write_all(page.as_bytes())?;
+            Ok(())
+        })?;
+
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let needs_full = Arc::new(AtomicBool::new(false));
+        {
+            let clients = clients.clone();
+            let needs_full = needs_full.clone();
+            server.ws_handler("/mirror/ws", move |connection| {
+                if connection.is_new() {
+                    let sender = connection.create_detached_sender()?;
+                    clients.lock().unwrap().push(sender);
+                    needs_full.store(true, Ordering::Relaxed);
+                } else if !connection.is_closed() {
+                    // the page sends nothing; drain whatever arrives
+                    let mut buf = [0u8; 64];
+                    let _ = connection.recv(&mut buf);
+                }
+                anyhow::Ok(())
+            })?;
+        }
+
+        Ok(Self {
+            clients,
+            needs_full,
+            previous: Vec::new(),
+            min_interval: Duration::from_secs(1) / DEFAULT_MAX_FPS,
+            last_sent: None,
+        })
+    }
+
+    /// Set the maximum number of updates sent per second (15).
+    pub fn with_max_fps(mut self, fps: u32) -> Self {
+        self.min_interval = Duration::from_secs(1) / fps.max(1);
+        self
+    }
+
+    /// Returns the number of connected browsers.
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Send the changes of the frame buffer since the last update. Skipped
+    /// without clients or when called faster than the maximum rate.
+    pub fn update(&mut self, fb: &FrameBuffer) -> Result<()> {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|x| !x.is_closed());
+        if clients.is_empty() {
+            return Ok(());
+        }
+        if self
+            .last_sent
+            .is_some_and(|x| x.elapsed() < self.min_interval)
+        {
+            return Ok(());
+        }
+
+        let pixels = fb.pixels();
+        let full = self.needs_full.swap(false, Ordering::Relaxed) || self.previous.is_empty();
+        let message = if full {
+            let mut message = Vec::with_capacity(8 + pixels.len() * 2);
+            push_rect(&mut message, pixels, 0, 0, WIDTH, HEIGHT);
+            message
+        } else {
+            diff(&self.previous, pixels)
+        };
+        self.previous.clear();
+        self.previous.extend_from_slice(pixels);
+        self.last_sent = Some(Instant::now());
+        if message.is_empty() {
+            return Ok(());
+        }
+        clients.retain_mut(|x| x.send(FrameType::Binary(false), &message).is_ok());
+        Ok(())
+    }
+}
+
+/// Encode the bounding box of the changes in each band of rows.
+fn diff(previous: &[Rgb565], current: &[Rgb565]) -> Vec<u8> {
+    let mut message = Vec::new();
+    for top in (0..HEIGHT).step_by(BAND_HEIGHT) {
+        let bottom = (top + BAND_HEIGHT).min(HEIGHT);
+        let mut span: Option<(usize, usize)> = None;
+        for y in top..bottom {
+            let row = y * WIDTH..(y + 1) * WIDTH;
+            let (old, new) = (&previous[row.clone()], &current[row]);
+            let Some(left) = (0..WIDTH).find(|x| old[*x] != new[*x]) else {
+                continue;
+            };
+            let right = (left..WIDTH)
+                .rev()
+                .find(|x| old[*x] != new[*x])
+                .unwrap_or(left);
+            span = Some(span.map_or((left, right), |(l, r)| (l.min(left), r.max(right))));
+        }
+        if let Some((left, right)) = span {
+            push_rect(
+                &mut message,
+                current,
+                left,
+                top,
+                right - left + 1,
+                bottom - top,
+            );
+        }
+    }
+    message
+}
+
+fn push_rect(message: &mut Vec<u8>, pixels: &[Rgb565], x: usize, y: usize, w: usize, h: usize) {
+    for value in [x, y, w, h] {
+        message.extend_from_slice(&(value as u16).to_le_bytes());
+    }
+    for row in y..y + h {
+        for pixel in &pixels[row * WIDTH + x..row * WIDTH + x + w] {
+            message.extend_from_slice(&color::to_raw(*pixel).to_le_bytes());
+        }
+    }
+}
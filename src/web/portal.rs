@@ -0,0 +1,393 @@
+//! Captive-portal provisioning in access point mode
+//!
+//! For devices without a usable keyboard: the Cardputer opens its own
+//! network, answers every DNS query with its address so phones show the
+//! sign-in page, and takes the WiFi credentials and the device name from
+//! a form. The result is written to a [`Store`] and the WiFi switches to
+//! station mode.
+use anyhow::{anyhow, bail, Result};
+use embedded_svc::{
+    http::server::Request,
+    io::{Read, Write},
+};
+use esp_idf_svc::{
+    http::{
+        server::{EspHttpConnection, EspHttpServer},
+        Method,
+    },
+    wifi::{
+        AccessPointConfiguration, AuthMethod, BlockingWifi, ClientConfiguration, Configuration,
+        EspWifi,
+    },
+};
+use std::{
+    net::{Ipv4Addr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::storage::Store;
+
+/// Store key of the network name
+pub const SSID_KEY: &str = "wifi_ssid";
+/// Store key of the network password
+pub const PASSWORD_KEY: &str = "wifi_password";
+/// Store key of the device name
+pub const DEVICE_NAME_KEY: &str = "device_name";
+
+const DNS_PORT: u16 = 53;
+const DNS_STACK_SIZE: usize = 4 * 1024;
+const MAX_FORM_SIZE: usize = 512;
+
+const PAGE: &str = r##"<!DOCTYPE html>
+<html><head><meta name="viewport" content="width=device-width">
+<title>Cardputer setup</title></head>
+<body style="font-family:sans-serif;max-width:24em;margin:auto;padding:1em">
+<h2>Cardputer setup</h2>
+<form method="post" action="/save">
+<p>WiFi network<br><input name="ssid" value="{{ssid}}" required style="width:100%"></p>
+<p>Password<br><input name="password" type="password" style="width:100%"></p>
+<p>Device name<br><input name="name" value="{{name}}" style="width:100%"></p>
+<p><button type="submit">Save and connect</button></p>
+</form></body></html>
+"##;
+
+/// Settings entered on the page
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Credentials {
+    pub ssid: String,
+    pub password: String,
+    pub device_name: String,
+}
+
+impl Credentials {
+    /// Read the settings from the store. Returns `None` without an SSID.
+    pub fn load(store: &impl Store) -> Result<Option<Self>> {
+        let Some(ssid) = store.read_string(SSID_KEY)? else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            ssid,
+            password: store.read_string(PASSWORD_KEY)?.unwrap_or_default(),
+            device_name: store.read_string(DEVICE_NAME_KEY)?.unwrap_or_default(),
+        }))
+    }
+
+    /// Write the settings to the store.
+    pub fn save(&self, store: &mut impl Store) -> Result<()> {
+        store.write(SSID_KEY, self.ssid.as_bytes())?;
+        store.write(PASSWORD_KEY, self.password.as_bytes())?;
+        store.write(DEVICE_NAME_KEY, self.device_name.as_bytes())
+    }
+}
+
+/// Connect to the network in station mode and wait for an address.
+pub fn connect(wifi: &mut BlockingWifi<EspWifi>, credentials: &Credentials) -> Result<()> {
+    let auth_method = if credentials.password.is_empty() {
+        AuthMethod::None
+    } else {
+        AuthMethod::WPA2Personal
+    };
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+        ssid: credentials
+            .ssid
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow!("SSID too long"))?,
+        password: credentials
+            .password
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow!("password too long"))?,
+        auth_method,
+        ..Default::default()
+    }))?;
+    if !wifi.is_started()? {
+        wifi.start()?;
+    }
+    wifi.connect()?;
+    wifi.wait_netif_up()?;
+    Ok(())
+}
+
+/// Connect with the stored settings, or run the portal until working
+/// settings are entered. Returns the settings in use.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{storage::nvs::NvsStore, web::portal};
+///
+/// let mut wifi = BlockingWifi::wrap(EspWifi::new(modem, sysloop.clone(), Some(nvs))?, sysloop)?;
+/// let store = NvsStore::new(EspDefaultNvs::new(partition, "config", true)?);
+/// let credentials = portal::provision(&mut wifi, "Cardputer-Setup", store).unwrap();
+/// ```
+pub fn provision(
+    wifi: &mut BlockingWifi<EspWifi>,
+    ap_ssid: &str,
+    mut store: impl Store + Send + 'static,
+) -> Result<Credentials> {
+    if let Some(credentials) = Credentials::load(&store)? {
+        if connect(wifi, &credentials).is_ok() {
+            return Ok(credentials);
+        }
+        wifi.stop()?;
+    }
+    loop {
+        let mut server = super::start_server()?;
+        let portal = CaptivePortal::start(wifi, &mut server, ap_ssid, store)?;
+        let credentials = portal.wait();
+        store = portal.stop();
+        drop(server);
+        wifi.stop()?;
+        if connect(wifi, &credentials).is_ok() {
+            return Ok(credentials);
+        }
+        wifi.stop()?;
+    }
+}
+
+/// Access point with the DNS responder and the configuration page
+pub struct CaptivePortal<S: Store + Send + 'static> {
+    received: mpsc::Receiver<Credentials>,
+    store: Arc<std::sync::Mutex<Option<S>>>,
+    stop: Arc<AtomicBool>,
+    dns: Option<JoinHandle<()>>,
+}
+
+impl<S: Store + Send + 'static> CaptivePortal<S> {
+    /// Switch the WiFi to an open access point with the SSID and register
+    /// the page on the server. Submitted settings are saved to the store.
+    pub fn start(
+        wifi: &mut BlockingWifi<EspWifi>,
+        server: &mut EspHttpServer,
+        ssid: &str,
+        store: S,
+    ) -> Result<Self> {
+        wifi.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+            ssid: ssid.try_into().map_err(|_| anyhow!("SSID too long"))?,
+            auth_method: AuthMethod::None,
+            ..Default::default()
+        }))?;
+        wifi.start()?;
+        let address = wifi.wifi().ap_netif().get_ip_info()?.ip;
+
+        let current = Credentials::load(&store)?.unwrap_or_default();
+        let page = fill(
+            PAGE,
+            &[
+                ("ssid", &html_escape(&current.ssid)),
+                ("name", &html_escape(&current.device_name)),
+            ],
+        );
+        server.fn_handler("/", Method::Get, move |request| {
+            request
+                .into_response(200, None, &[("Content-Type", "text/html")])?
+                .write_all(page.as_bytes())?;
+            Ok(())
+        })?;
+
+        let store = Arc::new(std::sync::Mutex::new(Some(store)));
+        let (sender, received) = mpsc::channel();
+        {
+            let store = store.clone();
+            let sender = std::sync::Mutex::new(sender);
+            server.fn_handler("/save", Method::Post, move |mut request| {
+                let result = read_form(&mut request).and_then(|credentials| {
+                    if let Some(store) = store.lock().unwrap().as_mut() {
+                        credentials.save(store)?;
+                    }
+                    sender.lock().unwrap().send(credentials)?;
+                    Ok(())
+                });
+                let message = match result {
+                    Ok(()) => "Saved. Connecting...".to_string(),
+                    Err(e) => format!("Error: {}", e),
+                };
+                request
+                    .into_response(200, None, &[("Content-Type", "text/html")])?
+                    .write_all(format!("<p>{}</p>", html_escape(&message)).as_bytes())?;
+                Ok(())
+            })?;
+        }
+        // connectivity checks of the phones land here and open the page
+        let location = format!("http://{}/", address);
+        server.fn_handler("/*", Method::Get, move |request| {
+            request.into_response(302, None, &[("Location", &location)])?;
+            Ok(())
+        })?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let dns = {
+            let stop = stop.clone();
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DNS_PORT))?;
+            socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+            thread::Builder::new()
+                .stack_size(DNS_STACK_SIZE)
+                .spawn(move || run_dns(socket, address, &stop))?
+        };
+
+        Ok(Self {
+            received,
+            store,
+            stop,
+            dns: Some(dns),
+        })
+    }
+
+    /// Returns the settings if they were submitted since the last call.
+    pub fn poll(&self) -> Option<Credentials> {
+        self.received.try_recv().ok()
+    }
+
+    /// Wait until settings are submitted.
+    pub fn wait(&self) -> Credentials {
+        loop {
+            if let Ok(credentials) = self.received.recv_timeout(Duration::from_secs(1)) {
+                return credentials;
+            }
+        }
+    }
+
+    /// Stop the DNS responder and return the store. The page handlers stay
+    /// on the server until it is dropped.
+    pub fn stop(mut self) -> S {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(dns) = self.dns.take() {
+            let _ = dns.join();
+        }
+        self.store
+            .lock()
+            .unwrap()
+            .take()
+            .expect("store taken only once")
+    }
+}
+
+impl<S: Store + Send + 'static> Drop for CaptivePortal<S> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Answer every A query with the address.
+fn run_dns(socket: UdpSocket, address: Ipv4Addr, stop: &AtomicBool) {
+    let mut buf = [0u8; 512];
+    while !stop.load(Ordering::Relaxed) {
+        let Ok((len, peer)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        if let Some(response) = dns_response(&buf[..len], address) {
+            let _ = socket.send_to(&response, peer);
+        }
+    }
+}
+
+fn dns_response(query: &[u8], address: Ipv4Addr) -> Option<Vec<u8>> {
+    // header and a single question
+    if query.len() < 12 || query[2] & 0x80 != 0 || query[4..6] != [0, 1] {
+        return None;
+    }
+    let mut end = 12;
+    while *query.get(end)? != 0 {
+        end += query[end] as usize + 1;
+    }
+    // terminator, type and class
+    let question = query.get(12..end + 5)?;
+    let mut response = Vec::with_capacity(12 + question.len() + 16);
+    response.extend_from_slice(&query[..2]);
+    response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+    response.extend_from_slice(question);
+    // pointer to the name in the question, A, IN, TTL 60 s, 4 bytes
+    response.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+    response.extend_from_slice(&address.octets());
+    Some(response)
+}
+
+fn read_form(request: &mut Request<&mut EspHttpConnection>) -> Result<Credentials> {
+    let mut body = Vec::new();
+    let mut buf = [0u8; 128];
+    loop {
+        let len = request.read(&mut buf).map_err(|e| anyhow!("{:?}", e))?;
+        if len == 0 {
+            break;
+        }
+        body.extend_from_slice(&buf[..len]);
+        if body.len() > MAX_FORM_SIZE {
+            bail!("form too large");
+        }
+    }
+    let mut credentials = Credentials::default();
+    for pair in std::str::from_utf8(&body)?.split('&') {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = url_decode(value);
+        match name {
+            "ssid" => credentials.ssid = value,
+            "password" => credentials.password = value,
+            "name" => credentials.device_name = value,
+            _ => {}
+        }
+    }
+    if credentials.ssid.is_empty() {
+        bail!("missing network name");
+    }
+    Ok(credentials)
+}
+
+/// Decode application/x-www-form-urlencoded text.
+fn url_decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [input.next().unwrap_or(b'0'), input.next().unwrap_or(b'0')];
+                let hex = std::str::from_utf8(&hex).unwrap_or("00");
+                bytes.push(u8::from_str_radix(hex, 16).unwrap_or(b'?'));
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Replace each `{{name}}` placeholder of the template with its value in
+/// one pass, so the values are never searched for placeholders. Unknown
+/// placeholders are left as they are.
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut page = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        page.push_str(&rest[..start]);
+        let tail = &rest[start + 2..];
+        let value = tail.find("}}").and_then(|end| {
+            let name = &tail[..end];
+            let (_, value) = values.iter().find(|(x, _)| *x == name)?;
+            Some((*value, end + 2))
+        });
+        match value {
+            Some((value, len)) => {
+                page.push_str(value);
+                rest = &tail[len..];
+            }
+            None => {
+                page.push_str("{{");
+                rest = tail;
+            }
+        }
+    }
+    page.push_str(rest);
+    page
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
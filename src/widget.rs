@@ -0,0 +1,11 @@
+//! Widgets drawn with embedded-graphics
+pub mod clock;
+pub mod console;
+pub mod expression;
+pub mod keymap;
+pub mod line_editor;
+pub mod memory_overlay;
+pub mod spectrum;
+pub mod typing_stats;
+pub mod usb_storage;
+pub use cardputer_core::widget::text_layout;
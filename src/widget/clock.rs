@@ -0,0 +1,383 @@
+//! Analog and digital clock faces
+//!
+//! Both faces remember what they drew, so [`AnalogClock::redraw`] and
+//! [`DigitalClock::redraw`] only touch the hands and digits that changed
+//! and can be called every frame directly on the display.
+use embedded_graphics::{
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle, Rectangle},
+};
+
+use crate::rtc::DateTime;
+
+/// Segments of the digits 0-9, bit 0 to 6 for the segments a to g
+const SEGMENTS: [u8; 10] = [0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F];
+
+/// Clock with hour, minute and second hands
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{rtc::SystemClock, widget::clock::AnalogClock};
+///
+/// let mut clock = SystemClock::new().with_utc_offset(9 * 3600);
+/// let mut face = AnalogClock::new(Point::new(120, 67), 60);
+/// loop {
+///     face.redraw(&clock.now().unwrap(), &mut display).unwrap();
+///     thread::sleep(Duration::from_millis(100));
+/// }
+/// ```
+pub struct AnalogClock {
+    center: Point,
+    radius: u32,
+    color: Rgb565,
+    second_color: Rgb565,
+    background: Rgb565,
+    time: DateTime,
+    /// Ends of the hour, minute and second hands on the target
+    drawn: Option<[Point; 3]>,
+}
+
+impl AnalogClock {
+    /// Create new clock face with the center and the radius.
+    pub fn new(center: Point, radius: u32) -> Self {
+        Self {
+            center,
+            radius,
+            color: Rgb565::WHITE,
+            second_color: Rgb565::RED,
+            background: Rgb565::BLACK,
+            time: DateTime::default(),
+            drawn: None,
+        }
+    }
+
+    /// Set the color of the dial and the hour and minute hands (white).
+    pub fn with_color(mut self, color: Rgb565) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Set the color of the second hand (red).
+    pub fn with_second_color(mut self, color: Rgb565) -> Self {
+        self.second_color = color;
+        self
+    }
+
+    /// Set the background color (black).
+    pub fn with_background(mut self, color: Rgb565) -> Self {
+        self.background = color;
+        self
+    }
+
+    /// Set the time drawn by [`Drawable::draw`].
+    pub fn set_time(&mut self, time: &DateTime) {
+        self.time = *time;
+    }
+
+    /// Draw the time, the whole face at the first call and after
+    /// [`invalidate`](Self::invalidate), otherwise only the moved hands.
+    pub fn redraw<D>(&mut self, time: &DateTime, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        self.time = *time;
+        let hands = self.hands();
+        match self.drawn {
+            None => self.draw(target)?,
+            Some(drawn) if drawn == hands => return Ok(()),
+            Some(drawn) => {
+                for (end, width) in drawn.iter().zip(self.hand_widths()) {
+                    self.hand(*end, width, self.background).draw(target)?;
+                }
+                self.draw_hands(target)?;
+            }
+        }
+        self.drawn = Some(hands);
+        Ok(())
+    }
+
+    /// Draw the whole face at the next [`redraw`](Self::redraw), e.g. after
+    /// the screen was cleared.
+    pub fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+
+    /// Ends of the hour, minute and second hands
+    fn hands(&self) -> [Point; 3] {
+        let (hour, minute, second) = (
+            self.time.hour as f32,
+            self.time.minute as f32,
+            self.time.second as f32,
+        );
+        let radius = self.radius as f32;
+        [
+            self.point((hour % 12.0) * 30.0 + minute * 0.5, radius * 0.5),
+            self.point(minute * 6.0 + second * 0.1, radius * 0.75),
+            self.point(second * 6.0, radius * 0.8),
+        ]
+    }
+
+    fn hand_widths(&self) -> [u32; 3] {
+        [3, 2, 1]
+    }
+
+    /// Point at the angle clockwise from 12 o'clock and the distance from the center
+    fn point(&self, degrees: f32, distance: f32) -> Point {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        self.center
+            + Point::new(
+                (sin * distance).round() as i32,
+                -(cos * distance).round() as i32,
+            )
+    }
+
+    fn hand(&self, end: Point, width: u32, color: Rgb565) -> impl Drawable<Color = Rgb565> {
+        Line::new(self.center, end).into_styled(PrimitiveStyle::with_stroke(color, width))
+    }
+
+    fn draw_hands<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let colors = [self.color, self.color, self.second_color];
+        for ((end, width), color) in self.hands().iter().zip(self.hand_widths()).zip(colors) {
+            self.hand(*end, width, color).draw(target)?;
+        }
+        Circle::with_center(self.center, 5)
+            .into_styled(PrimitiveStyle::with_fill(self.color))
+            .draw(target)?;
+        Ok(())
+    }
+}
+
+impl Drawable for AnalogClock {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let diameter = self.radius * 2 + 1;
+        Circle::with_center(self.center, diameter)
+            .into_styled(PrimitiveStyle::with_fill(self.background))
+            .draw(target)?;
+        Circle::with_center(self.center, diameter)
+            .into_styled(PrimitiveStyle::with_stroke(self.color, 1))
+            .draw(target)?;
+        let radius = self.radius as f32;
+        for hour in 0..12 {
+            let degrees = hour as f32 * 30.0;
+            let inner = if hour % 3 == 0 { 0.82 } else { 0.88 };
+            Line::new(
+                self.point(degrees, radius * inner),
+                self.point(degrees, radius * 0.95),
+            )
+            .into_styled(PrimitiveStyle::with_stroke(self.color, 2))
+            .draw(target)?;
+        }
+        self.draw_hands(target)
+    }
+}
+
+/// Large seven-segment clock showing HH:MM or HH:MM:SS
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::widget::clock::DigitalClock;
+///
+/// let mut face = DigitalClock::new(Point::new(10, 30)).with_seconds(true);
+/// loop {
+///     face.redraw(&clock.now().unwrap(), &mut display).unwrap();
+///     thread::sleep(Duration::from_millis(100));
+/// }
+/// ```
+pub struct DigitalClock {
+    top_left: Point,
+    digit_size: Size,
+    has_seconds: bool,
+    color: Rgb565,
+    background: Rgb565,
+    time: DateTime,
+    /// Digits on the target
+    drawn: Option<[u8; 6]>,
+}
+
+impl DigitalClock {
+    /// Create new clock with the top-left position and 30x60 digits.
+    pub fn new(top_left: Point) -> Self {
+        Self {
+            top_left,
+            digit_size: Size::new(30, 60),
+            has_seconds: false,
+            color: Rgb565::WHITE,
+            background: Rgb565::BLACK,
+            time: DateTime::default(),
+            drawn: None,
+        }
+    }
+
+    /// Set the size of a digit.
+    pub fn with_digit_size(mut self, size: Size) -> Self {
+        self.digit_size = size;
+        self
+    }
+
+    /// Show the seconds.
+    pub fn with_seconds(mut self, has_seconds: bool) -> Self {
+        self.has_seconds = has_seconds;
+        self
+    }
+
+    /// Set the color of the segments (white).
+    pub fn with_color(mut self, color: Rgb565) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Set the background color (black).
+    pub fn with_background(mut self, color: Rgb565) -> Self {
+        self.background = color;
+        self
+    }
+
+    /// Returns the size of the whole clock.
+    pub fn size(&self) -> Size {
+        let groups = if self.has_seconds { 3 } else { 2 };
+        let width = groups * 2 * self.digit_advance() + (groups - 1) * self.colon_width();
+        Size::new(width, self.digit_size.height)
+    }
+
+    /// Set the time drawn by [`Drawable::draw`].
+    pub fn set_time(&mut self, time: &DateTime) {
+        self.time = *time;
+    }
+
+    /// Draw the time, the whole clock at the first call and after
+    /// [`invalidate`](Self::invalidate), otherwise only the changed digits.
+    pub fn redraw<D>(&mut self, time: &DateTime, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        self.time = *time;
+        let digits = self.digits();
+        match self.drawn {
+            None => self.draw(target)?,
+            Some(drawn) => {
+                for (i, digit) in digits.iter().enumerate() {
+                    if drawn[i] != *digit && (i < 4 || self.has_seconds) {
+                        self.draw_digit(i, *digit, target)?;
+                    }
+                }
+            }
+        }
+        self.drawn = Some(digits);
+        Ok(())
+    }
+
+    /// Draw the whole clock at the next [`redraw`](Self::redraw).
+    pub fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+
+    fn digits(&self) -> [u8; 6] {
+        let DateTime {
+            hour,
+            minute,
+            second,
+            ..
+        } = self.time;
+        [
+            hour / 10,
+            hour % 10,
+            minute / 10,
+            minute % 10,
+            second / 10,
+            second % 10,
+        ]
+    }
+
+    fn thickness(&self) -> u32 {
+        (self.digit_size.width / 5).max(1)
+    }
+
+    fn digit_advance(&self) -> u32 {
+        self.digit_size.width + self.thickness()
+    }
+
+    fn colon_width(&self) -> u32 {
+        self.thickness() * 3
+    }
+
+    /// Left edge of the digit at the index
+    fn digit_x(&self, index: usize) -> i32 {
+        let index = index as u32;
+        (index * self.digit_advance() + index / 2 * self.colon_width()) as i32
+    }
+
+    fn draw_digit<D>(&self, index: usize, digit: u8, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let origin = self.top_left + Point::new(self.digit_x(index), 0);
+        target.fill_solid(&Rectangle::new(origin, self.digit_size), self.background)?;
+
+        let (w, h, t) = (
+            self.digit_size.width as i32,
+            self.digit_size.height as i32,
+            self.thickness() as i32,
+        );
+        let half = h / 2;
+        let segments = [
+            (t, 0, w - 2 * t, t),
+            (w - t, t, t, half - t),
+            (w - t, half, t, h - half - t),
+            (t, h - t, w - 2 * t, t),
+            (0, half, t, h - half - t),
+            (0, t, t, half - t),
+            (t, half - t / 2, w - 2 * t, t),
+        ];
+        for (i, (x, y, width, height)) in segments.into_iter().enumerate() {
+            if SEGMENTS[digit as usize % 10] & (1 << i) != 0 {
+                let area = Rectangle::new(
+                    origin + Point::new(x, y),
+                    Size::new(width.max(0) as u32, height.max(0) as u32),
+                );
+                target.fill_solid(&area, self.color)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drawable for DigitalClock {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.fill_solid(&Rectangle::new(self.top_left, self.size()), self.background)?;
+        let count = if self.has_seconds { 6 } else { 4 };
+        for (i, digit) in self.digits().iter().take(count).enumerate() {
+            self.draw_digit(i, *digit, target)?;
+        }
+        let t = self.thickness();
+        let height = self.digit_size.height as i32;
+        for i in (2..count).step_by(2) {
+            let x = self.digit_x(i) - self.colon_width() as i32;
+            for y in [height / 3, height * 2 / 3] {
+                let dot = Rectangle::new(
+                    self.top_left + Point::new(x, y - t as i32 / 2),
+                    Size::new(t, t),
+                );
+                target.fill_solid(&dot, self.color)?;
+            }
+        }
+        Ok(())
+    }
+}
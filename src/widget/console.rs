@@ -0,0 +1,114 @@
+//! Scrolling text console
+use embedded_graphics::{
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+};
+use std::collections::VecDeque;
+
+use super::text_layout::{LineBox, Span, TextLayout};
+
+/// Size of a character of the font used by the console
+const CHAR_SIZE: Size = Size::new(6, 10);
+
+/// Text area that wraps long lines and scrolls up as lines are added
+///
+/// Lines are wrapped at spaces by [`TextLayout`], and can mix colors and
+/// fonts with [`Console::push_spans`].
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::widget::{console::Console, text_layout::Span};
+///
+/// let mut console = Console::new(Rectangle::new(Point::zero(), Size::new(240, 120)));
+/// console.push_line("hello");
+/// console.push_colored_line("error", Rgb565::RED);
+/// console.push_spans(&[Span::new("wifi: "), Span::new("connected").with_color(Rgb565::GREEN)]);
+/// console.draw(&mut fb).unwrap();
+/// ```
+pub struct Console {
+    area: Rectangle,
+    lines: VecDeque<LineBox>,
+    color: Rgb565,
+    background: Rgb565,
+}
+
+impl Console {
+    /// Create new empty console in the area.
+    pub fn new(area: Rectangle) -> Self {
+        Self {
+            area,
+            lines: VecDeque::new(),
+            color: Rgb565::WHITE,
+            background: Rgb565::BLACK,
+        }
+    }
+
+    /// Set the default text color (white).
+    pub fn with_color(mut self, color: Rgb565) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Set the background color (black).
+    pub fn with_background(mut self, color: Rgb565) -> Self {
+        self.background = color;
+        self
+    }
+
+    /// Returns the number of characters of the default font per line.
+    pub fn columns(&self) -> usize {
+        (self.area.size.width / CHAR_SIZE.width).max(1) as usize
+    }
+
+    /// Returns the number of visible lines of the default font.
+    pub fn rows(&self) -> usize {
+        (self.area.size.height / CHAR_SIZE.height) as usize
+    }
+
+    /// Add the text in the default color. Line feeds start new lines.
+    pub fn push_line(&mut self, text: &str) {
+        self.push_colored_line(text, self.color);
+    }
+
+    /// Add the text in the color. Line feeds start new lines.
+    pub fn push_colored_line(&mut self, text: &str, color: Rgb565) {
+        self.push_spans(&[Span::new(text).with_color(color)]);
+    }
+
+    /// Add the styled text. Line feeds start new lines.
+    pub fn push_spans(&mut self, spans: &[Span]) {
+        let layout = TextLayout::new(self.area.size.width);
+        self.lines.extend(layout.layout(spans));
+        while self.lines.iter().map(|x| x.height).sum::<u32>() > self.area.size.height {
+            self.lines.pop_front();
+        }
+    }
+
+    /// Remove all lines.
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+}
+
+impl Drawable for Console {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.area
+            .into_styled(PrimitiveStyle::with_fill(self.background))
+            .draw(target)?;
+        let mut y = 0;
+        for line in self.lines.iter() {
+            // the lines keep their position in the text they were laid out from
+            line.draw_at(self.area.top_left + Point::new(0, y - line.y), target)?;
+            y += line.height as i32;
+        }
+        Ok(())
+    }
+}
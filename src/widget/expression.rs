@@ -0,0 +1,246 @@
+//! Calculator-style expression entry
+use anyhow::Result;
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_6X10},
+        MonoTextStyle,
+    },
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+
+use crate::keyboard::{numpad, KeyboardState, Modified};
+
+type Evaluator = Box<dyn FnMut(&str) -> Result<String> + Send>;
+
+/// Characters that continue the last result instead of starting anew
+const OPERATORS: &[char] = &['+', '-', '*', '/', '^', '%'];
+
+/// Expression field with the result below it
+///
+/// The keys are read through the numeric keypad layer (see
+/// [`numpad`](crate::keyboard::numpad)) without toggling it, so digits and
+/// operators are typed on the right half of the keyboard; the other keys
+/// keep their usual meaning, e.g. Shift+9 for `(`. Enter or `=` passes the
+/// expression to the evaluator and shows its result or error. An operator
+/// typed right after a result continues from it. Backspace deletes the
+/// last character and Esc clears the field.
+///
+/// The expression is drawn in a large font, right-aligned like a pocket
+/// calculator, keeping its end visible when it is too long.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::widget::expression::ExpressionInput;
+///
+/// let mut input = ExpressionInput::new(fb.bounding_box(), |expression| {
+///     let value: f64 = my_math::eval(expression)?;
+///     Ok(format!("{}", value))
+/// });
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     if let Some(result) = input.update(&keyboard_state) {
+///         log::info!("= {}", result);
+///     }
+///     input.draw(&mut fb).unwrap();
+/// }
+/// ```
+pub struct ExpressionInput {
+    area: Rectangle,
+    expression: String,
+    /// Result or error message of the last evaluation
+    result: Option<Result<String, String>>,
+    evaluator: Evaluator,
+    numpad: bool,
+    color: Rgb565,
+    background: Rgb565,
+}
+
+impl ExpressionInput {
+    /// Create new empty field in the area with the evaluator, which
+    /// returns the result to show or an error.
+    pub fn new(
+        area: Rectangle,
+        evaluator: impl FnMut(&str) -> Result<String> + Send + 'static,
+    ) -> Self {
+        Self {
+            area,
+            expression: String::new(),
+            result: None,
+            evaluator: Box::new(evaluator),
+            numpad: true,
+            color: Rgb565::WHITE,
+            background: Rgb565::BLACK,
+        }
+    }
+
+    /// Read the keys through the numeric keypad layer (true).
+    pub fn with_numpad(mut self, numpad: bool) -> Self {
+        self.numpad = numpad;
+        self
+    }
+
+    /// Set the color of the expression (white).
+    pub fn with_color(mut self, color: Rgb565) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Set the background color (black).
+    pub fn with_background(mut self, color: Rgb565) -> Self {
+        self.background = color;
+        self
+    }
+
+    /// Returns the expression being entered.
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// Replace the expression.
+    pub fn set_expression(&mut self, expression: &str) {
+        self.expression = expression.to_string();
+        self.result = None;
+    }
+
+    /// Returns the result or the error message of the last evaluation,
+    /// until the expression is edited.
+    pub fn result(&self) -> Option<&Result<String, String>> {
+        self.result.as_ref()
+    }
+
+    /// Clear the expression and the result.
+    pub fn clear(&mut self) {
+        self.expression.clear();
+        self.result = None;
+    }
+
+    /// Handle the keys pressed in the last update. Returns the result when
+    /// an expression was evaluated successfully.
+    pub fn update(&mut self, state: &KeyboardState) -> Option<String> {
+        let mut result = None;
+        for (imprint, key) in state
+            .pressed_imprints()
+            .into_iter()
+            .zip(state.pressed_keys())
+        {
+            let key = match numpad(imprint) {
+                Some(x) if self.numpad && !state.is_fn_pressed() => x,
+                _ => key,
+            };
+            if let Some(x) = self.handle_key(key) {
+                result = Some(x);
+            }
+        }
+        result
+    }
+
+    /// Handle a key. Returns the result when an expression was evaluated
+    /// successfully.
+    pub fn handle_key(&mut self, key: Modified) -> Option<String> {
+        match key {
+            Modified::Enter | Modified::Graph('=') => return self.evaluate(),
+            Modified::Graph(c) => {
+                if let Some(Ok(result)) = self.result.take() {
+                    self.expression = if OPERATORS.contains(&c) {
+                        result
+                    } else {
+                        String::new()
+                    };
+                }
+                self.expression.push(c);
+            }
+            Modified::Backspace => {
+                self.result = None;
+                self.expression.pop();
+            }
+            Modified::Escape => self.clear(),
+            _ => {}
+        }
+        None
+    }
+
+    /// Evaluate the expression and keep the result.
+    pub fn evaluate(&mut self) -> Option<String> {
+        if self.expression.is_empty() {
+            return None;
+        }
+        match (self.evaluator)(&self.expression) {
+            Ok(result) => {
+                self.result = Some(Ok(result.clone()));
+                Some(result)
+            }
+            Err(e) => {
+                self.result = Some(Err(format!("{:#}", e)));
+                None
+            }
+        }
+    }
+}
+
+/// Returns the end of the text that fits in the number of characters.
+fn tail(text: &str, columns: usize) -> &str {
+    let count = text.chars().count();
+    match text.char_indices().nth(count.saturating_sub(columns)) {
+        Some((i, _)) => &text[i..],
+        None => text,
+    }
+}
+
+impl Drawable for ExpressionInput {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.area
+            .into_styled(PrimitiveStyle::with_fill(self.background))
+            .draw(target)?;
+        let right = TextStyleBuilder::new()
+            .alignment(Alignment::Right)
+            .baseline(Baseline::Top)
+            .build();
+        let top_right = self.area.top_left + Point::new(self.area.size.width as i32 - 4, 4);
+        let large = FONT_10X20.character_size;
+        let columns = (self.area.size.width.saturating_sub(8) / large.width) as usize;
+
+        Text::with_text_style(
+            tail(&self.expression, columns),
+            top_right,
+            MonoTextStyle::new(&FONT_10X20, self.color),
+            right,
+        )
+        .draw(target)?;
+
+        let position = top_right + Point::new(0, large.height as i32 + 4);
+        match &self.result {
+            Some(Ok(result)) => {
+                let text = format!("={}", result);
+                Text::with_text_style(
+                    tail(&text, columns),
+                    position,
+                    MonoTextStyle::new(&FONT_10X20, Rgb565::GREEN),
+                    right,
+                )
+                .draw(target)?;
+            }
+            Some(Err(message)) => {
+                let columns = (self.area.size.width.saturating_sub(8) / 6) as usize;
+                Text::with_text_style(
+                    tail(message, columns),
+                    position,
+                    MonoTextStyle::new(&FONT_6X10, Rgb565::RED),
+                    right,
+                )
+                .draw(target)?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
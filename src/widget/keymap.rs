@@ -0,0 +1,165 @@
+//! Keymap visualization widget
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+
+use crate::keyboard::{KeyImprint, KeyType, KeyboardState, Layers, Modified, KEY_MAP};
+
+/// Widget that draws the 4x14 key layout and highlights the held keys
+///
+/// The keys assigned by an active layer, e.g. the Fn layer while Fn is
+/// held, are labelled with the assignment in a different color.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::widget::keymap::KeymapView;
+///
+/// keyboard_state.update(&mut keyboard).unwrap();
+/// KeymapView::new(&keyboard_state, Point::new(1, 60))
+///     .draw(&mut fb)
+///     .unwrap();
+/// ```
+pub struct KeymapView<'a> {
+    state: &'a KeyboardState,
+    top_left: Point,
+    key_size: Size,
+}
+
+impl<'a> KeymapView<'a> {
+    /// Create new widget with the top-left position.
+    pub fn new(state: &'a KeyboardState, top_left: Point) -> Self {
+        Self {
+            state,
+            top_left,
+            key_size: Size::new(17, 17),
+        }
+    }
+
+    /// Set the size of a key including the gap to the next key.
+    pub fn with_key_size(mut self, key_size: Size) -> Self {
+        self.key_size = key_size;
+        self
+    }
+
+    fn is_held(&self, key: &KeyType, hold: &[KeyImprint]) -> bool {
+        match key.imprint() {
+            KeyImprint::LeftFn => self.state.is_fn_pressed(),
+            KeyImprint::LeftShift => self.state.is_shift_pressed(),
+            KeyImprint::LeftCtrl => self.state.is_ctrl_pressed(),
+            KeyImprint::LeftAlt => self.state.is_alt_pressed(),
+            KeyImprint::LeftOpt => self.state.is_opt_pressed(),
+            x => hold.contains(&x),
+        }
+    }
+}
+
+impl Drawable for KeymapView<'_> {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let layers = self.state.layers();
+        let is_shift = self.state.is_shift_pressed();
+        let hold = self.state.hold_imprints();
+
+        let text_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+        let key_rect_size = self.key_size - Size::new(1, 1);
+
+        // the top row of the keyboard is the last row of the map
+        for (row, keys) in KEY_MAP.iter().rev().enumerate() {
+            for (col, key) in keys.iter().enumerate() {
+                let position = self.top_left
+                    + Point::new(
+                        col as i32 * self.key_size.width as i32,
+                        row as i32 * self.key_size.height as i32,
+                    );
+                let rect = Rectangle::new(position, key_rect_size);
+
+                let held = self.is_held(key, &hold);
+                let (label, is_layer) = label(key, layers, is_shift);
+                let (fill, text) = match (held, is_layer) {
+                    (true, _) => (Rgb565::GREEN, Rgb565::BLACK),
+                    (false, true) => (Rgb565::BLACK, Rgb565::CYAN),
+                    (false, false) => (Rgb565::BLACK, Rgb565::WHITE),
+                };
+
+                rect.into_styled(
+                    PrimitiveStyleBuilder::new()
+                        .fill_color(fill)
+                        .stroke_color(Rgb565::CSS_GRAY)
+                        .stroke_width(1)
+                        .build(),
+                )
+                .draw(target)?;
+                Text::with_text_style(
+                    &label,
+                    rect.center(),
+                    MonoTextStyle::new(&FONT_6X10, text),
+                    text_style,
+                )
+                .draw(target)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the label of the key and whether it is assigned by an active layer.
+fn label(key: &KeyType, layers: &Layers, is_shift: bool) -> (String, bool) {
+    let rule = match key {
+        KeyType::Modifier(imprint) => {
+            let label = match imprint {
+                KeyImprint::LeftFn => "Fn",
+                KeyImprint::LeftShift => "Sh",
+                KeyImprint::LeftCtrl => "Ct",
+                KeyImprint::LeftOpt => "Op",
+                KeyImprint::LeftAlt => "Al",
+                _ => "",
+            };
+            return (label.to_string(), false);
+        }
+        KeyType::Normal(rule) => rule,
+    };
+
+    let (modified, is_layer) = match layers.get(rule.imprint()) {
+        Some(x) => (x, true),
+        None => (rule.modified(false, is_shift), false),
+    };
+    let label = match modified {
+        Modified::Graph(c) => return (c.to_string(), is_layer),
+        Modified::Escape => "Es",
+        Modified::Enter => "En",
+        Modified::Space => "Sp",
+        Modified::Tab => "Tb",
+        Modified::LeftCursor => "<",
+        Modified::DownCursor => "v",
+        Modified::UpCursor => "^",
+        Modified::RightCursor => ">",
+        Modified::Home => "Hm",
+        Modified::End => "Ed",
+        Modified::Backspace => "BS",
+        Modified::Delete => "Dl",
+        Modified::VolumeUp => "V+",
+        Modified::VolumeDown => "V-",
+        Modified::Mute => "Mu",
+        Modified::PlayPause => "Pl",
+        Modified::NextTrack => ">|",
+        Modified::PreviousTrack => "|<",
+        Modified::BrightnessUp => "B+",
+        Modified::BrightnessDown => "B-",
+        Modified::NumLock => "NL",
+    };
+    (label.to_string(), is_layer)
+}
@@ -0,0 +1,438 @@
+//! Single-line text input widget
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+
+use anyhow::Result;
+use std::{
+    collections::VecDeque,
+    fmt, fs,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use crate::clipboard;
+use crate::keyboard::{KeyboardState, Modified};
+
+/// Size of a character of the font used by the editor
+const CHAR_SIZE: Size = Size::new(6, 10);
+
+/// Diameter of the bullets masking the text in password mode
+const BULLET_DIAMETER: u32 = 4;
+
+/// Default number of lines kept in the history
+const DEFAULT_HISTORY_SIZE: usize = 32;
+
+/// Single-line text editor
+///
+/// Ctrl+C copies the whole line to the [`clipboard`], Ctrl+X cuts it and
+/// Ctrl+V inserts the clipboard contents at the cursor.
+///
+/// Entered lines are kept in a history recalled with Fn+Up/Down like a shell.
+///
+/// Ctrl+Left/Right jump by words and Ctrl+Backspace/Delete delete a word.
+/// Home and End, which can be assigned to keys with a
+/// [`Layer`](crate::keyboard::Layer), move to the start and end of the line.
+///
+/// In password mode the text is shown as `•` until Tab toggles the reveal,
+/// copying and the history are disabled, word operations act on the whole
+/// line so they do not reveal the word boundaries, and the internal buffer is
+/// overwritten with zeros when it is cleared or dropped. The editor cannot
+/// be cloned, and its `Debug` output shows only the length of the text.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::widget::line_editor::LineEditor;
+///
+/// let mut editor = LineEditor::new(Point::new(0, 120), 240);
+/// loop {
+///     keyboard_state.update(&mut keyboard).unwrap();
+///     if let Some(line) = editor.update(&keyboard_state) {
+///         log::info!("{}", line);
+///     }
+///     editor.draw(&mut fb).unwrap();
+/// }
+/// ```
+pub struct LineEditor {
+    text: Vec<char>,
+    cursor: usize,
+    top_left: Point,
+    width: u32,
+    history: VecDeque<String>,
+    history_size: usize,
+    /// Index of the recalled history line, if any
+    recalled: Option<usize>,
+    /// Line being edited before the history was recalled
+    draft: String,
+    password: bool,
+    revealed: bool,
+}
+
+impl LineEditor {
+    /// Create new empty editor with the position and the width in pixels.
+    pub fn new(top_left: Point, width: u32) -> Self {
+        Self {
+            text: Vec::new(),
+            cursor: 0,
+            top_left,
+            width,
+            history: VecDeque::new(),
+            history_size: DEFAULT_HISTORY_SIZE,
+            recalled: None,
+            draft: String::new(),
+            password: false,
+            revealed: false,
+        }
+    }
+
+    /// Mask the text for entering a password or other secret.
+    pub fn with_password_mode(mut self) -> Self {
+        self.password = true;
+        self.history.clear();
+        self
+    }
+
+    /// Returns true if the editor is in password mode.
+    pub fn is_password_mode(&self) -> bool {
+        self.password
+    }
+
+    /// Show or mask the text in password mode.
+    pub fn set_revealed(&mut self, revealed: bool) {
+        self.revealed = revealed;
+    }
+
+    /// Returns true if the text in password mode is shown.
+    pub fn is_revealed(&self) -> bool {
+        self.revealed
+    }
+
+    /// Set the number of lines kept in the history.
+    pub fn with_history_size(mut self, size: usize) -> Self {
+        self.history_size = size;
+        self.history.truncate(size);
+        self
+    }
+
+    /// Returns the history from the oldest line.
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(|x| x.as_str())
+    }
+
+    /// Add the line to the history unless it is empty or the same as the latest line.
+    pub fn push_history(&mut self, line: &str) {
+        if self.password
+            || self.history_size == 0
+            || line.is_empty()
+            || self.history.back().map(|x| x.as_str()) == Some(line)
+        {
+            return;
+        }
+        while self.history.len() >= self.history_size {
+            self.history.pop_front();
+        }
+        self.history.push_back(line.to_string());
+    }
+
+    /// Load the history from the file, one line per entry, e.g. on the SD card.
+    ///
+    /// A missing file is treated as an empty history.
+    pub fn load_history(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let file = match fs::File::open(path) {
+            Ok(x) => x,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        self.history.clear();
+        for line in BufReader::new(file).lines() {
+            self.push_history(&line?);
+        }
+        Ok(())
+    }
+
+    /// Save the history to the file, one line per entry.
+    pub fn save_history(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut file = fs::File::create(path)?;
+        for line in self.history.iter() {
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Show the previous history line, keeping the current line to come back to.
+    fn recall_previous(&mut self) {
+        let index = match self.recalled {
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.text();
+                self.history.len() - 1
+            }
+            Some(x) => x.saturating_sub(1),
+        };
+        self.recalled = Some(index);
+        let line = self.history[index].clone();
+        self.set_text(&line);
+    }
+
+    /// Show the next history line, or the line being edited after the latest one.
+    fn recall_next(&mut self) {
+        let Some(index) = self.recalled else {
+            return;
+        };
+        if index + 1 < self.history.len() {
+            self.recalled = Some(index + 1);
+            let line = self.history[index + 1].clone();
+            self.set_text(&line);
+        } else {
+            self.recalled = None;
+            let draft = std::mem::take(&mut self.draft);
+            self.set_text(&draft);
+        }
+    }
+
+    /// Returns the text being edited.
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    /// Replace the text and move the cursor to the end.
+    pub fn set_text(&mut self, text: &str) {
+        self.clear();
+        for c in text.chars() {
+            self.insert_char(c);
+        }
+    }
+
+    /// Clear the text.
+    pub fn clear(&mut self) {
+        if self.password {
+            wipe(&mut self.text);
+        }
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    /// Insert the character at the cursor.
+    fn insert_char(&mut self, c: char) {
+        if self.password && self.text.len() == self.text.capacity() {
+            // grow by hand so that no copy of the secret is left in the old allocation
+            let mut text = Vec::with_capacity((self.text.capacity() * 2).max(16));
+            text.extend_from_slice(&self.text);
+            wipe(&mut self.text);
+            self.text = text;
+        }
+        self.text.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    /// Returns the cursor position in characters.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Insert the text at the cursor, ignoring control characters.
+    pub fn insert_str(&mut self, text: &str) {
+        for c in text.chars().filter(|c| !c.is_control()) {
+            self.insert_char(c);
+        }
+    }
+
+    /// Handle the keys pressed in the last update.
+    ///
+    /// Returns the line and clears the editor when Enter is pressed.
+    pub fn update(&mut self, state: &KeyboardState) -> Option<String> {
+        let ctrl = state.is_ctrl_pressed();
+        let mut line = None;
+        for key in state.pressed_keys() {
+            if let Some(x) = self.handle_key(key, ctrl) {
+                line = Some(x);
+            }
+        }
+        line
+    }
+
+    /// Handle a key. Returns the line and clears the editor on Enter.
+    ///
+    /// Up and Down recall the history. Tab toggles the reveal in password mode.
+    pub fn handle_key(&mut self, key: Modified, ctrl: bool) -> Option<String> {
+        match key {
+            Modified::Graph(c) if ctrl => match c.to_ascii_lowercase() {
+                'c' | 'x' if self.password => {}
+                'c' => clipboard::copy(&self.text()),
+                'x' => {
+                    clipboard::copy(&self.text());
+                    self.clear();
+                }
+                'v' => self.insert_str(&clipboard::paste()),
+                _ => {}
+            },
+            Modified::Graph(c) => self.insert_char(c),
+            Modified::Space => self.insert_char(' '),
+            Modified::Tab if self.password => self.revealed = !self.revealed,
+            Modified::Backspace if ctrl => {
+                let start = self.word_start();
+                self.text.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            Modified::Delete if ctrl => {
+                let end = self.word_end();
+                self.text.drain(self.cursor..end);
+            }
+            Modified::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.text.remove(self.cursor);
+            }
+            Modified::Delete if self.cursor < self.text.len() => {
+                self.text.remove(self.cursor);
+            }
+            Modified::LeftCursor if ctrl => self.cursor = self.word_start(),
+            Modified::RightCursor if ctrl => self.cursor = self.word_end(),
+            Modified::Home => self.cursor = 0,
+            Modified::End => self.cursor = self.text.len(),
+            Modified::LeftCursor => self.cursor = self.cursor.saturating_sub(1),
+            Modified::RightCursor => self.cursor = (self.cursor + 1).min(self.text.len()),
+            Modified::UpCursor => self.recall_previous(),
+            Modified::DownCursor => self.recall_next(),
+            Modified::Enter => {
+                let line = self.text();
+                self.push_history(&line);
+                self.recalled = None;
+                self.clear();
+                return Some(line);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Returns the start of the word before the cursor, skipping the
+    /// separators between.
+    fn word_start(&self) -> usize {
+        if self.password {
+            return 0;
+        }
+        let before = &self.text[..self.cursor];
+        let word_end = before
+            .iter()
+            .rposition(|x| is_word_char(*x))
+            .map_or(0, |x| x + 1);
+        before[..word_end]
+            .iter()
+            .rposition(|x| !is_word_char(*x))
+            .map_or(0, |x| x + 1)
+    }
+
+    /// Returns the end of the word after the cursor, skipping the
+    /// separators between.
+    fn word_end(&self) -> usize {
+        if self.password {
+            return self.text.len();
+        }
+        let after = &self.text[self.cursor..];
+        let word_start = after
+            .iter()
+            .position(|x| is_word_char(*x))
+            .unwrap_or(after.len());
+        let word_len = after[word_start..]
+            .iter()
+            .position(|x| !is_word_char(*x))
+            .unwrap_or(after.len() - word_start);
+        self.cursor + word_start + word_len
+    }
+
+    /// Returns the characters shown in the widget and the index of the first one.
+    fn visible_chars(&self) -> (usize, &[char]) {
+        let columns = (self.width / CHAR_SIZE.width).max(1) as usize;
+        let first = (self.cursor + 1).saturating_sub(columns);
+        let last = (first + columns).min(self.text.len());
+        (first, &self.text[first..last])
+    }
+}
+
+impl Drawable for LineEditor {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        Rectangle::new(self.top_left, Size::new(self.width, CHAR_SIZE.height))
+            .into_styled(PrimitiveStyle::with_fill(Rgb565::BLACK))
+            .draw(target)?;
+
+        let (first, chars) = self.visible_chars();
+        if self.password && !self.revealed {
+            // the font has no bullet, draw one in each character cell
+            let style = PrimitiveStyle::with_fill(Rgb565::WHITE);
+            for i in 0..chars.len() {
+                let x = i as i32 * CHAR_SIZE.width as i32 + 1;
+                Circle::new(self.top_left + Point::new(x, 3), BULLET_DIAMETER)
+                    .into_styled(style)
+                    .draw(target)?;
+            }
+        } else {
+            let text: String = chars.iter().collect();
+            Text::with_baseline(
+                &text,
+                self.top_left,
+                MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE),
+                Baseline::Top,
+            )
+            .draw(target)?;
+        }
+
+        let x = (self.cursor - first) as i32 * CHAR_SIZE.width as i32;
+        Rectangle::new(
+            self.top_left + Point::new(x, 0),
+            Size::new(1, CHAR_SIZE.height),
+        )
+        .into_styled(PrimitiveStyle::with_fill(Rgb565::YELLOW))
+        .draw(target)?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for LineEditor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the text may be a secret, and the history holds past entries
+        f.debug_struct("LineEditor")
+            .field("len", &self.text.len())
+            .field("cursor", &self.cursor)
+            .field("top_left", &self.top_left)
+            .field("width", &self.width)
+            .field("history_len", &self.history.len())
+            .field("recalled", &self.recalled)
+            .field("password", &self.password)
+            .field("revealed", &self.revealed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for LineEditor {
+    fn drop(&mut self) {
+        if self.password {
+            wipe(&mut self.text);
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Overwrite the characters including the unused capacity with zeros.
+fn wipe(text: &mut Vec<char>) {
+    let ptr = text.as_mut_ptr();
+    for i in 0..text.capacity() {
+        // SAFETY: the pointer is valid for writes up to the capacity, and
+        // volatile keeps the writes from being optimized away
+        unsafe { std::ptr::write_volatile(ptr.add(i), '\0') };
+    }
+}
@@ -0,0 +1,70 @@
+//! Debug overlay showing the heap usage
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyleBuilder},
+    pixelcolor::Rgb565,
+    prelude::*,
+    text::{Baseline, Text},
+};
+
+use crate::memory::MemoryStats;
+
+/// Widget that draws the free heap, the largest free block and the PSRAM usage
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{memory::MemoryStats, widget::memory_overlay::MemoryOverlay};
+///
+/// MemoryOverlay::new(MemoryStats::current(), Point::zero())
+///     .draw(&mut fb)
+///     .unwrap();
+/// fb.flush(&mut display).unwrap();
+/// ```
+pub struct MemoryOverlay {
+    stats: MemoryStats,
+    top_left: Point,
+}
+
+impl MemoryOverlay {
+    /// Create new widget with the top-left position.
+    pub fn new(stats: MemoryStats, top_left: Point) -> Self {
+        Self { stats, top_left }
+    }
+}
+
+impl Drawable for MemoryOverlay {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X10)
+            .text_color(Rgb565::YELLOW)
+            .background_color(Rgb565::BLACK)
+            .build();
+
+        let stats = &self.stats;
+        let mut lines = vec![format!(
+            "heap {}K blk {}K min {}K",
+            stats.free_internal / 1024,
+            stats.largest_internal_block / 1024,
+            stats.min_free_internal / 1024
+        )];
+        if stats.total_psram > 0 {
+            lines.push(format!(
+                "psram {}K/{}K",
+                (stats.total_psram - stats.free_psram) / 1024,
+                stats.total_psram / 1024
+            ));
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            let position = self.top_left + Point::new(0, i as i32 * 10);
+            Text::with_baseline(line, position, style, Baseline::Top).draw(target)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Spectrum bars for audio visualizers
+use embedded_graphics::{
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+};
+
+/// Default magnitude drawn at the full height
+const DEFAULT_SCALE: u16 = 4096;
+
+/// Widget that draws band magnitudes as vertical bars
+///
+/// The background of the area is cleared, so the widget can be redrawn in
+/// place every frame.
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::widget::spectrum::SpectrumBars;
+///
+/// let bands = spectrum.compute(&samples);
+/// SpectrumBars::new(bands, Rectangle::new(Point::new(0, 35), Size::new(240, 100)))
+///     .with_color(Rgb565::CYAN)
+///     .draw(&mut fb)
+///     .unwrap();
+/// ```
+pub struct SpectrumBars<'a> {
+    magnitudes: &'a [u16],
+    area: Rectangle,
+    scale: u16,
+    color: Rgb565,
+    background: Rgb565,
+}
+
+impl<'a> SpectrumBars<'a> {
+    /// Create new widget drawing the magnitudes in the area.
+    pub fn new(magnitudes: &'a [u16], area: Rectangle) -> Self {
+        Self {
+            magnitudes,
+            area,
+            scale: DEFAULT_SCALE,
+            color: Rgb565::GREEN,
+            background: Rgb565::BLACK,
+        }
+    }
+
+    /// Set the magnitude drawn at the full height; higher values are clipped.
+    pub fn with_scale(mut self, scale: u16) -> Self {
+        self.scale = scale.max(1);
+        self
+    }
+
+    /// Set the bar color.
+    pub fn with_color(mut self, color: Rgb565) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Set the background color.
+    pub fn with_background(mut self, color: Rgb565) -> Self {
+        self.background = color;
+        self
+    }
+}
+
+impl Drawable for SpectrumBars<'_> {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.area
+            .into_styled(PrimitiveStyle::with_fill(self.background))
+            .draw(target)?;
+        if self.magnitudes.is_empty() {
+            return Ok(());
+        }
+
+        let count = self.magnitudes.len() as u32;
+        let slot = self.area.size.width / count;
+        // one pixel gap between bars when they are wide enough
+        let width = if slot > 2 { slot - 1 } else { slot.max(1) };
+        let height = self.area.size.height;
+        let style = PrimitiveStyle::with_fill(self.color);
+
+        for (i, magnitude) in self.magnitudes.iter().enumerate() {
+            let bar = (*magnitude).min(self.scale) as u32 * height / self.scale as u32;
+            if bar == 0 {
+                continue;
+            }
+            let top_left =
+                self.area.top_left + Point::new((i as u32 * slot) as i32, (height - bar) as i32);
+            Rectangle::new(top_left, Size::new(width, bar))
+                .into_styled(style)
+                .draw(target)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+//! Typing statistics widget
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::{Baseline, Text},
+};
+
+use crate::keyboard::{typing_stats::TypingStats, KEY_MAP};
+
+/// Widget that draws the speed, the corrections and a heat map of the
+/// presses of each key
+///
+/// The keys are filled from black for the unused ones to red for the
+/// most pressed one; the exact counts are given by [`TypingStats`].
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::widget::typing_stats::TypingStatsView;
+///
+/// TypingStatsView::new(&stats, Point::new(1, 1))
+///     .draw(&mut fb)
+///     .unwrap();
+/// ```
+pub struct TypingStatsView<'a> {
+    stats: &'a TypingStats,
+    top_left: Point,
+    key_size: Size,
+}
+
+impl<'a> TypingStatsView<'a> {
+    /// Create new widget with the top-left position.
+    pub fn new(stats: &'a TypingStats, top_left: Point) -> Self {
+        Self {
+            stats,
+            top_left,
+            key_size: Size::new(17, 17),
+        }
+    }
+
+    /// Set the size of a key of the heat map including the gap to the
+    /// next key (17x17).
+    pub fn with_key_size(mut self, key_size: Size) -> Self {
+        self.key_size = key_size;
+        self
+    }
+}
+
+/// Returns the color of the count relative to the largest one.
+fn heat(count: u32, max: u32) -> Rgb565 {
+    if count == 0 || max == 0 {
+        return Rgb565::BLACK;
+    }
+    // at least a quarter, so that rare keys stand out from unused ones
+    let level = 0.25 + 0.75 * count as f32 / max as f32;
+    Rgb565::new(
+        (level * Rgb565::MAX_R as f32) as u8,
+        ((1.0 - level) * Rgb565::MAX_G as f32 / 2.0) as u8,
+        0,
+    )
+}
+
+impl Drawable for TypingStatsView<'_> {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let stats = self.stats;
+        let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        let active = stats.active_time().as_secs();
+        let lines = [
+            format!(
+                "{:.0} WPM  {} chars  {}:{:02}",
+                stats.wpm(),
+                stats.characters(),
+                active / 60,
+                active % 60
+            ),
+            format!(
+                "{} corrections ({:.1}%)",
+                stats.corrections(),
+                stats.correction_rate() * 100.0
+            ),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            Text::with_baseline(
+                line,
+                self.top_left + Point::new(0, i as i32 * 11),
+                style,
+                Baseline::Top,
+            )
+            .draw(target)?;
+        }
+
+        let origin = self.top_left + Point::new(0, lines.len() as i32 * 11 + 3);
+        let max = stats.most_pressed().first().map_or(0, |x| x.1);
+        let key_rect_size = self.key_size - Size::new(1, 1);
+
+        // the top row of the keyboard is the last row of the map
+        for (row, keys) in KEY_MAP.iter().rev().enumerate() {
+            for (col, key) in keys.iter().enumerate() {
+                let position = origin
+                    + Point::new(
+                        col as i32 * self.key_size.width as i32,
+                        row as i32 * self.key_size.height as i32,
+                    );
+                let rect = Rectangle::new(position, key_rect_size);
+                let count = stats.count(key.imprint());
+                rect.into_styled(
+                    PrimitiveStyleBuilder::new()
+                        .fill_color(heat(count, max))
+                        .stroke_color(Rgb565::CSS_GRAY)
+                        .stroke_width(1)
+                        .build(),
+                )
+                .draw(target)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! Switch showing whether the SD card is exposed over USB
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, PrimitiveStyle, Rectangle, RoundedRectangle},
+    text::{Baseline, Text},
+};
+
+/// Widget drawing a switch labeled "USB drive" and the current owner of
+/// the card
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::widget::usb_storage::UsbStorageToggle;
+///
+/// if keyboard_state.pressed_keys().contains(&Modified::Enter) {
+///     msc.toggle().unwrap();
+/// }
+/// UsbStorageToggle::new(msc.is_exposed(), Point::new(4, 4))
+///     .draw(&mut fb)
+///     .unwrap();
+/// ```
+pub struct UsbStorageToggle {
+    is_exposed: bool,
+    top_left: Point,
+}
+
+impl UsbStorageToggle {
+    /// Create new widget with the top-left position.
+    pub fn new(is_exposed: bool, top_left: Point) -> Self {
+        Self {
+            is_exposed,
+            top_left,
+        }
+    }
+}
+
+impl Drawable for UsbStorageToggle {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let (track, knob_x, state) = if self.is_exposed {
+            (Rgb565::GREEN, 14, "PC (eject before switching)")
+        } else {
+            (Rgb565::CSS_GRAY, 0, "local")
+        };
+        RoundedRectangle::with_equal_corners(
+            Rectangle::new(self.top_left, Size::new(24, 10)),
+            Size::new(5, 5),
+        )
+        .into_styled(PrimitiveStyle::with_fill(track))
+        .draw(target)?;
+        Circle::new(self.top_left + Point::new(knob_x, 0), 10)
+            .into_styled(PrimitiveStyle::with_fill(Rgb565::WHITE))
+            .draw(target)?;
+
+        let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        let text = format!("USB drive: {}", state);
+        Text::with_baseline(
+            &text,
+            self.top_left + Point::new(30, 0),
+            style,
+            Baseline::Top,
+        )
+        .draw(target)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+//! XMODEM and YMODEM file reception
+//!
+//! Receives files pushed from a terminal program (e.g. `sz`/`sx` of
+//! lrzsz, Tera Term or minicom) onto the SD card or the flash filesystem
+//! without removing the card. Only the CRC-16 variants with 128 and 1024
+//! byte blocks are supported.
+//!
+//! XMODEM carries no file size, so the received file keeps the padding
+//! (0x1A) of the last block; YMODEM truncates it to the announced size.
+use anyhow::{anyhow, bail, Result};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::usb_serial::UsbSerial;
+use cardputer_core::crc::crc16;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Request of the CRC-16 mode
+const CRC: u8 = b'C';
+
+const DEFAULT_START_TIMEOUT: Duration = Duration::from_secs(60);
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const START_INTERVAL: Duration = Duration::from_secs(3);
+const MAX_ERRORS: u32 = 10;
+
+/// Byte stream carrying the transfer
+pub trait Port {
+    /// Read the available bytes, waiting up to the timeout. Returns 0 on timeout.
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize>;
+    fn write_all(&mut self, data: &[u8]) -> Result<()>;
+}
+
+impl Port for UsbSerial {
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        UsbSerial::read(self, buf, timeout)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        UsbSerial::write_all(self, data)
+    }
+}
+
+enum Packet {
+    Block(u8, Vec<u8>),
+    EndOfTransmission,
+    /// Timeout, bad header or CRC error
+    Invalid,
+}
+
+/// Protocol of a transfer, YMODEM when the file header block 0 was received
+#[derive(Debug, Clone, Copy)]
+enum Protocol {
+    Xmodem,
+    /// The size is `None` if the header does not announce it
+    Ymodem {
+        size: Option<usize>,
+    },
+}
+
+/// File receiver
+///
+/// # Examples
+///
+/// ```
+/// use cardputer::{usb_serial::UsbSerial, xmodem::Receiver};
+///
+/// let card = SdCard::mount(/* ... */).unwrap();
+/// let mut receiver = Receiver::new(UsbSerial::new().unwrap());
+/// // run `sz --ymodem font.bin` on the host
+/// for path in receiver.receive_ymodem(card.mount_point()).unwrap() {
+///     log::info!("received {}", path.display());
+/// }
+/// ```
+pub struct Receiver<P: Port> {
+    port: P,
+    start_timeout: Duration,
+}
+
+impl<P: Port> Receiver<P> {
+    /// Create new receiver on the port.
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            start_timeout: DEFAULT_START_TIMEOUT,
+        }
+    }
+
+    /// Set how long to wait for the sender to start (60 s).
+    pub fn with_start_timeout(mut self, timeout: Duration) -> Self {
+        self.start_timeout = timeout;
+        self
+    }
+
+    /// Return the port.
+    pub fn release(self) -> P {
+        self.port
+    }
+
+    /// Receive a file with XMODEM and write it to the writer. Returns the
+    /// number of bytes written.
+    pub fn receive_xmodem(&mut self, writer: &mut impl Write) -> Result<usize> {
+        let first = self.start()?;
+        self.receive_data(first, writer, Protocol::Xmodem)
+    }
+
+    /// Receive a batch of files with YMODEM into the directory. Returns the
+    /// paths of the received files.
+    ///
+    /// Only the file names are used; directories sent by the host are dropped.
+    pub fn receive_ymodem(&mut self, directory: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        loop {
+            let header = match self.start()? {
+                Packet::Block(0, data) => data,
+                _ => return Err(self.cancel("expected the file header")),
+            };
+            self.port.write_all(&[ACK])?;
+            // an empty name ends the batch
+            if header[0] == 0 {
+                return Ok(paths);
+            }
+            let (name, size) = parse_header(&header)?;
+            let path = directory.as_ref().join(name);
+            let mut file = File::create(&path)?;
+
+            let first = self.start()?;
+            self.receive_data(first, &mut file, Protocol::Ymodem { size })?;
+            file.flush()?;
+            paths.push(path);
+        }
+    }
+
+    /// Request the CRC mode until the first packet arrives.
+    fn start(&mut self) -> Result<Packet> {
+        let deadline = Instant::now() + self.start_timeout;
+        while Instant::now() < deadline {
+            self.port.write_all(&[CRC])?;
+            match self.read_packet(START_INTERVAL)? {
+                Packet::Invalid => continue,
+                packet => return Ok(packet),
+            }
+        }
+        Err(self.cancel("sender did not start"))
+    }
+
+    /// Receive data blocks from number 1 until the end of transmission.
+    fn receive_data(
+        &mut self,
+        mut packet: Packet,
+        writer: &mut impl Write,
+        protocol: Protocol,
+    ) -> Result<usize> {
+        let mut expected = 1u8;
+        let mut written = 0;
+        let mut errors = 0;
+        let mut eot_count = 0;
+        loop {
+            match packet {
+                Packet::Block(number, data) if number == expected => {
+                    let len = match protocol {
+                        Protocol::Ymodem { size: Some(size) } => {
+                            data.len().min(size.saturating_sub(written))
+                        }
+                        _ => data.len(),
+                    };
+                    writer.write_all(&data[..len])?;
+                    written += len;
+                    expected = expected.wrapping_add(1);
+                    errors = 0;
+                    self.port.write_all(&[ACK])?;
+                }
+                // the ACK was lost and the sender repeated the block
+                Packet::Block(number, _) if number == expected.wrapping_sub(1) => {
+                    self.port.write_all(&[ACK])?;
+                }
+                Packet::Block(..) => return Err(self.cancel("block out of sequence")),
+                Packet::EndOfTransmission => {
+                    // YMODEM confirms the end with a second EOT
+                    eot_count += 1;
+                    if matches!(protocol, Protocol::Ymodem { .. }) && eot_count == 1 {
+                        self.port.write_all(&[NAK])?;
+                    } else {
+                        self.port.write_all(&[ACK])?;
+                        return Ok(written);
+                    }
+                }
+                Packet::Invalid => {
+                    errors += 1;
+                    if errors > MAX_ERRORS {
+                        return Err(self.cancel("too many errors"));
+                    }
+                    self.purge()?;
+                    self.port.write_all(&[NAK])?;
+                }
+            }
+            packet = self.read_packet(BLOCK_TIMEOUT)?;
+        }
+    }
+
+    fn read_packet(&mut self, timeout: Duration) -> Result<Packet> {
+        let mut header = [0u8];
+        if !self.read_exact(&mut header, timeout)? {
+            return Ok(Packet::Invalid);
+        }
+        let len = match header[0] {
+            SOH => 128,
+            STX => 1024,
+            EOT => return Ok(Packet::EndOfTransmission),
+            CAN => bail!("cancelled by the sender"),
+            _ => return Ok(Packet::Invalid),
+        };
+        // number, its complement, data and CRC
+        let mut packet = vec![0u8; len + 4];
+        if !self.read_exact(&mut packet, BLOCK_TIMEOUT)? {
+            return Ok(Packet::Invalid);
+        }
+        let (number, complement) = (packet[0], packet[1]);
+        let crc = u16::from_be_bytes([packet[len + 2], packet[len + 3]]);
+        if number != !complement || crc != crc16(&packet[2..len + 2]) {
+            return Ok(Packet::Invalid);
+        }
+        packet.truncate(len + 2);
+        packet.drain(..2);
+        Ok(Packet::Block(number, packet))
+    }
+
+    /// Fill the buffer, returning false on timeout.
+    fn read_exact(&mut self, buf: &mut [u8], timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        let mut filled = 0;
+        while filled < buf.len() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+            filled += self.port.read(&mut buf[filled..], remaining)?;
+        }
+        Ok(true)
+    }
+
+    /// Discard the input until the line is quiet.
+    fn purge(&mut self) -> Result<()> {
+        let mut buf = [0u8; 64];
+        while self.port.read(&mut buf, Duration::from_millis(500))? > 0 {}
+        Ok(())
+    }
+
+    /// Abort the transfer and return the error.
+    fn cancel(&mut self, reason: &str) -> anyhow::Error {
+        let _ = self.port.write_all(&[CAN; 3]);
+        anyhow!("transfer cancelled: {}", reason)
+    }
+}
+
+/// Parse the name and the size of a YMODEM header block.
+fn parse_header(header: &[u8]) -> Result<(String, Option<usize>)> {
+    let mut fields = header.split(|x| *x == 0);
+    let path = std::str::from_utf8(fields.next().unwrap_or_default())?;
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("invalid file name: {:?}", path))?;
+    let size = fields
+        .next()
+        .and_then(|info| info.split(|x| *x == b' ').next())
+        .and_then(|size| std::str::from_utf8(size).ok()?.parse().ok());
+    Ok((name.to_string(), size))
+}